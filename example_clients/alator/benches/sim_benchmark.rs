@@ -4,6 +4,7 @@ use criterion::{criterion_group, criterion_main, Criterion};
 use std::collections::HashMap;
 
 use alator::strategy::staticweight::StaticWeightStrategyBuilder;
+use alator::strategy::Strategy;
 use rotala::http::uist::uistv1_client::{TestClient, UistClient};
 use rotala::input::penelope::Penelope;
 
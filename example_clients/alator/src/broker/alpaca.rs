@@ -0,0 +1,468 @@
+//! Stub of a live broker that routes orders to the [Alpaca](https://alpaca.markets) REST API.
+//!
+//! Broker state is held locally: cash is loaded from the account on `connect` and holdings are not
+//! reconciled against the brokerage. There is no market data connection so quotes must be supplied
+//! with `update_quote`. Orders are checked against local cash and holdings, queued by `send_order`
+//! and submitted on `check`, same as [UistBroker](crate::broker::uist::UistBroker).
+
+use std::collections::HashMap;
+use std::mem;
+
+use log::info;
+use rotala::exchange::uist_v1::{Order, OrderType, UistQuote};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::{
+    BrokerCost, BrokerError, BrokerEvent, BrokerOperations, BrokerState, BrokerStates,
+    CashOperations, ConnectionError, DateTime, LiveTradingAdapter, OrderRejectionLog, Portfolio,
+    PortfolioHoldings, Quote, SendOrder, Update,
+};
+
+/// Order in the format expected by the Alpaca `/v2/orders` endpoint.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AlpacaOrder {
+    pub symbol: String,
+    pub qty: String,
+    pub side: String,
+    #[serde(rename = "type")]
+    pub order_type: String,
+    pub time_in_force: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_price: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_price: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trail_price: Option<String>,
+}
+
+impl From<&Order> for AlpacaOrder {
+    fn from(order: &Order) -> Self {
+        let (side, order_type) = match order.get_order_type() {
+            OrderType::MarketBuy => ("buy", "market"),
+            OrderType::MarketSell => ("sell", "market"),
+            OrderType::LimitBuy => ("buy", "limit"),
+            OrderType::LimitSell => ("sell", "limit"),
+            OrderType::StopBuy => ("buy", "stop"),
+            OrderType::StopSell => ("sell", "stop"),
+            OrderType::TrailingStopBuy => ("buy", "trailing_stop"),
+            OrderType::TrailingStopSell => ("sell", "trailing_stop"),
+        };
+        let price = order.get_price().map(|price| price.to_string());
+        let (limit_price, stop_price) = match order_type {
+            "limit" => (price, None),
+            "stop" => (None, price),
+            _ => (None, None),
+        };
+
+        Self {
+            symbol: order.get_symbol().to_string(),
+            qty: order.get_shares().to_string(),
+            side: side.to_string(),
+            order_type: order_type.to_string(),
+            time_in_force: "day".to_string(),
+            limit_price,
+            stop_price,
+            trail_price: order.get_trailing_delta().map(|delta| delta.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AlpacaAccount {
+    cash: String,
+}
+
+#[derive(Debug)]
+pub struct AlpacaLiveBroker {
+    base_url: String,
+    key_id: String,
+    secret_key: String,
+    client: reqwest::Client,
+    connected: bool,
+    broker_state: BrokerState,
+    cash: f64,
+    holdings: PortfolioHoldings,
+    latest_quotes: HashMap<String, UistQuote>,
+    unsent_orders: Vec<Order>,
+    rejection_log: OrderRejectionLog,
+}
+
+impl AlpacaLiveBroker {
+    pub fn new(
+        base_url: impl Into<String>,
+        key_id: impl Into<String>,
+        secret_key: impl Into<String>,
+    ) -> Self {
+        Self {
+            base_url: base_url.into(),
+            key_id: key_id.into(),
+            secret_key: secret_key.into(),
+            client: reqwest::Client::new(),
+            connected: false,
+            broker_state: BrokerState::Ready,
+            cash: 0.0,
+            holdings: PortfolioHoldings::new(),
+            latest_quotes: HashMap::new(),
+            unsent_orders: Vec::new(),
+            rejection_log: OrderRejectionLog::new(),
+        }
+    }
+
+    pub fn update_quote(&mut self, quote: UistQuote) {
+        self.latest_quotes.insert(quote.symbol.clone(), quote);
+    }
+
+    pub fn get_order_rejection_rate(&self) -> f64 {
+        self.rejection_log.rejection_rate()
+    }
+
+    pub fn get_rejection_reasons_summary(&self) -> HashMap<String, usize> {
+        self.rejection_log.reasons_summary()
+    }
+
+    pub fn get_rejection_log_between(
+        &self,
+        start: DateTime,
+        end: DateTime,
+    ) -> Vec<(DateTime, BrokerError)> {
+        self.rejection_log.between(start, end)
+    }
+
+    fn reject(&mut self, order: Order, error: BrokerError) -> BrokerEvent<Order> {
+        self.rejection_log
+            .record_rejection(OffsetDateTime::now_utc().into(), error);
+        BrokerEvent::OrderInvalid(order)
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, self.base_url.clone() + path)
+            .header("APCA-API-KEY-ID", &self.key_id)
+            .header("APCA-API-SECRET-KEY", &self.secret_key)
+    }
+
+    async fn submit_order(&self, order: &Order) -> Result<(), ConnectionError> {
+        let resp = self
+            .request(reqwest::Method::POST, "/v2/orders")
+            .json(&AlpacaOrder::from(order))
+            .send()
+            .await
+            .map_err(|e| ConnectionError(e.to_string()))?;
+        if !resp.status().is_success() {
+            return Err(ConnectionError(format!(
+                "Order rejected: {}",
+                resp.status()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Quote<UistQuote> for AlpacaLiveBroker {
+    fn get_quote(&self, symbol: &str) -> Option<UistQuote> {
+        self.latest_quotes.get(symbol).cloned()
+    }
+
+    fn get_quotes(&self) -> Option<Vec<UistQuote>> {
+        if self.latest_quotes.is_empty() {
+            return None;
+        }
+        Some(self.latest_quotes.values().cloned().collect())
+    }
+}
+
+impl Portfolio<UistQuote> for AlpacaLiveBroker {
+    fn get_cash_balance(&self) -> f64 {
+        self.cash
+    }
+
+    fn update_cash_balance(&mut self, cash: f64) {
+        self.cash = cash;
+    }
+
+    fn get_holdings(&self) -> PortfolioHoldings {
+        self.holdings.clone()
+    }
+
+    fn update_holdings(&mut self, symbol: &str, change: f64) {
+        if (change).eq(&0.0) {
+            self.holdings.remove(symbol);
+        } else {
+            self.holdings.insert(symbol.to_string(), change);
+        }
+    }
+
+    fn get_average_cost_basis(&self, _symbol: &str) -> Option<f64> {
+        None
+    }
+
+    //Only orders that haven't been submitted, fills are not reconciled with the brokerage
+    fn get_pending_orders(&self) -> PortfolioHoldings {
+        let mut pending = PortfolioHoldings::new();
+        for order in &self.unsent_orders {
+            let shares = match order.get_order_type() {
+                OrderType::MarketBuy
+                | OrderType::LimitBuy
+                | OrderType::StopBuy
+                | OrderType::TrailingStopBuy => order.get_shares(),
+                OrderType::MarketSell
+                | OrderType::LimitSell
+                | OrderType::StopSell
+                | OrderType::TrailingStopSell => -order.get_shares(),
+            };
+            let existing = pending.get(order.get_symbol()).copied().unwrap_or(0.0);
+            pending.insert(order.get_symbol().to_string(), existing + shares);
+        }
+        pending
+    }
+
+    fn get_trade_costs(&self) -> Vec<BrokerCost> {
+        Vec::new()
+    }
+
+    //Fills are not reconciled with the brokerage
+    fn get_trade_count(&self) -> usize {
+        0
+    }
+}
+
+impl BrokerStates for AlpacaLiveBroker {
+    fn get_broker_state(&self) -> BrokerState {
+        self.broker_state.clone()
+    }
+
+    fn update_broker_state(&mut self, state: BrokerState) {
+        self.broker_state = state;
+    }
+}
+
+impl CashOperations<UistQuote> for AlpacaLiveBroker {}
+
+impl BrokerOperations<Order, UistQuote> for AlpacaLiveBroker {}
+
+impl SendOrder<Order> for AlpacaLiveBroker {
+    /// Orders are checked with the same cash and holdings checks as
+    /// [UistBroker](crate::broker::uist::UistBroker), using the latest quote for the symbol.
+    fn send_order(&mut self, order: Order) -> BrokerEvent<Order> {
+        self.rejection_log.record_submitted();
+        if !self.connected {
+            info!("BROKER: Unable to send order as broker is not connected");
+            return self.reject(order, BrokerError::NotConnected);
+        }
+
+        let Some(quote) = self.get_quote(order.get_symbol()) else {
+            info!(
+                "BROKER: Unable to send order for {:?} without a quote",
+                order.get_symbol()
+            );
+            let symbol = order.get_symbol().to_string();
+            return self.reject(order, BrokerError::MissingQuote(symbol));
+        };
+        let price = match order.get_order_type() {
+            OrderType::MarketBuy
+            | OrderType::LimitBuy
+            | OrderType::StopBuy
+            | OrderType::TrailingStopBuy => quote.ask,
+            OrderType::MarketSell
+            | OrderType::LimitSell
+            | OrderType::StopSell
+            | OrderType::TrailingStopSell => quote.bid,
+        };
+
+        let checks = self
+            .client_has_sufficient_cash::<OrderType>(&order, &price)
+            .and_then(|_| self.client_has_sufficient_holdings_for_sale::<OrderType>(&order))
+            .and_then(|_| self.client_is_issuing_nonsense_order(&order));
+        if let Err(err) = checks {
+            info!(
+                "BROKER: Unable to send {:?} order for {:?} shares of {:?}: {}",
+                order.get_order_type(),
+                order.get_shares(),
+                order.get_symbol(),
+                err
+            );
+            return self.reject(order, err);
+        }
+        self.unsent_orders.push(order.clone());
+        BrokerEvent::OrderSentToExchange(order)
+    }
+
+    fn send_orders(&mut self, orders: &[Order]) -> Vec<BrokerEvent<Order>> {
+        orders.iter().map(|o| self.send_order(o.clone())).collect()
+    }
+}
+
+impl Update for AlpacaLiveBroker {
+    /// Submits queued orders. Orders that fail are dropped and recorded in the rejection log as
+    /// [BrokerError::ExchangeRejected].
+    async fn check(&mut self) {
+        for order in mem::take(&mut self.unsent_orders) {
+            if let Err(err) = self.submit_order(&order).await {
+                info!("BROKER: Failed to submit order to Alpaca: {}", err);
+                let symbol = order.get_symbol().to_string();
+                self.reject(order, BrokerError::ExchangeRejected(symbol));
+            }
+        }
+    }
+}
+
+impl LiveTradingAdapter<UistQuote, Order> for AlpacaLiveBroker {
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let account = self
+            .request(reqwest::Method::GET, "/v2/account")
+            .send()
+            .await
+            .map_err(|e| ConnectionError(e.to_string()))?
+            .error_for_status()
+            .map_err(|e| ConnectionError(e.to_string()))?
+            .json::<AlpacaAccount>()
+            .await
+            .map_err(|e| ConnectionError(e.to_string()))?;
+
+        self.cash = account
+            .cash
+            .parse::<f64>()
+            .map_err(|e| ConnectionError(e.to_string()))?;
+        self.connected = true;
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.connected = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rotala::exchange::uist_v1::{Order, UistQuote};
+
+    use super::{AlpacaLiveBroker, AlpacaOrder};
+    use crate::broker::{BrokerEvent, LiveTradingAdapter, Portfolio, SendOrder, Update};
+
+    #[test]
+    fn test_that_orders_translate_to_alpaca_format() {
+        let order = AlpacaOrder::from(&Order::stop_sell("ABC", 10.0, 95.5));
+        assert_eq!(order.side, "sell");
+        assert_eq!(order.order_type, "stop");
+        assert_eq!(order.stop_price, Some("95.5".to_string()));
+        assert_eq!(order.limit_price, None);
+
+        let order = AlpacaOrder::from(&Order::trailing_stop_sell("ABC", 10.0, 2.5));
+        assert_eq!(order.order_type, "trailing_stop");
+        assert_eq!(order.trail_price, Some("2.5".to_string()));
+        assert_eq!(order.stop_price, None);
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_connects_and_submits_orders() {
+        let mut server = mockito::Server::new_async().await;
+        let account = server
+            .mock("GET", "/v2/account")
+            .match_header("APCA-API-KEY-ID", "key")
+            .with_body(r#"{"cash": "1000.50"}"#)
+            .create_async()
+            .await;
+        let orders = server
+            .mock("POST", "/v2/orders")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"symbol": "ABC", "qty": "10", "side": "buy", "type": "market"}"#.to_string(),
+            ))
+            .with_body("{}")
+            .create_async()
+            .await;
+
+        let mut brkr = AlpacaLiveBroker::new(server.url(), "key", "secret");
+        let res = brkr.send_order(Order::market_buy("ABC", 10.0));
+        assert!(matches!(res, BrokerEvent::OrderInvalid(..)));
+        assert_eq!(
+            brkr.get_rejection_reasons_summary().get("NotConnected"),
+            Some(&1)
+        );
+
+        brkr.connect().await.unwrap();
+        assert_eq!(brkr.get_cash_balance(), 1000.50);
+        brkr.update_quote(quote(50.0, 51.0));
+
+        let res = brkr.send_order(Order::market_buy("ABC", 10.0));
+        assert!(matches!(res, BrokerEvent::OrderSentToExchange(..)));
+        brkr.check().await;
+
+        assert_eq!(brkr.get_order_rejection_rate(), 0.5);
+
+        account.assert_async().await;
+        orders.assert_async().await;
+    }
+
+    fn quote(bid: f64, ask: f64) -> UistQuote {
+        UistQuote {
+            bid,
+            ask,
+            date: 100,
+            symbol: "ABC".to_string(),
+            volume: None,
+        }
+    }
+
+    async fn connected(server: &mut mockito::Server) -> AlpacaLiveBroker {
+        server
+            .mock("GET", "/v2/account")
+            .with_body(r#"{"cash": "1000.0"}"#)
+            .create_async()
+            .await;
+        let mut brkr = AlpacaLiveBroker::new(server.url(), "key", "secret");
+        brkr.connect().await.unwrap();
+        brkr
+    }
+
+    #[tokio::test]
+    async fn test_that_orders_are_checked_against_cash_and_holdings() {
+        let mut server = mockito::Server::new_async().await;
+        let mut brkr = connected(&mut server).await;
+
+        let res = brkr.send_order(Order::market_buy("ABC", 10.0));
+        assert!(matches!(res, BrokerEvent::OrderInvalid(..)));
+
+        brkr.update_quote(quote(50.0, 51.0));
+        let res = brkr.send_order(Order::market_buy("ABC", 100.0));
+        assert!(matches!(res, BrokerEvent::OrderInvalid(..)));
+        let res = brkr.send_order(Order::market_sell("ABC", 10.0));
+        assert!(matches!(res, BrokerEvent::OrderInvalid(..)));
+
+        //Unsubmitted buys reserve cash, 15 * 51 leaves too little for the second order
+        let res = brkr.send_order(Order::market_buy("ABC", 15.0));
+        assert!(matches!(res, BrokerEvent::OrderSentToExchange(..)));
+        assert_eq!(*brkr.get_pending_orders().get("ABC").unwrap(), 15.0);
+        let res = brkr.send_order(Order::market_buy("ABC", 5.0));
+        assert!(matches!(res, BrokerEvent::OrderInvalid(..)));
+
+        let reasons = brkr.get_rejection_reasons_summary();
+        assert_eq!(reasons.get("MissingQuote"), Some(&1));
+        assert_eq!(reasons.get("InsufficientCash"), Some(&2));
+        assert_eq!(reasons.get("InsufficientHoldings"), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_that_failed_submissions_are_recorded() {
+        let mut server = mockito::Server::new_async().await;
+        let mut brkr = connected(&mut server).await;
+        let orders = server
+            .mock("POST", "/v2/orders")
+            .with_status(403)
+            .create_async()
+            .await;
+
+        brkr.update_quote(quote(50.0, 51.0));
+        let res = brkr.send_order(Order::market_buy("ABC", 10.0));
+        assert!(matches!(res, BrokerEvent::OrderSentToExchange(..)));
+        brkr.check().await;
+
+        assert!(brkr.get_pending_orders().is_empty());
+        assert_eq!(
+            brkr.get_rejection_reasons_summary().get("ExchangeRejected"),
+            Some(&1)
+        );
+        orders.assert_async().await;
+    }
+}
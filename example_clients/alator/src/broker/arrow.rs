@@ -0,0 +1,144 @@
+//! Reads and writes trade logs in the [Arrow IPC](https://arrow.apache.org/docs/format/Columnar.html#ipc-file-format)
+//! file format.
+//!
+//! Schema is `date: int64, symbol: utf8, qty: float64, value: float64, type: utf8` with type
+//! written as `Buy` or `Sell`. Order ids are not written so trades read from a file have no
+//! `order_id`.
+
+use std::fs::File;
+use std::path::Path;
+
+use arrow2::array::{Array, Float64Array, Int64Array, Utf8Array};
+use arrow2::chunk::Chunk;
+use arrow2::datatypes::{DataType, Field, Schema};
+use arrow2::io::ipc::read::{read_file_metadata, FileReader};
+use arrow2::io::ipc::write::{FileWriter, WriteOptions};
+use rotala::exchange::uist_v1::{Trade, TradeType};
+
+pub type ArrowError = arrow2::error::Error;
+
+fn trade_schema() -> Schema {
+    Schema::from(vec![
+        Field::new("date", DataType::Int64, false),
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("qty", DataType::Float64, false),
+        Field::new("value", DataType::Float64, false),
+        Field::new("type", DataType::Utf8, false),
+    ])
+}
+
+pub fn to_arrow_ipc(trades: &[Trade], path: &Path) -> Result<(), ArrowError> {
+    let dates = Int64Array::from_vec(trades.iter().map(|trade| trade.date).collect());
+    let symbols = Utf8Array::<i32>::from_iter_values(trades.iter().map(|trade| &trade.symbol));
+    let qtys = Float64Array::from_vec(trades.iter().map(|trade| trade.quantity).collect());
+    let values = Float64Array::from_vec(trades.iter().map(|trade| trade.value).collect());
+    let types = Utf8Array::<i32>::from_iter_values(trades.iter().map(|trade| match trade.typ {
+        TradeType::Buy => "Buy",
+        TradeType::Sell => "Sell",
+    }));
+
+    let chunk = Chunk::try_new(vec![
+        dates.boxed(),
+        symbols.boxed(),
+        qtys.boxed(),
+        values.boxed(),
+        types.boxed(),
+    ])?;
+
+    let file = File::create(path)?;
+    let mut writer = FileWriter::try_new(
+        file,
+        trade_schema(),
+        None,
+        WriteOptions { compression: None },
+    )?;
+    writer.write(&chunk, None)?;
+    writer.finish()
+}
+
+fn column<T: 'static>(chunk: &Chunk<Box<dyn Array>>, pos: usize) -> Result<&T, ArrowError> {
+    chunk.arrays()[pos]
+        .as_any()
+        .downcast_ref::<T>()
+        .ok_or_else(|| ArrowError::OutOfSpec(format!("Unexpected type for column {pos}")))
+}
+
+pub fn from_arrow_ipc(path: &Path) -> Result<Vec<Trade>, ArrowError> {
+    let mut file = File::open(path)?;
+    let metadata = read_file_metadata(&mut file)?;
+    if metadata.schema != trade_schema() {
+        return Err(ArrowError::OutOfSpec(format!(
+            "Schema does not match trade schema: {:?}",
+            metadata.schema
+        )));
+    }
+    let reader = FileReader::new(file, metadata, None, None);
+
+    let mut trades = Vec::new();
+    for chunk in reader {
+        let chunk = chunk?;
+        let dates = column::<Int64Array>(&chunk, 0)?;
+        let symbols = column::<Utf8Array<i32>>(&chunk, 1)?;
+        let qtys = column::<Float64Array>(&chunk, 2)?;
+        let values = column::<Float64Array>(&chunk, 3)?;
+        let types = column::<Utf8Array<i32>>(&chunk, 4)?;
+
+        for i in 0..chunk.len() {
+            let typ = match types.value(i) {
+                "Buy" => TradeType::Buy,
+                "Sell" => TradeType::Sell,
+                other => {
+                    return Err(ArrowError::OutOfSpec(format!(
+                        "Unknown trade type: {other}"
+                    )))
+                }
+            };
+            trades.push(Trade::new(
+                symbols.value(i),
+                values.value(i),
+                qtys.value(i),
+                dates.value(i),
+                typ,
+            ));
+        }
+    }
+    Ok(trades)
+}
+
+#[cfg(test)]
+mod tests {
+    use rotala::exchange::uist_v1::{Trade, TradeType};
+
+    use super::{from_arrow_ipc, to_arrow_ipc};
+
+    #[test]
+    fn test_that_trades_round_trip_through_arrow_ipc() {
+        let trades = (0..1000)
+            .map(|i| {
+                let (symbol, typ) = if i % 2 == 0 {
+                    ("ABC", TradeType::Buy)
+                } else {
+                    ("BCD", TradeType::Sell)
+                };
+                Trade::new(symbol, 100.0 * i as f64, i as f64 + 0.5, 100 + i, typ)
+            })
+            .collect::<Vec<_>>();
+
+        let path = std::env::temp_dir().join(format!(
+            "alator_arrow_round_trip_{}.arrow",
+            std::process::id()
+        ));
+        to_arrow_ipc(&trades, &path).unwrap();
+        let read = from_arrow_ipc(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.len(), 1000);
+        for (original, copy) in trades.iter().zip(read.iter()) {
+            assert_eq!(original.symbol, copy.symbol);
+            assert_eq!(original.value, copy.value);
+            assert_eq!(original.quantity, copy.quantity);
+            assert_eq!(original.date, copy.date);
+            assert_eq!(original.typ, copy.typ);
+        }
+    }
+}
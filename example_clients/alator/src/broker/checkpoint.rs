@@ -0,0 +1,162 @@
+//! Saves broker state to disk during a backtest so that a long simulation can be resumed after a
+//! crash.
+//!
+//! The exchange is on the other side of [UistClient](rotala::http::uist::uistv1_client::UistClient)
+//! so its state isn't saved. The checkpoint records the date instead and, on resume, the exchange
+//! is ticked forward to that date before broker state is restored. Orders that haven't executed
+//! when the checkpoint is taken are not saved, a strategy that rebalances against target weights
+//! will send them again.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::currency::MultiCurrencyCash;
+use super::uist::{PendingSettlement, UistBrokerLog};
+use super::PortfolioHoldings;
+
+#[derive(Clone, Debug)]
+pub enum CheckpointError {
+    Io(String),
+    Encoding(String),
+}
+
+impl Error for CheckpointError {}
+
+impl Display for CheckpointError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "Unable to access checkpoint: {err}"),
+            CheckpointError::Encoding(err) => write!(f, "Unable to encode checkpoint: {err}"),
+        }
+    }
+}
+
+/// State of a [UistBroker](crate::broker::uist::UistBroker) at the end of a tick.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BrokerCheckpoint {
+    pub date: i64,
+    pub tick_count: usize,
+    pub cash: MultiCurrencyCash,
+    pub holdings: PortfolioHoldings,
+    pub log: UistBrokerLog,
+    pub pending_settlements: Vec<PendingSettlement>,
+    pub last_seen_trade: usize,
+    pub last_interest_date: Option<i64>,
+    pub last_corporate_action_date: i64,
+}
+
+/// Writes a [BrokerCheckpoint] to `path` every `interval_ticks`, each checkpoint replaces the last.
+#[derive(Clone, Debug)]
+pub struct CheckpointManager {
+    pub path: PathBuf,
+    pub interval_ticks: usize,
+}
+
+impl CheckpointManager {
+    pub fn new(path: impl Into<PathBuf>, interval_ticks: usize) -> Self {
+        Self {
+            path: path.into(),
+            interval_ticks,
+        }
+    }
+
+    pub fn is_due(&self, tick_count: usize) -> bool {
+        self.interval_ticks > 0 && tick_count % self.interval_ticks == 0
+    }
+
+    /// Checkpoint is written to a temporary file that is then renamed so that a crash during the
+    /// write leaves the previous checkpoint intact.
+    pub fn save(&self, checkpoint: &BrokerCheckpoint) -> Result<(), CheckpointError> {
+        let tmp_path = self.path.with_extension("tmp");
+        let file = File::create(&tmp_path).map_err(|err| CheckpointError::Io(err.to_string()))?;
+        bincode::serialize_into(BufWriter::new(file), checkpoint)
+            .map_err(|err| CheckpointError::Encoding(err.to_string()))?;
+        fs::rename(&tmp_path, &self.path).map_err(|err| CheckpointError::Io(err.to_string()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<BrokerCheckpoint, CheckpointError> {
+        let file = File::open(path).map_err(|err| CheckpointError::Io(err.to_string()))?;
+        bincode::deserialize_from(BufReader::new(file))
+            .map_err(|err| CheckpointError::Encoding(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rotala::exchange::uist_v1::Order;
+    use rotala::http::uist::uistv1_client::{TestClient, UistClient};
+    use rotala::input::penelope::Penelope;
+
+    use super::CheckpointManager;
+    use crate::broker::uist::{UistBroker, UistBrokerBuilder};
+    use crate::broker::{CashOperations, Clock, Portfolio, SendOrder, TradeLog, Update};
+
+    async fn setup() -> (TestClient, u64) {
+        let mut source = Penelope::new();
+        for date in 100..110 {
+            source.add_quote(100.0 + date as f64, 101.0 + date as f64, date, "ABC");
+        }
+        let mut client = TestClient::single("Checkpoint", source);
+        let resp = client.init("Checkpoint".to_string()).await.unwrap();
+        (client, resp.backtest_id)
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_resumes_from_checkpoint() {
+        let path = std::env::temp_dir().join(format!(
+            "alator_test_that_broker_resumes_from_checkpoint_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let (client, backtest_id) = setup().await;
+        let mut brkr: UistBroker<TestClient> = UistBrokerBuilder::new()
+            .with_client(client, backtest_id)
+            .with_checkpoint_manager(CheckpointManager::new(&path, 2))
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+        brkr.send_order(Order::market_buy("ABC", 100.0));
+        brkr.check().await;
+        brkr.check().await;
+
+        //Written on second tick, after the order executed
+        let checkpoint = CheckpointManager::load(&path).unwrap();
+        assert_eq!(checkpoint.tick_count, 2);
+        assert_eq!(checkpoint.holdings.get("ABC"), Some(&100.0));
+
+        let (client, backtest_id) = setup().await;
+        let mut resumed = UistBrokerBuilder::new()
+            .with_client(client, backtest_id)
+            .resume_from_checkpoint(&path)
+            .await
+            .unwrap();
+
+        assert_eq!(resumed.now(), brkr.now());
+        assert_eq!(resumed.get_cash_balance(), brkr.get_cash_balance());
+        assert_eq!(resumed.get_position_qty("ABC"), Some(100.0));
+        assert_eq!(resumed.get_total_value(), brkr.get_total_value());
+        assert_eq!(resumed.trades_between(&i64::MIN, &i64::MAX).len(), 1);
+
+        brkr.check().await;
+        resumed.check().await;
+        assert_eq!(resumed.now(), brkr.now());
+        assert_eq!(resumed.get_total_value(), brkr.get_total_value());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_that_missing_checkpoint_errors() {
+        let path = std::env::temp_dir().join(format!(
+            "alator_test_that_missing_checkpoint_errors_{}",
+            std::process::id()
+        ));
+        assert!(CheckpointManager::load(path).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+//! Cash held in more than one currency.
+//!
+//! Each currency is held in its own bucket. Buckets are converted into the base currency with FX
+//! quotes from the exchange, quoted under the symbol `{currency}{base}` i.e. `EURUSD` is the
+//! number of USD per EUR. Conversion uses the mid price.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "checkpoint", derive(serde::Serialize, serde::Deserialize))]
+pub struct CurrencyCode(pub String);
+
+impl CurrencyCode {
+    pub fn new(code: impl Into<String>) -> Self {
+        Self(code.into())
+    }
+
+    /// Symbol of the FX quote giving the value of one unit of this currency in `base`.
+    pub fn fx_symbol(&self, base: &CurrencyCode) -> String {
+        format!("{}{}", self.0, base.0)
+    }
+}
+
+impl Default for CurrencyCode {
+    fn default() -> Self {
+        Self::new("USD")
+    }
+}
+
+impl Display for CurrencyCode {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "checkpoint", derive(serde::Serialize, serde::Deserialize))]
+pub struct MultiCurrencyCash {
+    base: CurrencyCode,
+    buckets: HashMap<CurrencyCode, f64>,
+}
+
+impl MultiCurrencyCash {
+    pub fn new(base: CurrencyCode) -> Self {
+        let mut buckets = HashMap::new();
+        buckets.insert(base.clone(), 0.0);
+        Self { base, buckets }
+    }
+
+    pub fn get_base_currency(&self) -> &CurrencyCode {
+        &self.base
+    }
+
+    pub fn get(&self, currency: &CurrencyCode) -> f64 {
+        *self.buckets.get(currency).unwrap_or(&0.0)
+    }
+
+    pub fn credit(&mut self, currency: &CurrencyCode, value: f64) {
+        *self.buckets.entry(currency.clone()).or_default() += value;
+    }
+
+    /// Can take a bucket negative, same as [CashOperations::debit_force](super::CashOperations).
+    pub fn debit(&mut self, currency: &CurrencyCode, value: f64) {
+        *self.buckets.entry(currency.clone()).or_default() -= value;
+    }
+
+    /// Sum of every bucket in the base currency. `rate` returns the value of one unit of a
+    /// currency in the base currency, buckets without a rate are excluded.
+    pub fn base_value(&self, rate: impl Fn(&CurrencyCode) -> Option<f64>) -> f64 {
+        let mut value = 0.0;
+        for (currency, amount) in &self.buckets {
+            if currency == &self.base {
+                value += amount;
+            } else if let Some(rate) = rate(currency) {
+                value += amount * rate;
+            }
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CurrencyCode, MultiCurrencyCash};
+
+    #[test]
+    fn test_that_buckets_are_valued_in_base_currency() {
+        let usd = CurrencyCode::new("USD");
+        let eur = CurrencyCode::new("EUR");
+        let jpy = CurrencyCode::new("JPY");
+        let mut cash = MultiCurrencyCash::new(usd.clone());
+        cash.credit(&usd, 100.0);
+        cash.credit(&eur, 100.0);
+        cash.credit(&jpy, 1000.0);
+        cash.debit(&eur, 50.0);
+
+        assert_eq!(eur.fx_symbol(&usd), "EURUSD");
+        assert_eq!(cash.get(&eur), 50.0);
+        //No JPY rate so the bucket is excluded
+        let value = cash.base_value(|currency| (currency == &eur).then_some(1.2));
+        assert_eq!(value, 160.0);
+    }
+}
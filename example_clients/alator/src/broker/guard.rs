@@ -0,0 +1,281 @@
+//! Circuit breaker that stops a strategy trading after a large drawdown.
+//!
+//! [DrawdownGuard] wraps a broker and implements the same traits by delegating to it, so it can be
+//! passed to a strategy in place of the broker without changes to either.
+
+use std::marker::PhantomData;
+
+use rotala::exchange::uist_v1::Trade;
+
+use super::currency::CurrencyCode;
+use super::{
+    BrokerCashEvent, BrokerCost, BrokerEvent, BrokerOperations, BrokerOrder, BrokerQuote,
+    BrokerState, BrokerStates, CashOperations, Clock, DateTime, Portfolio, PortfolioHoldings,
+    Quote, SendOrder, TradeLog, Update,
+};
+use crate::strategy::staticweight::StaticWeightBroker;
+
+/// Drawdown is measured from the highest total value seen on `check`. Trading halts once drawdown
+/// is greater than `halt_threshold_pct` and resumes once total value recovers above
+/// `high_water_mark * (1.0 - resume_pct)`. Both are fractions, so 0.2 is 20%.
+///
+/// Whilst halted every order is returned as [BrokerEvent::OrderInvalid] without being sent to the
+/// inner broker. Orders already sent to the exchange are unaffected.
+#[derive(Debug)]
+pub struct DrawdownGuard<Q: BrokerQuote, B: Portfolio<Q>> {
+    pub inner: B,
+    pub halt_threshold_pct: f64,
+    pub resume_pct: f64,
+    pub high_water_mark: f64,
+    pub halted: bool,
+    _quote: PhantomData<Q>,
+}
+
+impl<Q: BrokerQuote, B: Portfolio<Q>> DrawdownGuard<Q, B> {
+    pub fn new(inner: B, halt_threshold_pct: f64, resume_pct: f64) -> Self {
+        Self {
+            inner,
+            halt_threshold_pct,
+            resume_pct,
+            high_water_mark: 0.0,
+            halted: false,
+            _quote: PhantomData,
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    fn update_halt(&mut self, total_value: f64) {
+        if total_value > self.high_water_mark {
+            self.high_water_mark = total_value;
+        }
+        //No drawdown until the portfolio has been funded
+        if self.high_water_mark <= 0.0 {
+            return;
+        }
+
+        let drawdown = 1.0 - total_value / self.high_water_mark;
+        if !self.halted && drawdown > self.halt_threshold_pct {
+            self.halted = true;
+        } else if self.halted && total_value > self.high_water_mark * (1.0 - self.resume_pct) {
+            self.halted = false;
+        }
+    }
+}
+
+impl<O: BrokerOrder + Clone, Q: BrokerQuote, B: Portfolio<Q> + SendOrder<O>> SendOrder<O>
+    for DrawdownGuard<Q, B>
+{
+    fn send_order(&mut self, order: O) -> BrokerEvent<O> {
+        if self.halted {
+            return BrokerEvent::OrderInvalid(order);
+        }
+        self.inner.send_order(order)
+    }
+
+    fn send_orders(&mut self, orders: &[O]) -> Vec<BrokerEvent<O>> {
+        orders
+            .iter()
+            .map(|order| self.send_order(order.clone()))
+            .collect()
+    }
+}
+
+impl<Q: BrokerQuote, B: Portfolio<Q> + Update> Update for DrawdownGuard<Q, B> {
+    async fn check(&mut self) {
+        self.inner.check().await;
+        let total_value = self.inner.get_total_value();
+        self.update_halt(total_value);
+    }
+}
+
+impl<Q: BrokerQuote, B: Portfolio<Q>> Quote<Q> for DrawdownGuard<Q, B> {
+    fn get_quote(&self, symbol: &str) -> Option<Q> {
+        self.inner.get_quote(symbol)
+    }
+
+    fn get_quotes(&self) -> Option<Vec<Q>> {
+        self.inner.get_quotes()
+    }
+}
+
+impl<Q: BrokerQuote, B: Portfolio<Q>> Portfolio<Q> for DrawdownGuard<Q, B> {
+    fn get_min_cash_balance(&self) -> f64 {
+        self.inner.get_min_cash_balance()
+    }
+
+    fn get_unsettled_cash(&self) -> f64 {
+        self.inner.get_unsettled_cash()
+    }
+
+    fn get_short_margin_requirement(&self) -> Option<f64> {
+        self.inner.get_short_margin_requirement()
+    }
+
+    fn get_position_value(&self, symbol: &str) -> Option<f64> {
+        self.inner.get_position_value(symbol)
+    }
+
+    fn get_cash_balance(&self) -> f64 {
+        self.inner.get_cash_balance()
+    }
+
+    fn update_cash_balance(&mut self, cash: f64) {
+        self.inner.update_cash_balance(cash)
+    }
+
+    fn get_holdings(&self) -> PortfolioHoldings {
+        self.inner.get_holdings()
+    }
+
+    fn update_holdings(&mut self, symbol: &str, change: f64) {
+        self.inner.update_holdings(symbol, change)
+    }
+
+    fn get_average_cost_basis(&self, symbol: &str) -> Option<f64> {
+        self.inner.get_average_cost_basis(symbol)
+    }
+
+    fn get_first_purchase_date(&self, symbol: &str) -> Option<DateTime> {
+        self.inner.get_first_purchase_date(symbol)
+    }
+
+    fn get_pending_orders(&self) -> PortfolioHoldings {
+        self.inner.get_pending_orders()
+    }
+
+    fn get_trade_costs(&self) -> Vec<BrokerCost> {
+        self.inner.get_trade_costs()
+    }
+
+    fn get_trade_count(&self) -> usize {
+        self.inner.get_trade_count()
+    }
+}
+
+impl<Q: BrokerQuote, B: Portfolio<Q> + BrokerStates> BrokerStates for DrawdownGuard<Q, B> {
+    fn get_broker_state(&self) -> BrokerState {
+        self.inner.get_broker_state()
+    }
+
+    fn update_broker_state(&mut self, state: BrokerState) {
+        self.inner.update_broker_state(state)
+    }
+}
+
+impl<Q: BrokerQuote, B: CashOperations<Q>> CashOperations<Q> for DrawdownGuard<Q, B> {
+    fn get_base_currency(&self) -> CurrencyCode {
+        self.inner.get_base_currency()
+    }
+
+    fn get_symbol_currency(&self, symbol: &str) -> CurrencyCode {
+        self.inner.get_symbol_currency(symbol)
+    }
+
+    fn get_cash_balance_in(&self, currency: &CurrencyCode) -> f64 {
+        self.inner.get_cash_balance_in(currency)
+    }
+
+    fn deposit_cash_in(&mut self, currency: &CurrencyCode, cash: &f64) -> BrokerCashEvent {
+        self.inner.deposit_cash_in(currency, cash)
+    }
+
+    fn withdraw_cash_in(&mut self, currency: &CurrencyCode, cash: &f64) -> BrokerCashEvent {
+        self.inner.withdraw_cash_in(currency, cash)
+    }
+}
+
+//Liquidation and cash rebalancing send orders through the guard so are also blocked whilst halted
+impl<O: BrokerOrder + Clone, Q: BrokerQuote, B: CashOperations<Q> + SendOrder<O>>
+    BrokerOperations<O, Q> for DrawdownGuard<Q, B>
+{
+}
+
+impl<Q: BrokerQuote, B: Portfolio<Q> + Clock> Clock for DrawdownGuard<Q, B> {
+    fn now(&mut self) -> i64 {
+        self.inner.now()
+    }
+
+    fn has_next(&mut self) -> bool {
+        self.inner.has_next()
+    }
+
+    fn start(&self) -> i64 {
+        self.inner.start()
+    }
+}
+
+impl<Q: BrokerQuote, B: Portfolio<Q> + TradeLog> TradeLog for DrawdownGuard<Q, B> {
+    fn trades_between(&self, start: &i64, stop: &i64) -> Vec<Trade> {
+        self.inner.trades_between(start, stop)
+    }
+}
+
+impl<O: BrokerOrder + Clone, Q: BrokerQuote, B: StaticWeightBroker<Q, O>> StaticWeightBroker<Q, O>
+    for DrawdownGuard<Q, B>
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use rotala::exchange::uist_v1::Order;
+    use rotala::http::uist::uistv1_client::{TestClient, UistClient};
+    use rotala::input::penelope::Penelope;
+
+    use super::DrawdownGuard;
+    use crate::broker::uist::UistBrokerBuilder;
+    use crate::broker::{BrokerEvent, CashOperations, Portfolio, SendOrder, Update};
+
+    #[tokio::test]
+    async fn test_that_guard_halts_and_resumes_on_drawdown() {
+        let mut source = Penelope::new();
+        for (date, price) in [
+            (100, 100.0),
+            (101, 100.0),
+            (102, 100.0),
+            (103, 70.0),
+            (104, 80.0),
+            (105, 95.0),
+            (106, 95.0),
+        ] {
+            source.add_quote(price, price, date, "ABC");
+        }
+        let mut client = TestClient::single("Guard", source);
+        let resp = client.init("Guard".to_string()).await.unwrap();
+        let brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
+
+        let mut guard = DrawdownGuard::new(brkr, 0.2, 0.1);
+        guard.deposit_cash(&20_000.0);
+        guard.send_order(Order::market_buy("ABC", 150.0));
+        guard.check().await;
+        guard.check().await;
+        assert_eq!(guard.get_position_qty("ABC"), Some(150.0));
+        assert_eq!(guard.high_water_mark, 20_000.0);
+        assert!(!guard.is_halted());
+
+        //Down 22.5% from the high-water mark
+        guard.check().await;
+        assert!(guard.is_halted());
+        let event = guard.send_order(Order::market_sell("ABC", 10.0));
+        assert!(matches!(event, BrokerEvent::OrderInvalid(..)));
+        assert!(guard.get_pending_orders().is_empty());
+
+        //Still below 90% of the high-water mark
+        guard.check().await;
+        assert!(guard.is_halted());
+
+        guard.check().await;
+        assert!(!guard.is_halted());
+        let event = guard.send_order(Order::market_sell("ABC", 10.0));
+        assert!(matches!(event, BrokerEvent::OrderSentToExchange(..)));
+    }
+}
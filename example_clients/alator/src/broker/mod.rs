@@ -54,11 +54,23 @@ use std::{
 };
 
 use log::info;
+
+use crate::broker::currency::CurrencyCode;
+use crate::schedule::Timezone;
 use rotala::exchange::uist_v1::{
     Order as UistOrder, OrderType as UistOrderType, Trade as UistTrade, UistQuote,
 };
+use rotala::input::penelope::{OhlcvQuote, DEFAULT_OHLCV_SPREAD};
 use time::{format_description, Date, Month, OffsetDateTime, Weekday};
 
+#[cfg(feature = "alpaca")]
+pub mod alpaca;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "checkpoint")]
+pub mod checkpoint;
+pub mod currency;
+pub mod guard;
 pub mod uist;
 
 /// Once the broker moves into Failed state then all operations that mutate state are rejected.
@@ -99,6 +111,8 @@ pub enum BrokerOrderType {
     LimitSell,
     StopBuy,
     StopSell,
+    TrailingStopBuy,
+    TrailingStopSell,
 }
 
 impl From<UistOrderType> for BrokerOrderType {
@@ -110,6 +124,8 @@ impl From<UistOrderType> for BrokerOrderType {
             UistOrderType::LimitSell => BrokerOrderType::LimitSell,
             UistOrderType::StopBuy => BrokerOrderType::StopBuy,
             UistOrderType::StopSell => BrokerOrderType::StopSell,
+            UistOrderType::TrailingStopBuy => BrokerOrderType::TrailingStopBuy,
+            UistOrderType::TrailingStopSell => BrokerOrderType::TrailingStopSell,
         }
     }
 }
@@ -131,6 +147,10 @@ impl BrokerTrade for UistTrade {
 pub trait BrokerQuote {
     fn get_bid(&self) -> f64;
     fn get_ask(&self) -> f64;
+    fn get_date(&self) -> i64;
+    fn get_volume(&self) -> Option<f64> {
+        None
+    }
 }
 
 impl BrokerQuote for UistQuote {
@@ -141,6 +161,58 @@ impl BrokerQuote for UistQuote {
     fn get_ask(&self) -> f64 {
         self.ask
     }
+
+    fn get_date(&self) -> i64 {
+        self.date
+    }
+
+    fn get_volume(&self) -> Option<f64> {
+        self.volume
+    }
+}
+
+/// Quotes with candlestick prices, volume is [BrokerQuote::get_volume].
+pub trait OhlcvQuotable: BrokerQuote {
+    fn get_open(&self) -> f64;
+    fn get_high(&self) -> f64;
+    fn get_low(&self) -> f64;
+    fn get_close(&self) -> f64;
+}
+
+impl BrokerQuote for OhlcvQuote {
+    fn get_bid(&self) -> f64 {
+        self.close
+    }
+
+    fn get_ask(&self) -> f64 {
+        self.close * (1.0 + DEFAULT_OHLCV_SPREAD)
+    }
+
+    fn get_date(&self) -> i64 {
+        self.date
+    }
+
+    fn get_volume(&self) -> Option<f64> {
+        Some(self.volume)
+    }
+}
+
+impl OhlcvQuotable for OhlcvQuote {
+    fn get_open(&self) -> f64 {
+        self.open
+    }
+
+    fn get_high(&self) -> f64 {
+        self.high
+    }
+
+    fn get_low(&self) -> f64 {
+        self.low
+    }
+
+    fn get_close(&self) -> f64 {
+        self.close
+    }
 }
 
 /// Implicit in this trait is that the underlying exchange supports at least as many order types
@@ -160,8 +232,9 @@ impl BrokerOrder for UistOrder {
     fn get_order_type<UistOrderType>(&self) -> BrokerOrderType {
         self.order_type.into()
     }
+    //Includes the hidden reserve of iceberg orders
     fn get_shares(&self) -> f64 {
-        self.shares
+        self.shares + self.reserve_qty
     }
     fn get_symbol(&self) -> String {
         self.symbol.clone()
@@ -175,11 +248,22 @@ impl BrokerOrder for UistOrder {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "server", derive(serde::Serialize, serde::Deserialize))]
 pub enum BrokerEvent<O: BrokerOrder> {
     OrderSentToExchange(O),
     OrderInvalid(O),
     OrderCreated(O),
     OrderFailure(O),
+    //Holds the parent order of a scheduled execution, the first slice has been sent
+    TwapScheduled(O),
+    //Holds the parent order of a scheduled execution once the last slice has been sent
+    TwapCompleted(O),
+    //Holds the parent order of a scheduled execution that stopped before all slices were sent
+    TwapCancelled(O),
+    //Symbol, ticks until trading resumes. Orders for the symbol are held by the exchange
+    SymbolHalted(String, usize),
+    //Original order, order sent to the exchange after being reduced to fit portfolio constraints
+    OrderTrimmed(O, O),
 }
 
 #[derive(Clone, Debug)]
@@ -192,29 +276,181 @@ pub enum BrokerCashEvent {
     OperationFailure(f64),
 }
 
-/// Broker has attempted to execute an order which cannot be completed due to insufficient cash.
-#[derive(Clone, Debug)]
-pub struct InsufficientCashError;
+/// Broker state that is likely to be invalid and will produce incorrect results if the simulation
+/// continues, or an order that would leave the broker in that state.
+///
+/// [BrokerError::InsufficientCash] and [BrokerError::MinCashBalanceBreached] hold the value of the
+/// rejected order, [BrokerError::InsufficientMargin] holds the margin required.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BrokerError {
+    InvalidCash(f64),
+    NegativeQuantity(String, f64),
+    MissingQuote(String),
+    InvalidQuote(String),
+    NegativeValue(f64),
+    InsufficientCash(f64),
+    MinCashBalanceBreached(f64),
+    InsufficientHoldings(String, f64),
+    InsufficientMargin(f64),
+    LotNotFound(u64),
+    UnexecutableOrder(String),
+    ConstraintBreached(String),
+    ExchangeRejected(String),
+    BrokerFailed,
+    NotConnected,
+}
+
+impl BrokerError {
+    /// Name of the variant, used to group errors without their values.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BrokerError::InvalidCash(..) => "InvalidCash",
+            BrokerError::NegativeQuantity(..) => "NegativeQuantity",
+            BrokerError::MissingQuote(..) => "MissingQuote",
+            BrokerError::InvalidQuote(..) => "InvalidQuote",
+            BrokerError::NegativeValue(..) => "NegativeValue",
+            BrokerError::InsufficientCash(..) => "InsufficientCash",
+            BrokerError::MinCashBalanceBreached(..) => "MinCashBalanceBreached",
+            BrokerError::InsufficientHoldings(..) => "InsufficientHoldings",
+            BrokerError::InsufficientMargin(..) => "InsufficientMargin",
+            BrokerError::LotNotFound(..) => "LotNotFound",
+            BrokerError::UnexecutableOrder(..) => "UnexecutableOrder",
+            BrokerError::ConstraintBreached(..) => "ConstraintBreached",
+            BrokerError::ExchangeRejected(..) => "ExchangeRejected",
+            BrokerError::BrokerFailed => "BrokerFailed",
+            BrokerError::NotConnected => "NotConnected",
+        }
+    }
+}
 
-impl Error for InsufficientCashError {}
+impl Error for BrokerError {}
 
-impl Display for InsufficientCashError {
+impl Display for BrokerError {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "Client has insufficient cash to execute order")
+        match self {
+            BrokerError::InvalidCash(cash) => write!(f, "Cash balance is not finite: {cash}"),
+            BrokerError::NegativeQuantity(symbol, qty) => {
+                write!(f, "Negative quantity of {qty} held in {symbol}")
+            }
+            BrokerError::MissingQuote(symbol) => write!(f, "No quote for position in {symbol}"),
+            BrokerError::InvalidQuote(symbol) => write!(f, "Quote for {symbol} is not finite"),
+            BrokerError::NegativeValue(value) => {
+                write!(f, "Cash plus positions has negative value: {value}")
+            }
+            BrokerError::InsufficientCash(value) => {
+                write!(
+                    f,
+                    "Client has insufficient cash to execute order of {value}"
+                )
+            }
+            BrokerError::MinCashBalanceBreached(value) => {
+                write!(
+                    f,
+                    "Order of {value} would reduce cash below minimum balance"
+                )
+            }
+            BrokerError::InsufficientHoldings(symbol, qty) => {
+                write!(f, "Client holds less than {qty} of {symbol} to sell")
+            }
+            BrokerError::InsufficientMargin(margin) => {
+                write!(
+                    f,
+                    "Client has insufficient cash to cover short margin of {margin}"
+                )
+            }
+            BrokerError::LotNotFound(lot_id) => write!(f, "No open lot with id {lot_id}"),
+            BrokerError::UnexecutableOrder(symbol) => {
+                write!(f, "Client has passed unexecutable order for {symbol}")
+            }
+            BrokerError::ConstraintBreached(symbol) => {
+                write!(
+                    f,
+                    "Any order for {symbol} would breach portfolio constraints"
+                )
+            }
+            BrokerError::ExchangeRejected(symbol) => {
+                write!(f, "Exchange rejected order for {symbol}")
+            }
+            BrokerError::BrokerFailed => write!(f, "Broker is in Failed state"),
+            BrokerError::NotConnected => write!(f, "Broker is not connected"),
+        }
     }
 }
 
-#[derive(Debug, Clone)]
-/// Broker has attempted to execute an order which cannot be completed due to a problem with the
-/// order.
-pub struct UnexecutableOrderError;
+/// Orders rejected by a broker in `send_order`, with the reason for each rejection.
+#[derive(Clone, Debug, Default)]
+pub struct OrderRejectionLog {
+    submitted: usize,
+    rejections: Vec<(DateTime, BrokerError)>,
+}
+
+impl OrderRejectionLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-impl Error for UnexecutableOrderError {}
+    /// Called for every order passed to `send_order`, whether or not it is rejected.
+    pub fn record_submitted(&mut self) {
+        self.submitted += 1;
+    }
 
-impl Display for UnexecutableOrderError {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "Client has passed unexecutable order")
+    pub fn record_rejection(&mut self, date: DateTime, error: BrokerError) {
+        self.rejections.push((date, error));
     }
+
+    /// Rejections as a fraction of all orders submitted, zero if no orders have been submitted.
+    pub fn rejection_rate(&self) -> f64 {
+        if self.submitted == 0 {
+            return 0.0;
+        }
+        self.rejections.len() as f64 / self.submitted as f64
+    }
+
+    pub fn reasons_summary(&self) -> HashMap<String, usize> {
+        let mut summary = HashMap::new();
+        for (_date, error) in &self.rejections {
+            *summary.entry(error.name().to_string()).or_insert(0) += 1;
+        }
+        summary
+    }
+
+    /// Inclusive of both dates.
+    pub fn between(&self, start: DateTime, end: DateTime) -> Vec<(DateTime, BrokerError)> {
+        self.rejections
+            .iter()
+            .filter(|(date, _error)| *date >= start && *date <= end)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Broker state that can occur in normal operation but may indicate a problem.
+///
+/// Negative cash is expected briefly after trades settle at a worse price than estimated, see
+/// [CashOperations]. [BrokerWarning::StaleQuote] holds the symbol and the difference between the
+/// quote date and the most recent quote date held by the broker.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BrokerWarning {
+    NegativeCash(f64),
+    StaleQuote(String, usize),
+}
+
+/// Output of [Portfolio::health_check]. Broker is healthy if there are no errors.
+#[derive(Clone, Debug)]
+pub struct BrokerHealthStatus {
+    pub is_healthy: bool,
+    pub warnings: Vec<BrokerWarning>,
+    pub errors: Vec<BrokerError>,
+}
+
+/// Output of [Portfolio::scenario_analysis]. `by_position` holds the value of each position after
+/// the shock.
+#[derive(Clone, Debug)]
+pub struct ScenarioResult {
+    pub new_portfolio_value: f64,
+    pub value_change: f64,
+    pub pct_change: f64,
+    pub by_position: PortfolioValues,
 }
 
 /// Implementation of cost models for brokers.
@@ -284,6 +520,58 @@ impl BrokerCost {
     }
 }
 
+/// Interest paid by the broker on positive cash balances.
+#[derive(Clone, Debug, Default)]
+pub enum InterestModel {
+    #[default]
+    None,
+    AnnualRate(f64),
+}
+
+impl InterestModel {
+    /// Interest earned on `cash` held for `seconds`, the annual rate is applied pro rata over a
+    /// 365 day year.
+    pub fn accrual(&self, cash: f64, seconds: i64) -> f64 {
+        match self {
+            InterestModel::None => 0.0,
+            InterestModel::AnnualRate(rate) => {
+                if cash <= 0.0 {
+                    return 0.0;
+                }
+                cash * rate * seconds as f64 / (365.0 * 86400.0)
+            }
+        }
+    }
+}
+
+/// Limits checked against the portfolio after every order, orders that would breach a limit are
+/// reduced to the largest size that doesn't. Percentages are fractions of total value, so 0.2 is
+/// 20%.
+///
+/// Position and sector limits apply to the absolute value of the position so cap shorts as well as
+/// longs. `sectors` maps symbols to the sector used by `max_sector_pct`, symbols without a sector
+/// have no sector limit. Gross leverage is the sum of absolute position values over total value.
+#[derive(Clone, Debug)]
+pub struct PortfolioConstraints {
+    pub max_position_pct: HashMap<String, f64>,
+    pub max_sector_pct: HashMap<String, f64>,
+    pub sectors: HashMap<String, String>,
+    pub min_cash_pct: f64,
+    pub max_gross_leverage: f64,
+}
+
+impl Default for PortfolioConstraints {
+    fn default() -> Self {
+        Self {
+            max_position_pct: HashMap::new(),
+            max_sector_pct: HashMap::new(),
+            sectors: HashMap::new(),
+            min_cash_pct: 0.0,
+            max_gross_leverage: f64::INFINITY,
+        }
+    }
+}
+
 /// Producing quotes may not necessarily be the responsibility of broker in many implementations.
 /// The exchange should be the source of price data but it is quite possible that, whilst the
 /// broker holds the ability to retrieve prices itself, the strategy code does not call the broker.
@@ -296,6 +584,28 @@ pub trait Quote<Q: BrokerQuote> {
     fn get_quotes(&self) -> Option<Vec<Q>>;
 }
 
+/// Price level in an order book.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Level {
+    pub price: f64,
+    pub size: f64,
+}
+
+/// Order book for a symbol at `date`, bids are ordered best first as are asks.
+#[derive(Clone, Debug)]
+pub struct OrderBookSnapshot {
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+    pub symbol: String,
+    pub date: DateTime,
+}
+
+/// Brokers that can return order book depth for depth-aware strategies. Brokers backed by an
+/// exchange that only quotes best bid/ask return a single level on each side.
+pub trait GetsOrderBook {
+    fn get_order_book_snapshot(&self, symbol: &str) -> Option<OrderBookSnapshot>;
+}
+
 pub trait SendOrder<O: BrokerOrder> {
     fn send_order(&mut self, order: O) -> BrokerEvent<O>;
     fn send_orders(&mut self, orders: &[O]) -> Vec<BrokerEvent<O>>;
@@ -310,8 +620,30 @@ pub trait SendOrder<O: BrokerOrder> {
 /// Note that `update_holdings` and `update_cash_balance` mutate state, these are not purely
 /// immutable calculations but operations that can change the portfolio.
 pub trait Portfolio<Q: BrokerQuote>: Quote<Q> {
+    /// Cash that buy orders cannot spend. Withdrawals and trade costs can still take cash below
+    /// this floor.
+    fn get_min_cash_balance(&self) -> f64 {
+        0.0
+    }
+
+    /// Cash required as a fraction of the value of short positions. Short selling is disabled when
+    /// this is `None`, sales are then limited to the quantity held.
+    fn get_short_margin_requirement(&self) -> Option<f64> {
+        None
+    }
+
+    /// Net cash from trades that haven't settled, negative if unsettled buys are larger. Brokers
+    /// that settle immediately have nothing pending.
+    fn get_unsettled_cash(&self) -> f64 {
+        0.0
+    }
+
+    fn is_short(&self, symbol: &str) -> bool {
+        self.get_position_qty(symbol).is_some_and(|qty| qty < 0.0)
+    }
+
     fn get_position_profit(&self, symbol: &str) -> Option<f64> {
-        if let Some(cost) = self.get_position_cost(symbol) {
+        if let Some(cost) = self.get_average_cost_basis(symbol) {
             if let Some(qty) = self.get_position_qty(symbol) {
                 if let Some(position_value) = self.get_position_value(symbol) {
                     let price = position_value / qty;
@@ -327,8 +659,9 @@ pub trait Portfolio<Q: BrokerQuote>: Quote<Q> {
         if let Some(position_value) = self.get_position_value(symbol) {
             if let Some(qty) = self.get_position_qty(symbol) {
                 let price = position_value / qty;
+                //Closing a short is a buy
                 let (value_after_costs, _price_after_costs) =
-                    self.calc_trade_impact(&position_value, &price, false);
+                    self.calc_trade_impact(&position_value, &price, qty < 0.0);
                 return Some(value_after_costs);
             }
         }
@@ -375,9 +708,13 @@ pub trait Portfolio<Q: BrokerQuote>: Quote<Q> {
 
     fn get_position_value(&self, symbol: &str) -> Option<f64> {
         if let Some(quote) = self.get_quote(symbol) {
-            //We only have long positions so we only need to look at the bid
-            let price = quote.get_bid();
             if let Some(qty) = self.get_position_qty(symbol) {
+                //Long positions are closed at the bid, short positions at the ask
+                let price = if qty < 0.0 {
+                    quote.get_ask()
+                } else {
+                    quote.get_bid()
+                };
                 let val = price * qty;
                 return Some(val);
             }
@@ -389,10 +726,49 @@ pub trait Portfolio<Q: BrokerQuote>: Quote<Q> {
         None
     }
 
+    /// Symbols with a non-zero quantity, negative quantities are short positions.
     fn get_positions(&self) -> Vec<String> {
         self.get_holdings().keys().cloned().collect()
     }
 
+    /// Date of the buy that opened the current position, brokers that don't track this return
+    /// `None` and their positions have zero days held.
+    fn get_first_purchase_date(&self, _symbol: &str) -> Option<DateTime> {
+        None
+    }
+
+    /// Summary of every position with a price, sorted by symbol. Days held are calendar days from
+    /// the first purchase to the date of the latest quote. Average cost and unrealised profit are
+    /// `None` when the broker has no cost basis for the position, which includes shorts.
+    fn get_open_positions(&self) -> Vec<PositionInfo> {
+        let mut positions = self.get_positions();
+        positions.sort();
+        positions
+            .into_iter()
+            .filter_map(|symbol| {
+                let qty = self.get_position_qty(&symbol)?;
+                let current_value = self.get_position_value(&symbol)?;
+                let days_held = match (
+                    self.get_first_purchase_date(&symbol),
+                    self.get_quote(&symbol),
+                ) {
+                    (Some(first_purchase), Some(quote)) => {
+                        ((quote.get_date() - *first_purchase).max(0) / 86400) as usize
+                    }
+                    _ => 0,
+                };
+                Some(PositionInfo {
+                    average_cost: self.get_average_cost_basis(&symbol),
+                    unrealised_pnl: self.get_position_profit(&symbol),
+                    symbol,
+                    qty,
+                    current_value,
+                    days_held,
+                })
+            })
+            .collect()
+    }
+
     fn get_holdings_with_pending(&self) -> PortfolioHoldings {
         let mut merged_holdings = PortfolioHoldings::new();
         for (key, value) in self.get_holdings().iter() {
@@ -419,6 +795,17 @@ pub trait Portfolio<Q: BrokerQuote>: Quote<Q> {
         merged_holdings
     }
 
+    /// Alias of [Portfolio::get_average_cost_basis].
+    fn get_average_entry_price(&self, symbol: &str) -> Option<f64> {
+        self.get_average_cost_basis(symbol)
+    }
+
+    /// Price at which the position returns its cost net of income received. Dividends aren't
+    /// modelled by any broker yet, so this is the cost basis until they are.
+    fn get_breakeven_price(&self, symbol: &str) -> Option<f64> {
+        self.get_average_cost_basis(symbol)
+    }
+
     fn calculate_trade_costs(&self, trade: impl BrokerTrade) -> f64 {
         let mut cost = 0.0;
         for trade_cost in &self.get_trade_costs() {
@@ -431,13 +818,102 @@ pub trait Portfolio<Q: BrokerQuote>: Quote<Q> {
         BrokerCost::trade_impact_total(&self.get_trade_costs(), budget, price, is_buy)
     }
 
+    /// Sanity check of broker state, does not mutate state or change [BrokerState].
+    ///
+    /// Holdings are keyed by symbol so cannot hold duplicate symbols.
+    fn health_check(&self) -> BrokerHealthStatus {
+        let mut warnings = Vec::new();
+        let mut errors = Vec::new();
+
+        let cash = self.get_cash_balance();
+        if !cash.is_finite() {
+            errors.push(BrokerError::InvalidCash(cash));
+        } else if cash < 0.0 {
+            warnings.push(BrokerWarning::NegativeCash(cash));
+        }
+
+        let latest_date = self
+            .get_quotes()
+            .unwrap_or_default()
+            .iter()
+            .map(|quote| quote.get_date())
+            .max();
+
+        let mut positions = self.get_positions();
+        positions.sort();
+        for symbol in positions {
+            let qty = self.get_position_qty(&symbol).unwrap_or_default();
+            if qty < 0.0 && self.get_short_margin_requirement().is_none() {
+                errors.push(BrokerError::NegativeQuantity(symbol.clone(), qty));
+            }
+
+            if let Some(quote) = self.get_quote(&symbol) {
+                if !quote.get_bid().is_finite() || !quote.get_ask().is_finite() {
+                    errors.push(BrokerError::InvalidQuote(symbol.clone()));
+                }
+                if let Some(latest) = latest_date {
+                    if quote.get_date() < latest {
+                        let age = (latest - quote.get_date()) as usize;
+                        warnings.push(BrokerWarning::StaleQuote(symbol.clone(), age));
+                    }
+                }
+            } else {
+                errors.push(BrokerError::MissingQuote(symbol.clone()));
+            }
+        }
+
+        let value = self.get_total_value();
+        if value < 0.0 {
+            errors.push(BrokerError::NegativeValue(value));
+        }
+
+        BrokerHealthStatus {
+            is_healthy: errors.is_empty(),
+            warnings,
+            errors,
+        }
+    }
+
+    /// Values the portfolio as if the price of each symbol in `price_shocks` had changed by that
+    /// fraction, -0.1 is a 10% fall. Positions without a shock are valued at the current price.
+    /// Does not change broker state.
+    fn scenario_analysis(&self, price_shocks: &HashMap<String, f64>) -> ScenarioResult {
+        let current_value = self.get_total_value();
+        let mut new_portfolio_value = self.get_cash_balance();
+        let mut by_position = PortfolioValues::new();
+        for symbol in self.get_positions() {
+            if let Some(value) = self.get_position_value(&symbol) {
+                let shock = price_shocks.get(&symbol).unwrap_or(&0.0);
+                let shocked_value = value * (1.0 + shock);
+                new_portfolio_value += shocked_value;
+                by_position.insert(symbol, shocked_value);
+            }
+        }
+
+        let value_change = new_portfolio_value - current_value;
+        let pct_change = if current_value == 0.0 {
+            0.0
+        } else {
+            value_change / current_value
+        };
+        ScenarioResult {
+            new_portfolio_value,
+            value_change,
+            pct_change,
+            by_position,
+        }
+    }
+
     fn get_cash_balance(&self) -> f64;
     fn update_cash_balance(&mut self, cash: f64);
     fn get_holdings(&self) -> PortfolioHoldings;
     fn update_holdings(&mut self, symbol: &str, change: f64);
-    fn get_position_cost(&self, symbol: &str) -> Option<f64>;
+    /// Average price paid for the current position.
+    fn get_average_cost_basis(&self, symbol: &str) -> Option<f64>;
     fn get_pending_orders(&self) -> PortfolioHoldings;
     fn get_trade_costs(&self) -> Vec<BrokerCost>;
+    /// Number of trades that the broker has reconciled with the exchange.
+    fn get_trade_count(&self) -> usize;
 }
 
 /// Tightly bound to [BrokerState] and with [CashOperations]
@@ -457,6 +933,57 @@ pub trait BrokerStates {
 /// transactions such as dividends, so may change over time. Clients should depend on `withdraw_cash`
 /// and `deposit_cash`.
 pub trait CashOperations<Q: BrokerQuote>: Portfolio<Q> + BrokerStates {
+    /// Currency of `get_cash_balance`, `deposit_cash` and `withdraw_cash`.
+    fn get_base_currency(&self) -> CurrencyCode {
+        CurrencyCode::default()
+    }
+
+    /// Currency that `symbol` is quoted and settled in. Brokers that only hold their base currency
+    /// settle everything in it.
+    fn get_symbol_currency(&self, _symbol: &str) -> CurrencyCode {
+        self.get_base_currency()
+    }
+
+    /// Balance of the cash held in `currency`, in that currency. Brokers that only hold their base
+    /// currency have nothing in other currencies.
+    fn get_cash_balance_in(&self, currency: &CurrencyCode) -> f64 {
+        if *currency == self.get_base_currency() {
+            self.get_cash_balance()
+        } else {
+            0.0
+        }
+    }
+
+    /// Deposits into the cash held in `currency`. Brokers that only hold their base currency
+    /// reject other currencies.
+    fn deposit_cash_in(&mut self, currency: &CurrencyCode, cash: &f64) -> BrokerCashEvent {
+        if *currency != self.get_base_currency() {
+            info!(
+                "BROKER: Attempted cash deposit of {:?} {} but broker only holds {}",
+                cash,
+                currency,
+                self.get_base_currency()
+            );
+            return BrokerCashEvent::OperationFailure(*cash);
+        }
+        self.deposit_cash(cash)
+    }
+
+    /// Withdraws from the cash held in `currency`. Brokers that only hold their base currency
+    /// reject other currencies.
+    fn withdraw_cash_in(&mut self, currency: &CurrencyCode, cash: &f64) -> BrokerCashEvent {
+        if *currency != self.get_base_currency() {
+            info!(
+                "BROKER: Attempted cash withdraw of {:?} {} but broker only holds {}",
+                cash,
+                currency,
+                self.get_base_currency()
+            );
+            return BrokerCashEvent::OperationFailure(*cash);
+        }
+        self.withdraw_cash(cash)
+    }
+
     fn withdraw_cash(&mut self, cash: &f64) -> BrokerCashEvent {
         match self.get_broker_state() {
             BrokerState::Failed => {
@@ -564,8 +1091,16 @@ pub trait BrokerOperations<O: BrokerOrder, Q: BrokerQuote>:
         //Has to be less than, we can have zero value without needing to liquidate if we initialize
         //the portfolio but exchange doesn't execute any trades. This can happen if we are missing
         //prices at the start of the series
-        if self.get_cash_balance() < 0.0 {
-            let shortfall = self.get_cash_balance() * -1.0;
+        //Sales that haven't executed yet will raise cash so aren't sent again
+        let pending_sales = self
+            .get_pending_orders()
+            .iter()
+            .filter(|(_symbol, qty)| **qty < 0.0)
+            .filter_map(|(symbol, qty)| self.get_quote(symbol).map(|quote| -qty * quote.get_bid()))
+            .sum::<f64>();
+        let cash = self.get_cash_balance() + pending_sales;
+        if cash < 0.0 {
+            let shortfall = -cash;
             //When we raise cash, we try to raise a small amount more to stop continuous
             //rebalancing, this amount is arbitrary atm
             let plus_buffer = shortfall + 1000.0;
@@ -659,45 +1194,102 @@ pub trait BrokerOperations<O: BrokerOrder, Q: BrokerQuote>:
         }
     }
 
+    /// Value at the current ask of net pending buys in symbols settled in `currency`. Pending
+    /// orders may not have reached the exchange yet.
+    fn get_pending_buy_value(&self, currency: &CurrencyCode) -> f64 {
+        self.get_pending_orders()
+            .iter()
+            .filter(|(symbol, qty)| **qty > 0.0 && self.get_symbol_currency(symbol) == *currency)
+            .filter_map(|(symbol, qty)| self.get_quote(symbol).map(|quote| qty * quote.get_ask()))
+            .sum()
+    }
+
     fn client_has_sufficient_cash<T: Into<BrokerOrderType>>(
         &self,
         order: &O,
         price: &f64,
-    ) -> Result<(), InsufficientCashError> {
+    ) -> Result<(), BrokerError> {
         let shares = order.get_shares();
         let value = shares * *price;
         match order.get_order_type::<T>() {
-            BrokerOrderType::MarketBuy => {
-                if self.get_cash_balance() > value {
+            //Price of limit and stop orders is only an estimate, same as market orders
+            BrokerOrderType::MarketBuy
+            | BrokerOrderType::LimitBuy
+            | BrokerOrderType::StopBuy
+            | BrokerOrderType::TrailingStopBuy => {
+                //Orders in other currencies settle against the cash held in that currency, which
+                //can't be topped up from the base currency
+                //Pending buys haven't moved cash yet so they are reserved as well
+                let currency = self.get_symbol_currency(&order.get_symbol());
+                let pending = self.get_pending_buy_value(&currency);
+                if currency != self.get_base_currency() {
+                    if self.get_cash_balance_in(&currency) - pending < value {
+                        return Err(BrokerError::InsufficientCash(value));
+                    }
                     return Ok(());
                 }
-                Err(InsufficientCashError)
+
+                //Unsettled buys are reserved so the same cash cannot be spent twice before
+                //settlement, unsettled sales are not available until they settle
+                let cash = self.get_cash_balance() + self.get_unsettled_cash().min(0.0) - pending;
+                if cash < value {
+                    return Err(BrokerError::InsufficientCash(value));
+                }
+                if cash - self.get_min_cash_balance() < value {
+                    return Err(BrokerError::MinCashBalanceBreached(value));
+                }
+                Ok(())
             }
-            BrokerOrderType::MarketSell => Ok(()),
-            _ => unreachable!("Shouldn't hit unless something has gone wrong"),
+            BrokerOrderType::MarketSell
+            | BrokerOrderType::LimitSell
+            | BrokerOrderType::StopSell
+            | BrokerOrderType::TrailingStopSell => Ok(()),
         }
     }
 
     fn client_has_sufficient_holdings_for_sale<T: Into<BrokerOrderType>>(
         &self,
         order: &O,
-    ) -> Result<(), UnexecutableOrderError> {
-        if let BrokerOrderType::MarketSell = order.get_order_type::<T>() {
-            if let Some(holding) = self.get_position_qty(&order.get_symbol()) {
-                if holding >= order.get_shares() {
-                    return Ok(());
-                } else {
-                    return Err(UnexecutableOrderError);
+    ) -> Result<(), BrokerError> {
+        if let BrokerOrderType::MarketSell
+        | BrokerOrderType::LimitSell
+        | BrokerOrderType::StopSell
+        | BrokerOrderType::TrailingStopSell = order.get_order_type::<T>()
+        {
+            let symbol = order.get_symbol();
+            let holding = self.get_position_qty(&symbol).unwrap_or(0.0);
+            if holding.max(0.0) >= order.get_shares() {
+                return Ok(());
+            }
+            match self.get_short_margin_requirement() {
+                None => {
+                    return Err(BrokerError::InsufficientHoldings(
+                        symbol,
+                        order.get_shares(),
+                    ))
+                }
+                Some(margin) => {
+                    //Margin is required on the whole short position after the sale, which
+                    //includes any existing short
+                    let short_qty = order.get_shares() - holding;
+                    let price = self
+                        .get_quote(&symbol)
+                        .map(|quote| quote.get_ask())
+                        .unwrap_or(0.0);
+                    let required = margin * short_qty * price;
+                    if self.get_cash_balance() < required {
+                        return Err(BrokerError::InsufficientMargin(required));
+                    }
                 }
             }
         }
         Ok(())
     }
 
-    fn client_is_issuing_nonsense_order(&self, order: &O) -> Result<(), UnexecutableOrderError> {
+    fn client_is_issuing_nonsense_order(&self, order: &O) -> Result<(), BrokerError> {
         let shares = order.get_shares();
         if shares == 0.0 {
-            return Err(UnexecutableOrderError);
+            return Err(BrokerError::UnexecutableOrder(order.get_symbol()));
         }
         Ok(())
     }
@@ -737,13 +1329,33 @@ pub trait BrokerOperations<O: BrokerOrder, Q: BrokerQuote>:
             }
         };
 
+        let pending_orders = self.get_pending_orders();
         for symbol in target_weights.keys() {
-            let curr_val = self.get_position_value(symbol).unwrap_or(0.0);
+            //Orders that haven't executed yet count towards the position so that a rebalance
+            //before they execute doesn't send the same diff again
+            let pending_qty = pending_orders.get(symbol).copied().unwrap_or(0.0);
+            let pending_val = match self.get_quote(symbol) {
+                Some(quote) => pending_qty * quote.get_bid(),
+                None => 0.0,
+            };
+            let curr_val = self.get_position_value(symbol).unwrap_or(0.0) + pending_val;
             //Iterating over target_weights so will always find value
             let target_val = total_value * target_weights.get(symbol).unwrap();
+
+            //Zero weight closes the whole position, shares are rounded down otherwise which can
+            //leave fractional quantities from liquidation open
+            if target_val == 0.0 {
+                let qty = self.get_position_qty(symbol).unwrap_or(0.0) + pending_qty;
+                if qty > 0.0 {
+                    sell_orders.push(O::market_sell(symbol.clone(), qty));
+                } else if qty < 0.0 {
+                    buy_orders.push(O::market_buy(symbol.clone(), qty.abs()));
+                }
+                continue;
+            }
             let diff_val = target_val - curr_val;
             if (diff_val).eq(&0.0) {
-                break;
+                continue;
             }
 
             //We do not throw an error here, we just proceed assuming that the client has passed in data that will
@@ -774,6 +1386,97 @@ pub trait BrokerOperations<O: BrokerOrder, Q: BrokerQuote>:
     }
 }
 
+/// Broker has failed to connect, or lost connection, to a live brokerage.
+#[derive(Clone, Debug)]
+pub struct ConnectionError(pub String);
+
+impl Error for ConnectionError {}
+
+impl Display for ConnectionError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "Connection to brokerage failed: {}", self.0)
+    }
+}
+
+/// Broker that routes orders to a real brokerage. Exposes the same traits as simulated brokers so
+/// that strategy code can run against either.
+pub trait LiveTradingAdapter<Q: BrokerQuote, O: BrokerOrder>:
+    SendOrder<O> + Portfolio<Q> + Update
+{
+    fn connect(&mut self) -> impl std::future::Future<Output = Result<(), ConnectionError>>;
+    fn disconnect(&mut self) -> impl std::future::Future<Output = Result<(), ConnectionError>>;
+}
+
+/// Result of [OtoOrders::send_oto_order]. `id` identifies the pair and is `None` if the primary
+/// order was rejected, in which case the secondary order is discarded.
+#[derive(Clone, Debug)]
+pub struct OtoOrderResult<O: BrokerOrder> {
+    pub id: Option<usize>,
+    pub primary: BrokerEvent<O>,
+}
+
+/// One-triggers-other pairs: the secondary is only sent once the primary has executed, typically a
+/// stop-loss for a new position. Orders that cancel each other while both are live are exchange
+/// OCO groups, see [rotala::exchange::uist_v1::UistV1::insert_oco_pair].
+pub trait OtoOrders<O: BrokerOrder> {
+    fn send_oto_order(&mut self, primary: O, secondary: O) -> OtoOrderResult<O>;
+}
+
+/// Each share becomes `ratio` shares on `date`, so a 2-for-1 split has a ratio of 2.
+#[derive(Clone, Debug)]
+pub struct Split {
+    pub symbol: String,
+    pub ratio: f64,
+    pub date: i64,
+}
+
+/// Target is acquired for `price_per_share` in cash on `date`, the position is closed.
+#[derive(Clone, Debug)]
+pub struct CashAcquisition {
+    pub target_symbol: String,
+    pub price_per_share: f64,
+    pub date: i64,
+}
+
+/// Holders receive `ratio` shares of `spinco_symbol` for each share of `parent_symbol` on `date`.
+/// `cost_basis_allocation` is the fraction of the parent's cost basis moved to the new shares.
+#[derive(Clone, Debug)]
+pub struct SpinOff {
+    pub parent_symbol: String,
+    pub spinco_symbol: String,
+    pub ratio: f64,
+    pub cost_basis_allocation: f64,
+    pub date: i64,
+}
+
+/// Source of corporate actions that change broker holdings. Brokers apply each action once, on the
+/// first tick on or after its date, so sources can return every action for the simulation.
+///
+/// Sources are `Send` so that a broker holding one can be shared by the broker server.
+pub trait CorporateActionSource: std::fmt::Debug + Send {
+    fn get_splits(&self) -> Vec<Split>;
+    fn get_cash_acquisitions(&self) -> Vec<CashAcquisition> {
+        Vec::new()
+    }
+    fn get_spinoffs(&self) -> Vec<SpinOff> {
+        Vec::new()
+    }
+}
+
+/// Source of funding rates for perpetual futures. Rate is charged on the value of each position
+/// once per tick so sources should return zero on dates when there is no funding payment. Positive
+/// rates are paid by long positions and received by short positions.
+///
+/// Sources are `Send` so that a broker holding one can be shared by the broker server.
+pub trait FundingRateSource: std::fmt::Debug + Send {
+    fn get_funding_rate(&self, symbol: &str, date: DateTime) -> f64;
+}
+
+/// Trades executed by the broker, used by strategies to calculate trade-based performance stats.
+pub trait TradeLog {
+    fn trades_between(&self, start: &i64, stop: &i64) -> Vec<UistTrade>;
+}
+
 pub trait Update {
     fn check(&mut self) -> impl std::future::Future<Output = ()>;
 }
@@ -781,6 +1484,43 @@ pub trait Update {
 pub trait Clock {
     fn now(&mut self) -> i64;
     fn has_next(&mut self) -> bool;
+    /// Date of the first tick in the simulation.
+    fn start(&self) -> i64;
+
+    /// Calendar days between the first tick and now, so at the end of a simulation this covers the
+    /// full date range.
+    fn days_in_simulation(&mut self) -> u64 {
+        let start: OffsetDateTime = DateTime::from(self.start()).into();
+        let now: OffsetDateTime = DateTime::from(self.now()).into();
+        (now.date() - start.date()).whole_days().max(0) as u64
+    }
+
+    fn current_year(&mut self) -> i32 {
+        DateTime::from(self.now()).year()
+    }
+
+    fn current_month(&mut self) -> u8 {
+        DateTime::from(self.now()).month().into()
+    }
+
+    /// Zero is Sunday.
+    fn current_day_of_week(&mut self) -> u8 {
+        DateTime::from(self.now())
+            .weekday()
+            .number_days_from_sunday()
+    }
+
+    /// Wall-clock time of the current tick in `tz`, see [DateTime::to_local].
+    fn now_in_timezone(&mut self, tz: Timezone) -> DateTime {
+        DateTime::from(self.now()).to_local(tz)
+    }
+
+    fn is_weekend(&mut self) -> bool {
+        matches!(
+            DateTime::from(self.now()).weekday(),
+            Weekday::Saturday | Weekday::Sunday
+        )
+    }
 }
 
 ///[DateTime] is a wrapper around the epoch time as i64. This type also functions as a wrapper
@@ -790,6 +1530,7 @@ pub trait Clock {
 //results in some duplication of the API, this retains the option to get rid of the dependency on
 //time or change individual functions later.
 #[derive(Clone, Debug, Hash, Eq, PartialEq, PartialOrd, Copy, Ord)]
+#[cfg_attr(feature = "checkpoint", derive(serde::Serialize, serde::Deserialize))]
 pub struct DateTime(i64);
 
 impl DateTime {
@@ -808,8 +1549,19 @@ impl DateTime {
         date.month()
     }
 
+    pub fn year(&self) -> i32 {
+        let date: OffsetDateTime = (*self).into();
+        date.year()
+    }
+
+    /// Wall-clock time in `tz`. The result is only meaningful for calendar queries like
+    /// [DateTime::day] or [DateTime::weekday], it is not the same instant as `self`.
+    pub fn to_local(&self, tz: Timezone) -> DateTime {
+        DateTime(self.0 + tz.utc_offset_secs(self))
+    }
+
     pub fn from_date_string(val: &str, date_fmt: &str) -> Self {
-        let format = format_description::parse(date_fmt).unwrap();
+        let format = format_description::parse_borrowed::<1>(date_fmt).unwrap();
         let parsed_date = Date::parse(val, &format).unwrap();
         let parsed_time = parsed_date.with_time(time::macros::time!(09:00));
         Self::from(parsed_time.assume_utc().unix_timestamp())
@@ -855,6 +1607,17 @@ impl From<i64> for DateTime {
 pub type PortfolioValues = HashMap<String, f64>;
 pub type PortfolioHoldings = HashMap<String, f64>;
 
+/// Returned by [Portfolio::get_open_positions].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PositionInfo {
+    pub symbol: String,
+    pub qty: f64,
+    pub average_cost: Option<f64>,
+    pub current_value: f64,
+    pub unrealised_pnl: Option<f64>,
+    pub days_held: usize,
+}
+
 /// A point=in-time representation of the current state of a strategy. These statistics are currently
 /// recorded for use within performance calculations after the simulation has concluded. They are
 /// distinct from the transaction logging performed by brokers.
@@ -1,30 +1,99 @@
 use futures::executor;
 use itertools::Itertools;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     error::Error,
     fmt::{Display, Formatter},
     mem,
 };
 
 use log::info;
-use rotala::exchange::uist_v1::{Order, OrderType, Trade, TradeType, UistQuote, UistV1};
+use rotala::exchange::uist_v1::{
+    DollarOrder, Order, OrderId, OrderType, Trade, TradeType, UistQuote, UistV1,
+};
 use rotala::http::uist::uistv1_client::Client;
-use rotala::http::uist::uistv1_client::{BacktestId, UistClient};
+use rotala::http::uist::uistv1_client::{BacktestId, TestClient, UistClient};
+use rotala::http::uist::uistv1_server::{FetchQuotesResponse, TickResponse};
+use rotala::input::penelope::Penelope;
+use rotala::metrics::{CASH_BALANCE, TRADES_EXECUTED_TOTAL};
+use time::Weekday;
+use tokio::sync::broadcast;
 
 use crate::{broker::BrokerOrder, strategy::staticweight::StaticWeightBroker};
 
+#[cfg(feature = "checkpoint")]
+use super::checkpoint::{BrokerCheckpoint, CheckpointError, CheckpointManager};
+use super::currency::{CurrencyCode, MultiCurrencyCash};
 use super::{
-    BrokerCost, BrokerEvent, BrokerOperations, BrokerState, BrokerStates, CashOperations, Clock,
-    DateTime, Portfolio, PortfolioHoldings, Quote, SendOrder, Update,
+    BrokerCashEvent, BrokerCost, BrokerError, BrokerEvent, BrokerOperations, BrokerState,
+    BrokerStates, CashAcquisition, CashOperations, Clock, CorporateActionSource, DateTime,
+    FundingRateSource, GetsOrderBook, InterestModel, Level, OrderBookSnapshot, OrderRejectionLog,
+    OtoOrderResult, OtoOrders, Portfolio, PortfolioConstraints, PortfolioHoldings, Quote,
+    SendOrder, SpinOff, Split, TradeLog, Update,
 };
 
 type UistBrokerEvent = BrokerEvent<Order>;
 
+/// Cash and shares from a trade that move on `settle_date`, deltas are signed.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "checkpoint", derive(serde::Serialize, serde::Deserialize))]
+pub struct PendingSettlement {
+    pub settle_date: DateTime,
+    pub cash_delta: f64,
+    pub qty_delta: f64,
+    pub symbol: String,
+}
+
+/// Order resting in the exchange orderbook, `submitted_at` is the date of the quotes the exchange
+/// had when it inserted the order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingOrderInfo {
+    pub order_id: OrderId,
+    pub symbol: String,
+    pub order_type: OrderType,
+    pub qty: f64,
+    pub price: Option<f64>,
+    pub submitted_at: DateTime,
+}
+
+/// Weekends are skipped, there is no holiday calendar.
+fn add_trading_days(date: i64, days: u8) -> i64 {
+    let mut date = date;
+    for _ in 0..days {
+        date += 86400;
+        while matches!(
+            DateTime::from(date).weekday(),
+            Weekday::Saturday | Weekday::Sunday
+        ) {
+            date += 86400;
+        }
+    }
+    date
+}
+
+/// Order executed as equal market order slices, one slice sent on every `check`.
+#[derive(Clone, Debug)]
+pub struct TwapSlice {
+    pub order: Order,
+    pub slice_qty: f64,
+    pub remaining_qty: f64,
+}
+
+/// Order executed as market order slices sized as a fraction of the volume in each period.
+#[derive(Clone, Debug)]
+pub struct VwapSlice {
+    pub order: Order,
+    pub participation_rate: f64,
+    pub remaining_qty: f64,
+}
+
 /// Implementation of broker that uses the [Uist](rotala::exchange::uist::UistV1) exchange.
 #[derive(Debug)]
 pub struct UistBroker<C: UistClient> {
-    cash: f64,
+    cash: MultiCurrencyCash,
+    //Symbols quoted in a currency other than the base, trades in these symbols settle against the
+    //matching cash bucket
+    symbol_currencies: HashMap<String, CurrencyCode>,
     holdings: PortfolioHoldings,
     //Kept distinct from holdings because some perf calculations may need to distinguish between
     //trades that we know are booked vs ones that we think should get booked
@@ -37,10 +106,65 @@ pub struct UistBroker<C: UistClient> {
     broker_state: BrokerState,
     http_client: C,
     backtest_id: BacktestId,
+    //Exchanges other than the primary, a symbol in `routing_table` with index `i` is sent to
+    //`routed_exchanges[i - 1]` and every other symbol goes to the primary
+    routed_exchanges: Vec<(C, BacktestId)>,
+    routing_table: HashMap<String, usize>,
+    //Orders are queued and sent to the exchange on a later `check` because `send_order` is not
+    //async. Keyed by the tick on which the broker received the orders, orders are sent once they
+    //are `execution_delay_ticks` old
+    pending_order_queue: VecDeque<(usize, Vec<Order>)>,
+    execution_delay_ticks: usize,
+    //Number of exchange ticks seen by the broker
+    tick_count: usize,
+    //Orders that have been inserted into the exchange orderbook and haven't executed
+    open_orders: HashMap<OrderId, Order>,
+    //Date each open order was inserted, entries are removed once the order leaves `open_orders`
+    open_order_dates: HashMap<OrderId, i64>,
+    //Deletes are queued and sent on the next `check`, before queued orders
+    unsent_deletes: Vec<OrderId>,
+    //Effective spread of every trade, 2 * |execution price - mid price|
+    effective_spread_tracker: HashMap<String, Vec<f64>>,
+    funding_rate_source: Option<Box<dyn FundingRateSource>>,
+    corporate_action_source: Option<Box<dyn CorporateActionSource>>,
+    //Actions dated after this and up to the latest quote are applied on the next tick
+    last_corporate_action_date: i64,
+    interest_model: InterestModel,
+    //Date interest was last accrued to, interest starts accruing from the first tick
+    last_interest_date: Option<i64>,
+    min_cash_balance: f64,
+    short_margin_requirement: Option<f64>,
+    start_date: i64,
+    rejection_log: OrderRejectionLog,
+    //Pairs waiting for the primary to execute, the primary order id is set once the exchange has
+    //inserted the order
+    oto_book: HashMap<usize, (Order, Order)>,
+    last_oto_id: usize,
+    pending_twap_orders: Vec<TwapSlice>,
+    //Completions and cancellations of TWAP orders on the last check
+    twap_events: Vec<UistBrokerEvent>,
+    pending_vwap_orders: Vec<VwapSlice>,
+    //Updated from the exchange on every tick
+    halted_symbols: HashMap<String, usize>,
+    //Orders held by the exchange until the session opens, these aren't counted as pending
+    after_hours_orders: Vec<Order>,
+    //Trading days between a trade executing and cash and shares moving
+    settlement_lag: u8,
+    pending_settlements: Vec<PendingSettlement>,
+    constraints: Option<PortfolioConstraints>,
+    #[cfg(feature = "checkpoint")]
+    checkpoint_manager: Option<CheckpointManager>,
+    event_bus: broadcast::Sender<BrokerBusEvent>,
 }
 
 impl<C: UistClient> StaticWeightBroker<UistQuote, Order> for UistBroker<C> {}
 
+impl<C: UistClient> TradeLog for UistBroker<C> {
+    fn trades_between(&self, start: &i64, stop: &i64) -> Vec<Trade> {
+        self.log.trades_between(start, stop)
+    }
+}
+
 impl<C: UistClient> Quote<UistQuote> for UistBroker<C> {
     fn get_quote(&self, symbol: &str) -> Option<UistQuote> {
         self.latest_quotes.get(symbol).cloned()
@@ -59,27 +183,76 @@ impl<C: UistClient> Quote<UistQuote> for UistBroker<C> {
     }
 }
 
+/// UistV1 only quotes best bid/ask and fills orders of any size at that price, so the snapshot has
+/// one level on each side with unlimited size.
+impl<C: UistClient> GetsOrderBook for UistBroker<C> {
+    fn get_order_book_snapshot(&self, symbol: &str) -> Option<OrderBookSnapshot> {
+        let quote = self.latest_quotes.get(symbol)?;
+        Some(OrderBookSnapshot {
+            bids: vec![Level {
+                price: quote.bid,
+                size: f64::INFINITY,
+            }],
+            asks: vec![Level {
+                price: quote.ask,
+                size: f64::INFINITY,
+            }],
+            symbol: quote.symbol.clone(),
+            date: quote.date.into(),
+        })
+    }
+}
+
 impl<C: UistClient> Portfolio<UistQuote> for UistBroker<C> {
     fn get_trade_costs(&self) -> Vec<BrokerCost> {
         self.trade_costs.clone()
     }
 
+    fn get_unsettled_cash(&self) -> f64 {
+        self.pending_settlements
+            .iter()
+            .map(|settlement| settlement.cash_delta)
+            .sum()
+    }
+
     fn get_holdings(&self) -> PortfolioHoldings {
         self.holdings.clone()
     }
 
+    /// Value of every cash bucket in the base currency.
     fn get_cash_balance(&self) -> f64 {
-        self.cash
+        self.cash.base_value(|currency| self.fx_rate(currency))
     }
 
+    /// Only the base currency bucket changes, other buckets are left as they are.
     fn update_cash_balance(&mut self, cash: f64) {
-        self.cash = cash;
+        let change = cash - self.get_cash_balance();
+        let base = self.cash.get_base_currency().clone();
+        self.change_cash(&base, change);
     }
 
-    fn get_position_cost(&self, symbol: &str) -> Option<f64> {
+    fn get_average_cost_basis(&self, symbol: &str) -> Option<f64> {
         self.log.cost_basis(symbol)
     }
 
+    fn get_first_purchase_date(&self, symbol: &str) -> Option<DateTime> {
+        self.log.first_purchase_date(symbol)
+    }
+
+    /// Value in the base currency, positions in symbols quoted in another currency are converted
+    /// with the latest FX quote.
+    fn get_position_value(&self, symbol: &str) -> Option<f64> {
+        let quote = self.latest_quotes.get(symbol)?;
+        let qty = self.get_position_qty(symbol)?;
+        //Long positions are closed at the bid, short positions at the ask
+        let price = if qty < 0.0 { quote.ask } else { quote.bid };
+        let rate = match self.symbol_currencies.get(symbol) {
+            Some(currency) => self.fx_rate(currency)?,
+            None => 1.0,
+        };
+        Some(price * qty * rate)
+    }
+
     fn update_holdings(&mut self, symbol: &str, change: f64) {
         //We have to take ownership for logging but it is easier just to use ref for symbol as that
         //is used throughout
@@ -98,6 +271,18 @@ impl<C: UistClient> Portfolio<UistQuote> for UistBroker<C> {
     fn get_pending_orders(&self) -> PortfolioHoldings {
         self.pending_orders.clone()
     }
+
+    fn get_min_cash_balance(&self) -> f64 {
+        self.min_cash_balance
+    }
+
+    fn get_short_margin_requirement(&self) -> Option<f64> {
+        self.short_margin_requirement
+    }
+
+    fn get_trade_count(&self) -> usize {
+        self.log.trades().len()
+    }
 }
 
 impl<C: UistClient> BrokerStates for UistBroker<C> {
@@ -110,7 +295,61 @@ impl<C: UistClient> BrokerStates for UistBroker<C> {
     }
 }
 
-impl<C: UistClient> CashOperations<UistQuote> for UistBroker<C> {}
+impl<C: UistClient> CashOperations<UistQuote> for UistBroker<C> {
+    fn get_base_currency(&self) -> CurrencyCode {
+        self.cash.get_base_currency().clone()
+    }
+
+    fn get_symbol_currency(&self, symbol: &str) -> CurrencyCode {
+        self.symbol_currencies
+            .get(symbol)
+            .cloned()
+            .unwrap_or_else(|| self.get_base_currency())
+    }
+
+    /// Balance of a single cash bucket, in that bucket's currency.
+    fn get_cash_balance_in(&self, currency: &CurrencyCode) -> f64 {
+        self.cash.get(currency)
+    }
+
+    fn deposit_cash_in(&mut self, currency: &CurrencyCode, cash: &f64) -> BrokerCashEvent {
+        if let BrokerState::Failed = self.broker_state {
+            info!(
+                "BROKER: Attempted cash deposit of {:?} {} but broker in Failed State",
+                cash, currency
+            );
+            return BrokerCashEvent::OperationFailure(*cash);
+        }
+        info!("BROKER: Deposited {:?} {}", cash, currency);
+        self.change_cash(currency, *cash);
+        BrokerCashEvent::DepositSuccess(*cash)
+    }
+
+    fn withdraw_cash_in(&mut self, currency: &CurrencyCode, cash: &f64) -> BrokerCashEvent {
+        if let BrokerState::Failed = self.broker_state {
+            info!(
+                "BROKER: Attempted cash withdraw of {:?} {} but broker in Failed State",
+                cash, currency
+            );
+            return BrokerCashEvent::OperationFailure(*cash);
+        }
+        if *cash > self.cash.get(currency) {
+            info!(
+                "BROKER: Attempted cash withdraw of {:?} {} but only have {:?}",
+                cash,
+                currency,
+                self.cash.get(currency)
+            );
+            return BrokerCashEvent::WithdrawFailure(*cash);
+        }
+        info!(
+            "BROKER: Successful cash withdraw of {:?} {}",
+            cash, currency
+        );
+        self.change_cash(currency, -*cash);
+        BrokerCashEvent::WithdrawSuccess(*cash)
+    }
+}
 
 impl<C: UistClient> BrokerOperations<Order, UistQuote> for UistBroker<C> {}
 
@@ -118,6 +357,7 @@ impl<C: UistClient> SendOrder<Order> for UistBroker<C> {
     fn send_order(&mut self, order: Order) -> UistBrokerEvent {
         //This is an estimate of the cost based on the current price, can still end with negative
         //balance when we reconcile with actuals, may also reject valid orders at the margin
+        self.rejection_log.record_submitted();
         match self.get_broker_state() {
             BrokerState::Failed => {
                 info!(
@@ -126,7 +366,7 @@ impl<C: UistClient> SendOrder<Order> for UistBroker<C> {
                     order.get_shares(),
                     order.get_symbol()
                 );
-                UistBrokerEvent::OrderInvalid(order.clone())
+                self.reject(order, BrokerError::BrokerFailed)
             }
             BrokerState::Ready => {
                 info!(
@@ -138,55 +378,64 @@ impl<C: UistClient> SendOrder<Order> for UistBroker<C> {
 
                 let quote = self.get_quote(order.get_symbol()).unwrap();
                 let price = match order.get_order_type() {
-                    OrderType::MarketBuy | OrderType::LimitBuy | OrderType::StopBuy => quote.ask,
-                    OrderType::MarketSell | OrderType::LimitSell | OrderType::StopSell => quote.bid,
+                    OrderType::MarketBuy
+                    | OrderType::LimitBuy
+                    | OrderType::StopBuy
+                    | OrderType::TrailingStopBuy => quote.ask,
+                    OrderType::MarketSell
+                    | OrderType::LimitSell
+                    | OrderType::StopSell
+                    | OrderType::TrailingStopSell => quote.bid,
                 };
 
-                if let Err(_err) = self.client_has_sufficient_cash::<OrderType>(&order, &price) {
-                    info!(
-                        "BROKER: Unable to send {:?} order for {:?} shares of {:?} to exchange",
-                        order.get_order_type(),
-                        order.get_shares(),
-                        order.get_symbol()
-                    );
-                    return UistBrokerEvent::OrderInvalid(order.clone());
-                }
-                if let Err(_err) = self.client_has_sufficient_holdings_for_sale::<OrderType>(&order)
-                {
-                    info!(
-                        "BROKER: Unable to send {:?} order for {:?} shares of {:?} to exchange",
-                        order.get_order_type(),
-                        order.get_shares(),
-                        order.get_symbol()
-                    );
-                    return UistBrokerEvent::OrderInvalid(order.clone());
-                }
-                if let Err(_err) = self.client_is_issuing_nonsense_order(&order) {
+                let checks = self
+                    .client_has_sufficient_cash::<OrderType>(&order, &price)
+                    .and_then(|_| self.client_has_sufficient_holdings_for_sale::<OrderType>(&order))
+                    .and_then(|_| self.client_is_issuing_nonsense_order(&order))
+                    .and_then(|_| self.client_has_lots(&order));
+                if let Err(err) = checks {
                     info!(
-                        "BROKER: Unable to send {:?} order for {:?} shares of {:?} to exchange",
+                        "BROKER: Unable to send {:?} order for {:?} shares of {:?} to exchange: {}",
                         order.get_order_type(),
                         order.get_shares(),
-                        order.get_symbol()
+                        order.get_symbol(),
+                        err
                     );
-                    return UistBrokerEvent::OrderInvalid(order.clone());
+                    return self.reject(order, err);
                 }
 
-                self.http_client
-                    .insert_order(order.clone(), self.backtest_id);
+                let original = order.clone();
+                let order = match self.constrain_order(&order, &price) {
+                    Ok(None) => order,
+                    Ok(Some(shares)) => {
+                        let mut trimmed = order;
+                        trimmed.shares = shares;
+                        trimmed
+                    }
+                    Err(err) => {
+                        info!(
+                            "BROKER: Unable to send {:?} order for {:?} shares of {:?} to exchange: {}",
+                            original.get_order_type(),
+                            original.get_shares(),
+                            original.get_symbol(),
+                            err
+                        );
+                        return self.reject(original, err);
+                    }
+                };
+
+                match self.pending_order_queue.back_mut() {
+                    Some((tick, orders)) if *tick == self.tick_count => orders.push(order.clone()),
+                    _ => self
+                        .pending_order_queue
+                        .push_back((self.tick_count, vec![order.clone()])),
+                }
                 //From the point of view of strategy, an order pending is the same as an order
                 //executed. If the order is executed, then it is executed. If the order isn't
                 //executed then the strategy must wait but all the strategy's work has been
                 //done. So once we send the order, we need some way for clients to work out
                 //what orders are pending and whether they need to do more work.
-                let order_effect = match order.get_order_type() {
-                    OrderType::MarketBuy | OrderType::LimitBuy | OrderType::StopBuy => {
-                        order.get_shares()
-                    }
-
-                    OrderType::MarketSell | OrderType::LimitSell | OrderType::StopSell => {
-                        -order.get_shares()
-                    }
-                };
+                let order_effect = Self::order_effect(&order);
 
                 let symbol = order.get_symbol().to_string();
                 if let Some(position) = self.pending_orders.get(order.get_symbol()) {
@@ -201,6 +450,9 @@ impl<C: UistClient> SendOrder<Order> for UistBroker<C> {
                     order.get_shares(),
                     order.get_symbol()
                 );
+                if order.get_shares() != original.get_shares() {
+                    return UistBrokerEvent::OrderTrimmed(original, order);
+                }
                 UistBrokerEvent::OrderSentToExchange(order)
             }
         }
@@ -216,16 +468,100 @@ impl<C: UistClient> SendOrder<Order> for UistBroker<C> {
     }
 }
 
+impl<C: UistClient> OtoOrders<Order> for UistBroker<C> {
+    /// Secondary order is validated when it is sent, after the primary executes, and can be
+    /// rejected at that point.
+    fn send_oto_order(&mut self, primary: Order, secondary: Order) -> OtoOrderResult<Order> {
+        let res = self.send_order(primary.clone());
+        if let UistBrokerEvent::OrderSentToExchange(_) | UistBrokerEvent::OrderTrimmed(..) = res {
+            let id = self.last_oto_id;
+            self.last_oto_id += 1;
+            self.oto_book.insert(id, (primary, secondary));
+            return OtoOrderResult {
+                id: Some(id),
+                primary: res,
+            };
+        }
+        OtoOrderResult {
+            id: None,
+            primary: res,
+        }
+    }
+}
+
 impl<C: UistClient> Update for UistBroker<C> {
     /// Called on every tick of clock to ensure that state is synchronized with other components.
     ///
-    /// * Calls `check` on exchange
+    /// * Sends orders queued by `send_order` to exchange, after `execution_delay_ticks`
+    /// * Calls `check` on exchange, and on every routed exchange
     /// * Updates last seen prices for exchange tick
     /// * Reconciles internal state against trades completed on current tick
     /// * Rebalances cash, which can trigger new trades if broker is in invalid state
     async fn check(&mut self) {
-        if let Ok(tick_response) = self.http_client.tick(self.backtest_id).await {
-            if let Ok(quotes_response) = self.http_client.fetch_quotes(self.backtest_id).await {
+        for order_id in mem::take(&mut self.unsent_deletes) {
+            let (exchange, order_id) = self.to_exchange_order_id(order_id);
+            let (client, backtest_id) = self.exchange_mut(exchange);
+            let _ = client.delete_order(order_id, backtest_id).await;
+        }
+
+        while let Some((tick, _orders)) = self.pending_order_queue.front() {
+            if self.tick_count - tick < self.execution_delay_ticks {
+                break;
+            }
+            let (_tick, orders) = self.pending_order_queue.pop_front().unwrap();
+            for order in orders {
+                let exchange = self
+                    .routing_table
+                    .get(order.get_symbol())
+                    .copied()
+                    .unwrap_or(0);
+                let (client, backtest_id) = self.exchange_mut(exchange);
+                let res = client.insert_order(order.clone(), backtest_id).await;
+                if let Err(err) = res {
+                    info!(
+                        "BROKER: Exchange rejected {:?} order for {:?} shares of {:?}: {}",
+                        order.get_order_type(),
+                        order.get_shares(),
+                        order.get_symbol(),
+                        err
+                    );
+                    self.release_pending(&order);
+                    let symbol = order.get_symbol().to_string();
+                    self.reject(order, BrokerError::ExchangeRejected(symbol));
+                }
+            }
+        }
+
+        if let Ok(mut tick_response) = self.http_client.tick(self.backtest_id).await {
+            self.tick_count += 1;
+            if let Ok(mut quotes_response) = self.http_client.fetch_quotes(self.backtest_id).await {
+                self.remap_order_ids(&mut tick_response, 0);
+                self.tick_routed_exchanges(&mut tick_response, &mut quotes_response)
+                    .await;
+
+                //Trades execute against the quotes seen on the last check so this has to run
+                //before prices are updated
+                for trade in &tick_response.executed_trades {
+                    if let Some(quote) = self.latest_quotes.get(&trade.symbol) {
+                        if quote.date == trade.date && trade.quantity != 0.0 {
+                            let mid = (quote.bid + quote.ask) / 2.0;
+                            let price = trade.value / trade.quantity;
+                            self.effective_spread_tracker
+                                .entry(trade.symbol.clone())
+                                .or_default()
+                                .push(2.0 * (price - mid).abs());
+                        }
+                    }
+                }
+
+                //Orders inserted on this tick were received with the quotes from the last check
+                let inserted_at = self
+                    .latest_quotes
+                    .values()
+                    .map(|quote| quote.date)
+                    .max()
+                    .unwrap_or(self.start_date);
+
                 //Update prices, these prices are not tradable
                 for (symbol, quote) in &quotes_response.quotes {
                     self.latest_quotes
@@ -233,256 +569,3395 @@ impl<C: UistClient> Update for UistBroker<C> {
                 }
 
                 for trade in tick_response.executed_trades {
-                    match trade.typ {
-                        //Force debit so we can end up with negative cash here
-                        TradeType::Buy => self.debit_force(&trade.value),
-                        TradeType::Sell => self.credit(&trade.value),
-                    };
-                    self.log.record::<Trade>(trade.clone());
-
-                    let curr_position = self.get_position_qty(&trade.symbol).unwrap_or(0.0);
-
-                    let updated = match trade.typ {
-                        TradeType::Buy => curr_position + trade.quantity,
-                        TradeType::Sell => curr_position - trade.quantity,
+                    let (cash_delta, qty_delta) = match trade.typ {
+                        TradeType::Buy => (-trade.value, trade.quantity),
+                        TradeType::Sell => (trade.value, -trade.quantity),
                     };
-                    self.update_holdings(&trade.symbol, updated);
-
-                    //Because the order has completed, we should be able to unwrap pending_orders safetly
-                    //If this fails then there must be an application bug and panic is required.
-                    let pending = self.pending_orders.get(&trade.symbol).unwrap_or(&0.0);
-
-                    let updated_pending = match trade.typ {
-                        TradeType::Buy => *pending - trade.quantity,
-                        TradeType::Sell => *pending + trade.quantity,
+                    let settlement = PendingSettlement {
+                        settle_date: add_trading_days(trade.date, self.settlement_lag).into(),
+                        cash_delta,
+                        qty_delta,
+                        symbol: trade.symbol.clone(),
                     };
-                    if updated_pending == 0.0 {
-                        self.pending_orders.remove(&trade.symbol);
+                    if self.settlement_lag == 0 {
+                        self.settle(&settlement);
                     } else {
-                        self.pending_orders.insert(trade.symbol, updated_pending);
+                        self.pending_settlements.push(settlement);
                     }
 
+                    let lot_ids = trade
+                        .order_id
+                        .and_then(|order_id| self.open_orders.get(&order_id))
+                        .and_then(|order| order.get_lot_ids().clone());
+                    match lot_ids {
+                        Some(lot_ids) => self.log.record_with_lots(trade.clone(), &lot_ids),
+                        None => self.log.record::<Trade>(trade.clone()),
+                    }
+                    self.publish(BrokerBusEvent::Recorded(UistRecordedEvent::TradeCompleted(
+                        trade.clone(),
+                    )));
+                    TRADES_EXECUTED_TOTAL.inc();
+
+                    if let Some(order_id) = trade.order_id {
+                        //Iceberg orders stay open on the exchange until the reserve is used
+                        if let Some(order) = self
+                            .open_orders
+                            .get_mut(&order_id)
+                            .filter(|order| order.get_reserve_qty() > 0.0)
+                        {
+                            order.replenish_from_reserve();
+                        } else if let Some(order) = self.open_orders.remove(&order_id) {
+                            self.cancel_oco_group_siblings(&order);
+                        }
+                        self.trigger_oto_secondary(order_id);
+                    }
                     self.last_seen_trade += 1;
                 }
+
+                for order in tick_response.inserted_orders {
+                    //Order ids are assigned on insert so after hours orders are matched on fields
+                    if let Some(pos) = self
+                        .after_hours_orders
+                        .iter()
+                        .position(|held| held == &order)
+                    {
+                        let held = self.after_hours_orders.remove(pos);
+                        self.reserve_pending(&held);
+                    }
+                    if let Some(order_id) = order.order_id {
+                        self.match_oto_primary(&order, order_id);
+                        self.open_orders.insert(order_id, order);
+                        self.open_order_dates.insert(order_id, inserted_at);
+                    }
+                }
+
+                for order in tick_response.expired_orders {
+                    info!("BROKER: Order {:?} expired on exchange", order.order_id);
+                    if let Some(order_id) = order.order_id {
+                        self.open_orders.remove(&order_id);
+                        self.oto_book.retain(|_id, (primary, _secondary)| {
+                            primary.order_id != Some(order_id)
+                        });
+                    }
+                    self.release_pending(&order);
+                }
+
+                for order in tick_response.after_hours_orders {
+                    info!(
+                        "BROKER: Order for {:?} held by exchange until session opens",
+                        order.get_symbol()
+                    );
+                    self.release_pending(&order);
+                    self.after_hours_orders.push(order);
+                }
+
+                //Only the quantity removed by the exchange is released, the rest of the order is
+                //still pending
+                for adjustment in tick_response.policy_adjustments {
+                    info!(
+                        "BROKER: Exchange policy adjusted order for {:?} from {:?} to {:?} shares",
+                        adjustment.order.get_symbol(),
+                        adjustment.requested_qty,
+                        adjustment.order.get_shares()
+                    );
+                    let mut removed = adjustment.order;
+                    removed.shares = adjustment.requested_qty - removed.shares;
+                    removed.reserve_qty = 0.0;
+                    self.release_pending(&removed);
+                }
+
+                self.settle_due();
+                self.apply_corporate_actions();
+                self.halted_symbols = tick_response.halted_symbols;
+                self.pay_funding();
+                self.accrue_interest();
             }
         }
+        let open_orders = &self.open_orders;
+        self.open_order_dates
+            .retain(|order_id, _date| open_orders.contains_key(order_id));
         //Previous step can cause negative cash balance so we have to rebalance here, this
         //is not instant so will never balance properly if the series is very volatile
         self.rebalance_cash();
+        //Slices are queued after the tick so each is sent to the exchange on the next check
+        self.advance_twap_orders();
+        self.advance_vwap_orders();
+        CASH_BALANCE.set(self.get_cash_balance());
+        #[cfg(feature = "checkpoint")]
+        self.save_checkpoint().await;
     }
 }
 
+#[cfg(feature = "checkpoint")]
 impl<C: UistClient> UistBroker<C> {
-    pub fn cost_basis(&self, symbol: &str) -> Option<f64> {
-        self.log.cost_basis(symbol)
+    async fn save_checkpoint(&mut self) {
+        let Some(manager) = &self.checkpoint_manager else {
+            return;
+        };
+        if !manager.is_due(self.tick_count) {
+            return;
+        }
+        let Ok(now) = self.http_client.now(self.backtest_id).await else {
+            return;
+        };
+        let checkpoint = BrokerCheckpoint {
+            date: now.now,
+            tick_count: self.tick_count,
+            cash: self.cash.clone(),
+            holdings: self.holdings.clone(),
+            log: self.log.clone(),
+            pending_settlements: self.pending_settlements.clone(),
+            last_seen_trade: self.last_seen_trade,
+            last_interest_date: self.last_interest_date,
+            last_corporate_action_date: self.last_corporate_action_date,
+        };
+        //Failing to write a checkpoint shouldn't stop the simulation
+        match manager.save(&checkpoint) {
+            Ok(()) => info!("BROKER: Saved checkpoint on tick {:?}", self.tick_count),
+            Err(err) => info!("BROKER: Unable to save checkpoint: {}", err),
+        }
     }
 
-    pub fn trades_between(&self, start: &i64, stop: &i64) -> Vec<Trade> {
-        self.log.trades_between(start, stop)
+    //Exchanges are ticked to the checkpoint date before state is restored. No orders have been
+    //sent so nothing executes and the broker doesn't need to see the intermediate ticks
+    async fn restore(&mut self, checkpoint: BrokerCheckpoint) {
+        while let Ok(now) = self.http_client.now(self.backtest_id).await {
+            if now.now >= checkpoint.date || !now.has_next {
+                break;
+            }
+            if self.http_client.tick(self.backtest_id).await.is_err() {
+                break;
+            }
+            for (client, backtest_id) in self.routed_exchanges.iter_mut() {
+                let _ = client.tick(*backtest_id).await;
+            }
+        }
+
+        if let Ok(quotes) = self.http_client.fetch_quotes(self.backtest_id).await {
+            for (symbol, quote) in quotes.quotes {
+                self.latest_quotes.insert(symbol, quote.into());
+            }
+        }
+        for (client, backtest_id) in self.routed_exchanges.iter_mut() {
+            if let Ok(quotes) = client.fetch_quotes(*backtest_id).await {
+                for (symbol, quote) in quotes.quotes {
+                    self.latest_quotes.insert(symbol, quote.into());
+                }
+            }
+        }
+
+        self.tick_count = checkpoint.tick_count;
+        self.cash = checkpoint.cash;
+        self.holdings = checkpoint.holdings;
+        self.log = checkpoint.log;
+        self.pending_settlements = checkpoint.pending_settlements;
+        self.last_seen_trade = checkpoint.last_seen_trade;
+        self.last_interest_date = checkpoint.last_interest_date;
+        self.last_corporate_action_date = checkpoint.last_corporate_action_date;
     }
 }
 
-impl<C: UistClient> Clock for UistBroker<C> {
-    fn now(&mut self) -> i64 {
-        let res = executor::block_on(self.http_client.now(self.backtest_id));
-        res.unwrap().now
+impl<C: UistClient> UistBroker<C> {
+    /// Receives every event published after subscribing. A receiver that falls more than the
+    /// bus capacity behind skips the oldest events.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<BrokerBusEvent> {
+        self.event_bus.subscribe()
     }
 
-    fn has_next(&mut self) -> bool {
-        let res = executor::block_on(self.http_client.now(self.backtest_id));
-        res.unwrap().has_next
+    //Sending only fails when there are no subscribers
+    fn publish(&self, event: BrokerBusEvent) {
+        let _ = self.event_bus.send(event);
     }
-}
 
-pub struct UistBrokerBuilder<C: UistClient> {
-    trade_costs: Vec<BrokerCost>,
-    client: Option<C>,
-    backtest_id: Option<BacktestId>,
-}
+    fn record_event(&mut self, event: UistRecordedEvent) {
+        self.publish(BrokerBusEvent::Recorded(event.clone()));
+        self.log.record(event);
+    }
 
-impl<C: UistClient> UistBrokerBuilder<C> {
-    pub async fn build(&mut self) -> UistBroker<C> {
-        if self.client.is_none() {
-            panic!("Cannot build broker without client");
+    //Every change to cash goes through here so that it is published
+    fn change_cash(&mut self, currency: &CurrencyCode, delta: f64) {
+        self.cash.credit(currency, delta);
+        if delta != 0.0 {
+            self.publish(BrokerBusEvent::CashChanged(currency.clone(), delta));
         }
+    }
 
-        let mut client = mem::take(&mut self.client).unwrap();
-        let backtest_id = mem::take(&mut self.backtest_id).unwrap();
-
-        //If we don't have quotes on first tick, we shouldn't error but we should expect every
-        //`DataSource` to provide a first tick
-        let mut first_quotes = HashMap::new();
-        let quote_response = client.fetch_quotes(backtest_id).await.unwrap();
-        for (symbol, quote) in &quote_response.quotes {
-            first_quotes.insert(symbol.clone(), quote.clone().into());
+    fn exchange_mut(&mut self, exchange: usize) -> (&mut C, BacktestId) {
+        if exchange == 0 {
+            (&mut self.http_client, self.backtest_id)
+        } else {
+            let (client, backtest_id) = &mut self.routed_exchanges[exchange - 1];
+            (client, *backtest_id)
         }
+    }
 
-        let holdings = PortfolioHoldings::new();
-        let pending_orders = PortfolioHoldings::new();
-        let log = UistBrokerLog::new();
+    //Every exchange assigns its own order ids so ids are interleaved to keep them unique within
+    //the broker, with a single exchange ids are unchanged
+    fn to_broker_order_id(&self, order_id: OrderId, exchange: usize) -> OrderId {
+        order_id * (self.routed_exchanges.len() as u64 + 1) + exchange as u64
+    }
 
-        UistBroker {
-            //Intialised as invalid so errors throw if client tries to run before init
-            holdings,
-            pending_orders,
-            cash: 0.0,
-            log,
-            last_seen_trade: 0,
-            trade_costs: self.trade_costs.clone(),
-            latest_quotes: first_quotes,
-            broker_state: BrokerState::Ready,
-            http_client: client,
-            backtest_id,
+    fn to_exchange_order_id(&self, order_id: OrderId) -> (usize, OrderId) {
+        let exchanges = self.routed_exchanges.len() as u64 + 1;
+        ((order_id % exchanges) as usize, order_id / exchanges)
+    }
+
+    fn remap_order_ids(&self, response: &mut TickResponse, exchange: usize) {
+        let orders = response
+            .inserted_orders
+            .iter_mut()
+            .chain(response.expired_orders.iter_mut())
+            .chain(response.after_hours_orders.iter_mut())
+            .chain(
+                response
+                    .policy_adjustments
+                    .iter_mut()
+                    .map(|adjustment| &mut adjustment.order),
+            );
+        for order in orders {
+            order.order_id = order
+                .order_id
+                .map(|order_id| self.to_broker_order_id(order_id, exchange));
+        }
+        for trade in response.executed_trades.iter_mut() {
+            trade.order_id = trade
+                .order_id
+                .map(|order_id| self.to_broker_order_id(order_id, exchange));
         }
     }
 
-    pub fn with_client(&mut self, client: C, backtest_id: BacktestId) -> &mut Self {
-        self.client = Some(client);
-        self.backtest_id = Some(backtest_id);
-        self
+    //Routed exchanges tick after the primary and their output is merged into the primary's
+    //response
+    async fn tick_routed_exchanges(
+        &mut self,
+        tick_response: &mut TickResponse,
+        quotes_response: &mut FetchQuotesResponse,
+    ) {
+        for exchange in 1..=self.routed_exchanges.len() {
+            let (client, backtest_id) = self.exchange_mut(exchange);
+            let Ok(mut response) = client.tick(backtest_id).await else {
+                continue;
+            };
+            if let Ok(quotes) = client.fetch_quotes(backtest_id).await {
+                quotes_response.quotes.extend(quotes.quotes);
+            }
+            self.remap_order_ids(&mut response, exchange);
+            tick_response
+                .executed_trades
+                .extend(response.executed_trades);
+            tick_response
+                .inserted_orders
+                .extend(response.inserted_orders);
+            tick_response.expired_orders.extend(response.expired_orders);
+            tick_response
+                .policy_adjustments
+                .extend(response.policy_adjustments);
+            tick_response.halted_symbols.extend(response.halted_symbols);
+            tick_response
+                .after_hours_orders
+                .extend(response.after_hours_orders);
+        }
     }
 
-    pub fn with_trade_costs(&mut self, trade_costs: Vec<BrokerCost>) -> &mut Self {
-        self.trade_costs = trade_costs;
-        self
+    /// Value of one unit of `currency` in the base currency, taken from the mid of the latest FX
+    /// quote.
+    fn fx_rate(&self, currency: &CurrencyCode) -> Option<f64> {
+        let base = self.cash.get_base_currency();
+        if currency == base {
+            return Some(1.0);
+        }
+        self.latest_quotes
+            .get(&currency.fx_symbol(base))
+            .map(|quote| (quote.bid + quote.ask) / 2.0)
     }
 
-    pub fn new() -> Self {
-        UistBrokerBuilder {
-            trade_costs: Vec::new(),
-            client: None,
-            backtest_id: None,
+    /// Converts `amount` of `from` into `to` at the rate implied by the latest FX quotes. Fails if
+    /// either currency has no FX quote or the `from` bucket can't cover the amount.
+    pub fn convert_currency(
+        &mut self,
+        from: CurrencyCode,
+        to: CurrencyCode,
+        amount: f64,
+    ) -> BrokerCashEvent {
+        let (Some(from_rate), Some(to_rate)) = (self.fx_rate(&from), self.fx_rate(&to)) else {
+            info!("BROKER: No FX quote to convert {} into {}", from, to);
+            return BrokerCashEvent::WithdrawFailure(amount);
+        };
+        if amount > self.cash.get(&from) {
+            info!(
+                "BROKER: Attempted to convert {:?} {} but only have {:?}",
+                amount,
+                from,
+                self.cash.get(&from)
+            );
+            return BrokerCashEvent::WithdrawFailure(amount);
         }
+
+        let rate = from_rate / to_rate;
+        let base = self.cash.get_base_currency();
+        //Both quotes come from the same tick so either date can be used
+        let date = [&from, &to]
+            .iter()
+            .filter(|currency| *currency != &base)
+            .find_map(|currency| self.latest_quotes.get(&currency.fx_symbol(base)))
+            .map(|quote| quote.date)
+            .unwrap_or_default();
+
+        info!(
+            "BROKER: Converted {:?} {} into {:?} {}",
+            amount,
+            from,
+            amount * rate,
+            to
+        );
+        self.change_cash(&from, -amount);
+        self.change_cash(&to, amount * rate);
+        self.record_event(UistRecordedEvent::FxConversion {
+            from,
+            to,
+            amount,
+            rate,
+            date,
+        });
+        BrokerCashEvent::WithdrawSuccess(amount)
     }
-}
 
-impl<C: UistClient> Default for UistBrokerBuilder<C> {
-    fn default() -> Self {
-        Self::new()
+    /// Resolves the notional into shares at the current ask, for buys, or bid, for sells, and
+    /// then sends through [SendOrder::send_order]. Trade costs are taken from the notional so the
+    /// value of the order including costs doesn't exceed the notional.
+    pub fn send_dollar_order(&mut self, order: DollarOrder) -> UistBrokerEvent {
+        let Some(quote) = self.latest_quotes.get(&order.symbol) else {
+            info!(
+                "BROKER: Unable to resolve dollar order for {:?}, no quote",
+                order.symbol
+            );
+            self.rejection_log.record_submitted();
+            let symbol = order.symbol.clone();
+            return self.reject(order.resolve(0.0), BrokerError::MissingQuote(symbol));
+        };
+        let price = if order.is_buy() { quote.ask } else { quote.bid };
+        let (net_budget, net_price) =
+            self.calc_trade_impact(&order.notional, &price, order.is_buy());
+        let shares = net_budget / net_price;
+        self.send_order(order.resolve(shares))
     }
-}
 
-#[derive(Clone, Debug)]
-pub enum UistRecordedEvent {
-    TradeCompleted(Trade),
-}
+    /// One [BrokerEvent::SymbolHalted] for every symbol halted by an exchange circuit breaker,
+    /// as of the last `check`.
+    pub fn get_halted_symbols(&self) -> Vec<UistBrokerEvent> {
+        self.halted_symbols
+            .iter()
+            .sorted()
+            .map(|(symbol, remaining)| UistBrokerEvent::SymbolHalted(symbol.clone(), *remaining))
+            .collect()
+    }
 
-impl From<Trade> for UistRecordedEvent {
-    fn from(value: Trade) -> Self {
-        UistRecordedEvent::TradeCompleted(value)
+    fn reject(&mut self, order: Order, error: BrokerError) -> UistBrokerEvent {
+        //Date of the most recent quote, the exchange clock can't be queried without blocking
+        let date = self
+            .latest_quotes
+            .values()
+            .map(|quote| quote.date)
+            .max()
+            .unwrap_or_default();
+        self.rejection_log.record_rejection(date.into(), error);
+        UistBrokerEvent::OrderInvalid(order)
     }
-}
 
-//Records events generated by brokers. Used for internal calculations but is public for tax
-//calculations.
-#[doc(hidden)]
-#[derive(Clone, Debug)]
-pub struct UistBrokerLog {
-    log: Vec<UistRecordedEvent>,
-}
+    pub fn get_order_rejection_rate(&self) -> f64 {
+        self.rejection_log.rejection_rate()
+    }
 
-impl UistBrokerLog {
-    pub fn record<E: Into<UistRecordedEvent>>(&mut self, event: E) {
-        let brokerevent: UistRecordedEvent = event.into();
-        self.log.push(brokerevent);
+    pub fn get_rejection_reasons_summary(&self) -> HashMap<String, usize> {
+        self.rejection_log.reasons_summary()
     }
 
-    pub fn trades(&self) -> Vec<Trade> {
-        let mut trades = Vec::new();
-        for event in &self.log {
-            let UistRecordedEvent::TradeCompleted(trade) = event;
-            trades.push(trade.clone());
-        }
-        trades
+    pub fn get_rejection_log_between(
+        &self,
+        start: DateTime,
+        end: DateTime,
+    ) -> Vec<(DateTime, BrokerError)> {
+        self.rejection_log.between(start, end)
+    }
+
+    pub fn cost_basis(&self, symbol: &str) -> Option<f64> {
+        self.log.cost_basis(symbol)
     }
 
     pub fn trades_between(&self, start: &i64, stop: &i64) -> Vec<Trade> {
-        let trades = self.trades();
-        trades
-            .iter()
-            .filter(|v| v.date >= *DateTime::from(*start) && v.date <= *DateTime::from(*stop))
-            .cloned()
-            .collect_vec()
+        self.log.trades_between(start, stop)
     }
 
-    pub fn cost_basis(&self, symbol: &str) -> Option<f64> {
-        let mut cum_qty = 0.0;
-        let mut cum_val = f64::default();
-        for event in &self.log {
-            let UistRecordedEvent::TradeCompleted(trade) = event;
-            if trade.symbol.eq(symbol) {
-                match trade.typ {
-                    TradeType::Buy => {
-                        cum_qty += trade.quantity;
-                        cum_val += trade.value;
-                    }
-                    TradeType::Sell => {
-                        cum_qty -= trade.quantity;
-                        cum_val -= trade.value;
-                    }
-                }
-                //reset the value if we are back to zero
-                if (cum_qty).eq(&0.0) {
-                    cum_val = f64::default();
+    pub fn realized_gains_between(&self, start: &i64, end: &i64) -> Vec<RealizedGain> {
+        self.log.realized_gains_between(start, end)
+    }
+
+    pub fn query_open_lots(&self, symbol: &str) -> Vec<LotInfo> {
+        self.log.query_open_lots(symbol)
+    }
+
+    fn client_has_lots(&self, order: &Order) -> Result<(), BrokerError> {
+        if let Some(lot_ids) = order.get_lot_ids() {
+            let open = self.query_open_lots(order.get_symbol());
+            for lot_id in lot_ids {
+                if !open.iter().any(|lot| lot.lot_id == *lot_id) {
+                    return Err(BrokerError::LotNotFound(*lot_id));
                 }
             }
         }
-        if (cum_qty).eq(&0.0) {
-            return None;
+        Ok(())
+    }
+
+    pub fn get_trade_size_distribution(&self) -> TradeDistribution {
+        self.log.get_trade_size_distribution()
+    }
+
+    fn apply_corporate_actions(&mut self) {
+        let Some(source) = &self.corporate_action_source else {
+            return;
+        };
+        let Some(now) = self.latest_quotes.values().map(|quote| quote.date).max() else {
+            return;
+        };
+        let last = mem::replace(&mut self.last_corporate_action_date, now);
+        let is_due = |date: i64| date > last && date <= now;
+
+        let splits = source
+            .get_splits()
+            .into_iter()
+            .filter(|split| is_due(split.date))
+            .collect_vec();
+        let acquisitions = source
+            .get_cash_acquisitions()
+            .into_iter()
+            .filter(|acquisition| is_due(acquisition.date))
+            .collect_vec();
+        let spinoffs = source
+            .get_spinoffs()
+            .into_iter()
+            .filter(|spinoff| is_due(spinoff.date))
+            .collect_vec();
+
+        for split in splits {
+            self.apply_split(split);
+        }
+        for acquisition in acquisitions {
+            self.apply_cash_acquisition(acquisition);
+        }
+        for spinoff in spinoffs {
+            self.apply_spinoff(spinoff);
+        }
+    }
+
+    //The new position is valued once the exchange has quotes for it
+    fn apply_spinoff(&mut self, spinoff: SpinOff) {
+        let parent_qty = self.get_position_qty(&spinoff.parent_symbol).unwrap_or(0.0);
+        let spinco_qty = parent_qty * spinoff.ratio;
+        info!(
+            "BROKER: Receiving {:?} shares of {:?} from spin-off of {:?}",
+            spinco_qty, spinoff.spinco_symbol, spinoff.parent_symbol
+        );
+        if spinco_qty != 0.0 {
+            let curr_position = self.get_position_qty(&spinoff.spinco_symbol).unwrap_or(0.0);
+            self.update_holdings(&spinoff.spinco_symbol, curr_position + spinco_qty);
+        }
+        self.log.apply_spinoff(
+            &spinoff.parent_symbol,
+            &spinoff.spinco_symbol,
+            spinoff.ratio,
+            spinoff.cost_basis_allocation,
+        );
+        self.record_event(UistRecordedEvent::Spinoff {
+            parent_symbol: spinoff.parent_symbol,
+            spinco_symbol: spinoff.spinco_symbol,
+            quantity: spinco_qty,
+            date: spinoff.date,
+        });
+    }
+
+    //Orders for the target can't execute after the acquisition so are cancelled, including orders
+    //not yet sent to the exchange
+    fn apply_cash_acquisition(&mut self, acquisition: CashAcquisition) {
+        let symbol = acquisition.target_symbol;
+        let qty = self.get_position_qty(&symbol).unwrap_or(0.0);
+        let proceeds = qty * acquisition.price_per_share;
+        info!(
+            "BROKER: Closing {:?} shares of {:?} for cash acquisition, proceeds {:?}",
+            qty, symbol, proceeds
+        );
+
+        if let Some(currency) = self.symbol_currencies.get(&symbol) {
+            self.change_cash(&currency.clone(), proceeds);
+        } else if proceeds < 0.0 {
+            self.debit_force(&-proceeds);
+        } else {
+            self.credit(&proceeds);
+        }
+        self.update_holdings(&symbol, 0.0);
+
+        let cancelled = self
+            .open_orders
+            .iter()
+            .filter(|(_id, order)| order.get_symbol() == symbol)
+            .map(|(id, _order)| *id)
+            .collect_vec();
+        for order_id in cancelled {
+            let order = self.open_orders.remove(&order_id).unwrap();
+            self.unsent_deletes.push(order_id);
+            self.release_pending(&order);
+        }
+        let mut unsent = Vec::new();
+        for (_tick, orders) in self.pending_order_queue.iter_mut() {
+            let (target, rest): (Vec<_>, Vec<_>) = mem::take(orders)
+                .into_iter()
+                .partition(|order| order.get_symbol() == symbol);
+            *orders = rest;
+            unsent.extend(target);
+        }
+        for order in unsent {
+            self.release_pending(&order);
+        }
+
+        self.log
+            .close_lots(&symbol, acquisition.price_per_share, acquisition.date);
+        self.record_event(UistRecordedEvent::CashAcquisition {
+            symbol,
+            proceeds,
+            date: acquisition.date,
+        });
+    }
+
+    //Orders already on the exchange are not adjusted
+    fn apply_split(&mut self, split: Split) {
+        info!(
+            "BROKER: Applying split of {:?} to {:?}",
+            split.ratio, split.symbol
+        );
+        if let Some(qty) = self.get_position_qty(&split.symbol) {
+            self.update_holdings(&split.symbol, qty * split.ratio);
+        }
+        self.log.apply_split(&split.symbol, split.ratio);
+        self.record_event(UistRecordedEvent::Split {
+            symbol: split.symbol,
+            ratio: split.ratio,
+            date: split.date,
+        });
+    }
+
+    //Charged on positions after trades have been reconciled, valued at the latest mid price
+    fn pay_funding(&mut self) {
+        if self.funding_rate_source.is_none() {
+            return;
+        }
+
+        let mut payments = Vec::new();
+        for symbol in self.get_positions().iter().sorted() {
+            let qty = self.get_position_qty(symbol).unwrap_or(0.0);
+            if let Some(quote) = self.latest_quotes.get(symbol) {
+                let rate = self
+                    .funding_rate_source
+                    .as_ref()
+                    .unwrap()
+                    .get_funding_rate(symbol, quote.date.into());
+                let mid = (quote.bid + quote.ask) / 2.0;
+                let amount = qty * mid * rate;
+                if amount != 0.0 {
+                    payments.push((symbol.clone(), amount, quote.date));
+                }
+            }
+        }
+
+        for (symbol, amount, date) in payments {
+            info!("BROKER: Paying funding of {:?} on {:?}", amount, symbol);
+            if amount > 0.0 {
+                self.debit_force(&amount);
+            } else {
+                self.credit(&-amount);
+            }
+            self.record_event(UistRecordedEvent::FundingPayment(symbol, amount, date));
+        }
+    }
+
+    fn accrue_interest(&mut self) {
+        let Some(date) = self.latest_quotes.values().map(|quote| quote.date).max() else {
+            return;
+        };
+        let last = self.last_interest_date.replace(date).unwrap_or(date);
+        let amount = self
+            .interest_model
+            .accrual(self.get_cash_balance(), date - last);
+        if amount > 0.0 {
+            info!("BROKER: Accrued interest of {:?}", amount);
+            self.credit(&amount);
+            self.record_event(UistRecordedEvent::InterestPayment { amount, date });
+        }
+    }
+
+    /// Total funding paid, negative if the broker has received more funding than it has paid.
+    pub fn get_funding_rate_cost(&self) -> f64 {
+        self.log
+            .funding_payments()
+            .iter()
+            .map(|(_, amount, _)| amount)
+            .sum()
+    }
+
+    //Absolute value of holdings and pending orders in symbol, valued at the mid
+    fn gross_exposure(&self, symbol: &str) -> f64 {
+        let qty = self.holdings.get(symbol).unwrap_or(&0.0)
+            + self.pending_orders.get(symbol).unwrap_or(&0.0);
+        match self.latest_quotes.get(symbol) {
+            Some(quote) => qty.abs() * (quote.bid + quote.ask) / 2.0,
+            None => 0.0,
+        }
+    }
+
+    //Returns the trimmed shares if the order has to be reduced to fit within constraints. Every
+    //limit is converted into a maximum absolute position in the order's symbol at `price`, other
+    //positions, including pending orders, are held constant
+    fn constrain_order(&self, order: &Order, price: &f64) -> Result<Option<f64>, BrokerError> {
+        let Some(constraints) = &self.constraints else {
+            return Ok(None);
+        };
+        let total_value = self.get_total_value();
+        if total_value <= 0.0 || *price <= 0.0 {
+            return Ok(None);
+        }
+
+        let symbol = order.get_symbol();
+        let effect = Self::order_effect(order);
+        let current = self.holdings.get(symbol).unwrap_or(&0.0)
+            + self.pending_orders.get(symbol).unwrap_or(&0.0);
+        let other_symbols = self
+            .holdings
+            .keys()
+            .chain(self.pending_orders.keys())
+            .filter(|other| other.as_str() != symbol)
+            .unique()
+            .collect_vec();
+
+        let mut max_position = f64::INFINITY;
+        if let Some(pct) = constraints.max_position_pct.get(symbol) {
+            max_position = max_position.min(pct * total_value / price);
+        }
+        if let Some(pct) = constraints
+            .sectors
+            .get(symbol)
+            .and_then(|sector| constraints.max_sector_pct.get(sector))
+        {
+            let sector = constraints.sectors.get(symbol);
+            let sector_exposure: f64 = other_symbols
+                .iter()
+                .filter(|other| constraints.sectors.get(other.as_str()) == sector)
+                .map(|other| self.gross_exposure(other))
+                .sum();
+            max_position = max_position.min((pct * total_value - sector_exposure) / price);
+        }
+        if constraints.max_gross_leverage.is_finite() {
+            let gross_exposure: f64 = other_symbols
+                .iter()
+                .map(|other| self.gross_exposure(other))
+                .sum();
+            max_position = max_position
+                .min((constraints.max_gross_leverage * total_value - gross_exposure) / price);
+        }
+
+        let mut max_effect = if effect > 0.0 {
+            max_position - current
+        } else {
+            current + max_position
+        };
+        if effect > 0.0 {
+            let spendable = self.get_cash_balance() - constraints.min_cash_pct * total_value;
+            max_effect = max_effect.min(spendable / price);
+        }
+
+        if effect.abs() <= max_effect {
+            return Ok(None);
+        }
+        //Reserve of iceberg orders is kept, only the visible slice is reduced
+        let shares = max_effect.floor() - order.get_reserve_qty();
+        if shares <= 0.0 {
+            return Err(BrokerError::ConstraintBreached(symbol.to_string()));
+        }
+        info!(
+            "BROKER: Trimming {:?} order for {:?} shares of {:?} to {:?} shares to fit constraints",
+            order.get_order_type(),
+            order.get_shares(),
+            symbol,
+            shares
+        );
+        Ok(Some(shares))
+    }
+
+    //This is the change in holdings expected if the order executes
+    fn order_effect(order: &Order) -> f64 {
+        let shares = order.get_shares() + order.get_reserve_qty();
+        match order.get_order_type() {
+            OrderType::MarketBuy
+            | OrderType::LimitBuy
+            | OrderType::StopBuy
+            | OrderType::TrailingStopBuy => shares,
+            OrderType::MarketSell
+            | OrderType::LimitSell
+            | OrderType::StopSell
+            | OrderType::TrailingStopSell => -shares,
+        }
+    }
+
+    /// Orders that are in the exchange orderbook, orders are only known to the broker after the
+    /// `check` that inserts them into the exchange.
+    pub fn get_open_orders(&self) -> Vec<Order> {
+        self.open_orders
+            .iter()
+            .sorted_by_key(|(order_id, _order)| **order_id)
+            .map(|(_order_id, order)| order.clone())
+            .collect_vec()
+    }
+
+    /// Open orders with the date they entered the orderbook. Orders queued by `send_order` are
+    /// only included once they have been inserted, so a strategy checking for an existing order
+    /// before sending should also check `get_pending_orders`.
+    pub fn pending_orders(&self) -> Vec<PendingOrderInfo> {
+        self.get_open_orders()
+            .into_iter()
+            .filter_map(|order| {
+                let order_id = order.order_id?;
+                let submitted_at = self
+                    .open_order_dates
+                    .get(&order_id)
+                    .copied()
+                    .unwrap_or(self.start_date);
+                Some(PendingOrderInfo {
+                    order_id,
+                    symbol: order.symbol,
+                    order_type: order.order_type,
+                    qty: order.shares,
+                    price: order.price,
+                    submitted_at: submitted_at.into(),
+                })
+            })
+            .collect_vec()
+    }
+
+    /// Deletes an open order and sends a new order. Both are sent to the exchange on the next
+    /// `check` before the exchange ticks so the old order cannot execute after the new order is
+    /// inserted.
+    ///
+    /// If the old order is unknown or the new order is invalid then neither change is made.
+    pub fn cancel_and_replace(
+        &mut self,
+        old_order_id: OrderId,
+        new_order: Order,
+    ) -> UistBrokerEvent {
+        let old_order = match self.open_orders.get(&old_order_id) {
+            Some(order) => order.clone(),
+            None => {
+                info!(
+                    "BROKER: Unable to replace order {:?} as it is not open on exchange",
+                    old_order_id
+                );
+                return UistBrokerEvent::OrderInvalid(new_order);
+            }
+        };
+
+        let res = self.send_order(new_order);
+        if let UistBrokerEvent::OrderSentToExchange(_) | UistBrokerEvent::OrderTrimmed(..) = res {
+            info!("BROKER: Deleting order {:?} from exchange", old_order_id);
+            self.unsent_deletes.push(old_order_id);
+            self.open_orders.remove(&old_order_id);
+            self.release_pending(&old_order);
+        }
+        res
+    }
+
+    /// Deletes an open order from the exchange on the next `check`, before the exchange ticks.
+    /// Returns false if the order isn't open, orders queued by `send_order` can only be cancelled
+    /// once they have been inserted.
+    pub fn cancel_order(&mut self, order_id: OrderId) -> bool {
+        match self.open_orders.remove(&order_id) {
+            Some(order) => {
+                info!("BROKER: Deleting order {:?} from exchange", order_id);
+                self.unsent_deletes.push(order_id);
+                self.release_pending(&order);
+                true
+            }
+            None => {
+                info!(
+                    "BROKER: Unable to cancel order {:?} as it is not open on exchange",
+                    order_id
+                );
+                false
+            }
+        }
+    }
+
+    //Exchange removes orders in the same group on the tick that one executes
+    fn cancel_oco_group_siblings(&mut self, executed: &Order) {
+        if let Some(group_id) = executed.get_oco_group_id() {
+            let siblings = self
+                .open_orders
+                .iter()
+                .filter(|(_id, order)| order.get_oco_group_id() == &Some(*group_id))
+                .map(|(id, _order)| *id)
+                .collect_vec();
+            for order_id in siblings {
+                let order = self.open_orders.remove(&order_id).unwrap();
+                self.release_pending(&order);
+            }
+        }
+    }
+
+    /// Moves cash and shares for a trade. Until a trade settles the shares are still counted as
+    /// pending.
+    fn settle(&mut self, settlement: &PendingSettlement) {
+        let symbol = &settlement.symbol;
+        if let Some(currency) = self.symbol_currencies.get(symbol) {
+            self.change_cash(&currency.clone(), settlement.cash_delta);
+        } else if settlement.cash_delta < 0.0 {
+            //Force debit so we can end up with negative cash here
+            self.debit_force(&-settlement.cash_delta);
+        } else {
+            self.credit(&settlement.cash_delta);
+        }
+
+        let curr_position = self.get_position_qty(symbol).unwrap_or(0.0);
+        self.update_holdings(symbol, curr_position + settlement.qty_delta);
+
+        let updated_pending =
+            self.pending_orders.get(symbol).unwrap_or(&0.0) - settlement.qty_delta;
+        if updated_pending == 0.0 {
+            self.pending_orders.remove(symbol);
+        } else {
+            self.pending_orders.insert(symbol.clone(), updated_pending);
+        }
+    }
+
+    fn settle_due(&mut self) {
+        let Some(now) = self.latest_quotes.values().map(|quote| quote.date).max() else {
+            return;
+        };
+        let (due, pending): (Vec<_>, Vec<_>) = mem::take(&mut self.pending_settlements)
+            .into_iter()
+            .partition(|settlement| *settlement.settle_date <= now);
+        self.pending_settlements = pending;
+        for settlement in due {
+            self.settle(&settlement);
+        }
+    }
+
+    /// Cash balance excluding trades that haven't settled.
+    pub fn get_settled_cash(&self) -> f64 {
+        self.get_cash_balance()
+    }
+
+    pub fn get_pending_settlements(&self) -> &[PendingSettlement] {
+        &self.pending_settlements
+    }
+
+    //Adds the effect of an order to pending orders, used when an order held until the session
+    //opens reaches the exchange
+    fn reserve_pending(&mut self, order: &Order) {
+        let symbol = order.get_symbol();
+        let pending = self.pending_orders.get(symbol).unwrap_or(&0.0) + Self::order_effect(order);
+        if pending == 0.0 {
+            self.pending_orders.remove(symbol);
+        } else {
+            self.pending_orders.insert(symbol.to_string(), pending);
+        }
+    }
+
+    //Reverses the effect of an order that will not execute on pending orders
+    fn release_pending(&mut self, order: &Order) {
+        let symbol = order.get_symbol();
+        let pending = self.pending_orders.get(symbol).unwrap_or(&0.0) - Self::order_effect(order);
+        if pending == 0.0 {
+            self.pending_orders.remove(symbol);
+        } else {
+            self.pending_orders.insert(symbol.to_string(), pending);
+        }
+    }
+
+    //Orders don't carry a client id so the exchange order is matched to the first primary with the
+    //same fields that hasn't been matched already
+    fn match_oto_primary(&mut self, inserted: &Order, order_id: OrderId) {
+        let matched = self
+            .oto_book
+            .iter()
+            .filter(|(_id, (primary, _secondary))| {
+                primary.order_id.is_none()
+                    && primary.get_symbol() == inserted.get_symbol()
+                    && primary.get_shares() == inserted.get_shares()
+                    && primary.get_order_type() == inserted.get_order_type()
+                    && primary.get_price() == inserted.get_price()
+            })
+            .map(|(id, _pair)| *id)
+            .min();
+        if let Some(id) = matched {
+            if let Some((primary, _secondary)) = self.oto_book.get_mut(&id) {
+                primary.order_id = Some(order_id);
+            }
+        }
+    }
+
+    fn trigger_oto_secondary(&mut self, order_id: OrderId) {
+        let triggered = self
+            .oto_book
+            .iter()
+            .find(|(_id, (primary, _secondary))| primary.order_id == Some(order_id))
+            .map(|(id, _pair)| *id);
+        if let Some(id) = triggered {
+            let (_primary, secondary) = self.oto_book.remove(&id).unwrap();
+            info!(
+                "BROKER: Primary order of pair {:?} executed, sending secondary order",
+                id
+            );
+            self.send_order(secondary);
+        }
+    }
+
+    /// Splits the order into `num_periods` market orders of `ceil(shares / num_periods)`, the
+    /// last slice being the remainder. The first slice is sent now and one more on every `check`.
+    /// Remaining slices are cancelled if a slice can't be sent, buys are also cancelled if cash
+    /// doesn't cover the slice and unexecuted orders in the symbol.
+    ///
+    /// Returns [BrokerEvent::TwapScheduled] once the first slice is sent, or
+    /// [BrokerEvent::TwapCompleted] if that slice is the whole order. The completion or
+    /// cancellation of slices sent on `check` is returned by `get_twap_events`.
+    pub fn send_twap_order(&mut self, order: Order, num_periods: usize) -> UistBrokerEvent {
+        if num_periods == 0 || order.get_shares() == 0.0 {
+            info!("BROKER: Unable to schedule TWAP order with no periods or shares");
+            return UistBrokerEvent::OrderInvalid(order);
+        }
+        let mut twap = TwapSlice {
+            order: order.clone(),
+            slice_qty: (order.get_shares() / num_periods as f64).ceil(),
+            remaining_qty: order.get_shares(),
+        };
+        let qty = twap.slice_qty.min(twap.remaining_qty);
+        if !self.send_slice(&twap.order, qty) {
+            return UistBrokerEvent::TwapCancelled(order);
+        }
+        twap.remaining_qty -= qty;
+        if twap.remaining_qty <= 0.0 {
+            return UistBrokerEvent::TwapCompleted(order);
+        }
+        self.pending_twap_orders.push(twap);
+        UistBrokerEvent::TwapScheduled(order)
+    }
+
+    //Sends a market order for `qty` on the same side as `parent`, returns false if the remaining
+    //slices should be cancelled
+    fn send_slice(&mut self, parent: &Order, qty: f64) -> bool {
+        let symbol = parent.get_symbol();
+        let is_buy = Self::order_effect(parent) > 0.0;
+        if is_buy {
+            //Cash check on send_order doesn't include orders that haven't executed
+            let pending = self.pending_orders.get(symbol).unwrap_or(&0.0).max(0.0);
+            let ask = self.get_quote(symbol).map(|quote| quote.ask).unwrap_or(0.0);
+            if self.get_cash_balance() < (pending + qty) * ask {
+                info!(
+                    "BROKER: Insufficient cash to send slice of {:?} shares of {:?}",
+                    qty, symbol
+                );
+                return false;
+            }
+        }
+
+        let slice = if is_buy {
+            Order::market_buy(symbol, qty)
+        } else {
+            Order::market_sell(symbol, qty)
+        };
+        matches!(
+            self.send_order(slice),
+            UistBrokerEvent::OrderSentToExchange(_) | UistBrokerEvent::OrderTrimmed(..)
+        )
+    }
+
+    fn advance_twap_orders(&mut self) {
+        self.twap_events.clear();
+        for mut twap in mem::take(&mut self.pending_twap_orders) {
+            let qty = twap.slice_qty.min(twap.remaining_qty);
+            if !self.send_slice(&twap.order, qty) {
+                info!(
+                    "BROKER: Cancelling TWAP order for {:?}, {:?} shares unsent",
+                    twap.order.get_symbol(),
+                    twap.remaining_qty
+                );
+                self.twap_events
+                    .push(UistBrokerEvent::TwapCancelled(twap.order));
+                continue;
+            }
+            twap.remaining_qty -= qty;
+            if twap.remaining_qty > 0.0 {
+                self.pending_twap_orders.push(twap);
+            } else {
+                self.twap_events
+                    .push(UistBrokerEvent::TwapCompleted(twap.order));
+            }
+        }
+    }
+
+    /// One [BrokerEvent::TwapCompleted] for every TWAP order whose last slice was sent, and one
+    /// [BrokerEvent::TwapCancelled] for every TWAP order cancelled, as of the last `check`.
+    pub fn get_twap_events(&self) -> Vec<UistBrokerEvent> {
+        self.twap_events.clone()
+    }
+
+    /// Schedules the order to be sent as market orders of `participation_rate` multiplied by the
+    /// volume of the latest quote, starting on the next `check`. Periods without volume are
+    /// skipped. Remaining slices are cancelled if a slice can't be sent.
+    pub fn send_vwap_order(&mut self, order: Order, participation_rate: f64) -> UistBrokerEvent {
+        if participation_rate <= 0.0 || order.get_shares() == 0.0 {
+            info!("BROKER: Unable to schedule VWAP order with no participation or shares");
+            return UistBrokerEvent::OrderInvalid(order);
+        }
+        self.pending_vwap_orders.push(VwapSlice {
+            order: order.clone(),
+            participation_rate,
+            remaining_qty: order.get_shares(),
+        });
+        UistBrokerEvent::OrderCreated(order)
+    }
+
+    fn advance_vwap_orders(&mut self) {
+        for mut vwap in mem::take(&mut self.pending_vwap_orders) {
+            let volume = self
+                .get_quote(vwap.order.get_symbol())
+                .and_then(|quote| quote.volume)
+                .unwrap_or(0.0);
+            if volume <= 0.0 {
+                self.pending_vwap_orders.push(vwap);
+                continue;
+            }
+            let qty = (vwap.participation_rate * volume).min(vwap.remaining_qty);
+            if !self.send_slice(&vwap.order, qty) {
+                info!(
+                    "BROKER: Cancelling VWAP order for {:?}, {:?} shares unsent",
+                    vwap.order.get_symbol(),
+                    vwap.remaining_qty
+                );
+                continue;
+            }
+            vwap.remaining_qty -= qty;
+            if vwap.remaining_qty > 0.0 {
+                self.pending_vwap_orders.push(vwap);
+            }
         }
-        Some(cum_val / cum_qty)
     }
+
+    /// VWAP orders that have slices left to send.
+    pub fn get_vwap_orders(&self) -> Vec<VwapSlice> {
+        self.pending_vwap_orders.clone()
+    }
+
+    /// TWAP orders that have slices left to send.
+    pub fn get_twap_orders(&self) -> Vec<TwapSlice> {
+        self.pending_twap_orders.clone()
+    }
+
+    /// Pairs whose primary order has not executed yet.
+    pub fn get_oto_orders(&self) -> Vec<(Order, Order)> {
+        self.oto_book
+            .iter()
+            .sorted_by_key(|(id, _pair)| **id)
+            .map(|(_id, pair)| pair.clone())
+            .collect_vec()
+    }
+
+    /// Average effective spread, 2 * |execution price - mid price|, of trades in each symbol.
+    pub fn get_effective_spread(&self) -> HashMap<String, f64> {
+        self.effective_spread_tracker
+            .iter()
+            .map(|(symbol, spreads)| {
+                (
+                    symbol.clone(),
+                    spreads.iter().sum::<f64>() / spreads.len() as f64,
+                )
+            })
+            .collect()
+    }
+}
+
+impl<C: UistClient> Clock for UistBroker<C> {
+    fn now(&mut self) -> i64 {
+        let res = executor::block_on(self.http_client.now(self.backtest_id));
+        res.unwrap().now
+    }
+
+    fn has_next(&mut self) -> bool {
+        let res = executor::block_on(self.http_client.now(self.backtest_id));
+        res.unwrap().has_next
+    }
+
+    fn start(&self) -> i64 {
+        self.start_date
+    }
+}
+
+pub struct UistBrokerBuilder<C: UistClient> {
+    trade_costs: Vec<BrokerCost>,
+    client: Option<C>,
+    backtest_id: Option<BacktestId>,
+    routed_exchanges: Vec<(C, BacktestId, Vec<String>)>,
+    funding_rate_source: Option<Box<dyn FundingRateSource>>,
+    corporate_action_source: Option<Box<dyn CorporateActionSource>>,
+    interest_model: InterestModel,
+    min_cash_balance: f64,
+    short_margin_requirement: Option<f64>,
+    execution_delay_ticks: usize,
+    settlement_lag: u8,
+    cost_basis_method: CostBasisMethod,
+    base_currency: CurrencyCode,
+    additional_currencies: Vec<(CurrencyCode, f64)>,
+    symbol_currencies: HashMap<String, CurrencyCode>,
+    constraints: Option<PortfolioConstraints>,
+    #[cfg(feature = "checkpoint")]
+    checkpoint_manager: Option<CheckpointManager>,
+    event_bus_capacity: usize,
 }
 
-impl UistBrokerLog {
-    pub fn new() -> Self {
-        UistBrokerLog { log: Vec::new() }
-    }
-}
+impl<C: UistClient> UistBrokerBuilder<C> {
+    pub async fn build(&mut self) -> UistBroker<C> {
+        if self.client.is_none() {
+            panic!("Cannot build broker without client");
+        }
+
+        let mut client = mem::take(&mut self.client).unwrap();
+        let backtest_id = mem::take(&mut self.backtest_id).unwrap();
+
+        //If we don't have quotes on first tick, we shouldn't error but we should expect every
+        //`DataSource` to provide a first tick
+        let mut first_quotes = HashMap::new();
+        let quote_response = client.fetch_quotes(backtest_id).await.unwrap();
+        for (symbol, quote) in &quote_response.quotes {
+            first_quotes.insert(symbol.clone(), quote.clone().into());
+        }
+
+        let mut routed_exchanges = Vec::new();
+        let mut routing_table = HashMap::new();
+        for (mut routed_client, routed_backtest_id, symbols) in
+            mem::take(&mut self.routed_exchanges)
+        {
+            let quote_response = routed_client
+                .fetch_quotes(routed_backtest_id)
+                .await
+                .unwrap();
+            for (symbol, quote) in &quote_response.quotes {
+                first_quotes.insert(symbol.clone(), quote.clone().into());
+            }
+            routed_exchanges.push((routed_client, routed_backtest_id));
+            for symbol in symbols {
+                routing_table.insert(symbol, routed_exchanges.len());
+            }
+        }
+
+        let start_date = client.now(backtest_id).await.unwrap().now;
+
+        let holdings = PortfolioHoldings::new();
+        let pending_orders = PortfolioHoldings::new();
+        let log = UistBrokerLog::with_cost_basis_method(self.cost_basis_method);
+
+        let mut cash = MultiCurrencyCash::new(self.base_currency.clone());
+        for (currency, initial_amount) in &self.additional_currencies {
+            cash.credit(currency, *initial_amount);
+        }
+
+        UistBroker {
+            //Intialised as invalid so errors throw if client tries to run before init
+            holdings,
+            pending_orders,
+            cash,
+            symbol_currencies: mem::take(&mut self.symbol_currencies),
+            log,
+            last_seen_trade: 0,
+            trade_costs: self.trade_costs.clone(),
+            latest_quotes: first_quotes,
+            broker_state: BrokerState::Ready,
+            http_client: client,
+            backtest_id,
+            routed_exchanges,
+            routing_table,
+            pending_order_queue: VecDeque::new(),
+            execution_delay_ticks: self.execution_delay_ticks,
+            tick_count: 0,
+            open_orders: HashMap::new(),
+            open_order_dates: HashMap::new(),
+            unsent_deletes: Vec::new(),
+            effective_spread_tracker: HashMap::new(),
+            funding_rate_source: mem::take(&mut self.funding_rate_source),
+            corporate_action_source: mem::take(&mut self.corporate_action_source),
+            last_corporate_action_date: start_date,
+            interest_model: self.interest_model.clone(),
+            last_interest_date: None,
+            min_cash_balance: self.min_cash_balance,
+            short_margin_requirement: self.short_margin_requirement,
+            start_date,
+            rejection_log: OrderRejectionLog::new(),
+            oto_book: HashMap::new(),
+            last_oto_id: 0,
+            pending_twap_orders: Vec::new(),
+            twap_events: Vec::new(),
+            pending_vwap_orders: Vec::new(),
+            halted_symbols: HashMap::new(),
+            after_hours_orders: Vec::new(),
+            settlement_lag: self.settlement_lag,
+            pending_settlements: Vec::new(),
+            constraints: self.constraints.clone(),
+            #[cfg(feature = "checkpoint")]
+            checkpoint_manager: self.checkpoint_manager.take(),
+            event_bus: broadcast::channel(self.event_bus_capacity).0,
+        }
+    }
+
+    /// Builds the broker and restores the state saved at `path`. The builder must be given a
+    /// client for the same dataset, and the same settings, as the broker that saved the
+    /// checkpoint. Checkpoints continue to be written if a manager has been set.
+    #[cfg(feature = "checkpoint")]
+    pub async fn resume_from_checkpoint(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<UistBroker<C>, CheckpointError> {
+        let checkpoint = CheckpointManager::load(path)?;
+        let mut brkr = self.build().await;
+        brkr.restore(checkpoint).await;
+        Ok(brkr)
+    }
+
+    #[cfg(feature = "checkpoint")]
+    pub fn with_checkpoint_manager(&mut self, manager: CheckpointManager) -> &mut Self {
+        self.checkpoint_manager = Some(manager);
+        self
+    }
+
+    pub fn with_min_cash_balance(&mut self, min_cash: f64) -> &mut Self {
+        self.min_cash_balance = min_cash;
+        self
+    }
+
+    /// Number of events held for subscribers that haven't received them, defaults to 1024. Must be
+    /// greater than zero.
+    pub fn with_event_bus_capacity(&mut self, n: usize) -> &mut Self {
+        self.event_bus_capacity = n;
+        self
+    }
+
+    /// Orders that would breach `constraints` are reduced to the largest size that fits and
+    /// returned as [BrokerEvent::OrderTrimmed], orders that can't be reduced to fit are rejected.
+    pub fn with_constraints(&mut self, constraints: PortfolioConstraints) -> &mut Self {
+        self.constraints = Some(constraints);
+        self
+    }
+
+    /// Enables short selling. Cash must cover `requirement` multiplied by the value of the short
+    /// position after each sale, 1.5 requires cash of 150% of the short value.
+    pub fn with_short_margin_requirement(&mut self, requirement: f64) -> &mut Self {
+        self.short_margin_requirement = Some(requirement);
+        self
+    }
+
+    /// Method used for [Portfolio::get_average_cost_basis], and so position profit, and realized
+    /// gains. Defaults to [CostBasisMethod::Fifo].
+    pub fn with_cost_basis_method(&mut self, method: CostBasisMethod) -> &mut Self {
+        self.cost_basis_method = method;
+        self
+    }
+
+    /// Currency that [Portfolio::get_cash_balance] is reported in and that
+    /// [CashOperations::deposit_cash] deposits into. Defaults to USD.
+    pub fn with_base_currency(&mut self, currency: CurrencyCode) -> &mut Self {
+        self.base_currency = currency;
+        self
+    }
+
+    /// Cash held in another currency, valued in the base currency with the `{currency}{base}` FX
+    /// quote.
+    pub fn with_additional_currency(
+        &mut self,
+        currency: CurrencyCode,
+        initial_amount: f64,
+    ) -> &mut Self {
+        self.additional_currencies.push((currency, initial_amount));
+        self
+    }
+
+    /// Trades in `symbol` settle against the `currency` bucket rather than the base currency.
+    pub fn with_symbol_currency(
+        &mut self,
+        symbol: impl Into<String>,
+        currency: CurrencyCode,
+    ) -> &mut Self {
+        self.symbol_currencies.insert(symbol.into(), currency);
+        self
+    }
+
+    /// Orders received by the broker on tick `t` are sent to the exchange on tick `t + n`, this is
+    /// in addition to any latency at the exchange. With zero delay, orders are sent on the next
+    /// `check`.
+    pub fn with_execution_delay_ticks(&mut self, n: usize) -> &mut Self {
+        self.execution_delay_ticks = n;
+        self
+    }
+
+    /// Trades settle `trading_days` after execution, weekends aren't counted. Until then cash isn't
+    /// in the balance and shares are pending. Defaults to zero, settling on execution.
+    pub fn with_settlement_lag(&mut self, trading_days: u8) -> &mut Self {
+        self.settlement_lag = trading_days;
+        self
+    }
+
+    /// Actions dated on or before the first tick are assumed to be reflected in starting holdings
+    /// and are not applied.
+    pub fn with_corporate_action_source(
+        &mut self,
+        source: Box<dyn CorporateActionSource>,
+    ) -> &mut Self {
+        self.corporate_action_source = Some(source);
+        self
+    }
+
+    pub fn with_funding_rate_source(&mut self, source: Box<dyn FundingRateSource>) -> &mut Self {
+        self.funding_rate_source = Some(source);
+        self
+    }
+
+    /// Interest is accrued on positive cash on every `check`, pro rata to the time since the last
+    /// tick.
+    pub fn with_interest_model(&mut self, model: InterestModel) -> &mut Self {
+        self.interest_model = model;
+        self
+    }
+
+    /// Orders for `symbols` are sent to this exchange rather than the one passed to
+    /// `with_client`. Every exchange ticks on `check` but the clock is taken from the `with_client`
+    /// exchange so exchanges should share dates.
+    pub fn add_exchange(
+        &mut self,
+        client: C,
+        backtest_id: BacktestId,
+        symbols: &[&str],
+    ) -> &mut Self {
+        let symbols = symbols.iter().map(|symbol| symbol.to_string()).collect();
+        self.routed_exchanges.push((client, backtest_id, symbols));
+        self
+    }
+
+    pub fn with_client(&mut self, client: C, backtest_id: BacktestId) -> &mut Self {
+        self.client = Some(client);
+        self.backtest_id = Some(backtest_id);
+        self
+    }
+
+    pub fn with_trade_costs(&mut self, trade_costs: Vec<BrokerCost>) -> &mut Self {
+        self.trade_costs = trade_costs;
+        self
+    }
+
+    pub fn new() -> Self {
+        UistBrokerBuilder {
+            trade_costs: Vec::new(),
+            client: None,
+            backtest_id: None,
+            routed_exchanges: Vec::new(),
+            funding_rate_source: None,
+            corporate_action_source: None,
+            interest_model: InterestModel::default(),
+            min_cash_balance: 0.0,
+            short_margin_requirement: None,
+            execution_delay_ticks: 0,
+            settlement_lag: 0,
+            cost_basis_method: CostBasisMethod::default(),
+            base_currency: CurrencyCode::default(),
+            additional_currencies: Vec::new(),
+            symbol_currencies: HashMap::new(),
+            constraints: None,
+            #[cfg(feature = "checkpoint")]
+            checkpoint_manager: None,
+            event_bus_capacity: 1024,
+        }
+    }
+}
+
+impl<C: UistClient> Default for UistBrokerBuilder<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Creates the in-process exchange, initializes a backtest, and builds a [UistBroker] in one chain.
+/// The broker is also the clock for the backtest.
+///
+/// Replaces creating a [Penelope] source, a [TestClient], calling `init` and then building with
+/// [UistBrokerBuilder].
+pub struct BacktestBuilder {
+    name: String,
+    source: Option<Penelope>,
+    trade_costs: Vec<BrokerCost>,
+    min_cash_balance: f64,
+    base_currency: CurrencyCode,
+    additional_currencies: Vec<(CurrencyCode, f64)>,
+    symbol_currencies: HashMap<String, CurrencyCode>,
+    constraints: Option<PortfolioConstraints>,
+}
+
+impl BacktestBuilder {
+    pub async fn build(&mut self) -> UistBroker<TestClient> {
+        if self.source.is_none() {
+            panic!("Cannot build backtest without prices");
+        }
+
+        let source = self.source.take().unwrap();
+        let mut client = TestClient::single(&self.name, source);
+        let resp = client.init(self.name.clone()).await.unwrap();
+
+        let mut builder = UistBrokerBuilder::new();
+        builder
+            .with_client(client, resp.backtest_id)
+            .with_trade_costs(self.trade_costs.clone())
+            .with_min_cash_balance(self.min_cash_balance)
+            .with_base_currency(self.base_currency.clone());
+        for (currency, initial_amount) in &self.additional_currencies {
+            builder.with_additional_currency(currency.clone(), *initial_amount);
+        }
+        for (symbol, currency) in &self.symbol_currencies {
+            builder.with_symbol_currency(symbol.clone(), currency.clone());
+        }
+        if let Some(constraints) = &self.constraints {
+            builder.with_constraints(constraints.clone());
+        }
+        builder.build().await
+    }
+
+    pub fn with_name(&mut self, name: impl Into<String>) -> &mut Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn with_source(&mut self, source: Penelope) -> &mut Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Quotes can be passed in any order, dates are sorted before they are added to the source.
+    pub fn with_symbols_and_prices(&mut self, prices: HashMap<i64, Vec<UistQuote>>) -> &mut Self {
+        let mut source = Penelope::new();
+        for date in prices.keys().sorted() {
+            for quote in prices.get(date).unwrap() {
+                match quote.volume {
+                    Some(volume) => source.add_quote_with_volume(
+                        quote.bid,
+                        quote.ask,
+                        *date,
+                        quote.symbol.clone(),
+                        volume,
+                    ),
+                    None => source.add_quote(quote.bid, quote.ask, *date, quote.symbol.clone()),
+                }
+            }
+        }
+        self.source = Some(source);
+        self
+    }
+
+    pub fn with_trade_costs(&mut self, trade_costs: Vec<BrokerCost>) -> &mut Self {
+        self.trade_costs = trade_costs;
+        self
+    }
+
+    pub fn with_min_cash_balance(&mut self, min_cash: f64) -> &mut Self {
+        self.min_cash_balance = min_cash;
+        self
+    }
+
+    pub fn with_constraints(&mut self, constraints: PortfolioConstraints) -> &mut Self {
+        self.constraints = Some(constraints);
+        self
+    }
+
+    pub fn with_base_currency(&mut self, currency: CurrencyCode) -> &mut Self {
+        self.base_currency = currency;
+        self
+    }
+
+    pub fn with_additional_currency(
+        &mut self,
+        currency: CurrencyCode,
+        initial_amount: f64,
+    ) -> &mut Self {
+        self.additional_currencies.push((currency, initial_amount));
+        self
+    }
+
+    pub fn with_symbol_currency(
+        &mut self,
+        symbol: impl Into<String>,
+        currency: CurrencyCode,
+    ) -> &mut Self {
+        self.symbol_currencies.insert(symbol.into(), currency);
+        self
+    }
+
+    pub fn new() -> Self {
+        BacktestBuilder {
+            name: "Backtest".to_string(),
+            source: None,
+            trade_costs: Vec::new(),
+            min_cash_balance: 0.0,
+            base_currency: CurrencyCode::default(),
+            additional_currencies: Vec::new(),
+            symbol_currencies: HashMap::new(),
+            constraints: None,
+        }
+    }
+}
+
+impl Default for BacktestBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Published to subscribers of [UistBroker::subscribe_events] as broker state changes.
+#[derive(Clone, Debug)]
+pub enum BrokerBusEvent {
+    //Every event written to the broker log: trades, funding and interest payments, currency
+    //conversions, and corporate actions
+    Recorded(UistRecordedEvent),
+    //Signed change in the cash held in a currency
+    CashChanged(CurrencyCode, f64),
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "checkpoint", derive(serde::Serialize, serde::Deserialize))]
+pub enum UistRecordedEvent {
+    TradeCompleted(Trade),
+    //Symbol, amount paid, date
+    FundingPayment(String, f64, i64),
+    //Amount is in the `from` currency, rate is units of `to` per unit of `from`
+    FxConversion {
+        from: CurrencyCode,
+        to: CurrencyCode,
+        amount: f64,
+        rate: f64,
+        date: i64,
+    },
+    InterestPayment {
+        amount: f64,
+        date: i64,
+    },
+    Split {
+        symbol: String,
+        ratio: f64,
+        date: i64,
+    },
+    CashAcquisition {
+        symbol: String,
+        proceeds: f64,
+        date: i64,
+    },
+    Spinoff {
+        parent_symbol: String,
+        spinco_symbol: String,
+        quantity: f64,
+        date: i64,
+    },
+}
+
+impl From<Trade> for UistRecordedEvent {
+    fn from(value: Trade) -> Self {
+        UistRecordedEvent::TradeCompleted(value)
+    }
+}
+
+impl UistRecordedEvent {
+    pub fn date(&self) -> i64 {
+        match self {
+            UistRecordedEvent::TradeCompleted(trade) => trade.date,
+            UistRecordedEvent::FundingPayment(_symbol, _amount, date) => *date,
+            UistRecordedEvent::FxConversion { date, .. } => *date,
+            UistRecordedEvent::InterestPayment { date, .. } => *date,
+            UistRecordedEvent::Split { date, .. } => *date,
+            UistRecordedEvent::CashAcquisition { date, .. } => *date,
+            UistRecordedEvent::Spinoff { date, .. } => *date,
+        }
+    }
+}
+
+/// Interest credited on cash, reported separately from trading gains.
+#[derive(Clone, Debug)]
+pub struct InterestPayment {
+    pub amount: f64,
+    pub date: i64,
+}
+
+/// Order in which lots are matched to sales when calculating cost basis and realized gains.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "checkpoint", derive(serde::Serialize, serde::Deserialize))]
+pub enum CostBasisMethod {
+    #[default]
+    Fifo,
+    Lifo,
+    /// All purchases are merged into one lot at the average price, dated at the first purchase.
+    AverageCost,
+}
+
+/// Shares bought in one trade, or all purchases when using [CostBasisMethod::AverageCost].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "checkpoint", derive(serde::Serialize, serde::Deserialize))]
+pub struct LotInfo {
+    pub lot_id: u64,
+    pub acquired_date: i64,
+    pub qty: f64,
+    pub price: f64,
+}
+
+/// Gain on the sale of one lot. `holding_period` is the sale date less the purchase date.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "checkpoint", derive(serde::Serialize, serde::Deserialize))]
+pub struct RealizedGain {
+    pub symbol: String,
+    pub quantity: f64,
+    pub basis: f64,
+    pub proceeds: f64,
+    pub holding_period: i64,
+    pub date: i64,
+}
+
+impl RealizedGain {
+    pub fn gain(&self) -> f64 {
+        self.proceeds - self.basis
+    }
+}
+
+//Records events generated by brokers. Used for internal calculations but is public for tax
+//calculations.
+#[doc(hidden)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "checkpoint", derive(serde::Serialize, serde::Deserialize))]
+pub struct UistBrokerLog {
+    log: Vec<UistRecordedEvent>,
+    //Open lots of long positions in purchase order
+    lots: HashMap<String, VecDeque<LotInfo>>,
+    last_lot_id: u64,
+    realized_gains: Vec<RealizedGain>,
+    cost_basis_method: CostBasisMethod,
+    //Date of the buy that opened the current long position, removed when the last lot is sold
+    first_purchase_date_per_symbol: HashMap<String, DateTime>,
+    //Quantity sold beyond the open lots, covered by later buys before any new lot is opened
+    short_qty: HashMap<String, f64>,
+}
+
+impl UistBrokerLog {
+    pub fn record<E: Into<UistRecordedEvent>>(&mut self, event: E) {
+        let brokerevent: UistRecordedEvent = event.into();
+        if let UistRecordedEvent::TradeCompleted(trade) = &brokerevent {
+            self.update_lots(trade, &[]);
+        }
+        self.log.push(brokerevent);
+    }
+
+    /// Records a trade, a sale consumes the given lots in order before falling back to the cost
+    /// basis method for any excess. Lots that are no longer open are skipped.
+    pub fn record_with_lots(&mut self, trade: Trade, lot_ids: &[u64]) {
+        self.update_lots(&trade, lot_ids);
+        self.log.push(UistRecordedEvent::TradeCompleted(trade));
+    }
+
+    pub fn query_open_lots(&self, symbol: &str) -> Vec<LotInfo> {
+        self.lots
+            .get(symbol)
+            .map(|lots| lots.iter().cloned().collect_vec())
+            .unwrap_or_default()
+    }
+
+    //Sells are matched against lots in the order given by the cost basis method. Sales beyond the
+    //lots held open short positions, which have no lots
+    fn update_lots(&mut self, trade: &Trade, lot_ids: &[u64]) {
+        if trade.quantity == 0.0 {
+            return;
+        }
+        let price = trade.value / trade.quantity;
+        let lots = self.lots.entry(trade.symbol.clone()).or_default();
+        match trade.typ {
+            TradeType::Buy => {
+                //Covering an open short doesn't open a lot, only the quantity beyond the short does
+                let short = self.short_qty.remove(&trade.symbol).unwrap_or(0.0);
+                let qty = trade.quantity - short;
+                if qty < 0.0 {
+                    self.short_qty.insert(trade.symbol.clone(), -qty);
+                    return;
+                }
+                if qty == 0.0 {
+                    return;
+                }
+                //A buy with no open lots opens a new position
+                if lots.is_empty() {
+                    self.first_purchase_date_per_symbol
+                        .insert(trade.symbol.clone(), trade.date.into());
+                }
+                match (self.cost_basis_method, lots.front_mut()) {
+                    (CostBasisMethod::AverageCost, Some(lot)) => {
+                        let total = lot.qty + qty;
+                        lot.price = (lot.price * lot.qty + price * qty) / total;
+                        lot.qty = total;
+                    }
+                    _ => {
+                        lots.push_back(LotInfo {
+                            lot_id: self.last_lot_id,
+                            acquired_date: trade.date,
+                            qty,
+                            price,
+                        });
+                        self.last_lot_id += 1;
+                    }
+                }
+            }
+            TradeType::Sell => {
+                let mut remaining = trade.quantity;
+                for lot_id in lot_ids {
+                    if remaining <= 0.0 {
+                        break;
+                    }
+                    if let Some(pos) = lots.iter().position(|lot| lot.lot_id == *lot_id) {
+                        remaining -=
+                            Self::realize(lots, pos, remaining, trade, &mut self.realized_gains);
+                    }
+                }
+                while remaining > 0.0 && !lots.is_empty() {
+                    let pos = match self.cost_basis_method {
+                        CostBasisMethod::Lifo => lots.len() - 1,
+                        CostBasisMethod::Fifo | CostBasisMethod::AverageCost => 0,
+                    };
+                    remaining -=
+                        Self::realize(lots, pos, remaining, trade, &mut self.realized_gains);
+                }
+                if lots.is_empty() {
+                    self.first_purchase_date_per_symbol.remove(&trade.symbol);
+                }
+                //Anything left after the lots run out opens or adds to a short
+                if remaining > 0.0 {
+                    *self.short_qty.entry(trade.symbol.clone()).or_default() += remaining;
+                }
+            }
+        }
+    }
+
+    //Sells up to `remaining` from the lot at `pos`, returns the quantity sold
+    fn realize(
+        lots: &mut VecDeque<LotInfo>,
+        pos: usize,
+        remaining: f64,
+        trade: &Trade,
+        realized_gains: &mut Vec<RealizedGain>,
+    ) -> f64 {
+        let lot = &mut lots[pos];
+        let quantity = lot.qty.min(remaining);
+        realized_gains.push(RealizedGain {
+            symbol: trade.symbol.clone(),
+            quantity,
+            basis: lot.price * quantity,
+            proceeds: trade.value / trade.quantity * quantity,
+            holding_period: trade.date - lot.acquired_date,
+            date: trade.date,
+        });
+        lot.qty -= quantity;
+        if lot.qty <= 0.0 {
+            lots.remove(pos);
+        }
+        quantity
+    }
+
+    /// Rescales open lots so that the total cost of each lot is unchanged.
+    pub fn apply_split(&mut self, symbol: &str, ratio: f64) {
+        if let Some(lots) = self.lots.get_mut(symbol) {
+            for lot in lots {
+                lot.qty *= ratio;
+                lot.price /= ratio;
+            }
+        }
+        if let Some(short) = self.short_qty.get_mut(symbol) {
+            *short *= ratio;
+        }
+    }
+
+    /// Moves `allocation` of the cost of each open parent lot to a new spinco lot with the same
+    /// acquired date, so the holding period carries over.
+    pub fn apply_spinoff(&mut self, parent: &str, spinco: &str, ratio: f64, allocation: f64) {
+        let Some(parent_lots) = self.lots.get_mut(parent) else {
+            return;
+        };
+        let mut spinco_lots = Vec::new();
+        for lot in parent_lots.iter_mut() {
+            let qty = lot.qty * ratio;
+            if qty != 0.0 {
+                spinco_lots.push(LotInfo {
+                    lot_id: 0,
+                    acquired_date: lot.acquired_date,
+                    qty,
+                    price: lot.price * lot.qty * allocation / qty,
+                });
+            }
+            lot.price *= 1.0 - allocation;
+        }
+        //Spinco shares are treated as held since the parent position was opened
+        if let Some(date) = self.first_purchase_date_per_symbol.get(parent).copied() {
+            if !spinco_lots.is_empty() {
+                self.first_purchase_date_per_symbol
+                    .entry(spinco.to_string())
+                    .or_insert(date);
+            }
+        }
+        for mut lot in spinco_lots {
+            lot.lot_id = self.last_lot_id;
+            self.last_lot_id += 1;
+            self.lots
+                .entry(spinco.to_string())
+                .or_default()
+                .push_back(lot);
+        }
+    }
+
+    /// Realizes every open lot at `price`, used when a position is closed outside of a trade.
+    pub fn close_lots(&mut self, symbol: &str, price: f64, date: i64) {
+        self.first_purchase_date_per_symbol.remove(symbol);
+        self.short_qty.remove(symbol);
+        for lot in self.lots.remove(symbol).unwrap_or_default() {
+            self.realized_gains.push(RealizedGain {
+                symbol: symbol.to_string(),
+                quantity: lot.qty,
+                basis: lot.price * lot.qty,
+                proceeds: price * lot.qty,
+                holding_period: date - lot.acquired_date,
+                date,
+            });
+        }
+    }
+
+    /// Gains realized by sales between `start` and `end` inclusive, one entry per lot sold.
+    pub fn realized_gains_between(&self, start: &i64, end: &i64) -> Vec<RealizedGain> {
+        self.realized_gains
+            .iter()
+            .filter(|gain| gain.date >= *start && gain.date <= *end)
+            .cloned()
+            .collect_vec()
+    }
+
+    pub fn trades(&self) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        for event in &self.log {
+            if let UistRecordedEvent::TradeCompleted(trade) = event {
+                trades.push(trade.clone());
+            }
+        }
+        trades
+    }
+
+    pub fn funding_payments(&self) -> Vec<(String, f64, i64)> {
+        let mut payments = Vec::new();
+        for event in &self.log {
+            if let UistRecordedEvent::FundingPayment(symbol, amount, date) = event {
+                payments.push((symbol.clone(), *amount, *date));
+            }
+        }
+        payments
+    }
+
+    pub fn interest_payments_between(&self, start: &i64, end: &i64) -> Vec<InterestPayment> {
+        let mut payments = Vec::new();
+        for event in &self.log {
+            if let UistRecordedEvent::InterestPayment { amount, date } = event {
+                if date >= start && date <= end {
+                    payments.push(InterestPayment {
+                        amount: *amount,
+                        date: *date,
+                    });
+                }
+            }
+        }
+        payments
+    }
+
+    /// (from, to, amount, rate, date) of every currency conversion.
+    pub fn fx_conversions(&self) -> Vec<(CurrencyCode, CurrencyCode, f64, f64, i64)> {
+        let mut conversions = Vec::new();
+        for event in &self.log {
+            if let UistRecordedEvent::FxConversion {
+                from,
+                to,
+                amount,
+                rate,
+                date,
+            } = event
+            {
+                conversions.push((from.clone(), to.clone(), *amount, *rate, *date));
+            }
+        }
+        conversions
+    }
+
+    pub fn trades_between(&self, start: &i64, stop: &i64) -> Vec<Trade> {
+        let trades = self.trades();
+        trades
+            .iter()
+            .filter(|v| v.date >= *DateTime::from(*start) && v.date <= *DateTime::from(*stop))
+            .cloned()
+            .collect_vec()
+    }
+
+    /// Date of the buy that opened the current long position in `symbol`.
+    pub fn first_purchase_date(&self, symbol: &str) -> Option<DateTime> {
+        self.first_purchase_date_per_symbol.get(symbol).copied()
+    }
+
+    /// Average price of the lots still held, which lots remain depends on the cost basis method.
+    pub fn cost_basis(&self, symbol: &str) -> Option<f64> {
+        let lots = self.lots.get(symbol)?;
+        let qty: f64 = lots.iter().map(|lot| lot.qty).sum();
+        if qty.eq(&0.0) {
+            return None;
+        }
+        let value: f64 = lots.iter().map(|lot| lot.price * lot.qty).sum();
+        Some(value / qty)
+    }
+
+    pub fn get_trade_size_distribution(&self) -> TradeDistribution {
+        TradeDistribution::from_trades(&self.trades())
+    }
+
+    pub fn get_buy_size_distribution(&self) -> TradeDistribution {
+        let buys = self
+            .trades()
+            .into_iter()
+            .filter(|trade| matches!(trade.typ, TradeType::Buy))
+            .collect_vec();
+        TradeDistribution::from_trades(&buys)
+    }
+
+    pub fn get_sell_size_distribution(&self) -> TradeDistribution {
+        let sells = self
+            .trades()
+            .into_iter()
+            .filter(|trade| matches!(trade.typ, TradeType::Sell))
+            .collect_vec();
+        TradeDistribution::from_trades(&sells)
+    }
+}
+
+/// Summary of trade size, measured by the value of each trade. All fields are zero if there are
+/// no trades.
+///
+/// Percentiles are linearly interpolated at rank `p * (n + 1)`, with ranks outside the sample
+/// clamped to the smallest or largest trade. Standard deviation is the population standard
+/// deviation.
+#[derive(Clone, Debug, Default)]
+pub struct TradeDistribution {
+    pub mean: f64,
+    pub median: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub max: f64,
+    pub percentile_25: f64,
+    pub percentile_75: f64,
+    pub percentile_95: f64,
+}
+
+impl TradeDistribution {
+    fn percentile(sorted: &[f64], pct: f64) -> f64 {
+        let count = sorted.len() as f64;
+        let rank = (pct * (count + 1.0)).clamp(1.0, count);
+        let lower = rank.floor();
+        let upper = rank.ceil();
+        let lower_val = sorted[lower as usize - 1];
+        let upper_val = sorted[upper as usize - 1];
+        lower_val + (rank - lower) * (upper_val - lower_val)
+    }
+
+    fn from_trades(trades: &[Trade]) -> Self {
+        if trades.is_empty() {
+            return Self::default();
+        }
+
+        let sizes = trades
+            .iter()
+            .map(|trade| trade.value)
+            .sorted_by(|a, b| a.partial_cmp(b).unwrap())
+            .collect_vec();
+
+        let count = sizes.len() as f64;
+        let mean = sizes.iter().sum::<f64>() / count;
+        let var = sizes
+            .iter()
+            .map(|size| (size - mean).powf(2.0))
+            .sum::<f64>()
+            / count;
+
+        Self {
+            mean,
+            median: Self::percentile(&sizes, 0.5),
+            std_dev: var.sqrt(),
+            min: *sizes.first().unwrap(),
+            max: *sizes.last().unwrap(),
+            percentile_25: Self::percentile(&sizes, 0.25),
+            percentile_75: Self::percentile(&sizes, 0.75),
+            percentile_95: Self::percentile(&sizes, 0.95),
+        }
+    }
+}
+
+impl UistBrokerLog {
+    pub fn new() -> Self {
+        Self::with_cost_basis_method(CostBasisMethod::default())
+    }
+
+    pub fn with_cost_basis_method(cost_basis_method: CostBasisMethod) -> Self {
+        UistBrokerLog {
+            log: Vec::new(),
+            lots: HashMap::new(),
+            last_lot_id: 0,
+            realized_gains: Vec::new(),
+            cost_basis_method,
+            first_purchase_date_per_symbol: HashMap::new(),
+            short_qty: HashMap::new(),
+        }
+    }
+
+    /// Combines the logs of several brokers, for example every strategy in a backtest, into one
+    /// log sorted by date. Events on the same date keep the order of `logs`.
+    ///
+    /// Realized gains are taken from each log, calculated with that log's cost basis method,
+    /// rather than recalculated from the merged trades, so a sale is only matched against lots
+    /// bought by the same broker. The merged log holds no open lots so cost basis and lot queries
+    /// should be made against the original logs.
+    pub fn merge(logs: &[&UistBrokerLog]) -> UistBrokerLog {
+        let mut merged = UistBrokerLog::new();
+        merged.log = logs
+            .iter()
+            .flat_map(|log| log.log.iter())
+            .cloned()
+            .sorted_by_key(|event| event.date())
+            .collect();
+        merged.realized_gains = logs
+            .iter()
+            .flat_map(|log| log.realized_gains.iter())
+            .cloned()
+            .sorted_by_key(|gain| gain.date)
+            .collect();
+        merged
+    }
+
+    /// Same as [UistBrokerLog::merge] but trades with the same date, symbol, quantity and type
+    /// are only kept once. These can occur when two strategies trade the same security at the same
+    /// time. Realized gains with the same date, symbol, quantity, basis and proceeds are also only
+    /// kept once.
+    pub fn merge_dedup(logs: &[&UistBrokerLog]) -> UistBrokerLog {
+        let mut merged = Self::merge(logs);
+        let mut seen = HashSet::new();
+        merged.log.retain(|event| match event {
+            UistRecordedEvent::TradeCompleted(trade) => seen.insert((
+                trade.date,
+                trade.symbol.clone(),
+                trade.quantity.to_bits(),
+                matches!(trade.typ, TradeType::Buy),
+            )),
+            _ => true,
+        });
+        let mut seen = HashSet::new();
+        merged.realized_gains.retain(|gain| {
+            seen.insert((
+                gain.date,
+                gain.symbol.clone(),
+                gain.quantity.to_bits(),
+                gain.basis.to_bits(),
+                gain.proceeds.to_bits(),
+            ))
+        });
+        merged
+    }
+}
+
+impl Default for UistBrokerLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use tokio::sync::Mutex;
+
+    use crate::broker::{
+        BrokerCashEvent, BrokerCost, BrokerError, BrokerOperations, BrokerWarning, CashAcquisition,
+        CashOperations, Clock, CorporateActionSource, DateTime, FundingRateSource, GetsOrderBook,
+        InterestModel, OtoOrders, Portfolio, PortfolioConstraints, Quote, SendOrder, SpinOff,
+        Split, Update,
+    };
+    use rotala::exchange::uist_v1::{
+        CircuitBreaker, ExchangePolicy, Order, OrderType, SessionHours, Trade, TradeType,
+        UistQuote, UistV1,
+    };
+    use rotala::http::uist::uistv1_client::{Client, TestClient, UistClient};
+    use rotala::http::uist::AppState;
+    use rotala::input::penelope::Penelope;
+
+    use super::{
+        BacktestBuilder, BrokerBusEvent, CostBasisMethod, CurrencyCode, UistBroker,
+        UistBrokerBuilder, UistBrokerEvent, UistBrokerLog, UistRecordedEvent,
+    };
+
+    async fn setup() -> UistBroker<TestClient> {
+        let mut source = Penelope::new();
+
+        source.add_quote(100.00, 101.00, 100, "ABC");
+        source.add_quote(10.00, 11.00, 100, "BCD");
+
+        source.add_quote(104.00, 105.00, 101, "ABC");
+        source.add_quote(14.00, 15.00, 101, "BCD");
+
+        source.add_quote(95.00, 96.00, 102, "ABC");
+        source.add_quote(10.00, 11.00, 102, "BCD");
+
+        source.add_quote(95.00, 96.00, 103, "ABC");
+        source.add_quote(10.00, 11.00, 103, "BCD");
+
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+
+        UistBrokerBuilder::new()
+            .with_trade_costs(vec![BrokerCost::PctOfValue(0.01)])
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_that_backtest_builder_sorts_prices_by_date() {
+        let quote = |bid: f64, date: i64, symbol: &str| UistQuote {
+            bid,
+            ask: bid + 1.0,
+            date,
+            symbol: symbol.to_string(),
+            volume: None,
+        };
+        let mut prices = HashMap::new();
+        prices.insert(101, vec![quote(102.0, 101, "ABC"), quote(11.0, 101, "BCD")]);
+        prices.insert(100, vec![quote(100.0, 100, "ABC"), quote(10.0, 100, "BCD")]);
+        prices.insert(102, vec![quote(104.0, 102, "ABC"), quote(12.0, 102, "BCD")]);
+
+        let mut brkr = BacktestBuilder::new()
+            .with_symbols_and_prices(prices)
+            .build()
+            .await;
+        assert_eq!(brkr.now(), 100);
+        assert_eq!(brkr.get_quote("ABC").unwrap().bid, 100.0);
+
+        brkr.check().await;
+        assert_eq!(brkr.now(), 101);
+        assert_eq!(brkr.get_quote("BCD").unwrap().bid, 11.0);
+    }
+
+    #[tokio::test]
+    async fn test_that_clock_returns_calendar_dates() {
+        let mut source = Penelope::new();
+        //Friday 1st, Saturday 2nd and Monday 4th March 2024
+        source.add_quote(100.00, 101.00, 1709251200, "ABC");
+        source.add_quote(100.00, 101.00, 1709337600, "ABC");
+        source.add_quote(100.00, 101.00, 1709510400, "ABC");
+
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
+
+        assert_eq!(brkr.current_year(), 2024);
+        assert_eq!(brkr.current_month(), 3);
+        assert_eq!(brkr.current_day_of_week(), 5);
+        assert!(!brkr.is_weekend());
+        assert_eq!(brkr.days_in_simulation(), 0);
+
+        brkr.check().await;
+        assert_eq!(brkr.current_day_of_week(), 6);
+        assert!(brkr.is_weekend());
+
+        brkr.check().await;
+        assert_eq!(brkr.current_day_of_week(), 1);
+        assert_eq!(brkr.days_in_simulation(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_that_clock_follows_irregular_timestamps() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 101.00, 100, "ABC");
+        source.add_quote(101.00, 102.00, 103, "ABC");
+        source.add_quote(102.00, 103.00, 110, "ABC");
+        source.add_quote(10.00, 11.00, 110, "BCD");
+
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
+
+        let mut seen = Vec::new();
+        while brkr.has_next() {
+            //Reading the clock doesn't advance it
+            let now = brkr.now();
+            assert_eq!(brkr.now(), now);
+            seen.push(now);
+            brkr.check().await;
+        }
+        //Dates with more than one quote are one tick
+        assert_eq!(seen, vec![100, 103, 110]);
+        assert!(!brkr.has_next());
+    }
+
+    #[tokio::test]
+    async fn test_that_brokers_can_share_client() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 101.00, 100, "ABC");
+        source.add_quote(104.00, 105.00, 101, "ABC");
+        source.add_quote(95.00, 96.00, 102, "ABC");
+
+        let mut client = Arc::new(Mutex::new(TestClient::single("Random", source)));
+        let first_resp = client.init("Random".to_string()).await.unwrap();
+        let second_resp = client.init("Random".to_string()).await.unwrap();
+        assert_ne!(first_resp.backtest_id, second_resp.backtest_id);
+
+        let mut first = UistBrokerBuilder::new()
+            .with_client(Arc::clone(&client), first_resp.backtest_id)
+            .build()
+            .await;
+        let mut second = UistBrokerBuilder::new()
+            .with_client(Arc::clone(&client), second_resp.backtest_id)
+            .build()
+            .await;
+
+        first.deposit_cash(&100_000.0);
+        second.deposit_cash(&100_000.0);
+        first.send_order(Order::market_buy("ABC", 100.0));
+        first.check().await;
+        second.check().await;
+        first.check().await;
+        second.check().await;
+
+        assert_eq!(first.get_position_qty("ABC").unwrap(), 100.0);
+        assert!(second.get_position_qty("ABC").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_that_brokers_sharing_client_trade_independently() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 101.00, 100, "ABC");
+        source.add_quote(104.00, 105.00, 101, "ABC");
+        source.add_quote(95.00, 96.00, 102, "ABC");
+        source.add_quote(95.00, 96.00, 103, "ABC");
+
+        let mut client = Arc::new(Mutex::new(TestClient::single("Random", source)));
+        let first_resp = client.init("Random".to_string()).await.unwrap();
+        let second_resp = client.init("Random".to_string()).await.unwrap();
+
+        let mut first = UistBrokerBuilder::new()
+            .with_client(Arc::clone(&client), first_resp.backtest_id)
+            .build()
+            .await;
+        let mut second = UistBrokerBuilder::new()
+            .with_client(Arc::clone(&client), second_resp.backtest_id)
+            .build()
+            .await;
+
+        first.deposit_cash(&100_000.0);
+        second.deposit_cash(&100_000.0);
+        first.send_order(Order::market_buy("ABC", 100.0));
+        second.send_order(Order::market_buy("ABC", 50.0));
+        first.check().await;
+        second.check().await;
+        first.check().await;
+        second.check().await;
+
+        //Second broker sells on a later tick, first broker's position is unaffected
+        second.send_order(Order::market_sell("ABC", 20.0));
+        second.check().await;
+        second.check().await;
+        first.check().await;
+
+        assert_eq!(first.get_position_qty("ABC").unwrap(), 100.0);
+        assert_eq!(second.get_position_qty("ABC").unwrap(), 30.0);
+        assert_eq!(first.get_trade_count(), 1);
+        assert_eq!(second.get_trade_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_that_orders_rejected_by_exchange_are_released() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 101.00, 100, "ABC");
+        source.add_quote(104.00, 105.00, 101, "ABC");
+        source.add_quote(95.00, 96.00, 102, "ABC");
+
+        let mut client = Arc::new(Mutex::new(TestClient::single("Random", source.clone())));
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(Arc::clone(&client), resp.backtest_id)
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+        brkr.check().await;
+
+        //Shared exchange is replaced with one that doesn't know this backtest
+        *client.lock().await = TestClient::single("Random", source);
+        brkr.send_order(Order::market_buy("ABC", 100.0));
+        assert_eq!(*brkr.get_pending_orders().get("ABC").unwrap(), 100.0);
+
+        brkr.check().await;
+        assert!(brkr.get_pending_orders().is_empty());
+        assert_eq!(
+            brkr.get_rejection_reasons_summary().get("ExchangeRejected"),
+            Some(&1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_that_orders_are_routed_by_symbol() {
+        let mut equities = Penelope::new();
+        equities.add_quote(100.00, 101.00, 100, "ABC");
+        equities.add_quote(104.00, 105.00, 101, "ABC");
+        equities.add_quote(95.00, 96.00, 102, "ABC");
+        equities.add_quote(95.00, 96.00, 103, "ABC");
+
+        let mut futures = Penelope::new();
+        futures.add_quote(10.00, 11.00, 100, "BCD");
+        futures.add_quote(14.00, 15.00, 101, "BCD");
+        futures.add_quote(10.00, 11.00, 102, "BCD");
+        futures.add_quote(10.00, 11.00, 103, "BCD");
+
+        let mut equities_client = TestClient::single("Equities", equities);
+        let equities_resp = equities_client.init("Equities".to_string()).await.unwrap();
+        let mut futures_client = TestClient::single("Futures", futures);
+        let futures_resp = futures_client.init("Futures".to_string()).await.unwrap();
+
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(equities_client, equities_resp.backtest_id)
+            .add_exchange(futures_client, futures_resp.backtest_id, &["BCD"])
+            .build()
+            .await;
+        assert!(brkr.get_quote("BCD").is_some());
+
+        brkr.deposit_cash(&100_000.0);
+        brkr.send_order(Order::limit_buy("ABC", 100.0, 50.0));
+        brkr.send_order(Order::limit_buy("BCD", 100.0, 5.0));
+        brkr.check().await;
+        //Both exchanges assign the first id, these are kept unique by the broker
+        let open = brkr.get_open_orders();
+        assert_eq!(open.len(), 2);
+        assert_ne!(open[0].order_id, open[1].order_id);
+
+        brkr.send_order(Order::market_buy("BCD", 100.0));
+        brkr.check().await;
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("BCD"), Some(100.0));
+        assert_eq!(brkr.get_position_qty("ABC"), None);
+    }
+
+    #[tokio::test]
+    async fn test_that_health_check_finds_invalid_state() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&100_000.0);
+        assert!(brkr.health_check().is_healthy);
+
+        brkr.update_cash_balance(-100.0);
+        assert!(brkr
+            .health_check()
+            .warnings
+            .contains(&BrokerWarning::NegativeCash(-100.0)));
+
+        brkr.update_holdings("ABC", -10.0);
+        brkr.update_holdings("XYZ", 10.0);
+        let status = brkr.health_check();
+        assert!(!status.is_healthy);
+        assert!(status
+            .errors
+            .contains(&BrokerError::NegativeQuantity("ABC".to_string(), -10.0)));
+        assert!(status
+            .errors
+            .contains(&BrokerError::MissingQuote("XYZ".to_string())));
+        assert!(matches!(
+            status.errors.last(),
+            Some(BrokerError::NegativeValue(_))
+        ));
+
+        brkr.update_cash_balance(f64::NAN);
+        assert!(matches!(
+            brkr.health_check().errors.first(),
+            Some(BrokerError::InvalidCash(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_that_health_check_finds_stale_quote() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 101.00, 100, "ABC");
+        source.add_quote(10.00, 11.00, 100, "BCD");
+        source.add_quote(104.00, 105.00, 101, "ABC");
+        source.add_quote(104.00, 105.00, 102, "ABC");
+
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
+
+        brkr.deposit_cash(&100_000.0);
+        brkr.update_holdings("BCD", 10.0);
+        assert!(brkr.health_check().warnings.is_empty());
+
+        brkr.check().await;
+        brkr.check().await;
+        let status = brkr.health_check();
+        assert!(status.is_healthy);
+        assert_eq!(
+            status.warnings,
+            vec![BrokerWarning::StaleQuote("BCD".to_string(), 2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_that_scenario_analysis_applies_shocks_without_changing_state() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&1_000.0);
+        brkr.update_holdings("ABC", 10.0);
+        brkr.update_holdings("BCD", 100.0);
+
+        //ABC bid is 100 and BCD bid is 10, total value is 3000
+        let mut shocks = HashMap::new();
+        shocks.insert("ABC".to_string(), -0.5);
+        let res = brkr.scenario_analysis(&shocks);
+
+        assert_eq!(res.new_portfolio_value, 2500.0);
+        assert_eq!(res.value_change, -500.0);
+        assert_eq!((res.pct_change * 1000.0).round(), -167.0);
+        assert_eq!(res.by_position.get("ABC"), Some(&500.0));
+        assert_eq!(res.by_position.get("BCD"), Some(&1000.0));
+
+        assert_eq!(brkr.get_total_value(), 3000.0);
+        assert_eq!(brkr.get_quote("ABC").unwrap().bid, 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_tracks_effective_spread() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&100_000.0);
+
+        brkr.send_order(Order::market_buy("ABC", 10.0));
+        brkr.send_order(Order::market_buy("BCD", 10.0));
+        brkr.check().await;
+        brkr.check().await;
+
+        brkr.send_order(Order::market_sell("ABC", 10.0));
+        brkr.check().await;
+        brkr.check().await;
+
+        //Market orders execute at the touch so effective spread equals quoted spread
+        let spreads = brkr.get_effective_spread();
+        assert_eq!(*spreads.get("ABC").unwrap(), 1.0);
+        assert_eq!(*spreads.get("BCD").unwrap(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_cancels_and_replaces_order() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&100_000.0);
+
+        let res = brkr.cancel_and_replace(0, Order::market_buy("ABC", 10.0));
+        assert!(matches!(res, UistBrokerEvent::OrderInvalid(..)));
+
+        //Limit price is too low to execute
+        brkr.send_order(Order::limit_buy("ABC", 10.0, 50.0));
+        brkr.check().await;
+        let open = brkr.get_open_orders();
+        assert_eq!(open.len(), 1);
+        let order_id = open.first().unwrap().order_id.unwrap();
+
+        let res = brkr.cancel_and_replace(order_id, Order::limit_buy("ABC", 10.0, 200.0));
+        assert!(matches!(res, UistBrokerEvent::OrderSentToExchange(..)));
+        assert_eq!(*brkr.get_pending_orders().get("ABC").unwrap(), 10.0);
+
+        brkr.check().await;
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC").unwrap(), 10.0);
+        assert!(brkr.get_open_orders().is_empty());
+        assert!(brkr.get_pending_orders().is_empty());
+        assert_eq!(brkr.trades_between(&0, &1000).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_lists_pending_orders() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&100_000.0);
+        assert!(brkr.pending_orders().is_empty());
+
+        //Limit price is too low to execute
+        brkr.send_order(Order::limit_buy("ABC", 10.0, 50.0));
+        brkr.send_order(Order::market_buy("BCD", 10.0));
+        brkr.check().await;
+
+        let pending = brkr.pending_orders();
+        assert_eq!(pending.len(), 2);
+        let limit = pending.iter().find(|order| order.symbol == "ABC").unwrap();
+        assert_eq!(limit.order_type, OrderType::LimitBuy);
+        assert_eq!(limit.qty, 10.0);
+        assert_eq!(limit.price, Some(50.0));
+        assert_eq!(*limit.submitted_at, 100);
+
+        //Market order executes, limit order stays in the book with the same date
+        brkr.check().await;
+        let pending = brkr.pending_orders();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].symbol, "ABC");
+        assert_eq!(*pending[0].submitted_at, 100);
+
+        brkr.cancel_and_replace(pending[0].order_id, Order::market_buy("ABC", 10.0));
+        brkr.check().await;
+        brkr.check().await;
+        assert!(brkr.pending_orders().is_empty());
+        assert!(brkr.open_order_dates.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_cancels_open_order() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&100_000.0);
+
+        //Limit price is too low to execute
+        brkr.send_order(Order::limit_buy("ABC", 10.0, 50.0));
+        brkr.check().await;
+        let order_id = brkr.pending_orders()[0].order_id;
+
+        assert!(brkr.cancel_order(order_id));
+        assert!(!brkr.cancel_order(order_id));
+        assert!(brkr.pending_orders().is_empty());
+        assert!(brkr.get_pending_orders().is_empty());
+
+        brkr.check().await;
+        brkr.check().await;
+        assert_eq!(brkr.get_trade_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_summarises_open_positions() {
+        let day = 86400;
+        let mut source = Penelope::new();
+        for i in 0..6 {
+            let price = 100.0 + i as f64;
+            source.add_quote(price, price + 1.0, day * (i + 1), "ABC");
+            source.add_quote(10.0, 11.0, day * (i + 1), "BCD");
+        }
+        let mut client = TestClient::single("Positions", source);
+        let resp = client.init("Positions".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+        assert!(brkr.get_open_positions().is_empty());
+
+        brkr.send_order(Order::market_buy("ABC", 10.0));
+        brkr.send_order(Order::market_buy("BCD", 10.0));
+        brkr.check().await;
+        brkr.check().await;
+
+        let positions = brkr.get_open_positions();
+        assert_eq!(positions.len(), 2);
+        let abc = &positions[0];
+        assert_eq!(abc.symbol, "ABC");
+        assert_eq!(abc.qty, 10.0);
+        assert_eq!(abc.current_value, brkr.get_position_value("ABC").unwrap());
+        assert_eq!(abc.average_cost, brkr.get_average_cost_basis("ABC"));
+        assert_eq!(abc.unrealised_pnl, brkr.get_position_profit("ABC"));
+        let days_held = abc.days_held;
+
+        brkr.check().await;
+        assert_eq!(brkr.get_open_positions()[0].days_held, days_held + 1);
+
+        brkr.send_order(Order::market_sell("ABC", 10.0));
+        brkr.check().await;
+        brkr.check().await;
+        let positions = brkr.get_open_positions();
+        assert_eq!(positions.len(), 1);
+        assert_eq!(positions[0].symbol, "BCD");
+        assert_eq!(brkr.get_first_purchase_date("ABC"), None);
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_releases_expired_orders() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&100_000.0);
+
+        brkr.send_order(Order::limit_buy_gtd("ABC", 10.0, 50.0, 102));
+        brkr.check().await;
+        assert_eq!(brkr.get_open_orders().len(), 1);
+        assert_eq!(*brkr.get_pending_orders().get("ABC").unwrap(), 10.0);
+
+        //Still open on 101, removed on 102
+        brkr.check().await;
+        assert_eq!(brkr.get_open_orders().len(), 1);
+        brkr.check().await;
+        assert!(brkr.get_open_orders().is_empty());
+        assert!(brkr.get_pending_orders().is_empty());
+        assert_eq!(brkr.get_position_qty("ABC"), None);
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_drops_exchange_oco_siblings() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&100_000.0);
+        brkr.send_order(Order::market_buy("ABC", 10.0));
+        brkr.check().await;
+        brkr.check().await;
+
+        //Take profit can't execute, price falls to 95 on 102 so the stop executes
+        let mut take_profit = Order::limit_sell("ABC", 10.0, 150.0);
+        take_profit.oco_group_id = Some(1);
+        let mut stop_loss = Order::stop_sell("ABC", 10.0, 99.0);
+        stop_loss.oco_group_id = Some(1);
+        brkr.send_order(take_profit);
+        brkr.send_order(stop_loss);
+        brkr.check().await;
+        assert_eq!(brkr.get_open_orders().len(), 2);
+        assert_eq!(*brkr.get_pending_orders().get("ABC").unwrap(), -20.0);
+
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), None);
+        assert!(brkr.get_open_orders().is_empty());
+        assert!(brkr.get_pending_orders().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_that_iceberg_order_stays_open_until_reserve_is_used() {
+        let mut source = Penelope::new();
+        for date in 100..105 {
+            source.add_quote(100.00, 101.00, date, "ABC");
+        }
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+
+        brkr.send_order(Order::iceberg_buy("ABC", 25.0, 10.0, 105.0));
+        assert_eq!(*brkr.get_pending_orders().get("ABC").unwrap(), 25.0);
+        brkr.check().await;
+
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), Some(10.0));
+        assert_eq!(brkr.get_open_orders().len(), 1);
+        assert_eq!(*brkr.get_pending_orders().get("ABC").unwrap(), 15.0);
+
+        brkr.check().await;
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), Some(25.0));
+        assert!(brkr.get_open_orders().is_empty());
+        assert!(brkr.get_pending_orders().is_empty());
+    }
+
+    async fn setup_constant_price() -> UistBroker<TestClient> {
+        let mut source = Penelope::new();
+        for date in 100..106 {
+            source.add_quote(100.00, 101.00, date, "ABC");
+        }
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_that_twap_order_is_sent_in_equal_slices() {
+        let mut brkr = setup_constant_price().await;
+        brkr.deposit_cash(&100_000.0);
+
+        let res = brkr.send_twap_order(Order::market_buy("ABC", 25.0), 3);
+        assert!(matches!(res, UistBrokerEvent::TwapScheduled(..)));
+        assert_eq!(brkr.get_twap_orders().len(), 1);
+
+        brkr.check().await;
+        assert_eq!(brkr.get_twap_orders()[0].remaining_qty, 7.0);
+        assert!(brkr.get_twap_events().is_empty());
+        brkr.check().await;
+        assert!(brkr.get_twap_orders().is_empty());
+        let events = brkr.get_twap_events();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], UistBrokerEvent::TwapCompleted(..)));
+        brkr.check().await;
+        assert!(brkr.get_twap_events().is_empty());
+        brkr.check().await;
+
+        let trades = brkr.trades_between(&i64::MIN, &i64::MAX);
+        let quantities = trades
+            .iter()
+            .map(|trade| trade.quantity)
+            .collect::<Vec<_>>();
+        assert_eq!(quantities, vec![9.0, 9.0, 7.0]);
+        assert_eq!(brkr.get_position_qty("ABC"), Some(25.0));
+    }
+
+    #[tokio::test]
+    async fn test_that_twap_order_is_cancelled_when_cash_runs_out() {
+        let mut brkr = setup_constant_price().await;
+        //Covers two slices of 10 at 101
+        brkr.deposit_cash(&2100.0);
+
+        brkr.send_twap_order(Order::market_buy("ABC", 30.0), 3);
+        brkr.check().await;
+        brkr.check().await;
+        let events = brkr.get_twap_events();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], UistBrokerEvent::TwapCancelled(..)));
+        brkr.check().await;
+        brkr.check().await;
+        assert!(brkr.get_twap_orders().is_empty());
+        assert_eq!(brkr.get_position_qty("ABC"), Some(20.0));
+        assert_eq!(brkr.get_cash_balance(), 80.0);
+
+        let res = brkr.send_twap_order(Order::market_buy("ABC", 30.0), 3);
+        assert!(matches!(res, UistBrokerEvent::TwapCancelled(..)));
+
+        //Single slice is the whole order
+        let res = brkr.send_twap_order(Order::market_sell("ABC", 5.0), 1);
+        assert!(matches!(res, UistBrokerEvent::TwapCompleted(..)));
+    }
+
+    #[tokio::test]
+    async fn test_that_vwap_order_is_sized_by_volume() {
+        let mut source = Penelope::new();
+        for (date, volume) in [(100, 100.0), (101, 100.0), (102, 0.0), (103, 200.0)] {
+            source.add_quote_with_volume(100.00, 101.00, date, "ABC", volume);
+        }
+        source.add_quote(100.00, 101.00, 104, "ABC");
+        source.add_quote(100.00, 101.00, 105, "ABC");
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+
+        let res = brkr.send_vwap_order(Order::market_buy("ABC", 25.0), 0.1);
+        assert!(matches!(res, UistBrokerEvent::OrderCreated(..)));
+
+        //Slices of 10 on 101, none on 102, remainder of 15 on 103 capped from 20
+        brkr.check().await;
+        assert_eq!(brkr.get_vwap_orders()[0].remaining_qty, 15.0);
+        brkr.check().await;
+        assert_eq!(brkr.get_vwap_orders()[0].remaining_qty, 15.0);
+        brkr.check().await;
+        assert!(brkr.get_vwap_orders().is_empty());
+        brkr.check().await;
+        brkr.check().await;
+
+        let trades = brkr.trades_between(&i64::MIN, &i64::MAX);
+        let quantities = trades
+            .iter()
+            .map(|trade| trade.quantity)
+            .collect::<Vec<_>>();
+        assert_eq!(quantities, vec![10.0, 15.0]);
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_holds_short_positions_with_margin() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&1_000.0);
+
+        //Short selling is disabled by default
+        let res = brkr.send_order(Order::market_sell("ABC", 10.0));
+        assert!(matches!(res, UistBrokerEvent::OrderInvalid(..)));
+
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 101.00, 100, "ABC");
+        source.add_quote(104.00, 105.00, 101, "ABC");
+        source.add_quote(95.00, 96.00, 102, "ABC");
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .with_short_margin_requirement(1.5)
+            .build()
+            .await;
+        brkr.deposit_cash(&1_000.0);
+
+        //Margin of 1.5 * 10 * 101 is more than cash
+        let res = brkr.send_order(Order::market_sell("ABC", 10.0));
+        assert!(matches!(res, UistBrokerEvent::OrderInvalid(..)));
+        assert_eq!(
+            brkr.get_rejection_reasons_summary()
+                .get("InsufficientMargin"),
+            Some(&1)
+        );
+
+        let res = brkr.send_order(Order::market_sell("ABC", 5.0));
+        assert!(matches!(res, UistBrokerEvent::OrderSentToExchange(..)));
+        brkr.check().await;
+        brkr.check().await;
+
+        assert_eq!(brkr.get_position_qty("ABC"), Some(-5.0));
+        assert!(brkr.is_short("ABC"));
+        //Sold at 104 and valued at the ask of 96
+        assert_eq!(brkr.get_cash_balance(), 1_520.0);
+        assert_eq!(brkr.get_position_value("ABC"), Some(-480.0));
+        assert_eq!(brkr.get_total_value(), 1_040.0);
+        assert!(brkr.health_check().is_healthy);
+    }
+
+    #[derive(Debug)]
+    struct ConstantFundingRate(f64);
+
+    impl FundingRateSource for ConstantFundingRate {
+        fn get_funding_rate(&self, _symbol: &str, _date: DateTime) -> f64 {
+            self.0
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct FixedCorporateActions {
+        splits: Vec<Split>,
+        acquisitions: Vec<CashAcquisition>,
+        spinoffs: Vec<SpinOff>,
+    }
+
+    impl CorporateActionSource for FixedCorporateActions {
+        fn get_splits(&self) -> Vec<Split> {
+            self.splits.clone()
+        }
+
+        fn get_cash_acquisitions(&self) -> Vec<CashAcquisition> {
+            self.acquisitions.clone()
+        }
+
+        fn get_spinoffs(&self) -> Vec<SpinOff> {
+            self.spinoffs.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_that_split_scales_quantity_and_cost_basis() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 101.00, 100, "ABC");
+        source.add_quote(100.00, 101.00, 101, "ABC");
+        source.add_quote(50.00, 51.00, 102, "ABC");
+        source.add_quote(50.00, 51.00, 103, "ABC");
+
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let split = Split {
+            symbol: "ABC".to_string(),
+            ratio: 2.0,
+            date: 102,
+        };
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .with_corporate_action_source(Box::new(FixedCorporateActions {
+                splits: vec![split],
+                ..Default::default()
+            }))
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+
+        brkr.send_order(Order::market_buy("ABC", 100.0));
+        brkr.check().await;
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), Some(200.0));
+        assert_eq!(brkr.log.cost_basis("ABC"), Some(50.5));
+
+        //Splits are only applied once
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), Some(200.0));
+    }
+
+    #[tokio::test]
+    async fn test_that_cash_acquisition_closes_position() {
+        let mut source = Penelope::new();
+        for date in 100..104 {
+            source.add_quote(100.00, 101.00, date, "ABC");
+        }
+
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let acquisition = CashAcquisition {
+            target_symbol: "ABC".to_string(),
+            price_per_share: 120.0,
+            date: 103,
+        };
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .with_corporate_action_source(Box::new(FixedCorporateActions {
+                acquisitions: vec![acquisition],
+                ..Default::default()
+            }))
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+
+        brkr.send_order(Order::market_buy("ABC", 100.0));
+        brkr.check().await;
+        brkr.check().await;
+        brkr.send_order(Order::limit_sell("ABC", 100.0, 200.0));
+        assert_eq!(brkr.get_position_qty("ABC"), Some(100.0));
+
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), None);
+        assert_eq!(brkr.get_cash_balance(), 100_000.0 - 10_100.0 + 12_000.0);
+        assert!(brkr.get_open_orders().is_empty());
+        assert!(brkr.pending_orders.is_empty());
+        let gains = brkr.log.realized_gains_between(&103, &103);
+        assert_eq!(gains.len(), 1);
+        assert_eq!(gains[0].gain(), 1_900.0);
+    }
+
+    #[tokio::test]
+    async fn test_that_spinoff_creates_position_and_splits_cost_basis() {
+        let mut source = Penelope::new();
+        for date in 100..104 {
+            source.add_quote(100.00, 101.00, date, "ABC");
+        }
+
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let spinoff = SpinOff {
+            parent_symbol: "ABC".to_string(),
+            spinco_symbol: "XYZ".to_string(),
+            ratio: 0.5,
+            cost_basis_allocation: 0.2,
+            date: 103,
+        };
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .with_corporate_action_source(Box::new(FixedCorporateActions {
+                spinoffs: vec![spinoff],
+                ..Default::default()
+            }))
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+
+        brkr.send_order(Order::market_buy("ABC", 100.0));
+        brkr.check().await;
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("XYZ"), None);
+
+        //XYZ has no quotes
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), Some(100.0));
+        assert_eq!(brkr.get_position_qty("XYZ"), Some(50.0));
+        assert_eq!(brkr.log.cost_basis("ABC"), Some(101.0 * 0.8));
+        assert_eq!(brkr.log.cost_basis("XYZ"), Some(40.4));
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_delays_orders_by_execution_delay() {
+        let mut source = Penelope::new();
+        for date in 100..106 {
+            source.add_quote(100.00, 101.00, date, "ABC");
+        }
+
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .with_execution_delay_ticks(2)
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+
+        brkr.send_order(Order::market_buy("ABC", 10.0));
+        brkr.check().await;
+        brkr.check().await;
+        assert!(brkr.get_open_orders().is_empty());
+        assert_eq!(brkr.get_holdings_with_pending().get("ABC"), Some(&10.0));
+
+        //Sent to exchange on the third check, executes on the fourth
+        brkr.check().await;
+        assert_eq!(brkr.get_open_orders().len(), 1);
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), Some(10.0));
+        //Without delay the order would execute at 101
+        let trades = brkr.trades_between(&i64::MIN, &i64::MAX);
+        assert_eq!(trades[0].date, 103);
+    }
+
+    #[tokio::test]
+    async fn test_that_oto_secondary_is_sent_when_primary_executes() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&100_000.0);
+
+        let res = brkr.send_oto_order(
+            Order::market_buy("ABC", 10.0),
+            Order::stop_sell("ABC", 10.0, 99.0),
+        );
+        assert_eq!(res.id, Some(0));
+        assert_eq!(brkr.get_oto_orders().len(), 1);
+
+        //Primary is inserted on the first check and executes on the second
+        brkr.check().await;
+        assert_eq!(brkr.get_open_orders().len(), 1);
+        assert_eq!(brkr.get_position_qty("ABC"), None);
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), Some(10.0));
+        assert!(brkr.get_oto_orders().is_empty());
+
+        brkr.check().await;
+        let open = brkr.get_open_orders();
+        assert_eq!(open.len(), 1);
+        assert_eq!(*open[0].get_order_type(), OrderType::StopSell);
+
+        //Price falls to 95 so stop executes
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), None);
+        assert!(brkr.get_open_orders().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_that_oto_pair_is_discarded_if_primary_rejected() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&100.0);
+
+        let res = brkr.send_oto_order(
+            Order::market_buy("ABC", 10.0),
+            Order::stop_sell("ABC", 10.0, 99.0),
+        );
+        assert_eq!(res.id, None);
+        assert!(matches!(res.primary, UistBrokerEvent::OrderInvalid(..)));
+        assert!(brkr.get_oto_orders().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_pays_funding_on_positions() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 101.00, 100, "ABC");
+        source.add_quote(104.00, 105.00, 101, "ABC");
+        source.add_quote(95.00, 96.00, 102, "ABC");
+
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .with_funding_rate_source(Box::new(ConstantFundingRate(0.01)))
+            .build()
+            .await;
+
+        brkr.deposit_cash(&100_000.0);
+        brkr.send_order(Order::market_buy("ABC", 100.0));
+        brkr.check().await;
+        assert_eq!(brkr.get_funding_rate_cost(), 0.0);
+
+        //Trade executes at 105, funding is charged on the mid of the next quote
+        brkr.check().await;
+        assert_eq!(brkr.get_funding_rate_cost(), 95.5);
+        assert_eq!(brkr.get_cash_balance(), 100_000.0 - 10_500.0 - 95.5);
+    }
+
+    #[tokio::test]
+    async fn test_that_trades_in_other_currency_settle_against_that_bucket() {
+        let eur = CurrencyCode::new("EUR");
+        let mut source = Penelope::new();
+        for date in 100..103 {
+            source.add_quote(100.0, 101.0, date, "ABC");
+            source.add_quote(1.0, 1.5, date, "EURUSD");
+        }
+        let mut brkr = BacktestBuilder::new()
+            .with_source(source)
+            .with_additional_currency(eur.clone(), 1_000.0)
+            .with_symbol_currency("ABC", eur.clone())
+            .build()
+            .await;
+
+        //EUR is valued at the mid of EURUSD
+        assert_eq!(brkr.get_cash_balance(), 1_250.0);
+        brkr.deposit_cash(&100.0);
+
+        brkr.send_order(Order::market_buy("ABC", 5.0));
+        brkr.check().await;
+        brkr.check().await;
+
+        assert_eq!(brkr.get_position_qty("ABC").unwrap(), 5.0);
+        assert_eq!(brkr.get_cash_balance_in(&eur), 495.0);
+        assert_eq!(brkr.get_cash_balance_in(&brkr.get_base_currency()), 100.0);
+        assert_eq!(brkr.get_cash_balance(), 100.0 + 495.0 * 1.25);
+
+        assert!(matches!(
+            brkr.withdraw_cash_in(&eur, &500.0),
+            BrokerCashEvent::WithdrawFailure(..)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_that_buy_in_other_currency_is_checked_against_that_bucket() {
+        let eur = CurrencyCode::new("EUR");
+        let mut source = Penelope::new();
+        for date in 100..103 {
+            source.add_quote(100.0, 101.0, date, "ABC");
+            source.add_quote(1.0, 1.5, date, "EURUSD");
+        }
+        let mut brkr = BacktestBuilder::new()
+            .with_source(source)
+            .with_additional_currency(eur.clone(), 1_000.0)
+            .with_symbol_currency("ABC", eur.clone())
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+
+        //Total balance covers the order but the EUR bucket doesn't
+        let res = brkr.send_order(Order::market_buy("ABC", 20.0));
+        assert!(matches!(res, UistBrokerEvent::OrderInvalid(..)));
+        let res = brkr.send_order(Order::market_buy("ABC", 5.0));
+        assert!(matches!(res, UistBrokerEvent::OrderSentToExchange(..)));
+    }
+
+    #[tokio::test]
+    async fn test_that_currency_conversion_uses_fx_quote() {
+        let eur = CurrencyCode::new("EUR");
+        let jpy = CurrencyCode::new("JPY");
+        let mut source = Penelope::new();
+        for date in 100..103 {
+            source.add_quote(100.0, 101.0, date, "ABC");
+            source.add_quote(1.0, 1.5, date, "EURUSD");
+        }
+        let mut brkr = BacktestBuilder::new()
+            .with_source(source)
+            .with_additional_currency(eur.clone(), 1_000.0)
+            .with_symbol_currency("ABC", eur.clone())
+            .build()
+            .await;
+        let usd = brkr.get_base_currency();
+
+        assert!(matches!(
+            brkr.convert_currency(eur.clone(), usd.clone(), 400.0),
+            BrokerCashEvent::WithdrawSuccess(..)
+        ));
+        assert_eq!(brkr.get_cash_balance_in(&eur), 600.0);
+        assert_eq!(brkr.get_cash_balance_in(&usd), 500.0);
+        assert_eq!(brkr.log.fx_conversions()[0].3, 1.25);
+
+        assert!(matches!(
+            brkr.convert_currency(jpy, usd.clone(), 100.0),
+            BrokerCashEvent::WithdrawFailure(..)
+        ));
+        assert!(matches!(
+            brkr.convert_currency(eur.clone(), usd, 601.0),
+            BrokerCashEvent::WithdrawFailure(..)
+        ));
+
+        //Position value is converted from EUR
+        brkr.send_order(Order::market_buy("ABC", 5.0));
+        brkr.check().await;
+        brkr.check().await;
+        assert_eq!(brkr.get_position_value("ABC").unwrap(), 5.0 * 100.0 * 1.25);
+    }
+
+    #[tokio::test]
+    async fn test_that_interest_accrues_on_cash() {
+        let year = 365 * 86400;
+        let mut source = Penelope::new();
+        for date in [0, year, 2 * year] {
+            source.add_quote(100.0, 101.0, date, "ABC");
+        }
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .with_interest_model(InterestModel::AnnualRate(0.05))
+            .build()
+            .await;
+        brkr.deposit_cash(&1_000.0);
+
+        brkr.check().await;
+        brkr.check().await;
+
+        assert_eq!(brkr.get_cash_balance(), 1_050.0);
+        let payments = brkr.log.interest_payments_between(&0, &(2 * year));
+        assert_eq!(payments.len(), 1);
+        assert_eq!(payments[0].amount, 50.0);
+    }
+
+    #[tokio::test]
+    async fn test_that_dollar_order_resolves_to_fractional_shares() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&100_000.0);
+
+        //1% cost is taken from the notional before dividing by the ask
+        let res = brkr.send_dollar_order(Order::dollar_buy("ABC", 1_000.0));
+        assert!(matches!(res, UistBrokerEvent::OrderSentToExchange(..)));
+        brkr.check().await;
+        brkr.check().await;
+        let qty = brkr.get_position_qty("ABC").unwrap();
+        assert!((qty - 990.0 / 101.0).abs() < 1e-9);
+
+        let res = brkr.send_dollar_order(Order::dollar_buy("XYZ", 1_000.0));
+        assert!(matches!(res, UistBrokerEvent::OrderInvalid(..)));
+    }
+
+    #[tokio::test]
+    async fn test_that_pending_uses_quantity_adjusted_by_exchange_policy() {
+        let mut source = Penelope::new();
+        for date in 100..103 {
+            source.add_quote(100.0, 101.0, date, "ABC");
+        }
+        let mut policy = ExchangePolicy::default();
+        policy.min_lot_size.insert("ABC".to_string(), 100.0);
+        let mut client = TestClient::single_with_policy("Random", source, policy);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+
+        brkr.send_order(Order::market_buy("ABC", 150.0));
+        brkr.check().await;
+        assert_eq!(*brkr.get_holdings_with_pending().get("ABC").unwrap(), 100.0);
 
-impl Default for UistBrokerLog {
-    fn default() -> Self {
-        Self::new()
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC").unwrap(), 100.0);
+        assert!(brkr.pending_orders.is_empty());
     }
-}
 
-#[cfg(test)]
-mod tests {
+    #[tokio::test]
+    async fn test_that_broker_reports_symbols_halted_by_exchange() {
+        let mut source = Penelope::new();
+        source.add_quote(100.0, 101.0, 100, "ABC");
+        source.add_quote(150.0, 151.0, 101, "ABC");
+        source.add_quote(150.0, 151.0, 102, "ABC");
+        let state = AppState::single("Random", source).with_circuit_breaker(CircuitBreaker {
+            halt_threshold_pct: 0.1,
+            cooldown_ticks: 2,
+        });
+        let mut client = TestClient::from_state(state);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
 
-    use std::collections::HashMap;
+        brkr.check().await;
+        assert!(brkr.get_halted_symbols().is_empty());
+        brkr.check().await;
+        assert!(matches!(
+            brkr.get_halted_symbols()[0],
+            UistBrokerEvent::SymbolHalted(_, 1)
+        ));
+    }
 
-    use crate::broker::{
-        BrokerCashEvent, BrokerCost, BrokerOperations, CashOperations, Portfolio, SendOrder, Update,
-    };
-    use rotala::exchange::uist_v1::{Order, OrderType, Trade, TradeType, UistV1};
-    use rotala::http::uist::uistv1_client::{Client, TestClient, UistClient};
-    use rotala::input::penelope::Penelope;
+    #[tokio::test]
+    async fn test_that_after_hours_orders_are_not_pending_until_session_opens() {
+        let mut source = Penelope::new();
+        for date in [0, 1, 34200, 34201] {
+            source.add_quote(100.0, 101.0, date, "ABC");
+        }
+        let state = AppState::single("Random", source).with_session_hours(SessionHours {
+            market_open_offset_secs: 34200,
+            market_close_offset_secs: 57600,
+        });
+        let mut client = TestClient::from_state(state);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
 
-    use super::{UistBroker, UistBrokerBuilder, UistBrokerEvent, UistBrokerLog};
+        brkr.send_order(Order::market_buy("ABC", 100.0));
+        brkr.check().await;
+        assert!(brkr.pending_orders.is_empty());
+        brkr.check().await;
+        assert!(brkr.pending_orders.is_empty());
 
-    async fn setup() -> UistBroker<TestClient> {
-        let mut source = Penelope::new();
+        brkr.check().await;
+        assert_eq!(brkr.pending_orders.get("ABC"), Some(&100.0));
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), Some(100.0));
+        assert!(brkr.pending_orders.is_empty());
+    }
 
-        source.add_quote(100.00, 101.00, 100, "ABC");
-        source.add_quote(10.00, 11.00, 100, "BCD");
+    #[tokio::test]
+    async fn test_that_order_book_snapshot_is_top_of_book() {
+        let brkr = setup().await;
+
+        let snapshot = brkr.get_order_book_snapshot("ABC").unwrap();
+        assert_eq!(snapshot.bids.len(), 1);
+        assert_eq!(snapshot.asks.len(), 1);
+        assert_eq!(snapshot.bids[0].price, 100.0);
+        assert_eq!(snapshot.asks[0].price, 101.0);
+        assert_eq!(*snapshot.date, 100);
+        assert!(brkr.get_order_book_snapshot("XYZ").is_none());
+    }
 
-        source.add_quote(104.00, 105.00, 101, "ABC");
-        source.add_quote(14.00, 15.00, 101, "BCD");
+    #[tokio::test]
+    async fn test_that_trades_settle_after_lag() {
+        //Thursday, Friday, Monday, Tuesday
+        let mut source = Penelope::new();
+        for date in [0, 86400, 4 * 86400, 5 * 86400] {
+            source.add_quote(100.0, 101.0, date, "ABC");
+        }
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .with_settlement_lag(2)
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
 
-        source.add_quote(95.00, 96.00, 102, "ABC");
-        source.add_quote(10.00, 11.00, 102, "BCD");
+        brkr.send_order(Order::market_buy("ABC", 100.0));
+        brkr.check().await;
+        //Executes on Friday, settles on Tuesday
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), None);
+        assert_eq!(brkr.get_unsettled_cash(), -10_100.0);
+        assert_eq!(brkr.get_settled_cash(), 100_000.0);
+        assert_eq!(brkr.pending_orders.get("ABC"), Some(&100.0));
 
-        source.add_quote(95.00, 96.00, 103, "ABC");
-        source.add_quote(10.00, 11.00, 103, "BCD");
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), Some(100.0));
+        assert_eq!(brkr.get_unsettled_cash(), 0.0);
+        assert_eq!(brkr.get_settled_cash(), 100_000.0 - 10_100.0);
+        assert!(brkr.pending_orders.is_empty());
+    }
 
+    #[tokio::test]
+    async fn test_that_unsettled_buys_reserve_cash() {
+        let mut source = Penelope::new();
+        for date in [0, 86400, 2 * 86400, 3 * 86400] {
+            source.add_quote(100.0, 101.0, date, "ABC");
+        }
         let mut client = TestClient::single("Random", source);
         let resp = client.init("Random".to_string()).await.unwrap();
-
-        UistBrokerBuilder::new()
-            .with_trade_costs(vec![BrokerCost::PctOfValue(0.01)])
+        let mut brkr = UistBrokerBuilder::new()
             .with_client(client, resp.backtest_id)
+            .with_settlement_lag(2)
             .build()
-            .await
+            .await;
+        brkr.deposit_cash(&15_000.0);
+
+        brkr.send_order(Order::market_buy("ABC", 100.0));
+        brkr.check().await;
+        brkr.check().await;
+        assert_eq!(brkr.get_unsettled_cash(), -10_100.0);
+        assert_eq!(brkr.get_settled_cash(), 15_000.0);
+
+        //Only 4_900 is left once the unsettled buy is reserved
+        let res = brkr.send_order(Order::market_buy("ABC", 100.0));
+        assert!(matches!(res, UistBrokerEvent::OrderInvalid(..)));
     }
 
     #[tokio::test]
@@ -554,6 +4029,148 @@ mod tests {
         assert!(cash == 100.0);
     }
 
+    #[tokio::test]
+    async fn test_that_buy_order_cannot_breach_min_cash_balance() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 100.00, 100, "ABC");
+        source.add_quote(100.00, 100.00, 101, "ABC");
+        source.add_quote(100.00, 100.00, 102, "ABC");
+
+        let mut client = TestClient::single("MinCash", source);
+        let resp = client.init("MinCash".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .with_min_cash_balance(10_000.0)
+            .build()
+            .await;
+        brkr.deposit_cash(&15_000.0);
+
+        let res = brkr.send_order(Order::market_buy("ABC", 51.0));
+        assert!(matches!(res, UistBrokerEvent::OrderInvalid(..)));
+        assert_eq!(
+            brkr.client_has_sufficient_cash::<OrderType>(&Order::market_buy("ABC", 51.0), &100.0),
+            Err(BrokerError::MinCashBalanceBreached(5100.0))
+        );
+
+        let res = brkr.send_order(Order::market_buy("ABC", 50.0));
+        assert!(matches!(res, UistBrokerEvent::OrderSentToExchange(..)));
+        brkr.check().await;
+        brkr.check().await;
+
+        assert_eq!(brkr.get_position_qty("ABC"), Some(50.0));
+        assert_eq!(brkr.get_cash_balance(), 10_000.0);
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_publishes_fills_and_cash_changes() {
+        let mut brkr = setup().await;
+        let mut events = brkr.subscribe_events();
+
+        brkr.deposit_cash(&100_000.0);
+        brkr.send_order(Order::market_buy("ABC", 100.0));
+        brkr.check().await;
+        brkr.check().await;
+
+        let mut trades = Vec::new();
+        let mut cash_changes = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            match event {
+                BrokerBusEvent::Recorded(UistRecordedEvent::TradeCompleted(trade)) => {
+                    trades.push(trade)
+                }
+                BrokerBusEvent::CashChanged(_currency, delta) => cash_changes.push(delta),
+                _ => (),
+            }
+        }
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].symbol, "ABC");
+        //Deposit then payment for the trade
+        assert_eq!(cash_changes.len(), 2);
+        assert_eq!(cash_changes[0], 100_000.0);
+        assert_eq!(cash_changes[1], -trades[0].value);
+    }
+
+    #[tokio::test]
+    async fn test_that_single_name_cap_trims_order() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 101.00, 100, "ABC");
+        source.add_quote(10.00, 11.00, 100, "BCD");
+        source.add_quote(100.00, 101.00, 101, "ABC");
+        source.add_quote(10.00, 11.00, 101, "BCD");
+        source.add_quote(100.00, 101.00, 102, "ABC");
+        source.add_quote(10.00, 11.00, 102, "BCD");
+
+        let mut client = TestClient::single("Constraints", source);
+        let resp = client.init("Constraints".to_string()).await.unwrap();
+        let mut constraints = PortfolioConstraints::default();
+        constraints.max_position_pct.insert("ABC".to_string(), 0.2);
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .with_constraints(constraints)
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+
+        //20% of 100_000 at the ask of 101 is 198.02 shares
+        let res = brkr.send_order(Order::market_buy("ABC", 500.0));
+        match res {
+            UistBrokerEvent::OrderTrimmed(original, trimmed) => {
+                assert_eq!(original.get_shares(), 500.0);
+                assert_eq!(trimmed.get_shares(), 198.0);
+            }
+            _ => panic!("Expected order to be trimmed"),
+        }
+
+        //Pending orders count towards the cap so there is no room left
+        let res = brkr.send_order(Order::market_buy("ABC", 10.0));
+        assert!(matches!(res, UistBrokerEvent::OrderInvalid(..)));
+        assert_eq!(
+            brkr.get_rejection_reasons_summary()
+                .get("ConstraintBreached"),
+            Some(&1)
+        );
+
+        //Symbols without a limit are unaffected
+        let res = brkr.send_order(Order::market_buy("BCD", 100.0));
+        assert!(matches!(res, UistBrokerEvent::OrderSentToExchange(..)));
+
+        brkr.check().await;
+        brkr.check().await;
+        assert_eq!(brkr.get_position_qty("ABC"), Some(198.0));
+        assert_eq!(brkr.get_position_qty("BCD"), Some(100.0));
+    }
+
+    #[tokio::test]
+    async fn test_that_broker_logs_rejected_orders() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&1_000.0);
+        brkr.update_holdings("BCD", 5.0);
+
+        brkr.send_order(Order::market_buy("ABC", 5.0));
+        brkr.send_order(Order::market_buy("ABC", 100.0));
+        brkr.send_order(Order::market_sell("BCD", 10.0));
+        brkr.send_order(Order::market_buy("ABC", 0.0));
+        assert_eq!(brkr.get_order_rejection_rate(), 0.75);
+
+        let summary = brkr.get_rejection_reasons_summary();
+        assert_eq!(summary.get("InsufficientCash"), Some(&1));
+        assert_eq!(summary.get("InsufficientHoldings"), Some(&1));
+        assert_eq!(summary.get("UnexecutableOrder"), Some(&1));
+
+        brkr.check().await;
+        brkr.send_order(Order::market_buy("ABC", 100.0));
+        assert_eq!(
+            brkr.get_rejection_log_between(100.into(), 100.into()).len(),
+            3
+        );
+        let later = brkr.get_rejection_log_between(101.into(), 101.into());
+        assert_eq!(
+            later,
+            vec![(101.into(), BrokerError::InsufficientCash(10_500.0))]
+        );
+    }
+
     #[tokio::test]
     async fn test_that_sell_order_larger_than_holding_fails_with_error_returned_without_panic() {
         let mut brkr = setup().await;
@@ -822,6 +4439,66 @@ mod tests {
         assert!(between.len() == 3);
     }
 
+    #[test]
+    fn test_that_logs_merge_in_date_order() {
+        let first = setup_log();
+        let mut second = UistBrokerLog::new();
+        second.record(Trade::new("ABC", 100.0, 10.00, 100, TradeType::Buy));
+        second.record(super::UistRecordedEvent::FundingPayment(
+            "ABC".to_string(),
+            5.0,
+            101,
+        ));
+        second.record(Trade::new("CDE", 200.0, 20.00, 99, TradeType::Buy));
+
+        let merged = UistBrokerLog::merge(&[&first, &second]);
+        assert_eq!(merged.log.len(), first.log.len() + second.log.len());
+        assert_eq!(merged.funding_payments().len(), 1);
+        let dates: Vec<i64> = merged.log.iter().map(|event| event.date()).collect();
+        assert!(dates.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(merged.trades().first().unwrap().symbol, "CDE");
+
+        //ABC buy on 100 is in both logs
+        let deduped = UistBrokerLog::merge_dedup(&[&first, &second]);
+        assert_eq!(deduped.log.len(), merged.log.len() - 1);
+        assert_eq!(deduped.trades_between(&100, &100).len(), 1);
+        assert_eq!(deduped.funding_payments().len(), 1);
+    }
+
+    #[test]
+    fn test_that_merged_logs_keep_realized_gains_of_each_log() {
+        let mut first = UistBrokerLog::with_cost_basis_method(CostBasisMethod::Lifo);
+        first.record(Trade::new("ABC", 1000.0, 10.0, 100, TradeType::Buy));
+        first.record(Trade::new("ABC", 2000.0, 10.0, 101, TradeType::Buy));
+        first.record(Trade::new("ABC", 1500.0, 10.0, 102, TradeType::Sell));
+        //Sale without a purchase in this log is a short, there is no gain
+        let mut second = UistBrokerLog::new();
+        second.record(Trade::new("ABC", 1500.0, 10.0, 102, TradeType::Sell));
+
+        let merged = UistBrokerLog::merge(&[&first, &second]);
+        let gains = merged.realized_gains_between(&i64::MIN, &i64::MAX);
+        assert_eq!(gains, first.realized_gains_between(&i64::MIN, &i64::MAX));
+        //LIFO sells the lot bought at 200
+        assert_eq!(gains[0].gain(), -500.0);
+        assert_eq!(merged.cost_basis("ABC"), None);
+
+        let deduped = UistBrokerLog::merge_dedup(&[&first, &first]);
+        assert_eq!(deduped.realized_gains_between(&i64::MIN, &i64::MAX), gains);
+    }
+
+    #[test]
+    fn test_that_log_resets_first_purchase_date_when_position_closes() {
+        let mut log = setup_log();
+        assert_eq!(log.first_purchase_date("ABC"), Some(100.into()));
+        //BCD was sold down to zero on 103 and bought again on 104
+        assert_eq!(log.first_purchase_date("BCD"), Some(104.into()));
+
+        log.record(Trade::new("ABC", 600.0, 50.0, 105, TradeType::Sell));
+        assert_eq!(log.first_purchase_date("ABC"), Some(100.into()));
+        log.record(Trade::new("ABC", 600.0, 60.0, 106, TradeType::Sell));
+        assert_eq!(log.first_purchase_date("ABC"), None);
+    }
+
     #[test]
     fn test_that_log_calculates_the_cost_basis() {
         let log = setup_log();
@@ -832,6 +4509,212 @@ mod tests {
         assert_eq!(bcd_cost, 1.0);
     }
 
+    #[test]
+    fn test_that_covering_a_short_does_not_open_a_lot() {
+        let mut log = UistBrokerLog::new();
+        log.record(Trade::new("ABC", 1000.0, 10.0, 100, TradeType::Buy));
+        //Sells the lot and goes short 10
+        log.record(Trade::new("ABC", 2400.0, 20.0, 101, TradeType::Sell));
+        assert_eq!(log.cost_basis("ABC"), None);
+        assert_eq!(log.first_purchase_date("ABC"), None);
+
+        //Partial cover
+        log.record(Trade::new("ABC", 400.0, 5.0, 102, TradeType::Buy));
+        assert_eq!(log.cost_basis("ABC"), None);
+        assert_eq!(log.first_purchase_date("ABC"), None);
+
+        //Covers the remaining 5 and opens a long of 5
+        log.record(Trade::new("ABC", 900.0, 10.0, 103, TradeType::Buy));
+        assert_eq!(log.cost_basis("ABC"), Some(90.0));
+        assert_eq!(log.first_purchase_date("ABC"), Some(103.into()));
+
+        log.record(Trade::new("ABC", 500.0, 5.0, 104, TradeType::Sell));
+        assert_eq!(log.cost_basis("ABC"), None);
+        let gains = log.realized_gains_between(&104, &104);
+        assert_eq!(gains.len(), 1);
+        assert_eq!(gains[0].basis, 450.0);
+    }
+
+    #[test]
+    fn test_that_log_realizes_gains_first_in_first_out() {
+        let mut log = UistBrokerLog::new();
+        log.record(Trade::new("ABC", 1000.0, 10.0, 100, TradeType::Buy));
+        log.record(Trade::new("ABC", 2400.0, 20.0, 101, TradeType::Buy));
+        log.record(Trade::new("ABC", 1950.0, 15.0, 105, TradeType::Sell));
+
+        let gains = log.realized_gains_between(&100, &105);
+        assert_eq!(gains.len(), 2);
+        assert_eq!(gains[0].quantity, 10.0);
+        assert_eq!(gains[0].basis, 1000.0);
+        assert_eq!(gains[0].proceeds, 1300.0);
+        assert_eq!(gains[0].holding_period, 5);
+        assert_eq!(gains[1].quantity, 5.0);
+        assert_eq!(gains[1].gain(), 50.0);
+        assert_eq!(gains[1].holding_period, 4);
+
+        //Remaining lot of 15 at 120
+        log.record(Trade::new("ABC", 2100.0, 15.0, 106, TradeType::Sell));
+        let gains = log.realized_gains_between(&106, &106);
+        assert_eq!(gains.len(), 1);
+        assert_eq!(gains[0].basis, 1800.0);
+        assert!(log.realized_gains_between(&0, &99).is_empty());
+    }
+
+    #[test]
+    fn test_that_cost_basis_methods_match_different_lots() {
+        let gains = |method: CostBasisMethod| {
+            let mut log = UistBrokerLog::with_cost_basis_method(method);
+            log.record(Trade::new("ABC", 1000.0, 10.0, 100, TradeType::Buy));
+            log.record(Trade::new("ABC", 2000.0, 10.0, 101, TradeType::Buy));
+            log.record(Trade::new("ABC", 2250.0, 15.0, 102, TradeType::Sell));
+            let gain = log
+                .realized_gains_between(&100, &102)
+                .iter()
+                .map(|gain| gain.gain())
+                .sum::<f64>();
+            (gain, log.cost_basis("ABC").unwrap())
+        };
+
+        //Sale at 150 of 10 bought at 100 and 5 bought at 200
+        assert_eq!(gains(CostBasisMethod::Fifo), (250.0, 200.0));
+        assert_eq!(gains(CostBasisMethod::Lifo), (-250.0, 100.0));
+        assert_eq!(gains(CostBasisMethod::AverageCost), (0.0, 150.0));
+    }
+
+    #[tokio::test]
+    async fn test_that_sale_consumes_specified_lots() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 101.00, 100, "ABC");
+        source.add_quote(104.00, 105.00, 101, "ABC");
+        for date in 102..106 {
+            source.add_quote(95.00, 96.00, date, "ABC");
+        }
+        let mut client = TestClient::single("Random", source);
+        let resp = client.init("Random".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+
+        brkr.send_order(Order::market_buy("ABC", 10.0));
+        brkr.check().await;
+        brkr.send_order(Order::market_buy("ABC", 10.0));
+        brkr.check().await;
+        brkr.check().await;
+
+        //Bought at 105 then 96
+        let lots = brkr.query_open_lots("ABC");
+        assert_eq!(lots.len(), 2);
+        assert_eq!(lots[1].price, 96.0);
+
+        let res = brkr.send_order(Order::market_sell("ABC", 5.0).with_lot_ids(vec![7]));
+        assert!(matches!(res, UistBrokerEvent::OrderInvalid(..)));
+        assert_eq!(
+            brkr.get_rejection_reasons_summary().get("LotNotFound"),
+            Some(&1)
+        );
+
+        let lot_id = lots[1].lot_id;
+        brkr.send_order(Order::market_sell("ABC", 15.0).with_lot_ids(vec![lot_id]));
+        brkr.check().await;
+        brkr.check().await;
+
+        let gains = brkr.realized_gains_between(&i64::MIN, &i64::MAX);
+        assert_eq!(gains.len(), 2);
+        assert_eq!(gains[0].basis, 960.0);
+        assert_eq!(gains[1].basis, 525.0);
+        let lots = brkr.query_open_lots("ABC");
+        assert_eq!(lots.len(), 1);
+        assert_eq!(lots[0].qty, 5.0);
+    }
+
+    #[tokio::test]
+    async fn test_that_entry_and_breakeven_prices_match_cost_basis() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&100_000.0);
+
+        brkr.send_order(Order::market_buy("ABC", 10.0));
+        brkr.check().await;
+        brkr.send_order(Order::market_buy("ABC", 10.0));
+        brkr.check().await;
+        brkr.check().await;
+
+        //Buys execute at 105 and 96
+        assert_eq!(brkr.get_average_cost_basis("ABC").unwrap(), 100.5);
+        assert_eq!(brkr.get_average_entry_price("ABC").unwrap(), 100.5);
+        assert_eq!(brkr.get_breakeven_price("ABC").unwrap(), 100.5);
+    }
+
+    #[test]
+    fn test_that_log_calculates_trade_size_distribution() {
+        let mut log = UistBrokerLog::new();
+        for i in 1..=10 {
+            let typ = if i % 2 == 0 {
+                TradeType::Buy
+            } else {
+                TradeType::Sell
+            };
+            log.record(Trade::new("ABC", i as f64, 1.0, 100 + i, typ));
+        }
+
+        let dist = log.get_trade_size_distribution();
+        assert_eq!(dist.mean, 5.5);
+        assert_eq!(dist.median, 5.5);
+        assert_eq!(dist.min, 1.0);
+        assert_eq!(dist.max, 10.0);
+        assert_eq!(dist.percentile_25, 2.75);
+        assert_eq!(dist.percentile_75, 8.25);
+        assert_eq!(dist.percentile_95, 10.0);
+        assert_eq!((dist.std_dev * 1000.0).round(), 2872.0);
+
+        let buys = log.get_buy_size_distribution();
+        assert_eq!(buys.min, 2.0);
+        assert_eq!(buys.max, 10.0);
+        let sells = log.get_sell_size_distribution();
+        assert_eq!(sells.median, 5.0);
+
+        let empty = UistBrokerLog::new().get_trade_size_distribution();
+        assert_eq!(empty.mean, 0.0);
+    }
+
+    #[tokio::test]
+    async fn diff_includes_orders_that_have_not_executed() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&100_000.0);
+
+        let mut weights = HashMap::new();
+        weights.insert("ABC".to_string(), 0.5);
+        weights.insert("BCD".to_string(), 0.5);
+        let orders = brkr.diff_brkr_against_target_weights(&weights);
+        assert_eq!(orders.len(), 2);
+        brkr.send_orders(&orders);
+
+        //Orders are sent to the exchange but haven't executed, prices have risen so the pending
+        //orders are above target and nothing is bought again
+        brkr.check().await;
+        assert!(brkr.get_position_qty("ABC").is_none());
+        let orders = brkr.diff_brkr_against_target_weights(&weights);
+        assert!(!orders.is_empty());
+        assert!(orders
+            .iter()
+            .all(|order| order.get_order_type() == &OrderType::MarketSell));
+    }
+
+    #[tokio::test]
+    async fn pending_buys_reserve_cash() {
+        let mut brkr = setup().await;
+        brkr.deposit_cash(&100_000.0);
+
+        let res = brkr.send_order(Order::market_buy("ABC", 600.0));
+        assert!(matches!(res, UistBrokerEvent::OrderSentToExchange(..)));
+        //Cash covers this order alone but not with the pending buy of ABC
+        let res = brkr.send_order(Order::market_buy("BCD", 4_000.0));
+        assert!(matches!(res, UistBrokerEvent::OrderInvalid(..)));
+        let res = brkr.send_order(Order::market_buy("BCD", 3_000.0));
+        assert!(matches!(res, UistBrokerEvent::OrderSentToExchange(..)));
+    }
+
     #[tokio::test]
     async fn diff_direction_correct_if_need_to_buy() {
         let source = Penelope::random(100, vec!["ABC"]);
@@ -854,10 +4737,7 @@ mod tests {
 
         println!("{:?}", orders);
         let first = orders.first().unwrap();
-        assert!(matches!(
-            first.get_order_type(),
-            OrderType::MarketBuy { .. }
-        ));
+        assert!(matches!(first.get_order_type(), OrderType::MarketBuy));
     }
 
     #[tokio::test]
@@ -894,10 +4774,7 @@ mod tests {
 
         println!("{:?}", orders1);
         let first = orders1.first().unwrap();
-        assert!(matches!(
-            first.get_order_type(),
-            OrderType::MarketSell { .. }
-        ));
+        assert!(matches!(first.get_order_type(), OrderType::MarketSell));
     }
 
     #[tokio::test]
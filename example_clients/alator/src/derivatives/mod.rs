@@ -0,0 +1,77 @@
+//! Aggregates risk for portfolios that hold options alongside equities.
+//!
+//! There is no options pricing engine, option greeks are supplied by the caller.
+
+use crate::broker::PortfolioHoldings;
+
+/// Greeks are per contract, so position greeks are the greek multiplied by `qty`.
+#[derive(Clone, Debug)]
+pub struct OptionPosition {
+    pub symbol: String,
+    pub delta: f64,
+    pub gamma: f64,
+    pub theta: f64,
+    pub vega: f64,
+    pub qty: f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PortfolioGreeks {
+    pub net_delta: f64,
+    pub net_gamma: f64,
+    pub net_theta: f64,
+    pub net_vega: f64,
+}
+
+/// Equity positions contribute a delta of one per share and nothing to other greeks.
+pub fn compute_portfolio_greeks(
+    equity_positions: &PortfolioHoldings,
+    option_positions: &[OptionPosition],
+) -> PortfolioGreeks {
+    let mut greeks = PortfolioGreeks {
+        net_delta: equity_positions.values().sum(),
+        ..Default::default()
+    };
+
+    for option in option_positions {
+        greeks.net_delta += option.delta * option.qty;
+        greeks.net_gamma += option.gamma * option.qty;
+        greeks.net_theta += option.theta * option.qty;
+        greeks.net_vega += option.vega * option.qty;
+    }
+    greeks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compute_portfolio_greeks, OptionPosition, PortfolioGreeks};
+    use crate::broker::PortfolioHoldings;
+
+    #[test]
+    fn test_that_greeks_net_equity_and_options() {
+        let mut holdings = PortfolioHoldings::new();
+        holdings.insert("ABC".to_string(), 100.0);
+        holdings.insert("BCD".to_string(), 50.0);
+
+        //Short calls against the equity position
+        let options = vec![OptionPosition {
+            symbol: "ABC 110C".to_string(),
+            delta: 0.5,
+            gamma: 0.1,
+            theta: -0.2,
+            vega: 0.3,
+            qty: -100.0,
+        }];
+
+        let greeks = compute_portfolio_greeks(&holdings, &options);
+        assert_eq!(
+            greeks,
+            PortfolioGreeks {
+                net_delta: 100.0,
+                net_gamma: -10.0,
+                net_theta: 20.0,
+                net_vega: -30.0,
+            }
+        );
+    }
+}
@@ -53,6 +53,9 @@
 
 #[allow(unused)]
 pub mod broker;
+pub mod derivatives;
 pub mod perf;
 pub mod schedule;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod strategy;
@@ -1,8 +1,15 @@
 //! Generates performance stats for backtest
 
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
 use itertools::Itertools;
+use rotala::exchange::uist_v1::{Trade, TradeType};
+
+use crate::broker::{DateTime, StrategySnapshot};
 
-use crate::broker::StrategySnapshot;
+pub mod options;
+pub mod tca;
 
 ///The frequency of a process.
 #[derive(Clone, Debug)]
@@ -10,6 +17,21 @@ pub enum Frequency {
     Second,
     Daily,
     Fixed,
+    Weekly,
+    Monthly,
+}
+
+impl Frequency {
+    /// Used to annualise stats, daily is trading days. Returns `None` for frequencies that have
+    /// no fixed number of periods in a year.
+    pub fn periods_per_year(&self) -> Option<f64> {
+        match self {
+            Frequency::Daily => Some(252.0),
+            Frequency::Weekly => Some(52.0),
+            Frequency::Monthly => Some(12.0),
+            Frequency::Second | Frequency::Fixed => None,
+        }
+    }
 }
 
 impl From<Frequency> for u8 {
@@ -18,6 +40,8 @@ impl From<Frequency> for u8 {
             Frequency::Second => 0,
             Frequency::Daily => 1,
             Frequency::Fixed => 3,
+            Frequency::Weekly => 4,
+            Frequency::Monthly => 5,
         }
     }
 }
@@ -28,6 +52,55 @@ impl From<Frequency> for String {
             Frequency::Second => "SECOND".to_string(),
             Frequency::Daily => "DAILY".to_string(),
             Frequency::Fixed => "FIXED".to_string(),
+            Frequency::Weekly => "WEEKLY".to_string(),
+            Frequency::Monthly => "MONTHLY".to_string(),
+        }
+    }
+}
+
+/// Fall from a peak to the lowest point before the peak is recovered. `drawdown_pct` is negative,
+/// matching [BacktestOutput::mdd], and `recovery_date` is `None` if the peak wasn't recovered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrawdownPeriod {
+    pub peak_date: i64,
+    pub trough_date: i64,
+    pub recovery_date: Option<i64>,
+    pub drawdown_pct: f64,
+}
+
+/// Outcome of each sale against the cost basis of the position, output of
+/// [PerformanceCalculator::trade_statistics]. `avg_loss` is negative.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TradeStatistics {
+    pub n_trades: usize,
+    pub win_rate: f64,
+    pub avg_win: f64,
+    pub avg_loss: f64,
+    pub profit_factor: f64,
+    pub max_consecutive_wins: usize,
+    pub max_consecutive_losses: usize,
+}
+
+/// Settings shared by risk-adjusted return calculations, rates are annual.
+#[derive(Clone, Debug)]
+pub struct PerformanceConfig {
+    pub frequency: Frequency,
+    pub risk_free_rate: f64,
+    pub target_return: f64,
+}
+
+impl PerformanceConfig {
+    pub fn periods_per_year(&self) -> Option<f64> {
+        self.frequency.periods_per_year()
+    }
+}
+
+impl From<Frequency> for PerformanceConfig {
+    fn from(frequency: Frequency) -> Self {
+        Self {
+            frequency,
+            risk_free_rate: 0.0,
+            target_return: 0.0,
         }
     }
 }
@@ -37,9 +110,41 @@ impl From<Frequency> for String {
 pub struct BacktestOutput {
     pub ret: f64,
     pub cagr: f64,
+    //Mean period return scaled by periods per year, unlike `cagr` this isn't compounded
+    pub annualised_return: f64,
     pub vol: f64,
     pub mdd: f64,
     pub sharpe: f64,
+    pub sortino: f64,
+    //Annualised return over the absolute max drawdown
+    pub calmar: f64,
+    //Historical VaR and CVaR of period returns, expressed as positive losses
+    pub var_95: f64,
+    pub var_99: f64,
+    pub cvar_95: f64,
+    //Fraction of the portfolio traded in a year, a buy and a sell of the full portfolio is 100%
+    pub annual_turnover: f64,
+    //Annualised, None if no benchmark was passed to calculate
+    pub information_ratio: Option<f64>,
+    pub tracking_error: Option<f64>,
+    //Regression on benchmark excess returns, alpha is annualised. None without a benchmark
+    pub alpha: Option<f64>,
+    pub beta: Option<f64>,
+    pub r_squared: Option<f64>,
+    //Not calculated from snapshots, callers with per-symbol returns can fill this with
+    //PortfolioAnalytics::risk_contributions
+    pub risk_contributions: HashMap<String, f64>,
+    //Shape of period returns, zero when there are too few returns or no variance
+    pub return_skewness: f64,
+    pub return_excess_kurtosis: f64,
+    pub jarque_bera: f64,
+    //Calculated from sales against the average price paid, None if nothing was sold
+    pub trade_stats: Option<TradeStatistics>,
+    //Compound returns keyed by (year, month) and by year, calculated net of cash flows
+    pub monthly_returns: HashMap<(i32, u32), f64>,
+    pub annual_returns: HashMap<i32, f64>,
+    //Sharpe over the trailing 252 periods, None until there are enough returns
+    pub rolling_sharpe_252: Vec<Option<f64>>,
     pub values: Vec<f64>,
     pub returns: Vec<f64>,
     pub dates: Vec<i64>,
@@ -53,6 +158,94 @@ pub struct BacktestOutput {
     pub frequency: String,
 }
 
+impl BacktestOutput {
+    /// Compares period returns against a benchmark, periods are assumed to be aligned by date.
+    /// Returns [RelativeMetrics::nan_filled] if the number of periods doesn't match.
+    pub fn relative_to(&self, benchmark: &BacktestOutput) -> RelativeMetrics {
+        if self.returns.len() != benchmark.returns.len() || self.returns.is_empty() {
+            return RelativeMetrics::nan_filled();
+        }
+
+        let active = self
+            .returns
+            .iter()
+            .zip(benchmark.returns.iter())
+            .map(|(ret, bench)| ret - bench)
+            .collect_vec();
+        //Frequencies that can't be annualised produce NaN, as with the other annualised stats
+        let periods_per_year = self.periods_per_year().unwrap_or(f64::NAN);
+        let tracking_error = PerformanceCalculator::tracking_error(&active, periods_per_year);
+        let information_ratio = PerformanceCalculator::information_ratio(&active, periods_per_year);
+
+        let capture = |is_up: bool| -> f64 {
+            let (rets, bench_rets): (Vec<f64>, Vec<f64>) = self
+                .returns
+                .iter()
+                .zip(benchmark.returns.iter())
+                .filter(|(_ret, bench)| if is_up { **bench > 0.0 } else { **bench < 0.0 })
+                .map(|(ret, bench)| (*ret, *bench))
+                .unzip();
+            if rets.is_empty() {
+                return f64::NAN;
+            }
+            rets.iter().sum::<f64>() / bench_rets.iter().sum::<f64>()
+        };
+
+        let hits = active.iter().filter(|diff| **diff > 0.0).count();
+
+        RelativeMetrics {
+            excess_return: self.ret - benchmark.ret,
+            information_ratio,
+            tracking_error,
+            up_capture: capture(true),
+            down_capture: capture(false),
+            hit_rate: hits as f64 / active.len() as f64,
+        }
+    }
+
+    //Frequency is stored by name, see `From<Frequency> for String`
+    fn periods_per_year(&self) -> Option<f64> {
+        let frequency = match self.frequency.as_str() {
+            "SECOND" => Frequency::Second,
+            "DAILY" => Frequency::Daily,
+            "WEEKLY" => Frequency::Weekly,
+            "MONTHLY" => Frequency::Monthly,
+            _ => Frequency::Fixed,
+        };
+        frequency.periods_per_year()
+    }
+}
+
+/// Performance relative to a benchmark, output of [BacktestOutput::relative_to].
+///
+/// Tracking error and information ratio are annualised with the frequency of the strategy, so
+/// they match [BacktestOutput::tracking_error] calculated with the same benchmark. Capture ratios are the
+/// mean strategy return divided by the mean benchmark return in periods when the benchmark rose or
+/// fell, and are NaN if there are no such periods. Hit rate is the fraction of periods in which
+/// the strategy outperformed.
+#[derive(Clone, Debug)]
+pub struct RelativeMetrics {
+    pub excess_return: f64,
+    pub information_ratio: f64,
+    pub tracking_error: f64,
+    pub up_capture: f64,
+    pub down_capture: f64,
+    pub hit_rate: f64,
+}
+
+impl RelativeMetrics {
+    pub fn nan_filled() -> Self {
+        Self {
+            excess_return: f64::NAN,
+            information_ratio: f64::NAN,
+            tracking_error: f64::NAN,
+            up_capture: f64::NAN,
+            down_capture: f64::NAN,
+            hit_rate: f64::NAN,
+        }
+    }
+}
+
 /// Group of functions common to portfolio performance calculations.
 struct CalculationAlgos;
 
@@ -60,27 +253,29 @@ impl CalculationAlgos {
     /// Returns a tuple containing (max drawdown, position of drawdown start, end position)
     fn maxdd(values: &[f64]) -> (f64, usize, usize) {
         let mut maxdd = 0.0;
+        let mut maxdd_peak_pos: usize = 0;
+        let mut maxdd_trough_pos: usize = 0;
         let mut peak = 0.0;
         let mut peak_pos: usize = 0;
         let mut trough = 0.0;
-        let mut trough_pos: usize = 0;
         let mut t2;
         for (pos, t1) in values.iter().enumerate() {
             if t1 > &peak {
                 peak = *t1;
                 peak_pos = pos;
                 trough = peak;
-                trough_pos = peak_pos;
             } else if t1 < &trough {
                 trough = *t1;
-                trough_pos = pos;
                 t2 = (trough / peak) - 1.0;
+                //Positions are only updated for the largest drawdown, not the latest
                 if t2 < maxdd {
-                    maxdd = t2
+                    maxdd = t2;
+                    maxdd_peak_pos = peak_pos;
+                    maxdd_trough_pos = pos;
                 }
             }
         }
-        (maxdd, peak_pos, trough_pos)
+        (maxdd, maxdd_peak_pos, maxdd_trough_pos)
     }
 
     fn var(values: &[f64]) -> f64 {
@@ -99,6 +294,89 @@ impl CalculationAlgos {
         //Accepts returns not raw portfolio values
         CalculationAlgos::var(values).sqrt()
     }
+
+    /// Pearson correlation, series of unequal length are truncated to the shorter series. Returns
+    /// zero if either series has no variance.
+    fn corr(first: &[f64], second: &[f64]) -> f64 {
+        let count = first.len().min(second.len());
+        if count == 0 {
+            return 0.0;
+        }
+        let first = &first[..count];
+        let second = &second[..count];
+
+        let first_mean = first.iter().sum::<f64>() / (count as f64);
+        let second_mean = second.iter().sum::<f64>() / (count as f64);
+
+        let mut cov = 0.0;
+        let mut first_var = 0.0;
+        let mut second_var = 0.0;
+        for (x, y) in first.iter().zip(second.iter()) {
+            cov += (x - first_mean) * (y - second_mean);
+            first_var += (x - first_mean).powf(2.0);
+            second_var += (y - second_mean).powf(2.0);
+        }
+
+        if first_var == 0.0 || second_var == 0.0 {
+            return 0.0;
+        }
+        cov / (first_var.sqrt() * second_var.sqrt())
+    }
+
+    /// Inverse of the standard normal CDF using Acklam's rational approximation, relative error
+    /// is around 1e-9 which is more than enough for risk estimates.
+    fn inverse_normal_cdf(p: f64) -> f64 {
+        const A: [f64; 6] = [
+            -3.969683028665376e1,
+            2.209460984245205e2,
+            -2.759285104469687e2,
+            1.383_577_518_672_69e2,
+            -3.066479806614716e1,
+            2.506628277459239,
+        ];
+        const B: [f64; 5] = [
+            -5.447609879822406e1,
+            1.615858368580409e2,
+            -1.556989798598866e2,
+            6.680131188771972e1,
+            -1.328068155288572e1,
+        ];
+        const C: [f64; 6] = [
+            -7.784894002430293e-3,
+            -3.223964580411365e-1,
+            -2.400758277161838,
+            -2.549732539343734,
+            4.374664141464968,
+            2.938163982698783,
+        ];
+        const D: [f64; 4] = [
+            7.784695709041462e-3,
+            3.224671290700398e-1,
+            2.445134137142996,
+            3.754408661907416,
+        ];
+        const P_LOW: f64 = 0.02425;
+
+        if p <= 0.0 {
+            return f64::NEG_INFINITY;
+        }
+        if p >= 1.0 {
+            return f64::INFINITY;
+        }
+
+        if p < P_LOW {
+            let q = (-2.0 * p.ln()).sqrt();
+            (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+                / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+        } else if p <= 1.0 - P_LOW {
+            let q = p - 0.5;
+            let r = q * q;
+            (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+                / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+        } else {
+            -CalculationAlgos::inverse_normal_cdf(1.0 - p)
+        }
+    }
 }
 
 pub struct PortfolioCalculations;
@@ -109,19 +387,20 @@ impl PortfolioCalculations {
     //Most calculations of annualized returns start from a per-period, as opposed to cumulative
     //return, so the calculations will be more simple. The reason why we need to make additional
     //calculations with the exponent is because we need to convert from the cumulative return.
+    //Frequencies without a fixed number of periods per year can't be annualised and return NaN.
     fn annualize_returns(ret: f64, periods: i32, frequency: &Frequency) -> f64 {
-        match frequency {
-            Frequency::Daily => ((1_f64 + ret).powf(365_f64 / periods as f64)) - 1_f64,
-            Frequency::Second => panic!("No performance stats by second"),
-            Frequency::Fixed => panic!("No performance stats by fixed"),
+        match frequency.periods_per_year() {
+            Some(periods_per_year) => {
+                ((1_f64 + ret).powf(periods_per_year / periods as f64)) - 1_f64
+            }
+            None => f64::NAN,
         }
     }
 
     fn annualize_volatility(vol: f64, frequency: &Frequency) -> f64 {
-        match frequency {
-            Frequency::Daily => (vol) * (252_f64).sqrt(),
-            Frequency::Second => panic!("No performance stats by second"),
-            Frequency::Fixed => panic!("No performance stats by fixed"),
+        match frequency.periods_per_year() {
+            Some(periods_per_year) => vol * periods_per_year.sqrt(),
+            None => f64::NAN,
         }
     }
 
@@ -130,19 +409,6 @@ impl PortfolioCalculations {
         PortfolioCalculations::annualize_volatility(vol, freq)
     }
 
-    fn get_sharpe(rets: &[f64], log_rets: &[f64], days: i32, freq: &Frequency) -> f64 {
-        let vol = PortfolioCalculations::get_vol(rets, freq);
-        let ret = PortfolioCalculations::get_cagr(log_rets, days, freq);
-        if vol == 0.0 {
-            if ret != 0.0 {
-                return ret;
-            } else {
-                return 0.0;
-            }
-        }
-        ret / vol
-    }
-
     fn get_maxdd(rets: &[f64]) -> (f64, usize, usize) {
         //Adds N to the runtime, can run faster but it isn't worth the time atm
         let mut values_with_cashflows = vec![100_000.0];
@@ -211,24 +477,502 @@ impl PortfolioCalculations {
 pub struct PerformanceCalculator;
 
 impl PerformanceCalculator {
-    pub fn calculate(freq: Frequency, states: Vec<StrategySnapshot>) -> BacktestOutput {
-        //Cash flow on [StrategySnapshot] is the sum of cash flows to that date, so we need to
-        //calculate the difference in cash flows at each stage.
+    //Cash flow on [StrategySnapshot] is the sum of cash flows to that date, so we need to
+    //calculate the difference in cash flows at each stage.
+    fn get_cash_flows(states: &[StrategySnapshot]) -> Vec<f64> {
         let mut cash_flows: Vec<f64> = Vec::new();
-        let mut dates: Vec<i64> = Vec::new();
-        let mut total_values: Vec<f64> = Vec::new();
         cash_flows.push(0.0);
+        for i in 1..states.len() {
+            let last = states.get(i - 1).unwrap().net_cash_flow;
+            let curr = states.get(i).unwrap().net_cash_flow;
+            cash_flows.push(curr - last)
+        }
+        cash_flows
+    }
+
+    fn get_snapshot_returns(states: &[StrategySnapshot]) -> Vec<f64> {
+        let cash_flows = PerformanceCalculator::get_cash_flows(states);
+        let total_values: Vec<f64> = states.iter().map(|v| v.portfolio_value).collect();
+        let inflation: Vec<f64> = states.iter().map(|v| v.inflation).collect();
+        PortfolioCalculations::get_returns(&total_values, &cash_flows, &inflation, false)
+    }
+
+    /// Returns the matrix of pairwise correlations between the period returns of each strategy
+    /// history. Histories are assumed to be aligned by date, if they are of unequal length then
+    /// the longer history is truncated.
+    pub fn get_strategy_correlations(histories: &[Vec<StrategySnapshot>]) -> Vec<Vec<f64>> {
+        let returns = histories
+            .iter()
+            .map(|history| PerformanceCalculator::get_snapshot_returns(history))
+            .collect_vec();
 
-        for i in 0..states.len() {
-            dates.push(*states.get(i).unwrap().date);
-            total_values.push(states.get(i).unwrap().portfolio_value);
-            if i != 0 {
-                let last = states.get(i - 1).unwrap().net_cash_flow;
-                let curr = states.get(i).unwrap().net_cash_flow;
-                let diff = curr - last;
-                cash_flows.push(diff)
+        let mut correlations = vec![vec![0.0; returns.len()]; returns.len()];
+        for i in 0..returns.len() {
+            correlations[i][i] = 1.0;
+            for j in (i + 1)..returns.len() {
+                let corr = CalculationAlgos::corr(&returns[i], &returns[j]);
+                correlations[i][j] = corr;
+                correlations[j][i] = corr;
             }
         }
+        correlations
+    }
+
+    /// Returns the mean of the off-diagonal values in [PerformanceCalculator::get_strategy_correlations].
+    /// Returns zero if there are less than two histories.
+    pub fn get_average_pairwise_correlation(histories: &[Vec<StrategySnapshot>]) -> f64 {
+        let correlations = PerformanceCalculator::get_strategy_correlations(histories);
+        let mut total = 0.0;
+        let mut count = 0;
+        for (i, row) in correlations.iter().enumerate() {
+            for corr in row.iter().skip(i + 1) {
+                total += corr;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return 0.0;
+        }
+        total / (count as f64)
+    }
+
+    /// Portfolio value at every snapshot. Strategies take a snapshot on every tick so this is the
+    /// highest frequency series available, whether or not trades occurred.
+    pub fn get_equity_curve_series(states: &[StrategySnapshot]) -> Vec<(DateTime, f64)> {
+        states
+            .iter()
+            .map(|state| (state.date, state.portfolio_value))
+            .collect()
+    }
+
+    /// Change in portfolio value, less cash flows, from the last snapshot on or before `date` to
+    /// the most recent snapshot. If `date` is before the first snapshot then the change is taken
+    /// from the first snapshot.
+    pub fn get_pnl_since(states: &[StrategySnapshot], date: DateTime) -> f64 {
+        let (Some(first), Some(last)) = (states.first(), states.last()) else {
+            return 0.0;
+        };
+        let start = states
+            .iter()
+            .take_while(|state| state.date <= date)
+            .last()
+            .unwrap_or(first);
+        (last.portfolio_value - start.portfolio_value) - (last.net_cash_flow - start.net_cash_flow)
+    }
+
+    /// Annualised Sharpe ratio of period returns, `risk_free_rate` is annual. Returns infinity
+    /// when returns have no variance, with the sign of the excess return, or zero if there is no
+    /// excess return.
+    pub fn sharpe_ratio(returns: &[f64], risk_free_rate: f64, periods_per_year: f64) -> f64 {
+        let excess = returns
+            .iter()
+            .map(|ret| ret - risk_free_rate / periods_per_year)
+            .collect_vec();
+        let mean = excess.iter().sum::<f64>() / excess.len() as f64;
+        let vol = CalculationAlgos::vol(&excess);
+        if vol == 0.0 {
+            return match mean.partial_cmp(&0.0) {
+                Some(Ordering::Greater) => f64::INFINITY,
+                Some(Ordering::Less) => f64::NEG_INFINITY,
+                _ => 0.0,
+            };
+        }
+        (mean * periods_per_year) / (vol * periods_per_year.sqrt())
+    }
+
+    /// Evaluates every sale, in order, against the average price of the buys made before it.
+    /// `cost_basis_map` is the opening price of positions held before the first trade, a buy
+    /// into a closed position replaces it. Sales of symbols without a cost basis are skipped. A
+    /// sale is a win if it made a profit, [Trade] doesn't record costs so these aren't deducted.
+    /// Profit factor is infinite if there are no losses.
+    pub fn trade_statistics(
+        trades: &[Trade],
+        cost_basis_map: &HashMap<String, f64>,
+    ) -> TradeStatistics {
+        //Quantity held and average price for each symbol, only uses trades seen so far
+        let mut positions: HashMap<String, (f64, f64)> = cost_basis_map
+            .iter()
+            .map(|(symbol, price)| (symbol.clone(), (0.0, *price)))
+            .collect();
+        let mut pnls = Vec::new();
+        for trade in trades.iter().filter(|trade| trade.quantity != 0.0) {
+            match trade.typ {
+                TradeType::Buy => {
+                    let (quantity, price) = positions.entry(trade.symbol.clone()).or_default();
+                    let held = quantity.max(0.0);
+                    *price = (held * *price + trade.value) / (held + trade.quantity);
+                    *quantity = held + trade.quantity;
+                }
+                TradeType::Sell => {
+                    if let Some((quantity, price)) = positions.get_mut(&trade.symbol) {
+                        let sell_price = trade.value / trade.quantity;
+                        pnls.push((sell_price - *price) * trade.quantity);
+                        *quantity -= trade.quantity;
+                    }
+                }
+            }
+        }
+
+        let (wins, losses): (Vec<f64>, Vec<f64>) = pnls.iter().partition(|pnl| **pnl > 0.0);
+        let mean = |values: &[f64]| {
+            if values.is_empty() {
+                0.0
+            } else {
+                values.iter().sum::<f64>() / values.len() as f64
+            }
+        };
+
+        let mut max_consecutive_wins = 0;
+        let mut max_consecutive_losses = 0;
+        let mut streak = 0;
+        let mut last_was_win = None;
+        for pnl in &pnls {
+            let is_win = *pnl > 0.0;
+            streak = if last_was_win == Some(is_win) {
+                streak + 1
+            } else {
+                1
+            };
+            last_was_win = Some(is_win);
+            if is_win {
+                max_consecutive_wins = max_consecutive_wins.max(streak);
+            } else {
+                max_consecutive_losses = max_consecutive_losses.max(streak);
+            }
+        }
+
+        let total_wins = wins.iter().sum::<f64>();
+        let total_losses = losses.iter().sum::<f64>();
+        let profit_factor = if total_losses == 0.0 {
+            if total_wins > 0.0 {
+                f64::INFINITY
+            } else {
+                0.0
+            }
+        } else {
+            total_wins / total_losses.abs()
+        };
+
+        TradeStatistics {
+            n_trades: pnls.len(),
+            win_rate: if pnls.is_empty() {
+                0.0
+            } else {
+                wins.len() as f64 / pnls.len() as f64
+            },
+            avg_win: mean(&wins),
+            avg_loss: mean(&losses),
+            profit_factor,
+            max_consecutive_wins,
+            max_consecutive_losses,
+        }
+    }
+
+    //Sample variance, unlike CalculationAlgos::var this divides by n - 1
+    fn sample_var(returns: &[f64]) -> f64 {
+        let count = returns.len() as f64;
+        CalculationAlgos::var(returns) * count / (count - 1.0)
+    }
+
+    /// Adjusted Fisher-Pearson skewness of returns, matches SKEW in spreadsheets. Returns zero
+    /// for fewer than three returns or if there is no variance.
+    pub fn skewness(returns: &[f64]) -> f64 {
+        let n = returns.len() as f64;
+        if returns.len() < 3 {
+            return 0.0;
+        }
+        let std = PerformanceCalculator::sample_var(returns).sqrt();
+        if std == 0.0 {
+            return 0.0;
+        }
+        let mean = returns.iter().sum::<f64>() / n;
+        let cubed = returns.iter().map(|ret| (ret - mean).powi(3)).sum::<f64>();
+        n * cubed / ((n - 1.0) * (n - 2.0) * std.powi(3))
+    }
+
+    /// Sample excess kurtosis of returns, matches KURT in spreadsheets. Returns zero for fewer
+    /// than four returns or if there is no variance.
+    pub fn excess_kurtosis(returns: &[f64]) -> f64 {
+        let n = returns.len() as f64;
+        if returns.len() < 4 {
+            return 0.0;
+        }
+        let std = PerformanceCalculator::sample_var(returns).sqrt();
+        if std == 0.0 {
+            return 0.0;
+        }
+        let mean = returns.iter().sum::<f64>() / n;
+        let fourth = returns.iter().map(|ret| (ret - mean).powi(4)).sum::<f64>();
+        n * (n + 1.0) / ((n - 1.0) * (n - 2.0) * (n - 3.0)) * fourth / std.powi(4)
+            - 3.0 * (n - 1.0).powi(2) / ((n - 2.0) * (n - 3.0))
+    }
+
+    /// Jarque-Bera statistic for normality of returns, larger values are less likely to be normal.
+    pub fn jarque_bera(returns: &[f64]) -> f64 {
+        let n = returns.len() as f64;
+        let skew = PerformanceCalculator::skewness(returns);
+        let kurtosis = PerformanceCalculator::excess_kurtosis(returns);
+        n / 6.0 * (skew.powi(2) + kurtosis.powi(2) / 4.0)
+    }
+
+    //Each period's return is taken from the last value in the previous period, or the first value
+    //for the first period, to the last value in the period. Dates are assumed to be sorted.
+    fn calendar_returns<K: std::hash::Hash + Eq + Copy>(
+        equity_curve: &[f64],
+        dates: &[i64],
+        key: impl Fn(DateTime) -> K,
+    ) -> HashMap<K, f64> {
+        let mut returns = HashMap::new();
+        let Some(first) = equity_curve.first() else {
+            return returns;
+        };
+        let mut start_value = *first;
+        let mut last: Option<(K, f64)> = None;
+        for (value, date) in equity_curve.iter().zip(dates.iter()) {
+            let period = key(DateTime::from(*date));
+            if let Some((last_period, last_value)) = last {
+                if last_period != period {
+                    returns.insert(last_period, last_value / start_value - 1.0);
+                    start_value = last_value;
+                }
+            }
+            last = Some((period, *value));
+        }
+        if let Some((last_period, last_value)) = last {
+            returns.insert(last_period, last_value / start_value - 1.0);
+        }
+        returns
+    }
+
+    /// Compound return in each calendar month keyed by (year, month), months are in UTC.
+    pub fn monthly_returns(equity_curve: &[f64], dates: &[i64]) -> HashMap<(i32, u32), f64> {
+        PerformanceCalculator::calendar_returns(equity_curve, dates, |date| {
+            (date.year(), u8::from(date.month()) as u32)
+        })
+    }
+
+    /// Compound return in each calendar year, see [PerformanceCalculator::monthly_returns].
+    pub fn annual_returns(equity_curve: &[f64], dates: &[i64]) -> HashMap<i32, f64> {
+        PerformanceCalculator::calendar_returns(equity_curve, dates, |date| date.year())
+    }
+
+    /// OLS regression of strategy excess returns on benchmark excess returns, returns (alpha,
+    /// beta, r squared) with alpha annualised. `risk_free` is annual. Series of unequal length
+    /// are truncated to the shorter series. If the benchmark has no variance then beta is zero
+    /// and alpha is the mean excess return.
+    pub fn alpha_beta(
+        strategy_returns: &[f64],
+        benchmark_returns: &[f64],
+        risk_free: f64,
+        periods_per_year: f64,
+    ) -> (f64, f64, f64) {
+        let count = strategy_returns.len().min(benchmark_returns.len());
+        if count == 0 {
+            return (0.0, 0.0, 0.0);
+        }
+        let period_risk_free = risk_free / periods_per_year;
+        let strategy = strategy_returns[..count]
+            .iter()
+            .map(|ret| ret - period_risk_free)
+            .collect_vec();
+        let benchmark = benchmark_returns[..count]
+            .iter()
+            .map(|ret| ret - period_risk_free)
+            .collect_vec();
+
+        let strategy_mean = strategy.iter().sum::<f64>() / count as f64;
+        let benchmark_mean = benchmark.iter().sum::<f64>() / count as f64;
+        let benchmark_var = CalculationAlgos::var(&benchmark);
+        if benchmark_var == 0.0 {
+            return (strategy_mean * periods_per_year, 0.0, 0.0);
+        }
+
+        let cov = strategy
+            .iter()
+            .zip(benchmark.iter())
+            .map(|(x, y)| (x - strategy_mean) * (y - benchmark_mean))
+            .sum::<f64>()
+            / count as f64;
+        let beta = cov / benchmark_var;
+        let alpha = (strategy_mean - beta * benchmark_mean) * periods_per_year;
+        let r_squared = CalculationAlgos::corr(&strategy, &benchmark).powi(2);
+        (alpha, beta, r_squared)
+    }
+
+    /// [PerformanceCalculator::sharpe_ratio] over each trailing `window` of returns. The first
+    /// `window - 1` values are None so the output is aligned with `returns`.
+    pub fn rolling_sharpe(
+        returns: &[f64],
+        window: usize,
+        risk_free: f64,
+        periods_per_year: f64,
+    ) -> Vec<Option<f64>> {
+        if window == 0 {
+            return vec![None; returns.len()];
+        }
+        let mut rolling = vec![None; returns.len().min(window - 1)];
+        for trailing in returns.windows(window) {
+            rolling.push(Some(PerformanceCalculator::sharpe_ratio(
+                trailing,
+                risk_free,
+                periods_per_year,
+            )));
+        }
+        rolling
+    }
+
+    /// Returns (max drawdown, peak index, trough index), drawdown is negative.
+    pub fn max_drawdown(equity_curve: &[f64]) -> (f64, usize, usize) {
+        CalculationAlgos::maxdd(equity_curve)
+    }
+
+    /// Every drawdown in `equity_curve`, `dates` should be the same length.
+    pub fn drawdown_periods(equity_curve: &[f64], dates: &[i64]) -> Vec<DrawdownPeriod> {
+        let mut periods = Vec::new();
+        let Some(first) = equity_curve.first() else {
+            return periods;
+        };
+        let (mut peak, mut peak_pos) = (*first, 0);
+        let (mut trough, mut trough_pos) = (*first, 0);
+        for (pos, value) in equity_curve.iter().enumerate().skip(1) {
+            if *value >= peak {
+                if trough < peak {
+                    periods.push(DrawdownPeriod {
+                        peak_date: dates[peak_pos],
+                        trough_date: dates[trough_pos],
+                        recovery_date: Some(dates[pos]),
+                        drawdown_pct: trough / peak - 1.0,
+                    });
+                }
+                (peak, peak_pos) = (*value, pos);
+                (trough, trough_pos) = (*value, pos);
+            } else if *value < trough {
+                (trough, trough_pos) = (*value, pos);
+            }
+        }
+        if trough < peak {
+            periods.push(DrawdownPeriod {
+                peak_date: dates[peak_pos],
+                trough_date: dates[trough_pos],
+                recovery_date: None,
+                drawdown_pct: trough / peak - 1.0,
+            });
+        }
+        periods
+    }
+
+    /// Annualised Sortino ratio of period returns, `target_return` is annual. Downside deviation
+    /// only counts returns below the target. Follows [PerformanceCalculator::sharpe_ratio] when
+    /// there is no downside.
+    pub fn sortino_ratio(returns: &[f64], target_return: f64, periods_per_year: f64) -> f64 {
+        let target = target_return / periods_per_year;
+        let mean = returns.iter().sum::<f64>() / returns.len() as f64 - target;
+        let downside = (returns
+            .iter()
+            .map(|ret| (target - ret).max(0.0).powi(2))
+            .sum::<f64>()
+            / returns.len() as f64)
+            .sqrt();
+        if downside == 0.0 {
+            return match mean.partial_cmp(&0.0) {
+                Some(Ordering::Greater) => f64::INFINITY,
+                Some(Ordering::Less) => f64::NEG_INFINITY,
+                _ => 0.0,
+            };
+        }
+        (mean * periods_per_year) / (downside * periods_per_year.sqrt())
+    }
+
+    /// Empirical Value at Risk of period returns at `confidence` (i.e. 0.95), returned as a
+    /// positive loss. Takes the `1 - confidence` quantile of sorted returns without
+    /// interpolation. Returns that aren't finite are ignored.
+    pub fn historical_var(returns: &[f64], confidence: f64) -> f64 {
+        let mut sorted = returns
+            .iter()
+            .copied()
+            .filter(|ret| ret.is_finite())
+            .collect_vec();
+        if sorted.is_empty() {
+            return 0.0;
+        }
+        sorted.sort_by(f64::total_cmp);
+        let pos = ((1.0 - confidence) * sorted.len() as f64).floor() as usize;
+        -sorted[pos.min(sorted.len() - 1)]
+    }
+
+    /// Conditional VaR, the mean loss of returns worse than [PerformanceCalculator::historical_var].
+    /// If no returns are worse than the threshold then this is equal to VaR.
+    pub fn cvar(returns: &[f64], confidence: f64) -> f64 {
+        let var = PerformanceCalculator::historical_var(returns, confidence);
+        let tail = returns
+            .iter()
+            .filter(|ret| ret.is_finite() && **ret < -var)
+            .collect_vec();
+        if tail.is_empty() {
+            return var;
+        }
+        -tail.iter().copied().sum::<f64>() / tail.len() as f64
+    }
+
+    /// Value at Risk assuming normally distributed returns with `mean` and `vol` over the same
+    /// period, returned as a positive loss.
+    pub fn parametric_var(mean: f64, vol: f64, confidence: f64) -> f64 {
+        -(mean + vol * CalculationAlgos::inverse_normal_cdf(1.0 - confidence))
+    }
+
+    /// Half of the value bought and sold over the mean portfolio value, annualised by the number
+    /// of values in `portfolio_value_series` so that this is comparable across backtest lengths.
+    pub fn portfolio_turnover(
+        trades: &[Trade],
+        portfolio_value_series: &[f64],
+        periods_per_year: f64,
+    ) -> f64 {
+        let periods = portfolio_value_series.len() as f64;
+        let mean_value = portfolio_value_series.iter().sum::<f64>() / periods;
+        if portfolio_value_series.is_empty() || mean_value == 0.0 {
+            return 0.0;
+        }
+        let traded = trades.iter().map(|trade| trade.value.abs()).sum::<f64>();
+        traded / 2.0 / mean_value * (periods_per_year / periods)
+    }
+
+    /// Annualised standard deviation of active returns, the difference between strategy and
+    /// benchmark returns in each period.
+    pub fn tracking_error(active_returns: &[f64], periods_per_year: f64) -> f64 {
+        CalculationAlgos::vol(active_returns) * periods_per_year.sqrt()
+    }
+
+    /// Annualised mean active return over [PerformanceCalculator::tracking_error]. Returns NaN if
+    /// there is no tracking error, matching [BacktestOutput::relative_to].
+    pub fn information_ratio(active_returns: &[f64], periods_per_year: f64) -> f64 {
+        let tracking_error =
+            PerformanceCalculator::tracking_error(active_returns, periods_per_year);
+        if tracking_error == 0.0 {
+            return f64::NAN;
+        }
+        let mean = active_returns.iter().sum::<f64>() / active_returns.len() as f64;
+        (mean * periods_per_year) / tracking_error
+    }
+
+    /// Accepts a [Frequency] for calculations with zero risk-free rate and target return.
+    pub fn calculate(
+        config: impl Into<PerformanceConfig>,
+        states: Vec<StrategySnapshot>,
+        trades: &[Trade],
+        benchmark_returns: Option<&[f64]>,
+    ) -> BacktestOutput {
+        let config = config.into();
+        let freq = config.frequency.clone();
+        //Annualised stats are NaN when the frequency can't be annualised
+        let periods_per_year = config.periods_per_year().unwrap_or(f64::NAN);
+        let cash_flows = PerformanceCalculator::get_cash_flows(&states);
+        let mut dates: Vec<i64> = Vec::new();
+        let mut total_values: Vec<f64> = Vec::new();
+
+        for state in &states {
+            dates.push(*state.date);
+            total_values.push(state.portfolio_value);
+        }
 
         let inflation: Vec<f64> = states.iter().map(|v| v.inflation).collect();
 
@@ -244,6 +988,53 @@ impl PerformanceCalculator {
         let dd_start_date = dates[drawdown_start_pos];
         let dd_end_date = dates[drawdown_end_pos];
 
+        let annualised_return =
+            returns.iter().sum::<f64>() / returns.len() as f64 * periods_per_year;
+        let calmar = if annualised_return == 0.0 {
+            0.0
+        } else if mdd == 0.0 {
+            f64::INFINITY * annualised_return.signum()
+        } else {
+            annualised_return / mdd.abs()
+        };
+
+        //Benchmark should have a return for every period, mismatches are treated as missing
+        let active_returns = benchmark_returns
+            .filter(|benchmark| benchmark.len() == returns.len() && !returns.is_empty())
+            .map(|benchmark| {
+                returns
+                    .iter()
+                    .zip(benchmark.iter())
+                    .map(|(ret, bench)| ret - bench)
+                    .collect_vec()
+            });
+
+        let regression = benchmark_returns
+            .filter(|benchmark| !benchmark.is_empty() && !returns.is_empty())
+            .map(|benchmark| {
+                PerformanceCalculator::alpha_beta(
+                    &returns,
+                    benchmark,
+                    config.risk_free_rate,
+                    periods_per_year,
+                )
+            });
+
+        //Cost basis is built from the buys that precede each sale
+        let trade_stats = Some(PerformanceCalculator::trade_statistics(
+            trades,
+            &HashMap::new(),
+        ))
+        .filter(|stats| stats.n_trades > 0);
+
+        //Values are rebuilt from returns so that cash flows aren't counted as performance
+        let mut equity_curve = vec![1.0];
+        for ret in &returns {
+            equity_curve.push(equity_curve.last().unwrap() * (1.0 + ret));
+        }
+        let monthly_returns = PerformanceCalculator::monthly_returns(&equity_curve, &dates);
+        let annual_returns = PerformanceCalculator::annual_returns(&equity_curve, &dates);
+
         let best_return = *returns
             .iter()
             .max_by(|x, y| x.partial_cmp(y).unwrap())
@@ -256,13 +1047,49 @@ impl PerformanceCalculator {
         BacktestOutput {
             ret: PortfolioCalculations::get_portfolio_return(&log_returns),
             cagr: PortfolioCalculations::get_cagr(&log_returns, dates.len() as i32, &freq),
+            annualised_return,
             vol: PortfolioCalculations::get_vol(&returns, &freq),
             mdd,
-            sharpe: PortfolioCalculations::get_sharpe(
+            sharpe: PerformanceCalculator::sharpe_ratio(
+                &returns,
+                config.risk_free_rate,
+                periods_per_year,
+            ),
+            sortino: PerformanceCalculator::sortino_ratio(
+                &returns,
+                config.target_return,
+                periods_per_year,
+            ),
+            calmar,
+            var_95: PerformanceCalculator::historical_var(&returns, 0.95),
+            var_99: PerformanceCalculator::historical_var(&returns, 0.99),
+            cvar_95: PerformanceCalculator::cvar(&returns, 0.95),
+            annual_turnover: PerformanceCalculator::portfolio_turnover(
+                trades,
+                &total_values,
+                periods_per_year,
+            ),
+            information_ratio: active_returns
+                .as_ref()
+                .map(|active| PerformanceCalculator::information_ratio(active, periods_per_year)),
+            tracking_error: active_returns
+                .as_ref()
+                .map(|active| PerformanceCalculator::tracking_error(active, periods_per_year)),
+            alpha: regression.map(|(alpha, _beta, _r_squared)| alpha),
+            beta: regression.map(|(_alpha, beta, _r_squared)| beta),
+            r_squared: regression.map(|(_alpha, _beta, r_squared)| r_squared),
+            risk_contributions: HashMap::new(),
+            return_skewness: PerformanceCalculator::skewness(&returns),
+            return_excess_kurtosis: PerformanceCalculator::excess_kurtosis(&returns),
+            jarque_bera: PerformanceCalculator::jarque_bera(&returns),
+            trade_stats,
+            monthly_returns,
+            annual_returns,
+            rolling_sharpe_252: PerformanceCalculator::rolling_sharpe(
                 &returns,
-                &log_returns,
-                dates.len() as i32,
-                &freq,
+                252,
+                config.risk_free_rate,
+                periods_per_year,
             ),
             values: total_values.clone(),
             returns,
@@ -279,10 +1106,194 @@ impl PerformanceCalculator {
     }
 }
 
+/// Calculations on the positions within a portfolio, rather than the portfolio as a whole.
+pub struct PortfolioAnalytics;
+
+impl PortfolioAnalytics {
+    //Sample covariance, series are truncated to the shorter series
+    pub(crate) fn covariance(first: &[f64], second: &[f64]) -> f64 {
+        let count = first.len().min(second.len());
+        if count < 2 {
+            return 0.0;
+        }
+        let first_mean = first[..count].iter().sum::<f64>() / count as f64;
+        let second_mean = second[..count].iter().sum::<f64>() / count as f64;
+        first[..count]
+            .iter()
+            .zip(second[..count].iter())
+            .map(|(x, y)| (x - first_mean) * (y - second_mean))
+            .sum::<f64>()
+            / (count - 1) as f64
+    }
+
+    /// Contribution of each position to portfolio volatility, `w_i * (Σw)_i / σ_p`, so the
+    /// contributions sum to portfolio volatility. Symbols without returns are skipped. Returns
+    /// zero contributions if the portfolio has no variance.
+    pub fn risk_contributions(
+        returns_by_symbol: &HashMap<String, Vec<f64>>,
+        weights: &HashMap<String, f64>,
+    ) -> HashMap<String, f64> {
+        let symbols = weights
+            .keys()
+            .filter(|symbol| returns_by_symbol.contains_key(*symbol))
+            .sorted()
+            .collect_vec();
+
+        let marginal = symbols
+            .iter()
+            .map(|first| {
+                symbols
+                    .iter()
+                    .map(|second| {
+                        PortfolioAnalytics::covariance(
+                            &returns_by_symbol[*first],
+                            &returns_by_symbol[*second],
+                        ) * weights[*second]
+                    })
+                    .sum::<f64>()
+            })
+            .collect_vec();
+
+        let portfolio_var = symbols
+            .iter()
+            .zip(marginal.iter())
+            .map(|(symbol, sigma_w)| weights[*symbol] * sigma_w)
+            .sum::<f64>();
+        let portfolio_vol = portfolio_var.sqrt();
+
+        symbols
+            .iter()
+            .zip(marginal.iter())
+            .map(|(symbol, sigma_w)| {
+                let contribution = if portfolio_vol == 0.0 {
+                    0.0
+                } else {
+                    weights[*symbol] * sigma_w / portfolio_vol
+                };
+                (symbol.to_string(), contribution)
+            })
+            .collect()
+    }
+}
+
+/// Output of [BrinsonAttributor::attribute], effects sum to `total_active_return`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BrinsonResult {
+    pub allocation_effect: f64,
+    pub selection_effect: f64,
+    pub interaction_effect: f64,
+    pub total_active_return: f64,
+}
+
+/// Brinson-Hood-Beebower decomposition of active return over a single period. Keys are segments,
+/// these can be symbols or groups of symbols so long as all maps use the same keys.
+pub struct BrinsonAttributor;
+
+impl BrinsonAttributor {
+    /// Segments missing from a weight map have zero weight. If a segment is missing from one of
+    /// the return maps then the return from the other map is used, so a segment held by only one
+    /// side contributes no selection effect.
+    pub fn attribute(
+        portfolio_weights: &HashMap<String, f64>,
+        benchmark_weights: &HashMap<String, f64>,
+        portfolio_returns: &HashMap<String, f64>,
+        benchmark_returns: &HashMap<String, f64>,
+    ) -> BrinsonResult {
+        let mut result = BrinsonResult {
+            allocation_effect: 0.0,
+            selection_effect: 0.0,
+            interaction_effect: 0.0,
+            total_active_return: 0.0,
+        };
+
+        for segment in portfolio_weights
+            .keys()
+            .chain(benchmark_weights.keys())
+            .unique()
+        {
+            let portfolio_weight = portfolio_weights.get(segment).copied().unwrap_or(0.0);
+            let benchmark_weight = benchmark_weights.get(segment).copied().unwrap_or(0.0);
+            let (portfolio_return, benchmark_return) = match (
+                portfolio_returns.get(segment),
+                benchmark_returns.get(segment),
+            ) {
+                (Some(port), Some(bench)) => (*port, *bench),
+                (Some(port), None) => (*port, *port),
+                (None, Some(bench)) => (*bench, *bench),
+                (None, None) => (0.0, 0.0),
+            };
+
+            let active_weight = portfolio_weight - benchmark_weight;
+            let active_return = portfolio_return - benchmark_return;
+            result.allocation_effect += active_weight * benchmark_return;
+            result.selection_effect += benchmark_weight * active_return;
+            result.interaction_effect += active_weight * active_return;
+            result.total_active_return +=
+                portfolio_weight * portfolio_return - benchmark_weight * benchmark_return;
+        }
+        result
+    }
+}
+
+/// Summarizes how capital moved between positions in a rebalance. Maps from the symbol that was
+/// sold to the symbol that was bought, and then to the fraction of the sale proceeds that went to
+/// that purchase.
+#[derive(Clone, Debug, Default)]
+pub struct TransitionMatrix {
+    pub inner: HashMap<String, HashMap<String, f64>>,
+}
+
+impl TransitionMatrix {
+    /// Rows are `from,to,fraction` sorted by symbol.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("from,to,fraction\n");
+        for from in self.inner.keys().sorted() {
+            let row = self.inner.get(from).unwrap();
+            for to in row.keys().sorted() {
+                csv.push_str(&format!("{},{},{}\n", from, to, row.get(to).unwrap()));
+            }
+        }
+        csv
+    }
+}
+
+/// Proceeds from each sold symbol are matched to purchases in proportion to the value of each
+/// purchase, so every row sums to one. Proceeds that weren't spent, or purchases funded by
+/// existing cash, are not tracked. Trades that are not of the expected type are ignored.
+pub fn compute_transition_matrix(sells: &[Trade], buys: &[Trade]) -> TransitionMatrix {
+    let mut proceeds: HashMap<String, f64> = HashMap::new();
+    for trade in sells.iter().filter(|t| matches!(t.typ, TradeType::Sell)) {
+        *proceeds.entry(trade.symbol.clone()).or_default() += trade.value;
+    }
+
+    let mut costs: HashMap<String, f64> = HashMap::new();
+    for trade in buys.iter().filter(|t| matches!(t.typ, TradeType::Buy)) {
+        *costs.entry(trade.symbol.clone()).or_default() += trade.value;
+    }
+
+    let total_cost = costs.values().sum::<f64>();
+    let mut matrix = TransitionMatrix::default();
+    if total_cost == 0.0 {
+        return matrix;
+    }
+
+    for from in proceeds.keys() {
+        let mut row = HashMap::new();
+        for (to, cost) in &costs {
+            row.insert(to.clone(), cost / total_cost);
+        }
+        matrix.inner.insert(from.clone(), row);
+    }
+    matrix
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
 
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use rand_distr::{Distribution, Normal};
     use rotala::http::uist::uistv1_client::TestClient;
     use rotala::http::uist::uistv1_client::UistClient;
     use rotala::input::penelope::Penelope;
@@ -292,9 +1303,16 @@ mod tests {
     use crate::broker::BrokerCost;
     use crate::perf::StrategySnapshot;
     use crate::strategy::staticweight::StaticWeightStrategyBuilder;
+    use crate::strategy::Strategy;
 
+    use rotala::exchange::uist_v1::{Trade, TradeType};
+
+    use super::compute_transition_matrix;
+    use super::BrinsonAttributor;
+    use super::DrawdownPeriod;
     use super::Frequency;
     use super::PerformanceCalculator;
+    use super::PortfolioAnalytics;
     use super::PortfolioCalculations;
 
     async fn setup() -> UistBroker<TestClient> {
@@ -324,12 +1342,12 @@ mod tests {
         assert_eq!(
             (PortfolioCalculations::annualize_returns(0.29, 252, &Frequency::Daily) * 100.0)
                 .round(),
-            45.0
+            29.0
         );
         assert_eq!(
             (PortfolioCalculations::annualize_returns(0.05, 126, &Frequency::Daily) * 100.0)
                 .round(),
-            15.0
+            10.0
         );
 
         assert_eq!(
@@ -338,6 +1356,346 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_that_unannualisable_frequencies_return_nan() {
+        assert_eq!(Frequency::Second.periods_per_year(), None);
+        assert_eq!(Frequency::Fixed.periods_per_year(), None);
+        assert!(PortfolioCalculations::annualize_returns(0.05, 10, &Frequency::Fixed).is_nan());
+        assert!(PortfolioCalculations::annualize_volatility(0.01, &Frequency::Second).is_nan());
+    }
+
+    #[test]
+    fn test_that_sharpe_ratio_calculates_correctly() {
+        let sharpe = PerformanceCalculator::sharpe_ratio(&[0.01, 0.03], 0.0, 252.0);
+        assert_eq!((sharpe * 1000.0).round(), 31749.0);
+
+        //Annual risk free rate is 0.01% per period
+        let sharpe = PerformanceCalculator::sharpe_ratio(&[0.01, 0.03], 0.0252, 252.0);
+        assert_eq!((sharpe * 1000.0).round(), 31590.0);
+
+        let constant = PerformanceCalculator::sharpe_ratio(&[0.01, 0.01, 0.01], 0.0, 252.0);
+        assert_eq!(constant, f64::INFINITY);
+    }
+
+    #[test]
+    fn test_that_skewed_returns_have_positive_skew_and_kurtosis() {
+        let returns = [0.01, 0.02, 0.03, 0.04, 0.10];
+        let skew = PerformanceCalculator::skewness(&returns);
+        assert_eq!((skew * 1e6).round(), 1697056.0);
+        let kurtosis = PerformanceCalculator::excess_kurtosis(&returns);
+        assert_eq!((kurtosis * 1e6).round(), 3152000.0);
+        let jb = PerformanceCalculator::jarque_bera(&returns);
+        assert_eq!((jb * 1e6).round(), 4469813.0);
+
+        let mirrored = returns.iter().map(|ret| -ret).collect::<Vec<f64>>();
+        assert_eq!(
+            (PerformanceCalculator::skewness(&mirrored) * 1e6).round(),
+            -1697056.0
+        );
+        assert_eq!(PerformanceCalculator::skewness(&[0.01, 0.01, 0.01]), 0.0);
+        assert_eq!(PerformanceCalculator::excess_kurtosis(&[0.01, 0.02]), 0.0);
+    }
+
+    #[test]
+    fn test_that_monthly_returns_compound_to_annual_return() {
+        //Daily values from 2023-12-15 to 2025-01-14
+        let start = 1702598400;
+        let dates = (0..397).map(|i| start + i * 86400).collect::<Vec<i64>>();
+        let values = (0..397)
+            .map(|i| 100.0 + (i as f64 / 10.0).sin() * 5.0 + i as f64 * 0.05)
+            .collect::<Vec<f64>>();
+
+        let monthly = PerformanceCalculator::monthly_returns(&values, &dates);
+        let annual = PerformanceCalculator::annual_returns(&values, &dates);
+        assert_eq!(monthly.len(), 14);
+        assert_eq!(annual.len(), 3);
+
+        let compounded = (1..=12)
+            .map(|month| monthly.get(&(2024, month)).unwrap())
+            .fold(1.0, |acc, ret| acc * (1.0 + ret))
+            - 1.0;
+        assert!((compounded - annual.get(&2024).unwrap()).abs() < 1e-12);
+
+        //First month starts from the first value
+        let december = *monthly.get(&(2023, 12)).unwrap();
+        let last_december = values[16];
+        assert_eq!(december, last_december / values[0] - 1.0);
+    }
+
+    #[test]
+    fn test_that_consecutive_losses_reset_after_win() {
+        let mut cost_basis = HashMap::new();
+        cost_basis.insert("ABC".to_string(), 10.0);
+        //Loss, loss, win, loss, loss, loss, win
+        let trades = [9.0, 8.0, 12.0, 9.5, 9.0, 7.0, 11.0]
+            .iter()
+            .enumerate()
+            .map(|(i, price)| Trade::new("ABC", price * 10.0, 10.0, i as i64, TradeType::Sell))
+            .collect::<Vec<_>>();
+        let mut with_buys = vec![Trade::new("ABC", 1000.0, 100.0, 0, TradeType::Buy)];
+        with_buys.extend(trades);
+        //Not in the cost basis so skipped
+        with_buys.push(Trade::new("BCD", 100.0, 10.0, 8, TradeType::Sell));
+
+        let stats = PerformanceCalculator::trade_statistics(&with_buys, &cost_basis);
+        assert_eq!(stats.n_trades, 7);
+        assert_eq!(stats.max_consecutive_losses, 3);
+        assert_eq!(stats.max_consecutive_wins, 1);
+        assert_eq!((stats.win_rate * 1000.0).round(), 286.0);
+        assert_eq!(stats.avg_win, 15.0);
+        assert_eq!(stats.avg_loss, -15.0);
+        assert_eq!((stats.profit_factor * 1000.0).round(), 400.0);
+
+        let no_losses = PerformanceCalculator::trade_statistics(&with_buys[3..4], &cost_basis);
+        assert_eq!(no_losses.profit_factor, f64::INFINITY);
+    }
+
+    #[test]
+    fn test_that_trade_statistics_ignores_later_buys() {
+        //Averaging over every buy would put the cost at 15 and make the sale a loss
+        let trades = vec![
+            Trade::new("ABC", 1000.0, 100.0, 0, TradeType::Buy),
+            Trade::new("ABC", 1200.0, 100.0, 1, TradeType::Sell),
+            Trade::new("ABC", 2000.0, 100.0, 2, TradeType::Buy),
+            Trade::new("ABC", 1900.0, 100.0, 3, TradeType::Sell),
+        ];
+
+        let stats = PerformanceCalculator::trade_statistics(&trades, &HashMap::new());
+        assert_eq!(stats.n_trades, 2);
+        assert_eq!(stats.win_rate, 0.5);
+        assert_eq!(stats.avg_win, 200.0);
+        assert_eq!(stats.avg_loss, -100.0);
+    }
+
+    #[test]
+    fn test_that_alpha_beta_regresses_on_benchmark() {
+        let benchmark = [0.01, -0.02, 0.03, 0.01, -0.01];
+        let (alpha, beta, r_squared) =
+            PerformanceCalculator::alpha_beta(&benchmark, &benchmark, 0.0252, 252.0);
+        assert_eq!((alpha * 1e9).round(), 0.0);
+        assert_eq!((beta * 1e9).round(), 1e9);
+        assert_eq!((r_squared * 1e9).round(), 1e9);
+
+        //Twice the benchmark plus 0.1% per period, extra strategy return is ignored
+        let strategy = [0.021, -0.039, 0.061, 0.021, -0.019, 0.5];
+        let (alpha, beta, _r_squared) =
+            PerformanceCalculator::alpha_beta(&strategy, &benchmark, 0.0, 252.0);
+        assert_eq!((alpha * 1000.0).round(), 252.0);
+        assert_eq!((beta * 1000.0).round(), 2000.0);
+
+        let (alpha, beta, r_squared) =
+            PerformanceCalculator::alpha_beta(&benchmark, &[0.01; 5], 0.0, 252.0);
+        assert_eq!(beta, 0.0);
+        assert_eq!(r_squared, 0.0);
+        assert_eq!((alpha * 1000.0).round(), 1008.0);
+    }
+
+    #[test]
+    fn test_that_rolling_sharpe_converges_to_full_period_sharpe() {
+        let returns = [0.01, -0.02, 0.03, 0.01, -0.01, 0.02];
+        let rolling = PerformanceCalculator::rolling_sharpe(&returns, 3, 0.0, 252.0);
+        assert_eq!(rolling.len(), returns.len());
+        assert!(rolling[0].is_none() && rolling[1].is_none());
+        assert_eq!(
+            rolling[2],
+            Some(PerformanceCalculator::sharpe_ratio(
+                &returns[..3],
+                0.0,
+                252.0
+            ))
+        );
+
+        let full = PerformanceCalculator::sharpe_ratio(&returns, 0.0, 252.0);
+        let rolling = PerformanceCalculator::rolling_sharpe(&returns, returns.len(), 0.0, 252.0);
+        assert_eq!(*rolling.last().unwrap(), Some(full));
+        assert_eq!(rolling.iter().filter(|v| v.is_some()).count(), 1);
+
+        let too_short = PerformanceCalculator::rolling_sharpe(&returns, 10, 0.0, 252.0);
+        assert!(too_short.iter().all(|v| v.is_none()));
+    }
+
+    #[test]
+    fn test_that_sortino_ratio_only_penalises_downside() {
+        let returns = [0.02, -0.01, 0.03, -0.02];
+        let sortino = PerformanceCalculator::sortino_ratio(&returns, 0.0, 252.0);
+        assert_eq!((sortino * 1000.0).round(), 7099.0);
+
+        let sortino = PerformanceCalculator::sortino_ratio(&returns, 0.0252, 252.0);
+        assert_eq!((sortino * 1000.0).round(), 6916.0);
+
+        let no_downside = PerformanceCalculator::sortino_ratio(&[0.01, 0.02], 0.0, 252.0);
+        assert_eq!(no_downside, f64::INFINITY);
+    }
+
+    #[test]
+    fn test_that_max_drawdown_reports_largest_drawdown() {
+        let (mdd, peak, trough) = PerformanceCalculator::max_drawdown(&[100.0, 80.0, 120.0, 110.0]);
+        assert_eq!(mdd, 80.0 / 100.0 - 1.0);
+        assert_eq!((peak, trough), (0, 1));
+    }
+
+    #[test]
+    fn test_that_var_and_cvar_are_losses_in_the_tail() {
+        let returns = (1..=100)
+            .map(|i| (i as f64 - 51.0) / 1000.0)
+            .collect::<Vec<f64>>();
+        //Returns run from -0.05 to 0.049, the 5% quantile is the sixth worst return
+        let var = PerformanceCalculator::historical_var(&returns, 0.95);
+        assert_eq!((var * 1000.0).round(), 45.0);
+        let cvar = PerformanceCalculator::cvar(&returns, 0.95);
+        assert_eq!((cvar * 1000.0).round(), 48.0);
+
+        let parametric = PerformanceCalculator::parametric_var(0.0, 0.01, 0.95);
+        assert_eq!((parametric * 10000.0).round(), 164.0);
+        let parametric = PerformanceCalculator::parametric_var(0.001, 0.01, 0.99);
+        assert_eq!((parametric * 10000.0).round(), 223.0);
+    }
+
+    #[test]
+    fn test_that_cvar_exceeds_var() {
+        let normal = Normal::new(0.0005, 0.01).unwrap();
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..20 {
+            let returns = (0..500)
+                .map(|_| normal.sample(&mut rng))
+                .collect::<Vec<f64>>();
+            let var = PerformanceCalculator::historical_var(&returns, 0.95);
+            let cvar = PerformanceCalculator::cvar(&returns, 0.95);
+            assert!(cvar > var);
+        }
+    }
+
+    #[test]
+    fn test_that_var_ignores_returns_that_are_not_finite() {
+        let returns = [0.01, f64::NAN, -0.05, f64::NEG_INFINITY, 0.02, -0.01];
+        assert_eq!(PerformanceCalculator::historical_var(&returns, 0.8), 0.05);
+        assert_eq!(PerformanceCalculator::cvar(&returns, 0.8), 0.05);
+        assert_eq!(
+            PerformanceCalculator::historical_var(&[f64::NAN], 0.95),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_that_turnover_is_low_for_buy_and_hold_and_high_for_daily_rebalance() {
+        //Ten years of daily values with a single purchase at the start
+        let values = vec![100_000.0; 2520];
+        let buy_and_hold = vec![Trade::new("ABC", 100_000.0, 1000.0, 100, TradeType::Buy)];
+        let turnover = PerformanceCalculator::portfolio_turnover(&buy_and_hold, &values, 252.0);
+        assert!(turnover < 0.1);
+
+        //Moves 1% of the portfolio between positions every day
+        let values = vec![100_000.0; 252];
+        let mut rebalance = Vec::new();
+        for date in 0..252 {
+            rebalance.push(Trade::new("ABC", 1_000.0, 10.0, date, TradeType::Sell));
+            rebalance.push(Trade::new("BCD", 1_000.0, 10.0, date, TradeType::Buy));
+        }
+        let turnover = PerformanceCalculator::portfolio_turnover(&rebalance, &values, 252.0);
+        assert!(turnover > 1.0);
+        assert_eq!((turnover * 100.0).round(), 252.0);
+
+        assert_eq!(
+            PerformanceCalculator::portfolio_turnover(&rebalance, &[], 252.0),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_that_calmar_is_zero_for_flat_returns() {
+        let values = |values: &[f64]| {
+            values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| StrategySnapshot::nominal((100 + i as i64).into(), *value, 0.0))
+                .collect::<Vec<_>>()
+        };
+        let flat = PerformanceCalculator::calculate(
+            Frequency::Daily,
+            values(&[100.0, 100.0, 100.0]),
+            &[],
+            None,
+        );
+        assert_eq!(flat.calmar, 0.0);
+
+        //No drawdown with a gain is still infinite
+        let rising = PerformanceCalculator::calculate(
+            Frequency::Daily,
+            values(&[100.0, 101.0, 102.0]),
+            &[],
+            None,
+        );
+        assert_eq!(rising.calmar, f64::INFINITY);
+    }
+
+    #[test]
+    fn test_that_information_ratio_is_none_without_benchmark() {
+        let values = [100.0, 110.0, 99.0, 108.9]
+            .iter()
+            .enumerate()
+            .map(|(i, value)| StrategySnapshot::nominal((100 + i as i64).into(), *value, 0.0))
+            .collect::<Vec<_>>();
+        let perf = PerformanceCalculator::calculate(Frequency::Daily, values.clone(), &[], None);
+        assert!(perf.information_ratio.is_none());
+        assert!(perf.tracking_error.is_none());
+
+        //Strategy returns: 10%, -10%, 10%
+        let benchmark = [0.05, -0.05, 0.03];
+        let perf =
+            PerformanceCalculator::calculate(Frequency::Daily, values, &[], Some(&benchmark));
+        let active = [0.05, -0.05, 0.07];
+        let tracking_error = PerformanceCalculator::tracking_error(&active, 252.0);
+        assert_eq!(
+            (perf.tracking_error.unwrap() * 1000.0).round(),
+            (tracking_error * 1000.0).round()
+        );
+        assert_eq!(
+            (perf.information_ratio.unwrap() * 1000.0).round(),
+            (PerformanceCalculator::information_ratio(&active, 252.0) * 1000.0).round()
+        );
+
+        //Constant outperformance has no tracking error
+        assert!(PerformanceCalculator::information_ratio(&[0.01, 0.01], 252.0).is_nan());
+    }
+
+    #[test]
+    fn test_that_drawdown_periods_include_unrecovered_drawdown() {
+        let values = [100.0, 80.0, 120.0, 110.0, 100.0, 130.0, 125.0];
+        let dates = [1, 2, 3, 4, 5, 6, 7];
+        let periods = PerformanceCalculator::drawdown_periods(&values, &dates);
+        assert_eq!(periods.len(), 3);
+        assert_eq!(
+            periods[0],
+            DrawdownPeriod {
+                peak_date: 1,
+                trough_date: 2,
+                recovery_date: Some(3),
+                drawdown_pct: 80.0 / 100.0 - 1.0,
+            }
+        );
+        assert_eq!(periods[1].trough_date, 5);
+        assert_eq!(periods[1].recovery_date, Some(6));
+        assert_eq!(periods[2].peak_date, 6);
+        assert_eq!(periods[2].recovery_date, None);
+    }
+
+    #[test]
+    fn test_that_weekly_and_monthly_annualize_by_periods_per_year() {
+        assert_eq!(Frequency::Weekly.periods_per_year(), Some(52.0));
+        assert_eq!(Frequency::Monthly.periods_per_year(), Some(12.0));
+
+        assert_eq!(
+            (PortfolioCalculations::annualize_returns(0.10, 26, &Frequency::Weekly) * 100.0)
+                .round(),
+            21.0
+        );
+        assert_eq!(
+            (PortfolioCalculations::annualize_volatility(0.05, &Frequency::Monthly) * 100.0)
+                .round(),
+            17.0
+        );
+    }
+
     #[tokio::test]
     async fn test_that_portfolio_calculates_performance_accurately() {
         let brkr = setup().await;
@@ -362,14 +1720,44 @@ mod tests {
 
         let output = strat.get_history();
         println!("{:?}", output);
-        let perf = PerformanceCalculator::calculate(Frequency::Daily, output);
+        let perf = PerformanceCalculator::calculate(Frequency::Daily, output, &[], None);
         println!("{:?}", perf.returns);
 
         let portfolio_return = perf.ret;
         //We need to round up to cmp properly
         let to_comp = (portfolio_return * 1000.0).round();
         println!("{:?}", to_comp);
-        assert_eq!(to_comp, 24.0);
+        assert_eq!(to_comp, 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_that_identical_strategies_are_perfectly_correlated() {
+        let mut target_weights = HashMap::new();
+        target_weights.insert("ABC".to_string(), 0.4);
+        target_weights.insert("BCD".to_string(), 0.4);
+
+        let mut histories = Vec::new();
+        for _ in 0..2 {
+            let brkr = setup().await;
+            let mut strat = StaticWeightStrategyBuilder::new()
+                .with_brkr(brkr)
+                .with_weights(target_weights.clone())
+                .default();
+
+            strat.init(&100_000.0);
+            strat.update().await;
+            strat.update().await;
+            strat.update().await;
+            histories.push(strat.get_history());
+        }
+
+        let correlations = PerformanceCalculator::get_strategy_correlations(&histories);
+        assert_eq!(correlations.len(), 2);
+        assert_eq!((correlations[0][1] * 1000.0).round(), 1000.0);
+        assert_eq!(correlations[0][1], correlations[1][0]);
+
+        let average = PerformanceCalculator::get_average_pairwise_correlation(&histories);
+        assert_eq!((average * 1000.0).round(), 1000.0);
     }
 
     #[test]
@@ -430,8 +1818,9 @@ mod tests {
         };
         let without_cash_flows = vec![snap3, snap4, snap5, snap6];
 
-        let perf0 = PerformanceCalculator::calculate(Frequency::Daily, with_cash_flows);
-        let perf1 = PerformanceCalculator::calculate(Frequency::Daily, without_cash_flows);
+        let perf0 = PerformanceCalculator::calculate(Frequency::Daily, with_cash_flows, &[], None);
+        let perf1 =
+            PerformanceCalculator::calculate(Frequency::Daily, without_cash_flows, &[], None);
 
         let ret0 = f64::round(perf0.ret * 100.0);
         let ret1 = f64::round(perf1.ret * 100.0);
@@ -441,6 +1830,82 @@ mod tests {
         assert_eq!(ret0, ret1);
     }
 
+    #[test]
+    fn test_that_pnl_since_date_excludes_cash_flows() {
+        let states = vec![
+            StrategySnapshot::nominal(100.into(), 100.0, 0.0),
+            StrategySnapshot::nominal(101.into(), 110.0, 0.0),
+            StrategySnapshot::nominal(102.into(), 135.0, 20.0),
+            StrategySnapshot::nominal(103.into(), 130.0, 20.0),
+        ];
+
+        let curve = PerformanceCalculator::get_equity_curve_series(&states);
+        assert_eq!(curve.len(), 4);
+        assert_eq!(curve[2], (102.into(), 135.0));
+
+        assert_eq!(
+            PerformanceCalculator::get_pnl_since(&states, 101.into()),
+            0.0
+        );
+        assert_eq!(
+            PerformanceCalculator::get_pnl_since(&states, 102.into()),
+            -5.0
+        );
+        assert_eq!(
+            PerformanceCalculator::get_pnl_since(&states, 50.into()),
+            10.0
+        );
+        assert_eq!(PerformanceCalculator::get_pnl_since(&[], 100.into()), 0.0);
+    }
+
+    #[test]
+    fn test_that_relative_metrics_compare_against_benchmark() {
+        let values = |values: &[f64]| {
+            values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| StrategySnapshot::nominal((100 + i as i64).into(), *value, 0.0))
+                .collect::<Vec<_>>()
+        };
+        //Benchmark returns: 10%, -10%, 10%. Strategy returns: 20%, -5%, 0%
+        let benchmark = PerformanceCalculator::calculate(
+            Frequency::Daily,
+            values(&[100.0, 110.0, 99.0, 108.9]),
+            &[],
+            None,
+        );
+        let strat = PerformanceCalculator::calculate(
+            Frequency::Daily,
+            values(&[100.0, 120.0, 114.0, 114.0]),
+            &[],
+            None,
+        );
+
+        let relative = strat.relative_to(&benchmark);
+        assert_eq!((relative.excess_return * 1000.0).round(), 51.0);
+        assert_eq!((relative.hit_rate * 1000.0).round(), 667.0);
+        assert_eq!((relative.up_capture * 100.0).round(), 100.0);
+        assert_eq!((relative.down_capture * 100.0).round(), 50.0);
+        assert!(relative.tracking_error > 0.0);
+        assert!(relative.information_ratio.is_finite());
+
+        let with_benchmark = PerformanceCalculator::calculate(
+            Frequency::Daily,
+            values(&[100.0, 120.0, 114.0, 114.0]),
+            &[],
+            Some(&benchmark.returns),
+        );
+        assert_eq!(with_benchmark.tracking_error, Some(relative.tracking_error));
+        assert_eq!(
+            with_benchmark.information_ratio,
+            Some(relative.information_ratio)
+        );
+
+        let short =
+            PerformanceCalculator::calculate(Frequency::Daily, values(&[100.0, 110.0]), &[], None);
+        assert!(strat.relative_to(&short).hit_rate.is_nan());
+    }
+
     #[test]
     fn test_that_perf_completes_with_zeros() {
         let snap1 = StrategySnapshot {
@@ -464,7 +1929,7 @@ mod tests {
 
         let with_zeros = vec![snap1, snap2, snap3];
 
-        let perf = PerformanceCalculator::calculate(Frequency::Daily, with_zeros);
+        let perf = PerformanceCalculator::calculate(Frequency::Daily, with_zeros, &[], None);
 
         dbg!(&perf.returns);
         assert!(perf.returns == vec![0.0, 0.0])
@@ -493,7 +1958,89 @@ mod tests {
 
         let snaps = vec![snap1, snap2, snap3];
 
-        let perf = PerformanceCalculator::calculate(Frequency::Daily, snaps);
+        let perf = PerformanceCalculator::calculate(Frequency::Daily, snaps, &[], None);
         assert!(perf.best_return > perf.worst_return);
     }
+
+    #[test]
+    fn test_that_risk_contributions_sum_to_portfolio_vol() {
+        let mut returns = HashMap::new();
+        returns.insert("ABC".to_string(), vec![0.01, -0.02, 0.03, 0.00, 0.01]);
+        returns.insert("BCD".to_string(), vec![0.02, 0.01, -0.01, 0.01, 0.00]);
+        returns.insert("CDE".to_string(), vec![0.03, -0.04, 0.05, -0.01, 0.02]);
+        let mut weights = HashMap::new();
+        weights.insert("ABC".to_string(), 0.3);
+        weights.insert("BCD".to_string(), 0.5);
+        weights.insert("CDE".to_string(), 0.2);
+        //Not in returns so skipped
+        weights.insert("DEF".to_string(), 0.1);
+
+        let portfolio = (0..5)
+            .map(|i| 0.3 * returns["ABC"][i] + 0.5 * returns["BCD"][i] + 0.2 * returns["CDE"][i])
+            .collect::<Vec<f64>>();
+        let mean = portfolio.iter().sum::<f64>() / 5.0;
+        let vol = (portfolio.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / 4.0).sqrt();
+
+        let contributions = PortfolioAnalytics::risk_contributions(&returns, &weights);
+        assert_eq!(contributions.len(), 3);
+        assert!((contributions.values().sum::<f64>() - vol).abs() < 1e-12);
+        //Most volatile position contributes more than its weight
+        assert!(contributions["CDE"] / vol > 0.2);
+    }
+
+    #[test]
+    fn test_that_brinson_effects_sum_to_active_return() {
+        let map = |values: &[(&str, f64)]| {
+            values
+                .iter()
+                .map(|(segment, value)| (segment.to_string(), *value))
+                .collect::<HashMap<String, f64>>()
+        };
+        let portfolio_weights = map(&[("Equity", 0.7), ("Bonds", 0.2), ("Cash", 0.1)]);
+        let benchmark_weights = map(&[("Equity", 0.6), ("Bonds", 0.4)]);
+        let portfolio_returns = map(&[("Equity", 0.08), ("Bonds", 0.02), ("Cash", 0.01)]);
+        let benchmark_returns = map(&[("Equity", 0.05), ("Bonds", 0.03)]);
+
+        let result = BrinsonAttributor::attribute(
+            &portfolio_weights,
+            &benchmark_weights,
+            &portfolio_returns,
+            &benchmark_returns,
+        );
+        let sum = result.allocation_effect + result.selection_effect + result.interaction_effect;
+        assert!((sum - result.total_active_return).abs() < 1e-12);
+        //0.7 * 0.08 + 0.2 * 0.02 + 0.1 * 0.01 - (0.6 * 0.05 + 0.4 * 0.03)
+        assert_eq!((result.total_active_return * 1e4).round(), 190.0);
+        assert_eq!((result.selection_effect * 1e4).round(), 140.0);
+
+        let identical = BrinsonAttributor::attribute(
+            &benchmark_weights,
+            &benchmark_weights,
+            &benchmark_returns,
+            &benchmark_returns,
+        );
+        assert_eq!(identical.allocation_effect, 0.0);
+        assert_eq!(identical.selection_effect, 0.0);
+        assert_eq!(identical.interaction_effect, 0.0);
+        assert_eq!(identical.total_active_return, 0.0);
+    }
+
+    #[test]
+    fn test_that_transition_matrix_matches_sells_to_buys() {
+        let sells = vec![Trade::new("ABC", 1000.0, 10.0, 100, TradeType::Sell)];
+        let buys = vec![Trade::new("BCD", 1000.0, 100.0, 100, TradeType::Buy)];
+
+        let matrix = compute_transition_matrix(&sells, &buys);
+        assert_eq!(*matrix.inner.get("ABC").unwrap().get("BCD").unwrap(), 1.0);
+        assert_eq!(matrix.to_csv(), "from,to,fraction\nABC,BCD,1\n");
+
+        let buys = vec![
+            Trade::new("BCD", 750.0, 75.0, 100, TradeType::Buy),
+            Trade::new("CDE", 250.0, 25.0, 100, TradeType::Buy),
+        ];
+        let matrix = compute_transition_matrix(&sells, &buys);
+        let row = matrix.inner.get("ABC").unwrap();
+        assert_eq!(*row.get("BCD").unwrap(), 0.75);
+        assert_eq!(*row.get("CDE").unwrap(), 0.25);
+    }
 }
@@ -0,0 +1,160 @@
+//! Black-Scholes pricing and greeks for European options on non-dividend paying stocks.
+//!
+//! Inputs are spot `s`, strike `k`, continuously compounded risk-free rate `r`, time to expiry in
+//! years `t`, and annualized volatility `sigma`. Vega is per unit of volatility and theta is per
+//! year.
+
+pub struct BlackScholes;
+
+impl BlackScholes {
+    //Complementary error function with fractional error less than 1.2e-7, from Numerical Recipes
+    fn erfc(x: f64) -> f64 {
+        let z = x.abs();
+        let t = 1.0 / (1.0 + 0.5 * z);
+        let ans = t
+            * (-z * z - 1.26551223
+                + t * (1.00002368
+                    + t * (0.37409196
+                        + t * (0.09678418
+                            + t * (-0.18628806
+                                + t * (0.27886807
+                                    + t * (-1.13520398
+                                        + t * (1.48851587
+                                            + t * (-0.82215223 + t * 0.17087277)))))))))
+                .exp();
+        if x >= 0.0 {
+            ans
+        } else {
+            2.0 - ans
+        }
+    }
+
+    fn norm_cdf(x: f64) -> f64 {
+        0.5 * Self::erfc(-x / std::f64::consts::SQRT_2)
+    }
+
+    fn norm_pdf(x: f64) -> f64 {
+        (-0.5 * x * x).exp() / (2.0 * std::f64::consts::PI).sqrt()
+    }
+
+    fn d1_d2(s: f64, k: f64, r: f64, t: f64, sigma: f64) -> (f64, f64) {
+        let d1 = ((s / k).ln() + (r + 0.5 * sigma * sigma) * t) / (sigma * t.sqrt());
+        (d1, d1 - sigma * t.sqrt())
+    }
+
+    pub fn call_price(s: f64, k: f64, r: f64, t: f64, sigma: f64) -> f64 {
+        let (d1, d2) = Self::d1_d2(s, k, r, t, sigma);
+        s * Self::norm_cdf(d1) - k * (-r * t).exp() * Self::norm_cdf(d2)
+    }
+
+    pub fn put_price(s: f64, k: f64, r: f64, t: f64, sigma: f64) -> f64 {
+        let (d1, d2) = Self::d1_d2(s, k, r, t, sigma);
+        k * (-r * t).exp() * Self::norm_cdf(-d2) - s * Self::norm_cdf(-d1)
+    }
+
+    pub fn call_delta(s: f64, k: f64, r: f64, t: f64, sigma: f64) -> f64 {
+        let (d1, _d2) = Self::d1_d2(s, k, r, t, sigma);
+        Self::norm_cdf(d1)
+    }
+
+    pub fn put_delta(s: f64, k: f64, r: f64, t: f64, sigma: f64) -> f64 {
+        Self::call_delta(s, k, r, t, sigma) - 1.0
+    }
+
+    /// Same for calls and puts.
+    pub fn gamma(s: f64, k: f64, r: f64, t: f64, sigma: f64) -> f64 {
+        let (d1, _d2) = Self::d1_d2(s, k, r, t, sigma);
+        Self::norm_pdf(d1) / (s * sigma * t.sqrt())
+    }
+
+    /// Same for calls and puts.
+    pub fn vega(s: f64, k: f64, r: f64, t: f64, sigma: f64) -> f64 {
+        let (d1, _d2) = Self::d1_d2(s, k, r, t, sigma);
+        s * Self::norm_pdf(d1) * t.sqrt()
+    }
+
+    pub fn theta(s: f64, k: f64, r: f64, t: f64, sigma: f64, is_call: bool) -> f64 {
+        let (d1, d2) = Self::d1_d2(s, k, r, t, sigma);
+        let decay = -(s * Self::norm_pdf(d1) * sigma) / (2.0 * t.sqrt());
+        let discounted_strike = r * k * (-r * t).exp();
+        if is_call {
+            decay - discounted_strike * Self::norm_cdf(d2)
+        } else {
+            decay + discounted_strike * Self::norm_cdf(-d2)
+        }
+    }
+
+    /// Solves for volatility with Newton-Raphson. Returns None if the solver does not converge,
+    /// which will happen if the price is outside the no-arbitrage bounds.
+    pub fn implied_volatility(
+        option_price: f64,
+        s: f64,
+        k: f64,
+        r: f64,
+        t: f64,
+        is_call: bool,
+    ) -> Option<f64> {
+        let mut sigma = 0.2;
+        for _ in 0..100 {
+            let price = if is_call {
+                Self::call_price(s, k, r, t, sigma)
+            } else {
+                Self::put_price(s, k, r, t, sigma)
+            };
+            let diff = price - option_price;
+            if diff.abs() < 1e-8 {
+                return Some(sigma);
+            }
+
+            let vega = Self::vega(s, k, r, t, sigma);
+            if vega < 1e-10 {
+                return None;
+            }
+            sigma -= diff / vega;
+            if !sigma.is_finite() || sigma <= 0.0 {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlackScholes;
+
+    fn round(val: f64) -> f64 {
+        (val * 10_000.0).round() / 10_000.0
+    }
+
+    #[test]
+    fn test_that_prices_satisfy_put_call_parity() {
+        for (s, k) in [(100.0, 100.0), (90.0, 110.0), (120.0, 95.0)] {
+            let (r, t, sigma) = (0.05, 0.75, 0.3);
+            let call = BlackScholes::call_price(s, k, r, t, sigma);
+            let put = BlackScholes::put_price(s, k, r, t, sigma);
+            let forward = s - k * f64::exp(-r * t);
+            assert_eq!(round(call - put), round(forward));
+
+            let delta_diff = BlackScholes::call_delta(s, k, r, t, sigma)
+                - BlackScholes::put_delta(s, k, r, t, sigma);
+            assert_eq!(round(delta_diff), 1.0);
+        }
+    }
+
+    #[test]
+    fn test_that_call_price_matches_reference_value() {
+        let call = BlackScholes::call_price(100.0, 100.0, 0.05, 1.0, 0.2);
+        assert_eq!(round(call), 10.4506);
+    }
+
+    #[test]
+    fn test_that_implied_volatility_recovers_sigma() {
+        let put = BlackScholes::put_price(100.0, 105.0, 0.02, 0.5, 0.35);
+        let sigma = BlackScholes::implied_volatility(put, 100.0, 105.0, 0.02, 0.5, false).unwrap();
+        assert_eq!(round(sigma), 0.35);
+
+        //Call cannot be worth more than the stock
+        assert!(BlackScholes::implied_volatility(150.0, 100.0, 100.0, 0.02, 0.5, true).is_none());
+    }
+}
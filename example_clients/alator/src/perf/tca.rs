@@ -0,0 +1,126 @@
+//! Transaction cost analysis, splits the difference between the price when a trade was decided and
+//! the price it executed at into the move before the order was submitted and the move after.
+//!
+//! Costs are in currency and positive when the price moved against the trade, so a buy that
+//! executes above the decision price or a sell that executes below it has a positive cost.
+
+use std::collections::HashMap;
+
+use rotala::exchange::uist_v1::TradeType;
+
+/// One trade. `submission_price` is the price when the order reached the exchange, without it
+/// timing and market impact can't be separated.
+#[derive(Clone, Debug)]
+pub struct TcaRecord {
+    pub symbol: String,
+    pub decision_price: f64,
+    pub submission_price: f64,
+    pub execution_price: f64,
+    pub qty: f64,
+    pub side: TradeType,
+    pub timestamp: i64,
+}
+
+/// Totals for a group of trades. `implementation_shortfall` is the sum of `timing_cost` and
+/// `market_impact_cost`, `decision_value` is the value of the trades at the decision price and
+/// can be used to express costs as a fraction of value traded.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TcaSummary {
+    pub n_trades: usize,
+    pub decision_value: f64,
+    pub implementation_shortfall: f64,
+    pub timing_cost: f64,
+    pub market_impact_cost: f64,
+}
+
+impl TcaSummary {
+    fn add(&mut self, record: &TcaRecord) {
+        //Price rises are a cost to buyers and a gain to sellers
+        let direction = match record.side {
+            TradeType::Buy => 1.0,
+            TradeType::Sell => -1.0,
+        };
+        let qty = record.qty.abs();
+        let timing = (record.submission_price - record.decision_price) * qty * direction;
+        let impact = (record.execution_price - record.submission_price) * qty * direction;
+
+        self.n_trades += 1;
+        self.decision_value += record.decision_price * qty;
+        self.timing_cost += timing;
+        self.market_impact_cost += impact;
+        self.implementation_shortfall += timing + impact;
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TcaReport {
+    pub overall: TcaSummary,
+    pub by_symbol: HashMap<String, TcaSummary>,
+}
+
+pub struct TcaAnalyzer;
+
+impl TcaAnalyzer {
+    pub fn analyze(records: &[TcaRecord]) -> TcaReport {
+        let mut report = TcaReport::default();
+        for record in records {
+            report.overall.add(record);
+            report
+                .by_symbol
+                .entry(record.symbol.clone())
+                .or_default()
+                .add(record);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rotala::exchange::uist_v1::TradeType;
+
+    use super::{TcaAnalyzer, TcaRecord};
+
+    fn record(symbol: &str, prices: (f64, f64, f64), qty: f64, side: TradeType) -> TcaRecord {
+        TcaRecord {
+            symbol: symbol.to_string(),
+            decision_price: prices.0,
+            submission_price: prices.1,
+            execution_price: prices.2,
+            qty,
+            side,
+            timestamp: 100,
+        }
+    }
+
+    #[test]
+    fn test_that_shortfall_splits_into_timing_and_impact() {
+        let records = vec![
+            //Price rose before submission and again on execution
+            record("ABC", (100.0, 101.0, 101.5), 10.0, TradeType::Buy),
+            //Price fell before submission, impact was adverse for a sale
+            record("ABC", (50.0, 49.0, 48.0), 20.0, TradeType::Sell),
+            //Price fell after decision, a gain for a buyer
+            record("BCD", (10.0, 9.5, 9.5), 100.0, TradeType::Buy),
+        ];
+
+        let report = TcaAnalyzer::analyze(&records);
+        let abc = report.by_symbol.get("ABC").unwrap();
+        assert_eq!(abc.n_trades, 2);
+        assert_eq!(abc.timing_cost, 10.0 + 20.0);
+        assert_eq!(abc.market_impact_cost, 5.0 + 20.0);
+        assert_eq!(abc.implementation_shortfall, 55.0);
+        assert_eq!(abc.decision_value, 2000.0);
+
+        let bcd = report.by_symbol.get("BCD").unwrap();
+        assert_eq!(bcd.timing_cost, -50.0);
+        assert_eq!(bcd.market_impact_cost, 0.0);
+
+        assert_eq!(report.overall.n_trades, 3);
+        assert_eq!(report.overall.implementation_shortfall, 5.0);
+        assert_eq!(
+            report.overall.timing_cost + report.overall.market_impact_cost,
+            report.overall.implementation_shortfall
+        );
+    }
+}
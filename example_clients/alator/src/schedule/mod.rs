@@ -1,6 +1,7 @@
 //! Schedules for running backtests
 
-use time::Weekday;
+use itertools::Itertools;
+use time::{Date, Month, Weekday};
 
 use crate::broker::DateTime;
 
@@ -58,10 +59,484 @@ impl TradingSchedule for LastBusinessDayTradingSchedule {
     }
 }
 
+/// Schedule selected at runtime rather than by type, used when the schedule comes from
+/// configuration.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RebalanceFrequency {
+    #[default]
+    Daily,
+    LastBusinessDay,
+}
+
+impl RebalanceFrequency {
+    pub fn should_trade(&self, date: &DateTime) -> bool {
+        match self {
+            RebalanceFrequency::Daily => DefaultTradingSchedule::should_trade(date),
+            RebalanceFrequency::LastBusinessDay => {
+                LastBusinessDayTradingSchedule::should_trade(date)
+            }
+        }
+    }
+}
+
+/// Timezones of supported markets. Daylight saving follows the rules in force since 2007, dates
+/// before that may be off by an hour around the transitions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Timezone {
+    Utc,
+    NewYork,
+    Toronto,
+    London,
+    Sydney,
+}
+
+impl Timezone {
+    pub fn utc_offset_secs(&self, date: &DateTime) -> i64 {
+        let hour = 3600;
+        let year = date.year();
+        let in_dst = |start: i64, end: i64| **date >= start && **date < end;
+        match self {
+            Timezone::Utc => 0,
+            //Second Sunday of March to first Sunday of November, at 2am local time
+            Timezone::NewYork | Timezone::Toronto => {
+                let start = nth_sunday(year, Month::March, 2) + 7 * hour;
+                let end = nth_sunday(year, Month::November, 1) + 6 * hour;
+                if in_dst(start, end) {
+                    -4 * hour
+                } else {
+                    -5 * hour
+                }
+            }
+            //Last Sunday of March to last Sunday of October, at 1am UTC
+            Timezone::London => {
+                let start = last_sunday(year, Month::March) + hour;
+                let end = last_sunday(year, Month::October) + hour;
+                if in_dst(start, end) {
+                    hour
+                } else {
+                    0
+                }
+            }
+            //First Sunday of October to first Sunday of April, at 2am and 3am local time
+            Timezone::Sydney => {
+                let end = nth_sunday(year, Month::April, 1) - 8 * hour;
+                let start = nth_sunday(year, Month::October, 1) - 8 * hour;
+                if **date < end || **date >= start {
+                    11 * hour
+                } else {
+                    10 * hour
+                }
+            }
+        }
+    }
+}
+
+//Midnight UTC of the `n`th Sunday in the month
+fn nth_sunday(year: i32, month: Month, n: u8) -> i64 {
+    let first = Date::from_calendar_date(year, month, 1).unwrap();
+    let until_sunday = (7 - first.weekday().number_days_from_sunday()) % 7;
+    let day = 1 + until_sunday + 7 * (n - 1);
+    let date = Date::from_calendar_date(year, month, day).unwrap();
+    date.midnight().assume_utc().unix_timestamp()
+}
+
+fn last_sunday(year: i32, month: Month) -> i64 {
+    let last_day = month.length(year);
+    let last = Date::from_calendar_date(year, month, last_day).unwrap();
+    let day = last_day - last.weekday().number_days_from_sunday();
+    let date = Date::from_calendar_date(year, month, day).unwrap();
+    date.midnight().assume_utc().unix_timestamp()
+}
+
+/// Exchanges with an embedded holiday calendar.
+///
+/// Holidays are only included for 2020 to 2025, outside those years only weekends are skipped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Market {
+    NyseEquity,
+    Lse,
+    Tsx,
+    Asx,
+}
+
+impl Market {
+    //Sorted so that lookups can use binary search
+    fn holidays(&self) -> &'static [&'static str] {
+        match self {
+            Market::NyseEquity => NYSE_EQUITY_HOLIDAYS,
+            Market::Lse => LSE_HOLIDAYS,
+            Market::Tsx => TSX_HOLIDAYS,
+            Market::Asx => ASX_HOLIDAYS,
+        }
+    }
+
+    pub fn timezone(&self) -> Timezone {
+        match self {
+            Market::NyseEquity => Timezone::NewYork,
+            Market::Lse => Timezone::London,
+            Market::Tsx => Timezone::Toronto,
+            Market::Asx => Timezone::Sydney,
+        }
+    }
+
+    /// Uses the calendar date in the market's timezone, so a date late in the UTC day can be on
+    /// the next day in Sydney.
+    pub fn is_trading_day(&self, date: &DateTime) -> bool {
+        let date = date.to_local(self.timezone());
+        if matches!(date.weekday(), Weekday::Saturday | Weekday::Sunday) {
+            return false;
+        }
+        let key = format!(
+            "{:04}-{:02}-{:02}",
+            date.year(),
+            date.month() as u8,
+            date.day()
+        );
+        self.holidays().binary_search(&key.as_str()).is_err()
+    }
+
+    /// Dates in the `length_in_days` calendar days from `start` that are trading days, each at the
+    /// same time of day as `start`.
+    pub fn trading_days(&self, start: &DateTime, length_in_days: i64) -> Vec<DateTime> {
+        let seconds_in_day = 86400;
+        (0..length_in_days)
+            .map(|day| DateTime::from(**start + day * seconds_in_day))
+            .filter(|date| self.is_trading_day(date))
+            .collect()
+    }
+
+    /// Last trading day of each month in the `length_in_days` calendar days from `start`, for
+    /// strategies that run at [Frequency::Monthly](crate::perf::Frequency::Monthly).
+    pub fn month_end_trading_days(&self, start: &DateTime, length_in_days: i64) -> Vec<DateTime> {
+        let mut month_ends: Vec<DateTime> = Vec::new();
+        for date in self.trading_days(start, length_in_days) {
+            match month_ends.last_mut() {
+                Some(last) if last.month() == date.month() && last.year() == date.year() => {
+                    *last = date
+                }
+                _ => month_ends.push(date),
+            }
+        }
+        month_ends
+    }
+
+    /// First trading day of each week in the `length_in_days` calendar days from `start`, this is
+    /// Monday unless Monday is a holiday, for strategies that run at
+    /// [Frequency::Weekly](crate::perf::Frequency::Weekly).
+    pub fn week_start_trading_days(&self, start: &DateTime, length_in_days: i64) -> Vec<DateTime> {
+        let seconds_in_day = 86400;
+        //Epoch is a Thursday so offsetting by three days makes weeks start on Monday
+        let week = |date: &DateTime| (**date / seconds_in_day + 3).div_euclid(7);
+        self.trading_days(start, length_in_days)
+            .into_iter()
+            .dedup_by(|prev, date| week(prev) == week(date))
+            .collect()
+    }
+}
+
+static NYSE_EQUITY_HOLIDAYS: &[&str] = &[
+    "2020-01-01",
+    "2020-01-20",
+    "2020-02-17",
+    "2020-04-10",
+    "2020-05-25",
+    "2020-07-03",
+    "2020-09-07",
+    "2020-11-26",
+    "2020-12-25",
+    "2021-01-01",
+    "2021-01-18",
+    "2021-02-15",
+    "2021-04-02",
+    "2021-05-31",
+    "2021-07-05",
+    "2021-09-06",
+    "2021-11-25",
+    "2021-12-24",
+    "2022-01-17",
+    "2022-02-21",
+    "2022-04-15",
+    "2022-05-30",
+    "2022-06-20",
+    "2022-07-04",
+    "2022-09-05",
+    "2022-11-24",
+    "2022-12-26",
+    "2023-01-02",
+    "2023-01-16",
+    "2023-02-20",
+    "2023-04-07",
+    "2023-05-29",
+    "2023-06-19",
+    "2023-07-04",
+    "2023-09-04",
+    "2023-11-23",
+    "2023-12-25",
+    "2024-01-01",
+    "2024-01-15",
+    "2024-02-19",
+    "2024-03-29",
+    "2024-05-27",
+    "2024-06-19",
+    "2024-07-04",
+    "2024-09-02",
+    "2024-11-28",
+    "2024-12-25",
+    "2025-01-01",
+    "2025-01-09",
+    "2025-01-20",
+    "2025-02-17",
+    "2025-04-18",
+    "2025-05-26",
+    "2025-06-19",
+    "2025-07-04",
+    "2025-09-01",
+    "2025-11-27",
+    "2025-12-25",
+];
+
+static LSE_HOLIDAYS: &[&str] = &[
+    "2020-01-01",
+    "2020-04-10",
+    "2020-04-13",
+    "2020-05-08",
+    "2020-05-25",
+    "2020-08-31",
+    "2020-12-25",
+    "2020-12-28",
+    "2021-01-01",
+    "2021-04-02",
+    "2021-04-05",
+    "2021-05-03",
+    "2021-05-31",
+    "2021-08-30",
+    "2021-12-27",
+    "2021-12-28",
+    "2022-01-03",
+    "2022-04-15",
+    "2022-04-18",
+    "2022-05-02",
+    "2022-06-02",
+    "2022-06-03",
+    "2022-08-29",
+    "2022-09-19",
+    "2022-12-26",
+    "2022-12-27",
+    "2023-01-02",
+    "2023-04-07",
+    "2023-04-10",
+    "2023-05-01",
+    "2023-05-08",
+    "2023-05-29",
+    "2023-08-28",
+    "2023-12-25",
+    "2023-12-26",
+    "2024-01-01",
+    "2024-03-29",
+    "2024-04-01",
+    "2024-05-06",
+    "2024-05-27",
+    "2024-08-26",
+    "2024-12-25",
+    "2024-12-26",
+    "2025-01-01",
+    "2025-04-18",
+    "2025-04-21",
+    "2025-05-05",
+    "2025-05-26",
+    "2025-08-25",
+    "2025-12-25",
+    "2025-12-26",
+];
+
+static TSX_HOLIDAYS: &[&str] = &[
+    "2020-01-01",
+    "2020-02-17",
+    "2020-04-10",
+    "2020-05-18",
+    "2020-07-01",
+    "2020-08-03",
+    "2020-09-07",
+    "2020-10-12",
+    "2020-12-25",
+    "2020-12-28",
+    "2021-01-01",
+    "2021-02-15",
+    "2021-04-02",
+    "2021-05-24",
+    "2021-07-01",
+    "2021-08-02",
+    "2021-09-06",
+    "2021-10-11",
+    "2021-12-27",
+    "2021-12-28",
+    "2022-01-03",
+    "2022-02-21",
+    "2022-04-15",
+    "2022-05-23",
+    "2022-07-01",
+    "2022-08-01",
+    "2022-09-05",
+    "2022-10-10",
+    "2022-12-26",
+    "2022-12-27",
+    "2023-01-02",
+    "2023-02-20",
+    "2023-04-07",
+    "2023-05-22",
+    "2023-07-03",
+    "2023-08-07",
+    "2023-09-04",
+    "2023-10-09",
+    "2023-12-25",
+    "2023-12-26",
+    "2024-01-01",
+    "2024-02-19",
+    "2024-03-29",
+    "2024-05-20",
+    "2024-07-01",
+    "2024-08-05",
+    "2024-09-02",
+    "2024-10-14",
+    "2024-12-25",
+    "2024-12-26",
+    "2025-01-01",
+    "2025-02-17",
+    "2025-04-18",
+    "2025-05-19",
+    "2025-07-01",
+    "2025-08-04",
+    "2025-09-01",
+    "2025-10-13",
+    "2025-12-25",
+    "2025-12-26",
+];
+
+static ASX_HOLIDAYS: &[&str] = &[
+    "2020-01-01",
+    "2020-01-27",
+    "2020-04-10",
+    "2020-04-13",
+    "2020-06-08",
+    "2020-12-25",
+    "2020-12-28",
+    "2021-01-01",
+    "2021-01-26",
+    "2021-04-02",
+    "2021-04-05",
+    "2021-06-14",
+    "2021-12-27",
+    "2021-12-28",
+    "2022-01-03",
+    "2022-01-26",
+    "2022-04-15",
+    "2022-04-18",
+    "2022-04-25",
+    "2022-06-13",
+    "2022-09-22",
+    "2022-12-26",
+    "2022-12-27",
+    "2023-01-02",
+    "2023-01-26",
+    "2023-04-07",
+    "2023-04-10",
+    "2023-04-25",
+    "2023-06-12",
+    "2023-12-25",
+    "2023-12-26",
+    "2024-01-01",
+    "2024-01-26",
+    "2024-03-29",
+    "2024-04-01",
+    "2024-04-25",
+    "2024-06-10",
+    "2024-12-25",
+    "2024-12-26",
+    "2025-01-01",
+    "2025-01-27",
+    "2025-04-18",
+    "2025-04-21",
+    "2025-04-25",
+    "2025-06-09",
+    "2025-12-25",
+    "2025-12-26",
+];
+
 #[cfg(test)]
 mod tests {
 
-    use super::{LastBusinessDayTradingSchedule, TradingSchedule};
+    use super::{LastBusinessDayTradingSchedule, Market, Timezone, TradingSchedule};
+    use crate::broker::DateTime;
+
+    #[test]
+    fn test_that_nyse_calendar_skips_weekends_and_holidays() {
+        // Date 1/1/21 - 9:00:0000
+        let start = DateTime::from(1609491600);
+        let days = Market::NyseEquity.trading_days(&start, 15);
+        assert_eq!(days.len(), 10);
+        assert!(!days.contains(&start));
+        // Date 18/1/21, MLK day
+        assert!(!Market::NyseEquity.is_trading_day(&DateTime::from(1610960400)));
+        // Date 19/1/21
+        assert!(Market::NyseEquity.is_trading_day(&DateTime::from(1611046800)));
+    }
+
+    #[test]
+    fn test_that_nyse_calendar_returns_month_ends_and_week_starts() {
+        // Date 1/1/21 - 9:00:0000
+        let start = DateTime::from(1609491600);
+        let seconds_in_day = 86400;
+
+        let month_ends = Market::NyseEquity.month_end_trading_days(&start, 90);
+        //29/1/21, 26/2/21 and 31/3/21
+        let expected = [28, 56, 89]
+            .map(|offset| DateTime::from(*start + offset * seconds_in_day))
+            .to_vec();
+        assert_eq!(month_ends, expected);
+
+        let week_starts = Market::NyseEquity.week_start_trading_days(&start, 28);
+        //1/1/21 is a holiday so first week starts on 4/1/21, 18/1/21 is a holiday so that week
+        //starts on 19/1/21
+        let expected = [3, 10, 18, 24]
+            .map(|offset| DateTime::from(*start + offset * seconds_in_day))
+            .to_vec();
+        assert_eq!(week_starts, expected);
+    }
+
+    #[test]
+    fn test_that_local_time_follows_daylight_saving() {
+        let hour = 3600;
+        // Date 15/1/21 - 12:00:0000
+        let winter = DateTime::from(1610712000);
+        // Date 15/7/21 - 12:00:0000
+        let summer = DateTime::from(1626350400);
+
+        assert_eq!(*winter.to_local(Timezone::NewYork) - *winter, -5 * hour);
+        assert_eq!(*summer.to_local(Timezone::NewYork) - *summer, -4 * hour);
+        assert_eq!(*winter.to_local(Timezone::London) - *winter, 0);
+        assert_eq!(*summer.to_local(Timezone::London) - *summer, hour);
+        assert_eq!(*winter.to_local(Timezone::Sydney) - *winter, 11 * hour);
+        assert_eq!(*summer.to_local(Timezone::Sydney) - *summer, 10 * hour);
+        assert_eq!(*summer.to_local(Timezone::Utc), *summer);
+    }
+
+    #[test]
+    fn test_that_trading_day_uses_local_date() {
+        // Date 4/1/21 - 02:00:0000, a Monday in UTC but Sunday evening in New York
+        let date = DateTime::from(1609725600);
+        assert!(!Market::NyseEquity.is_trading_day(&date));
+        assert!(Market::Lse.is_trading_day(&date));
+        // Date 1/1/21 - 14:00:0000, already 2/1/21 in Sydney but that is a Saturday
+        assert!(!Market::Asx.is_trading_day(&DateTime::from(1609509600)));
+    }
+
+    #[test]
+    fn test_that_holidays_are_sorted() {
+        for market in [Market::NyseEquity, Market::Lse, Market::Tsx, Market::Asx] {
+            let holidays = market.holidays();
+            assert!(holidays.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+    }
 
     #[test]
     fn test_that_schedule_returns_true_for_last_day_of_month() {
@@ -0,0 +1,184 @@
+//! Exposes the state of a [UistBroker] over HTTP.
+//!
+//! Routes are registered with [configure] and expect [AppState] as app data:
+//!
+//! * `GET /broker/cash` returns the cash balance
+//! * `GET /broker/holdings` returns [PortfolioHoldings]
+//! * `POST /broker/order` takes a [SendOrderRequest] and returns the [BrokerEvent] from the broker
+//! * `GET /broker/trades?start=<i64>&end=<i64>` returns trades executed between the two dates
+//! * `POST /broker/check` calls `check` on the broker, moving the backtest forward one tick
+//! * `GET /metrics` returns [rotala::metrics] in the Prometheus text format
+//!
+//! The broker sends orders to the exchange on `check` so a client has to call `/broker/check` to
+//! advance the simulation, the same as a strategy running in-process.
+
+use actix_web::{web, HttpResponse};
+use rotala::exchange::uist_v1::{Order, Trade};
+use rotala::http::uist::uistv1_client::UistClient;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::broker::uist::UistBroker;
+use crate::broker::{BrokerEvent, Clock, Portfolio, PortfolioHoldings, SendOrder, Update};
+
+/// Broker is behind an async lock because `check` is held across calls to the exchange.
+pub struct AppState<C: UistClient> {
+    pub broker: Mutex<UistBroker<C>>,
+}
+
+impl<C: UistClient> AppState<C> {
+    pub fn new(broker: UistBroker<C>) -> Self {
+        Self {
+            broker: Mutex::new(broker),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SendOrderRequest {
+    pub order: Order,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TradesQuery {
+    pub start: i64,
+    pub end: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CheckResponse {
+    pub now: i64,
+    pub has_next: bool,
+}
+
+pub async fn cash<C: UistClient>(app: web::Data<AppState<C>>) -> web::Json<f64> {
+    let brkr = app.broker.lock().await;
+    web::Json(brkr.get_cash_balance())
+}
+
+pub async fn holdings<C: UistClient>(app: web::Data<AppState<C>>) -> web::Json<PortfolioHoldings> {
+    let brkr = app.broker.lock().await;
+    web::Json(brkr.get_holdings())
+}
+
+pub async fn order<C: UistClient>(
+    app: web::Data<AppState<C>>,
+    send_order: web::Json<SendOrderRequest>,
+) -> web::Json<BrokerEvent<Order>> {
+    let mut brkr = app.broker.lock().await;
+    web::Json(brkr.send_order(send_order.into_inner().order))
+}
+
+pub async fn trades<C: UistClient>(
+    app: web::Data<AppState<C>>,
+    query: web::Query<TradesQuery>,
+) -> web::Json<Vec<Trade>> {
+    let brkr = app.broker.lock().await;
+    web::Json(brkr.trades_between(&query.start, &query.end))
+}
+
+pub async fn check<C: UistClient>(app: web::Data<AppState<C>>) -> web::Json<CheckResponse> {
+    let mut brkr = app.broker.lock().await;
+    brkr.check().await;
+    web::Json(CheckResponse {
+        now: brkr.now(),
+        has_next: brkr.has_next(),
+    })
+}
+
+pub async fn metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(rotala::metrics::gather())
+}
+
+/// Handlers are generic over the broker's exchange client so are registered here rather than with
+/// the route macros.
+pub fn configure<C: UistClient + 'static>(cfg: &mut web::ServiceConfig) {
+    cfg.route("/broker/cash", web::get().to(cash::<C>))
+        .route("/broker/holdings", web::get().to(holdings::<C>))
+        .route("/broker/order", web::post().to(order::<C>))
+        .route("/broker/trades", web::get().to(trades::<C>))
+        .route("/broker/check", web::post().to(check::<C>))
+        .route("/metrics", web::get().to(metrics));
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App};
+    use rotala::exchange::uist_v1::{Order, Trade};
+    use rotala::http::uist::uistv1_client::{TestClient, UistClient};
+    use rotala::input::penelope::Penelope;
+
+    use super::{configure, AppState, CheckResponse, SendOrderRequest};
+    use crate::broker::uist::UistBrokerBuilder;
+    use crate::broker::{BrokerEvent, CashOperations, PortfolioHoldings};
+
+    #[actix_web::test]
+    async fn test_that_server_sends_order_and_returns_state() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 101.00, 100, "ABC");
+        source.add_quote(102.00, 103.00, 101, "ABC");
+        source.add_quote(104.00, 105.00, 102, "ABC");
+        source.add_quote(104.00, 105.00, 103, "ABC");
+
+        let mut client = TestClient::single("Server", source);
+        let resp = client.init("Server".to_string()).await.unwrap();
+        let mut brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
+        brkr.deposit_cash(&100_000.0);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::new(brkr)))
+                .configure(configure::<TestClient>),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/broker/cash").to_request();
+        let cash: f64 = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(cash, 100_000.0);
+
+        let req = test::TestRequest::post()
+            .uri("/broker/order")
+            .set_json(SendOrderRequest {
+                order: Order::market_buy("ABC", 100.0),
+            })
+            .to_request();
+        let event: BrokerEvent<Order> = test::call_and_read_body_json(&app, req).await;
+        assert!(matches!(event, BrokerEvent::OrderSentToExchange(..)));
+
+        for _ in 0..2 {
+            let req = test::TestRequest::post().uri("/broker/check").to_request();
+            let resp: CheckResponse = test::call_and_read_body_json(&app, req).await;
+            assert!(resp.has_next);
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/broker/holdings")
+            .to_request();
+        let holdings: PortfolioHoldings = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(holdings.get("ABC"), Some(&100.0));
+
+        let req = test::TestRequest::get()
+            .uri("/broker/trades?start=100&end=103")
+            .to_request();
+        let trades: Vec<Trade> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].symbol, "ABC");
+
+        let req = test::TestRequest::get()
+            .uri("/broker/trades?start=103&end=103")
+            .to_request();
+        let trades: Vec<Trade> = test::call_and_read_body_json(&app, req).await;
+        assert!(trades.is_empty());
+
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("cash_balance"));
+        assert!(text.contains("trades_executed_total"));
+    }
+}
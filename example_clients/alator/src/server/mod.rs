@@ -0,0 +1,4 @@
+//! HTTP servers that expose Alator components so that strategies written in other languages can
+//! drive a backtest. Mirrors the exchange servers in [Rotala](rotala).
+
+pub mod broker;
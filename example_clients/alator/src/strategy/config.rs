@@ -0,0 +1,231 @@
+//! Loads [StaticWeightStrategy](crate::strategy::staticweight::StaticWeightStrategy) setup from a
+//! TOML file so that a strategy can be changed without recompiling:
+//!
+//! ```toml
+//! initial_cash = 100000.0
+//! rebalance_frequency = "last_business_day"
+//!
+//! [weights]
+//! ABC = 0.5
+//! BCD = 0.5
+//!
+//! [[trade_costs]]
+//! type = "pct_of_value"
+//! rate = 0.005
+//! ```
+//!
+//! The config is only data, it is passed to the existing builders with `with_weights`,
+//! `with_trade_costs` and `with_rebalance_frequency`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::broker::BrokerCost;
+use crate::schedule::RebalanceFrequency;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigError {
+    Io(String),
+    Parse(String),
+    UnknownRebalanceFrequency(String),
+}
+
+impl Error for ConfigError {}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "Unable to read config: {err}"),
+            ConfigError::Parse(err) => write!(f, "Unable to parse config: {err}"),
+            ConfigError::UnknownRebalanceFrequency(frequency) => {
+                write!(f, "Unknown rebalance frequency: {frequency}")
+            }
+        }
+    }
+}
+
+/// Deserializable form of [BrokerCost], tagged by `type`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TradeCostConfig {
+    PerShare { rate: f64 },
+    PctOfValue { rate: f64 },
+    Flat { amount: f64 },
+}
+
+impl From<TradeCostConfig> for BrokerCost {
+    fn from(value: TradeCostConfig) -> Self {
+        match value {
+            TradeCostConfig::PerShare { rate } => BrokerCost::per_share(rate),
+            TradeCostConfig::PctOfValue { rate } => BrokerCost::pct_of_value(rate),
+            TradeCostConfig::Flat { amount } => BrokerCost::flat(amount),
+        }
+    }
+}
+
+/// `rebalance_frequency` is either `daily` or `last_business_day`, it is kept as a string so that
+/// an unknown value is reported by [StaticWeightStrategyConfig::get_rebalance_frequency] rather than as
+/// a parse error.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StaticWeightStrategyConfig {
+    pub weights: HashMap<String, f64>,
+    #[serde(default)]
+    pub trade_costs: Vec<TradeCostConfig>,
+    pub initial_cash: f64,
+    #[serde(default = "default_rebalance_frequency")]
+    pub rebalance_frequency: String,
+}
+
+fn default_rebalance_frequency() -> String {
+    "daily".to_string()
+}
+
+impl StaticWeightStrategyConfig {
+    pub fn from_toml_file(path: &str) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|err| ConfigError::Io(err.to_string()))?;
+        Self::from_toml_str(&contents)
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<Self, ConfigError> {
+        toml::from_str(contents).map_err(|err| ConfigError::Parse(err.to_string()))
+    }
+
+    pub fn get_trade_costs(&self) -> Vec<BrokerCost> {
+        self.trade_costs
+            .iter()
+            .cloned()
+            .map(BrokerCost::from)
+            .collect()
+    }
+
+    pub fn get_rebalance_frequency(&self) -> Result<RebalanceFrequency, ConfigError> {
+        match self.rebalance_frequency.as_str() {
+            "daily" => Ok(RebalanceFrequency::Daily),
+            "last_business_day" => Ok(RebalanceFrequency::LastBusinessDay),
+            other => Err(ConfigError::UnknownRebalanceFrequency(other.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rotala::http::uist::uistv1_client::{TestClient, UistClient};
+    use rotala::input::penelope::Penelope;
+
+    use super::{ConfigError, StaticWeightStrategyConfig, TradeCostConfig};
+    use crate::broker::uist::UistBrokerBuilder;
+    use crate::broker::BrokerCost;
+    use crate::schedule::RebalanceFrequency;
+    use crate::strategy::staticweight::StaticWeightStrategyBuilder;
+    use crate::strategy::Strategy;
+
+    const CONFIG: &str = r#"
+        initial_cash = 100000.0
+        rebalance_frequency = "last_business_day"
+
+        [weights]
+        ABC = 0.5
+        BCD = 0.5
+
+        [[trade_costs]]
+        type = "pct_of_value"
+        rate = 0.005
+
+        [[trade_costs]]
+        type = "flat"
+        amount = 1.0
+    "#;
+
+    #[test]
+    fn test_that_config_round_trips_through_toml() {
+        let config = StaticWeightStrategyConfig::from_toml_str(CONFIG).unwrap();
+        assert_eq!(config.initial_cash, 100_000.0);
+        assert_eq!(config.weights.get("ABC"), Some(&0.5));
+        assert_eq!(
+            config.trade_costs,
+            vec![
+                TradeCostConfig::PctOfValue { rate: 0.005 },
+                TradeCostConfig::Flat { amount: 1.0 }
+            ]
+        );
+        assert_eq!(
+            config.get_rebalance_frequency(),
+            Ok(RebalanceFrequency::LastBusinessDay)
+        );
+        assert!(matches!(
+            config.get_trade_costs()[0],
+            BrokerCost::PctOfValue(rate) if rate == 0.005
+        ));
+
+        let serialized = toml::to_string(&config).unwrap();
+        let round_trip = StaticWeightStrategyConfig::from_toml_str(&serialized).unwrap();
+        assert_eq!(round_trip, config);
+    }
+
+    #[test]
+    fn test_that_bad_config_errors() {
+        let missing_cash = "[weights]\nABC = 1.0";
+        assert!(matches!(
+            StaticWeightStrategyConfig::from_toml_str(missing_cash),
+            Err(ConfigError::Parse(_))
+        ));
+
+        let unknown_cost = "initial_cash = 1.0\n[weights]\nABC = 1.0\n[[trade_costs]]\ntype = \"spread\"\nrate = 0.1";
+        assert!(StaticWeightStrategyConfig::from_toml_str(unknown_cost).is_err());
+
+        let config = StaticWeightStrategyConfig::from_toml_str(
+            "initial_cash = 1.0\nrebalance_frequency = \"hourly\"\n[weights]\nABC = 1.0",
+        )
+        .unwrap();
+        assert_eq!(
+            config.get_rebalance_frequency(),
+            Err(ConfigError::UnknownRebalanceFrequency("hourly".to_string()))
+        );
+
+        assert!(matches!(
+            StaticWeightStrategyConfig::from_toml_file("/does/not/exist.toml"),
+            Err(ConfigError::Io(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_that_config_builds_strategy() {
+        let path = std::env::temp_dir().join(format!(
+            "alator_test_that_config_builds_strategy_{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "initial_cash = 100000.0\n[weights]\nABC = 0.5\nBCD = 0.5",
+        )
+        .unwrap();
+        let config = StaticWeightStrategyConfig::from_toml_file(path.to_str().unwrap()).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let mut source = Penelope::new();
+        for date in 100..105 {
+            source.add_quote(100.0, 101.0, date, "ABC");
+            source.add_quote(50.0, 51.0, date, "BCD");
+        }
+        let mut client = TestClient::single("Config", source);
+        let resp = client.init("Config".to_string()).await.unwrap();
+        let brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .with_trade_costs(config.get_trade_costs())
+            .build()
+            .await;
+
+        let mut strat = StaticWeightStrategyBuilder::new()
+            .with_brkr(brkr)
+            .with_weights(config.weights.clone())
+            .with_rebalance_frequency(config.get_rebalance_frequency().unwrap())
+            .default();
+        strat.init(&config.initial_cash);
+        strat.run().await;
+        assert!(!strat.get_trades().is_empty());
+    }
+}
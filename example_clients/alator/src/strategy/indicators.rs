@@ -0,0 +1,216 @@
+//! Technical indicators implementing [Signal]. Prices are the mid of bid and ask and state is held
+//! per symbol so a single indicator can score every symbol in a strategy.
+
+use std::collections::HashMap;
+
+use crate::broker::BrokerQuote;
+use crate::strategy::signal::Signal;
+
+fn mid(quote: &dyn BrokerQuote) -> f64 {
+    (quote.get_bid() + quote.get_ask()) / 2.0
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RsiState {
+    last_price: Option<f64>,
+    avg_gain: f64,
+    avg_loss: f64,
+    observations: usize,
+}
+
+/// Relative strength index with Wilder's smoothing. Averages are seeded with the simple mean of the
+/// first `period` price changes and then smoothed by `1/period`. Scores zero until there are
+/// `period` price changes, then `(rsi - 50) / 50`.
+pub struct Rsi {
+    pub period: usize,
+    pub state: HashMap<String, RsiState>,
+}
+
+impl Rsi {
+    pub fn new(period: usize) -> Self {
+        Self {
+            period,
+            state: HashMap::new(),
+        }
+    }
+}
+
+impl Signal for Rsi {
+    fn compute(&mut self, symbol: &str, quote: &dyn BrokerQuote) -> f64 {
+        let price = mid(quote);
+        let period = self.period.max(1) as f64;
+        let state = self.state.entry(symbol.to_string()).or_default();
+
+        let Some(last_price) = state.last_price.replace(price) else {
+            return 0.0;
+        };
+        let change = price - last_price;
+        let (gain, loss) = (change.max(0.0), (-change).max(0.0));
+
+        state.observations += 1;
+        if state.observations <= self.period {
+            //Running mean until the seed is complete
+            let count = state.observations as f64;
+            state.avg_gain += (gain - state.avg_gain) / count;
+            state.avg_loss += (loss - state.avg_loss) / count;
+        } else {
+            state.avg_gain += (gain - state.avg_gain) / period;
+            state.avg_loss += (loss - state.avg_loss) / period;
+        }
+
+        if state.observations < self.period {
+            return 0.0;
+        }
+
+        let rsi = if state.avg_gain + state.avg_loss == 0.0 {
+            50.0
+        } else {
+            100.0 * state.avg_gain / (state.avg_gain + state.avg_loss)
+        };
+        (rsi - 50.0) / 50.0
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MacState {
+    pub fast: f64,
+    pub slow: f64,
+}
+
+/// Scores `1.0` on the tick that the fast EMA crosses above the slow EMA, `-1.0` on the tick that
+/// it crosses below, and zero otherwise. EMAs are seeded with the first price and smoothed by
+/// `2 / (period + 1)`.
+pub struct MovingAverageCrossover {
+    pub fast_period: usize,
+    pub slow_period: usize,
+    pub state: HashMap<String, MacState>,
+}
+
+impl MovingAverageCrossover {
+    pub fn new(fast_period: usize, slow_period: usize) -> Self {
+        Self {
+            fast_period,
+            slow_period,
+            state: HashMap::new(),
+        }
+    }
+}
+
+impl Signal for MovingAverageCrossover {
+    fn compute(&mut self, symbol: &str, quote: &dyn BrokerQuote) -> f64 {
+        let price = mid(quote);
+        let Some(state) = self.state.get_mut(symbol) else {
+            self.state.insert(
+                symbol.to_string(),
+                MacState {
+                    fast: price,
+                    slow: price,
+                },
+            );
+            return 0.0;
+        };
+
+        let was_below = state.fast < state.slow;
+        let was_above = state.fast > state.slow;
+        let fast_alpha = 2.0 / (self.fast_period as f64 + 1.0);
+        let slow_alpha = 2.0 / (self.slow_period as f64 + 1.0);
+        state.fast += fast_alpha * (price - state.fast);
+        state.slow += slow_alpha * (price - state.slow);
+
+        if was_below && state.fast > state.slow {
+            1.0
+        } else if was_above && state.fast < state.slow {
+            -1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rotala::exchange::uist_v1::UistQuote;
+
+    use super::{MovingAverageCrossover, Rsi};
+    use crate::strategy::signal::Signal;
+
+    fn quote(price: f64, date: i64) -> UistQuote {
+        UistQuote {
+            bid: price,
+            ask: price,
+            date,
+            symbol: "ABC".to_string(),
+            volume: None,
+        }
+    }
+
+    #[test]
+    fn test_that_rsi_scores_trends() {
+        let mut rsi = Rsi::new(5);
+        let mut scores = Vec::new();
+        for i in 0..20 {
+            scores.push(rsi.compute("ABC", &quote(100.0 + i as f64, i)));
+        }
+        //Five price changes needs six prices
+        assert!(scores[..5].iter().all(|score| *score == 0.0));
+        assert_eq!(*scores.last().unwrap(), 1.0);
+
+        let mut rsi = Rsi::new(5);
+        let mut score = 0.0;
+        for i in 0..20 {
+            score = rsi.compute("ABC", &quote(100.0 - i as f64, i));
+        }
+        assert_eq!(score, -1.0);
+    }
+
+    #[test]
+    fn test_that_rsi_keeps_state_per_symbol() {
+        let mut rsi = Rsi::new(3);
+        let mut up = 0.0;
+        let mut down = 0.0;
+        //Ticks for the two symbols are interleaved
+        for i in 0..10 {
+            up = rsi.compute("ABC", &quote(100.0 + i as f64, i));
+            down = rsi.compute("BCD", &quote(100.0 - i as f64, i));
+        }
+        assert_eq!(up, 1.0);
+        assert_eq!(down, -1.0);
+
+        //Mixed moves score between the extremes
+        let mut rsi = Rsi::new(3);
+        let mut score = 0.0;
+        for (i, price) in [100.0, 102.0, 101.0, 104.0, 103.0].iter().enumerate() {
+            score = rsi.compute("ABC", &quote(*price, i as i64));
+        }
+        assert!(score > 0.0 && score < 1.0);
+    }
+
+    #[test]
+    fn test_that_crossover_fires_once_per_cross() {
+        //Falls then recovers, fast EMA drops below slow and then crosses back above
+        let prices = [
+            100.0, 100.0, 98.0, 96.0, 94.0, 92.0, 90.0, 92.0, 96.0, 100.0, 104.0, 108.0, 110.0,
+            110.0, 110.0,
+        ];
+        let mut mac = MovingAverageCrossover::new(2, 5);
+        let scores = prices
+            .iter()
+            .enumerate()
+            .map(|(i, price)| mac.compute("ABC", &quote(*price, i as i64)))
+            .collect::<Vec<f64>>();
+
+        assert_eq!(scores.iter().filter(|score| **score == 1.0).count(), 1);
+        let cross = scores.iter().position(|score| *score == 1.0).unwrap();
+        assert_eq!(scores[cross + 1], 0.0);
+        //Fast and slow start equal so the initial fall isn't a crossover
+        assert!(!scores.contains(&-1.0));
+
+        //Reversing the recovery gives a downward cross
+        let mut mac = MovingAverageCrossover::new(2, 5);
+        let mut down = Vec::new();
+        for (i, price) in [100.0, 102.0, 104.0, 106.0, 100.0, 94.0].iter().enumerate() {
+            down.push(mac.compute("ABC", &quote(*price, i as i64)));
+        }
+        assert_eq!(down.iter().filter(|score| **score == -1.0).count(), 1);
+    }
+}
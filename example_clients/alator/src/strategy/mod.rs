@@ -10,13 +10,259 @@
 //! clients but future exchange implementations will have some protection for environments with
 //! multiple strategies running concurrently.
 
+#[cfg(feature = "config")]
+pub mod config;
+pub mod indicators;
+pub mod momentum;
+pub mod rankers;
+pub mod reversal;
+pub mod runner;
+pub mod signal;
 pub mod staticweight;
+pub mod walkforward;
+pub mod weights;
 
-#[allow(unused)]
+use std::marker::PhantomData;
+
+use log::info;
+use rotala::exchange::uist_v1::Trade;
+
+use crate::broker::{
+    BrokerCashEvent, BrokerEvent, BrokerOrder, BrokerQuote, DateTime, StrategySnapshot,
+};
+use crate::perf::{BacktestOutput, PerformanceCalculator, PerformanceConfig};
+use staticweight::{PortfolioAllocation, StaticWeightBroker};
 
+#[allow(unused)]
 /// Used to log cash flows which may be used in performance calculations.
 pub enum StrategyEvent {
     WithdrawSuccess(f64),
     WithdrawFailure(f64),
     DepositSuccess(f64),
 }
+
+/// Counts of what happened during a run, as opposed to the performance of the strategy.
+///
+/// Orders and rebalances are counted by the strategy so exclude orders created by the broker, such
+/// as sales from `withdraw_cash_with_liquidation`. Trades are counted by the broker. Statistics for
+/// multiple strategies can be combined with [SimulationStatistics::aggregate].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimulationStatistics {
+    pub total_ticks: usize,
+    pub total_orders_submitted: u64,
+    pub total_trades_executed: u64,
+    pub total_rebalances: u64,
+    pub strategies_count: usize,
+}
+
+impl SimulationStatistics {
+    pub fn aggregate(stats: &[SimulationStatistics]) -> SimulationStatistics {
+        let mut total = SimulationStatistics::default();
+        for stat in stats {
+            total.total_ticks += stat.total_ticks;
+            total.total_orders_submitted += stat.total_orders_submitted;
+            total.total_trades_executed += stat.total_trades_executed;
+            total.total_rebalances += stat.total_rebalances;
+            total.strategies_count += stat.strategies_count;
+        }
+        total
+    }
+}
+
+/// Broker and the record of a run that every strategy keeps: cash flows, snapshots and counts.
+///
+/// Strategies own one of these and implement [Strategy] to get cash handling, history and
+/// performance, leaving only the signal and allocation logic in the strategy.
+pub struct StrategyState<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> {
+    brkr: B,
+    net_cash_flow: f64,
+    history: Vec<StrategySnapshot>,
+    ticks: usize,
+    orders_submitted: u64,
+    rebalances: u64,
+    _quote: PhantomData<Q>,
+    _order: PhantomData<O>,
+}
+
+impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> StrategyState<Q, O, B> {
+    pub fn new(brkr: B) -> Self {
+        Self {
+            brkr,
+            net_cash_flow: 0.0,
+            history: Vec::new(),
+            ticks: 0,
+            orders_submitted: 0,
+            rebalances: 0,
+            _quote: PhantomData,
+            _order: PhantomData,
+        }
+    }
+
+    pub fn brkr(&self) -> &B {
+        &self.brkr
+    }
+
+    pub fn brkr_mut(&mut self) -> &mut B {
+        &mut self.brkr
+    }
+
+    /// Moves the broker forward, called at the start of every update.
+    pub async fn tick(&mut self) {
+        self.brkr.check().await;
+        self.ticks += 1;
+    }
+
+    pub fn get_snapshot(&mut self) -> StrategySnapshot {
+        // Defaults to zero inflation because most users probably aren't looking
+        // for real returns calcs
+        let now = self.brkr.now();
+        StrategySnapshot {
+            date: now.into(),
+            portfolio_value: self.brkr.get_total_value(),
+            net_cash_flow: self.net_cash_flow,
+            inflation: 0.0,
+        }
+    }
+
+    /// Adds a snapshot of the portfolio to history, called at the end of every update.
+    pub fn record_snapshot(&mut self) {
+        let snap = self.get_snapshot();
+        self.history.push(snap);
+    }
+
+    /// Returns true if the broker accepted the order.
+    pub fn send_order(&mut self, order: O) -> bool {
+        let sent = matches!(
+            self.brkr.send_order(order),
+            BrokerEvent::OrderSentToExchange(..) | BrokerEvent::OrderTrimmed(..)
+        );
+        if sent {
+            self.orders_submitted += 1;
+        }
+        sent
+    }
+
+    /// Sends the orders needed to move the broker to `target_weights`.
+    pub fn rebalance(&mut self, target_weights: &PortfolioAllocation) {
+        let orders = self.brkr.diff_brkr_against_target_weights(target_weights);
+        if !orders.is_empty() {
+            let sent = self
+                .brkr
+                .send_orders(&orders)
+                .iter()
+                .filter(|event| {
+                    matches!(
+                        event,
+                        BrokerEvent::OrderSentToExchange(..) | BrokerEvent::OrderTrimmed(..)
+                    )
+                })
+                .count();
+            //Rebalance is only counted if the broker accepted at least one order
+            if sent > 0 {
+                self.rebalances += 1;
+                self.orders_submitted += sent as u64;
+            }
+        }
+    }
+
+    /// Zero weight for every symbol in `symbols` with an open position. Strategies that select
+    /// from a universe start from this so that positions which are no longer selected are closed.
+    pub fn close_unselected(&self, symbols: &[String]) -> PortfolioAllocation {
+        let mut weights = PortfolioAllocation::new();
+        for symbol in symbols {
+            if self
+                .brkr
+                .get_position_qty(symbol)
+                .is_some_and(|qty| qty != 0.0)
+            {
+                weights.insert(symbol.clone(), 0.0);
+            }
+        }
+        weights
+    }
+}
+
+/// Cash handling, history and performance shared by every strategy. Implementors only provide
+/// access to their [StrategyState].
+pub trait Strategy<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> {
+    fn state(&self) -> &StrategyState<Q, O, B>;
+    fn state_mut(&mut self) -> &mut StrategyState<Q, O, B>;
+
+    fn init(&mut self, initital_cash: &f64) {
+        self.deposit_cash(initital_cash);
+    }
+
+    fn perf(&self, config: impl Into<PerformanceConfig>) -> BacktestOutput {
+        //Intended to be called at end of simulation
+        let trades = self.get_trades();
+        PerformanceCalculator::calculate(config, self.get_history(), &trades, None)
+    }
+
+    fn get_snapshot(&mut self) -> StrategySnapshot {
+        self.state_mut().get_snapshot()
+    }
+
+    //Trades executed between the first and last snapshot in history
+    fn get_trades(&self) -> Vec<Trade> {
+        let state = self.state();
+        match (state.history.first(), state.history.last()) {
+            (Some(first), Some(last)) => state.brkr.trades_between(&first.date, &last.date),
+            _ => Vec::new(),
+        }
+    }
+
+    fn deposit_cash(&mut self, cash: &f64) -> StrategyEvent {
+        info!("STRATEGY: Depositing {:?} into strategy", cash);
+        let state = self.state_mut();
+        state.brkr.deposit_cash(cash);
+        state.net_cash_flow += cash;
+        StrategyEvent::DepositSuccess(*cash)
+    }
+
+    fn withdraw_cash(&mut self, cash: &f64) -> StrategyEvent {
+        let state = self.state_mut();
+        if let BrokerCashEvent::WithdrawSuccess(withdrawn) = state.brkr.withdraw_cash(cash) {
+            info!("STRATEGY: Successfully withdrew {:?} from strategy", cash);
+            state.net_cash_flow -= withdrawn;
+            return StrategyEvent::WithdrawSuccess(*cash);
+        }
+        info!("STRATEGY: Failed to withdraw {:?} from strategy", cash);
+        StrategyEvent::WithdrawFailure(*cash)
+    }
+
+    fn withdraw_cash_with_liquidation(&mut self, cash: &f64) -> StrategyEvent {
+        let state = self.state_mut();
+        if let BrokerCashEvent::WithdrawSuccess(withdrawn) =
+            //No logging here because the implementation is fully logged due to the greater
+            //complexity of this task vs standard withdraw
+            state.brkr.withdraw_cash_with_liquidation(cash)
+        {
+            state.net_cash_flow -= withdrawn;
+            return StrategyEvent::WithdrawSuccess(*cash);
+        }
+        StrategyEvent::WithdrawFailure(*cash)
+    }
+
+    fn get_history(&self) -> Vec<StrategySnapshot> {
+        self.state().history.clone()
+    }
+
+    fn get_equity_curve_series(&self) -> Vec<(DateTime, f64)> {
+        PerformanceCalculator::get_equity_curve_series(&self.state().history)
+    }
+
+    fn get_pnl_since(&self, date: DateTime) -> f64 {
+        PerformanceCalculator::get_pnl_since(&self.state().history, date)
+    }
+
+    fn get_simulation_statistics(&self) -> SimulationStatistics {
+        let state = self.state();
+        SimulationStatistics {
+            total_ticks: state.ticks,
+            total_orders_submitted: state.orders_submitted,
+            total_trades_executed: state.brkr.get_trade_count() as u64,
+            total_rebalances: state.rebalances,
+            strategies_count: 1,
+        }
+    }
+}
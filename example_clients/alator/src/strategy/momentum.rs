@@ -0,0 +1,187 @@
+use std::marker::PhantomData;
+
+use rotala::input::penelope::Penelope;
+
+use crate::broker::{BrokerOrder, BrokerQuote};
+use crate::schedule::{DefaultTradingSchedule, TradingSchedule};
+use crate::strategy::rankers::{CrossSectionalMomentumRanker, Ranker};
+use crate::strategy::staticweight::{PortfolioAllocation, StaticWeightBroker};
+use crate::strategy::{Strategy, StrategyState};
+
+pub struct MomentumStrategyBuilder<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> {
+    //If missing either field, we cannot run this strategy
+    brkr: Option<B>,
+    symbols: Option<Vec<String>>,
+    lookback_periods: usize,
+    n_long: usize,
+    n_short: usize,
+    _quote: PhantomData<Q>,
+    _order: PhantomData<O>,
+}
+
+impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> MomentumStrategyBuilder<Q, O, B> {
+    pub fn default(&mut self) -> MomentumStrategy<Q, O, B> {
+        if self.brkr.is_none() || self.symbols.is_none() {
+            panic!("Strategy must have broker and symbols");
+        }
+
+        MomentumStrategy {
+            state: StrategyState::new(self.brkr.take().unwrap()),
+            symbols: self.symbols.take().unwrap(),
+            lookback_periods: self.lookback_periods,
+            n_long: self.n_long,
+            n_short: self.n_short,
+            prices: Penelope::new(),
+        }
+    }
+
+    pub fn with_brkr(&mut self, brkr: B) -> &mut Self {
+        self.brkr = Some(brkr);
+        self
+    }
+
+    pub fn with_symbols(&mut self, symbols: &[&str]) -> &mut Self {
+        self.symbols = Some(symbols.iter().map(|symbol| symbol.to_string()).collect());
+        self
+    }
+
+    pub fn with_lookback_periods(&mut self, lookback_periods: usize) -> &mut Self {
+        self.lookback_periods = lookback_periods;
+        self
+    }
+
+    pub fn with_n_long(&mut self, n_long: usize) -> &mut Self {
+        self.n_long = n_long;
+        self
+    }
+
+    pub fn with_n_short(&mut self, n_short: usize) -> &mut Self {
+        self.n_short = n_short;
+        self
+    }
+
+    pub fn new() -> Self {
+        Self {
+            brkr: None,
+            symbols: None,
+            lookback_periods: 20,
+            n_long: 1,
+            n_short: 0,
+            _quote: PhantomData,
+            _order: PhantomData,
+        }
+    }
+}
+
+impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> Default
+    for MomentumStrategyBuilder<Q, O, B>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+///Cross-sectional momentum. Ranks `symbols` by their return over the last `lookback_periods` ticks
+///and targets an equal weight in the `n_long` best performers and an equal short weight in the
+///`n_short` worst performers. Longs sum to one and shorts sum to minus one, shorts require short
+///selling to be enabled on the broker.
+///
+///Returns are taken from the mid of the broker's latest quote on each tick. The broker only holds
+///the latest quote so the strategy keeps its own window of prices, symbols are only ranked once
+///they have a price at both ends of the window. Positions that fall out of the selection are closed on the next
+///rebalance.
+pub struct MomentumStrategy<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> {
+    state: StrategyState<Q, O, B>,
+    symbols: Vec<String>,
+    lookback_periods: usize,
+    n_long: usize,
+    n_short: usize,
+    prices: Penelope,
+}
+
+impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> MomentumStrategy<Q, O, B> {
+    pub async fn run(&mut self) {
+        while self.state.brkr_mut().has_next() {
+            self.update().await;
+        }
+    }
+
+    pub async fn update(&mut self) {
+        self.state.tick().await;
+
+        let now = self.state.brkr_mut().now();
+        for symbol in &self.symbols {
+            if let Some(quote) = self.state.brkr().get_quote(symbol) {
+                self.prices
+                    .add_quote(quote.get_bid(), quote.get_ask(), now, symbol.clone());
+            }
+        }
+        //Return over n periods needs n + 1 dates
+        let window = self.lookback_periods + 1;
+        if self.prices.len() > window {
+            self.prices = self
+                .prices
+                .slice(self.prices.len() - window, self.prices.len());
+        }
+
+        if DefaultTradingSchedule::should_trade(&now.into()) {
+            self.rebalance();
+        }
+        self.state.record_snapshot();
+    }
+
+    /// Symbols with a price at both ends of the window sorted by return over the window, best
+    /// first. Ranking is done by [CrossSectionalMomentumRanker].
+    pub fn rank(&self) -> Vec<(String, f64)> {
+        let Some(date) = self
+            .prices
+            .len()
+            .checked_sub(1)
+            .and_then(|pos| self.prices.get_date(pos))
+        else {
+            return Vec::new();
+        };
+        let symbols = self.symbols.iter().map(String::as_str).collect::<Vec<_>>();
+        CrossSectionalMomentumRanker::new(self.lookback_periods)
+            .rank(&symbols, (*date).into(), &self.prices)
+            .into_iter()
+            .map(|(symbol, ret)| (symbol.to_string(), ret))
+            .collect()
+    }
+
+    fn target_weights(&self) -> PortfolioAllocation {
+        let ranked = self.rank();
+        let mut weights = self.state.close_unselected(&self.symbols);
+
+        let n_long = self.n_long.min(ranked.len());
+        //Shorts can't overlap with longs if there are too few ranked symbols
+        let n_short = self.n_short.min(ranked.len() - n_long);
+        for (symbol, _ret) in ranked.iter().take(n_long) {
+            weights.insert(symbol.clone(), 1.0 / n_long as f64);
+        }
+        for (symbol, _ret) in ranked.iter().rev().take(n_short) {
+            weights.insert(symbol.clone(), -1.0 / n_short as f64);
+        }
+        weights
+    }
+
+    fn rebalance(&mut self) {
+        if self.rank().is_empty() {
+            return;
+        }
+        let target_weights = self.target_weights();
+        self.state.rebalance(&target_weights);
+    }
+}
+
+impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> Strategy<Q, O, B>
+    for MomentumStrategy<Q, O, B>
+{
+    fn state(&self) -> &StrategyState<Q, O, B> {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut StrategyState<Q, O, B> {
+        &mut self.state
+    }
+}
@@ -0,0 +1,342 @@
+//! Cross-sectional rankers that score every symbol on a date so that strategies can go long/short
+//! the extremes.
+//!
+//! Rankers read directly from a [Penelope] input rather than the broker because they typically
+//! need a window of historical prices, the broker only holds the latest quote. Prices are taken as
+//! the mid of bid and ask. Scores are always sorted descending so a ranker that should prefer low
+//! values (i.e. low volatility) can be inverted with a negative weight in [CombinedRanker].
+
+use itertools::Itertools;
+use rotala::input::penelope::Penelope;
+
+use crate::broker::DateTime;
+
+pub trait Ranker {
+    /// Returns None if there isn't enough data to score the symbol on this date.
+    fn score(&self, symbol: &str, date: DateTime, data: &Penelope) -> Option<f64>;
+
+    /// Symbols without a score, or with a score that isn't finite, are dropped from the result.
+    fn rank<'a>(
+        &self,
+        symbols: &[&'a str],
+        date: DateTime,
+        data: &Penelope,
+    ) -> Vec<(&'a str, f64)> {
+        let mut ranked = symbols
+            .iter()
+            .filter_map(|symbol| self.score(symbol, date, data).map(|score| (*symbol, score)))
+            .filter(|(_symbol, score)| score.is_finite())
+            .collect_vec();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked
+    }
+}
+
+/// Returns the mid price for the date `lookback` periods before `date` and every date up to and
+/// including `date`. Returns None if `date` is unknown or there is not `lookback` periods of history.
+fn price_history(
+    symbol: &str,
+    date: DateTime,
+    lookback: usize,
+    data: &Penelope,
+) -> Option<Vec<Option<f64>>> {
+    let pos = data.get_position(&date)?;
+    if pos < lookback {
+        return None;
+    }
+
+    let mut prices = Vec::new();
+    for i in (pos - lookback)..=pos {
+        let price = data
+            .get_date(i)
+            .and_then(|d| data.get_quotes(d))
+            .and_then(|quotes| quotes.get(symbol))
+            .map(|quote| (quote.bid + quote.ask) / 2.0);
+        prices.push(price);
+    }
+    Some(prices)
+}
+
+/// Returns the change between consecutive prices, missing prices are skipped.
+fn period_returns(prices: &[Option<f64>]) -> Vec<f64> {
+    prices
+        .iter()
+        .flatten()
+        .tuple_windows()
+        .map(|(start, end)| (end / start) - 1.0)
+        .collect_vec()
+}
+
+fn mean_and_vol(returns: &[f64]) -> Option<(f64, f64)> {
+    if returns.is_empty() {
+        return None;
+    }
+    let count = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / count;
+    let var = returns
+        .iter()
+        .map(|ret| (ret - mean).powf(2.0))
+        .sum::<f64>()
+        / count;
+    Some((mean, var.sqrt()))
+}
+
+/// Ranks symbols by return over the last `lookback` periods.
+pub struct CrossSectionalMomentumRanker {
+    pub lookback: usize,
+}
+
+impl CrossSectionalMomentumRanker {
+    pub fn new(lookback: usize) -> Self {
+        Self { lookback }
+    }
+
+    pub fn rank_by_volatility<'a>(
+        &self,
+        symbols: &[&'a str],
+        date: DateTime,
+        data: &Penelope,
+    ) -> Vec<(&'a str, f64)> {
+        VolatilityRanker::new(self.lookback).rank(symbols, date, data)
+    }
+
+    pub fn rank_by_volume<'a>(
+        &self,
+        symbols: &[&'a str],
+        date: DateTime,
+        data: &Penelope,
+    ) -> Vec<(&'a str, f64)> {
+        VolumeRanker::new(self.lookback).rank(symbols, date, data)
+    }
+
+    pub fn rank_by_sharpe<'a>(
+        &self,
+        symbols: &[&'a str],
+        date: DateTime,
+        data: &Penelope,
+    ) -> Vec<(&'a str, f64)> {
+        SharpeRanker::new(self.lookback).rank(symbols, date, data)
+    }
+}
+
+impl Ranker for CrossSectionalMomentumRanker {
+    fn score(&self, symbol: &str, date: DateTime, data: &Penelope) -> Option<f64> {
+        let prices = price_history(symbol, date, self.lookback, data)?;
+        //Both ends of the window are required, gaps in the middle don't change the return
+        let start = (*prices.first()?)?;
+        let end = (*prices.last()?)?;
+        Some((end / start) - 1.0)
+    }
+}
+
+/// Ranks symbols by the volatility of period returns over the last `lookback` periods.
+pub struct VolatilityRanker {
+    pub lookback: usize,
+}
+
+impl VolatilityRanker {
+    pub fn new(lookback: usize) -> Self {
+        Self { lookback }
+    }
+}
+
+impl Ranker for VolatilityRanker {
+    fn score(&self, symbol: &str, date: DateTime, data: &Penelope) -> Option<f64> {
+        let prices = price_history(symbol, date, self.lookback, data)?;
+        let (_mean, vol) = mean_and_vol(&period_returns(&prices))?;
+        Some(vol)
+    }
+}
+
+/// Ranks symbols by mean volume over the last `lookback` periods. Volume is only loaded by some
+/// Penelope sources, symbols without volume in the window have no score.
+pub struct VolumeRanker {
+    pub lookback: usize,
+}
+
+impl VolumeRanker {
+    pub fn new(lookback: usize) -> Self {
+        Self { lookback }
+    }
+}
+
+impl Ranker for VolumeRanker {
+    fn score(&self, symbol: &str, date: DateTime, data: &Penelope) -> Option<f64> {
+        let pos = data.get_position(&date)?;
+        if pos < self.lookback {
+            return None;
+        }
+
+        let volumes = ((pos - self.lookback)..=pos)
+            .filter_map(|i| {
+                data.get_date(i)
+                    .and_then(|d| data.get_quotes(d))
+                    .and_then(|quotes| quotes.get(symbol))
+                    .and_then(|quote| quote.volume)
+            })
+            .collect_vec();
+        if volumes.is_empty() {
+            return None;
+        }
+        Some(volumes.iter().sum::<f64>() / volumes.len() as f64)
+    }
+}
+
+/// Ranks symbols by mean period return divided by volatility over the last `lookback` periods.
+/// Symbols with zero volatility have no score.
+pub struct SharpeRanker {
+    pub lookback: usize,
+}
+
+impl SharpeRanker {
+    pub fn new(lookback: usize) -> Self {
+        Self { lookback }
+    }
+}
+
+impl Ranker for SharpeRanker {
+    fn score(&self, symbol: &str, date: DateTime, data: &Penelope) -> Option<f64> {
+        let prices = price_history(symbol, date, self.lookback, data)?;
+        let (mean, vol) = mean_and_vol(&period_returns(&prices))?;
+        if vol == 0.0 {
+            return None;
+        }
+        Some(mean / vol)
+    }
+}
+
+/// Combines rankers by taking the weighted sum of their scores. Scores are not normalized so
+/// weights should account for the different scale of each ranker. If any ranker cannot score a
+/// symbol then the symbol has no combined score.
+pub struct CombinedRanker {
+    pub rankers: Vec<Box<dyn Ranker>>,
+    pub weights: Vec<f64>,
+}
+
+impl CombinedRanker {
+    pub fn new(rankers: Vec<Box<dyn Ranker>>, weights: Vec<f64>) -> Self {
+        if rankers.len() != weights.len() {
+            panic!("CombinedRanker requires one weight per ranker");
+        }
+        Self { rankers, weights }
+    }
+}
+
+impl Ranker for CombinedRanker {
+    fn score(&self, symbol: &str, date: DateTime, data: &Penelope) -> Option<f64> {
+        let mut total = 0.0;
+        for (ranker, weight) in self.rankers.iter().zip(self.weights.iter()) {
+            total += ranker.score(symbol, date, data)? * weight;
+        }
+        Some(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rotala::input::penelope::Penelope;
+
+    use super::{CombinedRanker, CrossSectionalMomentumRanker, Ranker, VolatilityRanker};
+    use crate::broker::DateTime;
+
+    fn setup() -> Penelope {
+        let mut source = Penelope::new();
+        source.add_quote(100.0, 100.0, 100, "ABC");
+        source.add_quote(100.0, 100.0, 100, "BCD");
+        source.add_quote(100.0, 100.0, 100, "CDE");
+
+        source.add_quote(110.0, 110.0, 101, "ABC");
+        source.add_quote(90.0, 90.0, 101, "BCD");
+        source.add_quote(100.0, 100.0, 101, "CDE");
+
+        source.add_quote(120.0, 120.0, 102, "ABC");
+        source.add_quote(110.0, 110.0, 102, "BCD");
+        source.add_quote(95.0, 95.0, 102, "CDE");
+        source
+    }
+
+    #[test]
+    fn test_that_momentum_ranks_by_lookback_return() {
+        let source = setup();
+        let ranker = CrossSectionalMomentumRanker::new(2);
+
+        let ranked = ranker.rank(&["CDE", "BCD", "ABC"], 102.into(), &source);
+        let symbols: Vec<&str> = ranked.iter().map(|(symbol, _)| *symbol).collect();
+        assert_eq!(symbols, vec!["ABC", "BCD", "CDE"]);
+        assert_eq!((ranked.first().unwrap().1 * 100.0).round(), 20.0);
+    }
+
+    #[test]
+    fn test_that_ranker_drops_symbols_without_enough_history() {
+        let source = setup();
+        let ranker = CrossSectionalMomentumRanker::new(2);
+
+        assert!(ranker.rank(&["ABC", "BCD"], 101.into(), &source).is_empty());
+        assert!(ranker.rank(&["XYZ"], 102.into(), &source).is_empty());
+    }
+
+    #[test]
+    fn test_that_volatility_ranks_most_volatile_first() {
+        let source = setup();
+        let ranker = CrossSectionalMomentumRanker::new(2);
+
+        let ranked = ranker.rank_by_volatility(&["ABC", "BCD", "CDE"], 102.into(), &source);
+        assert_eq!(ranked.first().unwrap().0, "BCD");
+    }
+
+    #[test]
+    fn test_that_volume_ranks_by_mean_volume() {
+        let mut source = setup();
+        for (date, abc, bcd) in [(103, 100.0, 300.0), (104, 200.0, 0.0)] {
+            source.add_quote_with_volume(120.0, 120.0, date, "ABC", abc);
+            source.add_quote_with_volume(110.0, 110.0, date, "BCD", bcd);
+            source.add_quote(95.0, 95.0, date, "CDE");
+        }
+        let ranker = CrossSectionalMomentumRanker::new(2);
+
+        //CDE has no volume so isn't ranked
+        let ranked = ranker.rank_by_volume(&["ABC", "BCD", "CDE"], 104.into(), &source);
+        assert_eq!(ranked, vec![("ABC", 150.0), ("BCD", 150.0)]);
+        let ranked = ranker.rank_by_volume(&["ABC", "BCD", "CDE"], 103.into(), &source);
+        assert_eq!(ranked, vec![("BCD", 300.0), ("ABC", 100.0)]);
+    }
+
+    struct NanRanker;
+
+    impl Ranker for NanRanker {
+        fn score(&self, symbol: &str, _date: DateTime, _data: &Penelope) -> Option<f64> {
+            match symbol {
+                "ABC" => Some(f64::NAN),
+                _ => Some(1.0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_that_ranker_drops_scores_that_are_not_finite() {
+        let source = setup();
+        let ranked = NanRanker.rank(&["ABC", "BCD"], 102.into(), &source);
+        assert_eq!(ranked, vec![("BCD", 1.0)]);
+    }
+
+    #[test]
+    fn test_that_combined_ranker_weights_scores() {
+        let source = setup();
+        let combined = CombinedRanker::new(
+            vec![
+                Box::new(CrossSectionalMomentumRanker::new(2)),
+                Box::new(VolatilityRanker::new(2)),
+            ],
+            vec![1.0, -1.0],
+        );
+
+        let momentum = CrossSectionalMomentumRanker::new(2)
+            .score("BCD", 102.into(), &source)
+            .unwrap();
+        let vol = VolatilityRanker::new(2)
+            .score("BCD", 102.into(), &source)
+            .unwrap();
+        let score = combined.score("BCD", 102.into(), &source).unwrap();
+        assert_eq!(score, momentum - vol);
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::VecDeque;
+
+use log::info;
+use rotala::exchange::uist_v1::{Order, OrderType, UistQuote};
+use rotala::http::uist::uistv1_client::UistClient;
+
+use crate::broker::uist::UistBroker;
+use crate::broker::{Clock, Portfolio, Quote};
+use crate::strategy::{Strategy, StrategyState};
+
+pub struct ReversalStrategyBuilder<C: UistClient> {
+    //If missing either field, we cannot run this strategy
+    brkr: Option<UistBroker<C>>,
+    symbol: Option<String>,
+    lookback: usize,
+    entry_pct: f64,
+    stop_loss_pct: f64,
+    entry_timeout: usize,
+}
+
+impl<C: UistClient> ReversalStrategyBuilder<C> {
+    pub fn default(&mut self) -> ReversalStrategy<C> {
+        if self.brkr.is_none() || self.symbol.is_none() {
+            panic!("Strategy must have broker and symbol");
+        }
+
+        ReversalStrategy {
+            state: StrategyState::new(self.brkr.take().unwrap()),
+            symbol: self.symbol.take().unwrap(),
+            lookback: self.lookback,
+            entry_pct: self.entry_pct,
+            stop_loss_pct: self.stop_loss_pct,
+            entry_timeout: self.entry_timeout,
+            prices: VecDeque::new(),
+            reversal_state: ReversalState::Flat,
+        }
+    }
+
+    pub fn with_brkr(&mut self, brkr: UistBroker<C>) -> &mut Self {
+        self.brkr = Some(brkr);
+        self
+    }
+
+    pub fn with_symbol(&mut self, symbol: impl Into<String>) -> &mut Self {
+        self.symbol = Some(symbol.into());
+        self
+    }
+
+    pub fn with_lookback(&mut self, lookback: usize) -> &mut Self {
+        self.lookback = lookback;
+        self
+    }
+
+    pub fn with_entry_pct(&mut self, entry_pct: f64) -> &mut Self {
+        self.entry_pct = entry_pct;
+        self
+    }
+
+    pub fn with_stop_loss_pct(&mut self, stop_loss_pct: f64) -> &mut Self {
+        self.stop_loss_pct = stop_loss_pct;
+        self
+    }
+
+    //Defaults to five ticks
+    pub fn with_entry_timeout(&mut self, entry_timeout: usize) -> &mut Self {
+        self.entry_timeout = entry_timeout;
+        self
+    }
+
+    pub fn new() -> Self {
+        Self {
+            brkr: None,
+            symbol: None,
+            lookback: 20,
+            entry_pct: 0.1,
+            stop_loss_pct: 0.05,
+            entry_timeout: 5,
+        }
+    }
+}
+
+impl<C: UistClient> Default for ReversalStrategyBuilder<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum ReversalState {
+    Flat,
+    //Price of the stop and the number of ticks it has been waiting
+    EntryPending { entry: f64, ticks: usize },
+    //Price of the stop below the long position
+    Long { stop: f64 },
+    Short,
+}
+
+///Trades a single symbol using stop orders. When the price falls `entry_pct` below the high of the
+///last `lookback` ticks, the strategy places a [StopBuy](rotala::exchange::uist_v1::OrderType) at
+///the low of the window so that it only enters once the price stops making new lows. Once the
+///entry executes, a [StopSell](rotala::exchange::uist_v1::OrderType) is placed `stop_loss_pct`
+///below the entry price.
+///
+///If the broker allows short selling, the stop sells twice the position so that the strategy
+///reverses into a short of the same size when it triggers. The short is covered by a
+///[StopBuy](rotala::exchange::uist_v1::OrderType) `stop_loss_pct` above the stop price. Without
+///short selling the stop only sells the position and the strategy exits to cash. Either way the
+///strategy waits for the next entry signal once it is flat.
+///
+///An entry that hasn't executed after `entry_timeout` ticks, or once the price falls below the
+///entry, is cancelled and the strategy waits for the next entry signal, which may be at the new
+///low.
+///
+///Entries use 95% of cash to leave a buffer for stop orders that execute above the current price.
+///When short selling is enabled, entries are reduced so that the cash left after entry covers the
+///margin on the reversal.
+pub struct ReversalStrategy<C: UistClient> {
+    state: StrategyState<UistQuote, Order, UistBroker<C>>,
+    symbol: String,
+    lookback: usize,
+    entry_pct: f64,
+    stop_loss_pct: f64,
+    entry_timeout: usize,
+    prices: VecDeque<f64>,
+    reversal_state: ReversalState,
+}
+
+impl<C: UistClient> ReversalStrategy<C> {
+    pub async fn run(&mut self) {
+        while self.state.brkr_mut().has_next() {
+            self.update().await;
+        }
+    }
+
+    pub async fn update(&mut self) {
+        self.state.tick().await;
+
+        let qty = self
+            .state
+            .brkr()
+            .get_position_qty(&self.symbol)
+            .unwrap_or(0.0);
+        match self.reversal_state {
+            ReversalState::EntryPending { .. } if qty > 0.0 => {
+                //Cost basis is the entry price because we only hold one position at a time
+                let entry = self.state.brkr().cost_basis(&self.symbol).unwrap();
+                let stop_price = entry * (1.0 - self.stop_loss_pct);
+                //Selling twice the position reverses it into a short of the same size
+                let stop_qty = if self.allows_short() { qty * 2.0 } else { qty };
+                info!(
+                    "STRATEGY: Entered {:?} at {:?}, placing stop at {:?}",
+                    self.symbol, entry, stop_price
+                );
+                self.state
+                    .send_order(Order::stop_sell(self.symbol.clone(), stop_qty, stop_price));
+                self.reversal_state = ReversalState::Long { stop: stop_price };
+            }
+            ReversalState::Long { stop } if qty < 0.0 => {
+                let cover_price = stop * (1.0 + self.stop_loss_pct);
+                info!(
+                    "STRATEGY: Stop triggered for {:?}, reversed to short with cover at {:?}",
+                    self.symbol, cover_price
+                );
+                self.state
+                    .send_order(Order::stop_buy(self.symbol.clone(), -qty, cover_price));
+                self.reversal_state = ReversalState::Short;
+            }
+            ReversalState::Long { .. } if qty == 0.0 => {
+                info!("STRATEGY: Stop triggered for {:?}", self.symbol);
+                self.reversal_state = ReversalState::Flat;
+            }
+            ReversalState::Short if qty >= 0.0 => {
+                info!("STRATEGY: Short in {:?} covered", self.symbol);
+                self.reversal_state = ReversalState::Flat;
+            }
+            _ => {}
+        }
+
+        if let Some(quote) = self.state.brkr().get_quote(&self.symbol) {
+            let mid = (quote.bid + quote.ask) / 2.0;
+            self.prices.push_back(mid);
+            while self.prices.len() > self.lookback {
+                self.prices.pop_front();
+            }
+
+            if let ReversalState::EntryPending { entry, ticks } = &mut self.reversal_state {
+                *ticks += 1;
+                if *ticks > self.entry_timeout || mid < *entry {
+                    self.cancel_entry();
+                }
+            }
+
+            if self.reversal_state == ReversalState::Flat && self.prices.len() == self.lookback {
+                let high = self.prices.iter().cloned().fold(f64::MIN, f64::max);
+                let low = self.prices.iter().cloned().fold(f64::MAX, f64::min);
+                if mid <= high * (1.0 - self.entry_pct) {
+                    let margin = self
+                        .state
+                        .brkr()
+                        .get_short_margin_requirement()
+                        .unwrap_or(0.0);
+                    let shares = ((self.state.brkr().get_cash_balance() * 0.95)
+                        / (quote.ask * (1.0 + margin)))
+                        .floor();
+                    if shares > 0.0 {
+                        info!(
+                            "STRATEGY: Price of {:?} fell from {:?} to {:?}, placing entry at {:?}",
+                            self.symbol, high, mid, low
+                        );
+                        self.state
+                            .send_order(Order::stop_buy(self.symbol.clone(), shares, low));
+                        self.reversal_state = ReversalState::EntryPending {
+                            entry: low,
+                            ticks: 0,
+                        };
+                    }
+                }
+            }
+        }
+
+        self.state.record_snapshot();
+    }
+
+    fn allows_short(&self) -> bool {
+        self.state.brkr().get_short_margin_requirement().is_some()
+    }
+
+    //Entry is left pending if the order hasn't reached the exchange yet, it is cancelled on a
+    //later tick
+    fn cancel_entry(&mut self) {
+        let entry = self
+            .state
+            .brkr()
+            .get_open_orders()
+            .into_iter()
+            .find(|order| order.symbol == self.symbol && order.order_type == OrderType::StopBuy)
+            .and_then(|order| order.order_id);
+        if let Some(order_id) = entry {
+            if self.state.brkr_mut().cancel_order(order_id) {
+                info!("STRATEGY: Cancelled entry for {:?}", self.symbol);
+                self.reversal_state = ReversalState::Flat;
+            }
+        }
+    }
+}
+
+//Strategy doesn't rebalance, positions are only entered and stopped out
+impl<C: UistClient> Strategy<UistQuote, Order, UistBroker<C>> for ReversalStrategy<C> {
+    fn state(&self) -> &StrategyState<UistQuote, Order, UistBroker<C>> {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut StrategyState<UistQuote, Order, UistBroker<C>> {
+        &mut self.state
+    }
+}
@@ -0,0 +1,100 @@
+//! Runs several backtests concurrently, used to sweep parameters or compare strategies against the
+//! same prices.
+//!
+//! Every backtest gets its own in-process exchange and broker so there is no shared mutable state
+//! between tasks. Prices are shared through an [Arc] and copied into the exchange when the task
+//! starts.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+use rotala::http::uist::uistv1_client::{TestClient, UistClient};
+use rotala::input::penelope::Penelope;
+
+use crate::broker::uist::UistBrokerBuilder;
+use crate::broker::BrokerCost;
+use crate::perf::{BacktestOutput, PerformanceConfig};
+use crate::strategy::staticweight::{PortfolioAllocation, StaticWeightStrategyBuilder};
+use crate::strategy::Strategy;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BacktestError {
+    //Index of the config whose task panicked
+    StrategyPanicked(usize),
+}
+
+impl Error for BacktestError {}
+
+impl Display for BacktestError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            BacktestError::StrategyPanicked(index) => {
+                write!(f, "Strategy for config {index} panicked")
+            }
+        }
+    }
+}
+
+/// Complete setup for one [StaticWeightStrategy](crate::strategy::staticweight::StaticWeightStrategy)
+/// backtest.
+#[derive(Clone, Debug)]
+pub struct StrategyConfig {
+    pub source: Arc<Penelope>,
+    pub weights: PortfolioAllocation,
+    pub initial_cash: f64,
+    pub trade_costs: Vec<BrokerCost>,
+    pub perf_config: PerformanceConfig,
+}
+
+pub struct ParallelBacktestRunner {
+    configs: Vec<StrategyConfig>,
+}
+
+impl ParallelBacktestRunner {
+    pub fn new(configs: Vec<StrategyConfig>) -> Self {
+        Self { configs }
+    }
+
+    /// Spawns one task per config and waits for all of them. Results are in the same order as the
+    /// configs, a task that panics returns [BacktestError::StrategyPanicked] and doesn't affect
+    /// the other tasks.
+    pub async fn run(self) -> Vec<Result<BacktestOutput, BacktestError>> {
+        let handles = self
+            .configs
+            .into_iter()
+            .enumerate()
+            .map(|(index, config)| tokio::task::spawn(Self::run_config(index, config)))
+            .collect::<Vec<_>>();
+
+        let mut results = Vec::new();
+        for (index, handle) in handles.into_iter().enumerate() {
+            results.push(
+                handle
+                    .await
+                    .map_err(|_| BacktestError::StrategyPanicked(index)),
+            );
+        }
+        results
+    }
+
+    async fn run_config(index: usize, config: StrategyConfig) -> BacktestOutput {
+        let name = format!("Backtest{index}");
+        let mut client = TestClient::single(&name, Penelope::clone(&config.source));
+        let resp = client.init(name).await.unwrap();
+
+        let brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .with_trade_costs(config.trade_costs)
+            .build()
+            .await;
+
+        let mut strat = StaticWeightStrategyBuilder::new()
+            .with_brkr(brkr)
+            .with_weights(config.weights)
+            .default();
+        strat.init(&config.initial_cash);
+        strat.run().await;
+        strat.perf(config.perf_config)
+    }
+}
@@ -0,0 +1,212 @@
+//! Signals score symbols from quotes so that indicator logic can be written, and combined, without
+//! touching order generation.
+//!
+//! A [Signal] is called once per tick for each symbol with a quote and keeps whatever rolling
+//! state it needs for each symbol, it never sees the broker. [SignalBasedStrategy] combines
+//! weighted signals into a single score per symbol and allocates from that score.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use itertools::Itertools;
+
+use crate::broker::{BrokerOrder, BrokerQuote};
+use crate::schedule::{DefaultTradingSchedule, TradingSchedule};
+use crate::strategy::staticweight::{PortfolioAllocation, StaticWeightBroker};
+use crate::strategy::{Strategy, StrategyState};
+
+pub trait Signal {
+    /// Updates state for `symbol` with the latest quote and returns a score in `[-1.0, 1.0]`,
+    /// positive scores are bullish.
+    fn compute(&mut self, symbol: &str, quote: &dyn BrokerQuote) -> f64;
+}
+
+pub struct SignalBasedStrategyBuilder<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> {
+    //If missing either field, we cannot run this strategy
+    brkr: Option<B>,
+    symbols: Option<Vec<String>>,
+    signals: Vec<(Box<dyn Signal>, f64)>,
+    threshold: f64,
+    _quote: PhantomData<Q>,
+    _order: PhantomData<O>,
+}
+
+impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>>
+    SignalBasedStrategyBuilder<Q, O, B>
+{
+    pub fn default(&mut self) -> SignalBasedStrategy<Q, O, B> {
+        if self.brkr.is_none() || self.symbols.is_none() {
+            panic!("Strategy must have broker and symbols");
+        }
+
+        SignalBasedStrategy {
+            state: StrategyState::new(self.brkr.take().unwrap()),
+            symbols: self.symbols.take().unwrap(),
+            signals: std::mem::take(&mut self.signals),
+            threshold: self.threshold,
+            scores: HashMap::new(),
+            last_quote_dates: HashMap::new(),
+        }
+    }
+
+    pub fn with_brkr(&mut self, brkr: B) -> &mut Self {
+        self.brkr = Some(brkr);
+        self
+    }
+
+    pub fn with_symbols(&mut self, symbols: &[&str]) -> &mut Self {
+        self.symbols = Some(symbols.iter().map(|symbol| symbol.to_string()).collect());
+        self
+    }
+
+    pub fn with_signal(&mut self, signal: Box<dyn Signal>, weight: f64) -> &mut Self {
+        self.signals.push((signal, weight));
+        self
+    }
+
+    pub fn with_signals(&mut self, signals: Vec<(Box<dyn Signal>, f64)>) -> &mut Self {
+        self.signals.extend(signals);
+        self
+    }
+
+    /// Combined score has to be strictly above `threshold` to go long and strictly below
+    /// `-threshold` to go short.
+    pub fn with_threshold(&mut self, threshold: f64) -> &mut Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn new() -> Self {
+        Self {
+            brkr: None,
+            symbols: None,
+            signals: Vec::new(),
+            threshold: 0.0,
+            _quote: PhantomData,
+            _order: PhantomData,
+        }
+    }
+}
+
+impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> Default
+    for SignalBasedStrategyBuilder<Q, O, B>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+///Scores every symbol with the weighted average of its signals and holds an equal weight long in
+///symbols above the threshold and an equal weight short in symbols below it, symbols in between are
+///neutral. Longs sum to one and shorts sum to minus one, shorts require short selling to be
+///enabled on the broker.
+pub struct SignalBasedStrategy<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> {
+    state: StrategyState<Q, O, B>,
+    symbols: Vec<String>,
+    signals: Vec<(Box<dyn Signal>, f64)>,
+    threshold: f64,
+    scores: HashMap<String, f64>,
+    last_quote_dates: HashMap<String, i64>,
+}
+
+impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> SignalBasedStrategy<Q, O, B> {
+    pub async fn run(&mut self) {
+        while self.state.brkr_mut().has_next() {
+            self.update().await;
+        }
+    }
+
+    pub async fn update(&mut self) {
+        self.state.tick().await;
+
+        //Signals are updated on every tick, not just when trading, so their state doesn't skip
+        //quotes. The broker keeps the last quote if there isn't a new one so a quote is only
+        //passed to signals once.
+        for symbol in &self.symbols {
+            if let Some(quote) = self.state.brkr().get_quote(symbol) {
+                if self.last_quote_dates.get(symbol) == Some(&quote.get_date()) {
+                    continue;
+                }
+                self.last_quote_dates
+                    .insert(symbol.clone(), quote.get_date());
+                let score = Self::combine(&mut self.signals, symbol, &quote);
+                self.scores.insert(symbol.clone(), score);
+            }
+        }
+
+        let now = self.state.brkr_mut().now();
+        if DefaultTradingSchedule::should_trade(&now.into()) {
+            self.rebalance();
+        }
+        self.state.record_snapshot();
+    }
+
+    //Weighted average of signal scores, zero if there are no signals or the weights sum to zero
+    fn combine(signals: &mut [(Box<dyn Signal>, f64)], symbol: &str, quote: &Q) -> f64 {
+        let mut total = 0.0;
+        let mut total_weight = 0.0;
+        for (signal, weight) in signals.iter_mut() {
+            total += signal.compute(symbol, quote) * *weight;
+            total_weight += weight.abs();
+        }
+        if total_weight == 0.0 {
+            return 0.0;
+        }
+        (total / total_weight).clamp(-1.0, 1.0)
+    }
+
+    /// Latest combined score for each symbol that has received a quote.
+    pub fn get_scores(&self) -> &HashMap<String, f64> {
+        &self.scores
+    }
+
+    fn target_weights(&self) -> PortfolioAllocation {
+        let mut weights = self.state.close_unselected(&self.symbols);
+
+        let longs = self
+            .symbols
+            .iter()
+            .filter(|symbol| {
+                self.scores
+                    .get(*symbol)
+                    .is_some_and(|s| *s > self.threshold)
+            })
+            .collect_vec();
+        let shorts = self
+            .symbols
+            .iter()
+            .filter(|symbol| {
+                self.scores
+                    .get(*symbol)
+                    .is_some_and(|s| *s < -self.threshold)
+            })
+            .collect_vec();
+        for symbol in &longs {
+            weights.insert(symbol.to_string(), 1.0 / longs.len() as f64);
+        }
+        for symbol in &shorts {
+            weights.insert(symbol.to_string(), -1.0 / shorts.len() as f64);
+        }
+        weights
+    }
+
+    fn rebalance(&mut self) {
+        let target_weights = self.target_weights();
+        if target_weights.is_empty() {
+            return;
+        }
+        self.state.rebalance(&target_weights);
+    }
+}
+
+impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> Strategy<Q, O, B>
+    for SignalBasedStrategy<Q, O, B>
+{
+    fn state(&self) -> &StrategyState<Q, O, B> {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut StrategyState<Q, O, B> {
+        &mut self.state
+    }
+}
@@ -1,15 +1,12 @@
 use std::collections::HashMap;
 use std::marker::PhantomData;
 
-use log::info;
-
 use crate::broker::{
-    BrokerCashEvent, BrokerOperations, BrokerOrder, BrokerQuote, BrokerStates, CashOperations,
-    Clock, Portfolio, SendOrder, StrategySnapshot, Update,
+    BrokerOperations, BrokerOrder, BrokerQuote, BrokerStates, CashOperations, Clock, Portfolio,
+    SendOrder, TradeLog, Update,
 };
-use crate::perf::{BacktestOutput, PerformanceCalculator};
-use crate::schedule::{DefaultTradingSchedule, TradingSchedule};
-use crate::strategy::StrategyEvent;
+use crate::schedule::RebalanceFrequency;
+use crate::strategy::{Strategy, StrategyState};
 
 pub trait StaticWeightBroker<Q: BrokerQuote, O: BrokerOrder>:
     CashOperations<Q>
@@ -19,6 +16,7 @@ pub trait StaticWeightBroker<Q: BrokerQuote, O: BrokerOrder>:
     + BrokerStates
     + Update
     + Clock
+    + TradeLog
 {
 }
 
@@ -29,6 +27,7 @@ pub struct StaticWeightStrategyBuilder<Q: BrokerQuote, O: BrokerOrder, B: Static
     //If missing either field, we cannot run this strategy
     brkr: Option<B>,
     weights: Option<PortfolioAllocation>,
+    rebalance_frequency: RebalanceFrequency,
     _quote: PhantomData<Q>,
     _order: PhantomData<O>,
 }
@@ -44,12 +43,9 @@ impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>>
         let brkr = self.brkr.take();
         let weights = self.weights.take();
         StaticWeightStrategy {
-            brkr: brkr.unwrap(),
+            state: StrategyState::new(brkr.unwrap()),
             target_weights: weights.unwrap(),
-            net_cash_flow: 0.0,
-            history: Vec::new(),
-            _quote: PhantomData,
-            _order: PhantomData,
+            rebalance_frequency: self.rebalance_frequency,
         }
     }
 
@@ -63,10 +59,20 @@ impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>>
         self
     }
 
+    //Defaults to rebalancing on every tick
+    pub fn with_rebalance_frequency(
+        &mut self,
+        rebalance_frequency: RebalanceFrequency,
+    ) -> &mut Self {
+        self.rebalance_frequency = rebalance_frequency;
+        self
+    }
+
     pub fn new() -> Self {
         Self {
             brkr: None,
             weights: None,
+            rebalance_frequency: RebalanceFrequency::default(),
             _quote: PhantomData,
             _order: PhantomData,
         }
@@ -84,96 +90,44 @@ impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> Default
 ///Basic implementation of an investment strategy which takes a set of fixed-weight allocations and
 ///rebalances over time towards those weights.
 pub struct StaticWeightStrategy<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> {
-    brkr: B,
+    state: StrategyState<Q, O, B>,
     target_weights: PortfolioAllocation,
-    net_cash_flow: f64,
-    history: Vec<StrategySnapshot>,
-    _quote: PhantomData<Q>,
-    _order: PhantomData<O>,
+    rebalance_frequency: RebalanceFrequency,
 }
 
 impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> StaticWeightStrategy<Q, O, B> {
     pub async fn run(&mut self) {
-        while self.brkr.has_next() {
+        while self.state.brkr_mut().has_next() {
             self.update().await;
         }
     }
 
-    pub fn perf(&self, freq: crate::perf::Frequency) -> BacktestOutput {
-        //Intended to be called at end of simulation
-        let hist = self.get_history();
-        PerformanceCalculator::calculate(freq, hist)
-    }
-
-    pub fn get_snapshot(&mut self) -> StrategySnapshot {
-        // Defaults to zero inflation because most users probably aren't looking
-        // for real returns calcs
-        let now = self.brkr.now();
-        StrategySnapshot {
-            date: now.into(),
-            portfolio_value: self.brkr.get_total_value(),
-            net_cash_flow: self.net_cash_flow,
-            inflation: 0.0,
-        }
-    }
-
-    pub fn init(&mut self, initital_cash: &f64) {
-        self.deposit_cash(initital_cash);
-        if DefaultTradingSchedule::should_trade(&self.brkr.now().into()) {
-            let orders = self
-                .brkr
-                .diff_brkr_against_target_weights(&self.target_weights);
-            if !orders.is_empty() {
-                self.brkr.send_orders(&orders);
-            }
-        }
-    }
-
     pub async fn update(&mut self) {
-        self.brkr.check().await;
-        let now = self.brkr.now();
-        if DefaultTradingSchedule::should_trade(&now.into()) {
-            let orders = self
-                .brkr
-                .diff_brkr_against_target_weights(&self.target_weights);
-            if !orders.is_empty() {
-                self.brkr.send_orders(&orders);
-            }
+        self.state.tick().await;
+        let now = self.state.brkr_mut().now();
+        if self.rebalance_frequency.should_trade(&now.into()) {
+            self.state.rebalance(&self.target_weights);
         }
-        let snap = self.get_snapshot();
-        self.history.push(snap);
+        self.state.record_snapshot();
     }
+}
 
-    fn deposit_cash(&mut self, cash: &f64) -> StrategyEvent {
-        info!("STRATEGY: Depositing {:?} into strategy", cash);
-        self.brkr.deposit_cash(cash);
-        self.net_cash_flow += self.net_cash_flow;
-        StrategyEvent::DepositSuccess(*cash)
+impl<Q: BrokerQuote, O: BrokerOrder, B: StaticWeightBroker<Q, O>> Strategy<Q, O, B>
+    for StaticWeightStrategy<Q, O, B>
+{
+    fn state(&self) -> &StrategyState<Q, O, B> {
+        &self.state
     }
 
-    pub fn withdraw_cash(&mut self, cash: &f64) -> StrategyEvent {
-        if let BrokerCashEvent::WithdrawSuccess(withdrawn) = self.brkr.withdraw_cash(cash) {
-            info!("STRATEGY: Succesfully withdrew {:?} from strategy", cash);
-            self.net_cash_flow -= withdrawn;
-            return StrategyEvent::WithdrawSuccess(*cash);
-        }
-        info!("STRATEGY: Failed to withdraw {:?} from strategy", cash);
-        StrategyEvent::WithdrawFailure(*cash)
+    fn state_mut(&mut self) -> &mut StrategyState<Q, O, B> {
+        &mut self.state
     }
 
-    pub fn withdraw_cash_with_liquidation(&mut self, cash: &f64) -> StrategyEvent {
-        if let BrokerCashEvent::WithdrawSuccess(withdrawn) =
-            //No logging here because the implementation is fully logged due to the greater
-            //complexity of this task vs standard withdraw
-            self.brkr.withdraw_cash_with_liquidation(cash)
-        {
-            self.net_cash_flow -= withdrawn;
-            return StrategyEvent::WithdrawSuccess(*cash);
+    fn init(&mut self, initital_cash: &f64) {
+        self.deposit_cash(initital_cash);
+        let now = self.state.brkr_mut().now();
+        if self.rebalance_frequency.should_trade(&now.into()) {
+            self.state.rebalance(&self.target_weights);
         }
-        StrategyEvent::WithdrawFailure(*cash)
-    }
-
-    pub fn get_history(&self) -> Vec<StrategySnapshot> {
-        self.history.clone()
     }
 }
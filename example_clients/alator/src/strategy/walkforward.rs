@@ -0,0 +1,174 @@
+//! Walk-forward optimisation splits prices into rolling windows, picks parameters on the in-sample
+//! part of each window and then tests them on the out-of-sample part that follows. Performance is
+//! judged on the out-of-sample results only so that parameters aren't fitted to the prices they
+//! are evaluated on.
+
+use std::future::Future;
+
+use rotala::exchange::uist_v1::Trade;
+use rotala::input::penelope::Penelope;
+
+use crate::broker::StrategySnapshot;
+use crate::perf::{BacktestOutput, PerformanceCalculator, PerformanceConfig};
+
+/// History and trades of one backtest, returned by [StrategyParameters::run].
+#[derive(Clone, Debug)]
+pub struct StrategyRun {
+    pub history: Vec<StrategySnapshot>,
+    pub trades: Vec<Trade>,
+}
+
+/// One point in the parameter grid. Implementations build a strategy with these parameters, run it
+/// over every tick in `source`, and return the strategy's history and trades.
+pub trait StrategyParameters: Clone {
+    fn run(&self, source: Penelope) -> impl Future<Output = StrategyRun>;
+}
+
+#[derive(Clone, Debug)]
+pub struct WalkForwardWindow<P: StrategyParameters> {
+    //Dates of the first and last tick in each part of the window
+    pub in_sample_dates: (i64, i64),
+    pub out_of_sample_dates: (i64, i64),
+    pub parameters: P,
+    pub in_sample: BacktestOutput,
+    pub out_of_sample: BacktestOutput,
+}
+
+#[derive(Clone, Debug)]
+pub struct WalkForwardResult<P: StrategyParameters> {
+    pub windows: Vec<WalkForwardWindow<P>>,
+    //Out-of-sample histories joined end to end, `None` if there were no complete windows
+    pub out_of_sample: Option<BacktestOutput>,
+}
+
+/// Windows are `in_sample_periods + out_of_sample_periods` ticks long and move forward by
+/// `out_of_sample_periods` so out-of-sample slices follow each other without overlapping. Ticks
+/// at the end that don't fill a window are not used.
+pub struct WalkForwardOptimizer<P: StrategyParameters> {
+    pub in_sample_periods: usize,
+    pub out_of_sample_periods: usize,
+    pub parameter_grid: Vec<P>,
+}
+
+impl<P: StrategyParameters> WalkForwardOptimizer<P> {
+    pub fn new(
+        in_sample_periods: usize,
+        out_of_sample_periods: usize,
+        parameter_grid: Vec<P>,
+    ) -> Self {
+        Self {
+            in_sample_periods,
+            out_of_sample_periods,
+            parameter_grid,
+        }
+    }
+
+    /// Parameters with the highest in-sample Sharpe ratio are chosen for each window, parameters
+    /// with a NaN ratio are only chosen if every ratio is NaN.
+    pub async fn run(
+        &self,
+        source: &Penelope,
+        config: impl Into<PerformanceConfig>,
+    ) -> WalkForwardResult<P> {
+        let config = config.into();
+        let window_len = self.in_sample_periods + self.out_of_sample_periods;
+
+        let mut windows = Vec::new();
+        let mut history = Vec::new();
+        let mut trades = Vec::new();
+        let mut start = 0;
+        while self.out_of_sample_periods > 0 && start + window_len <= source.len() {
+            let in_sample_source = source.slice(start, start + self.in_sample_periods);
+            let out_of_sample_source =
+                source.slice(start + self.in_sample_periods, start + window_len);
+            start += self.out_of_sample_periods;
+
+            let mut best: Option<(P, BacktestOutput)> = None;
+            for parameters in &self.parameter_grid {
+                let run = parameters.run(in_sample_source.clone()).await;
+                if run.history.is_empty() {
+                    continue;
+                }
+                let output = PerformanceCalculator::calculate(
+                    config.clone(),
+                    run.history,
+                    &run.trades,
+                    None,
+                );
+                let is_better = match &best {
+                    Some((_, best_output)) => {
+                        output.sharpe > best_output.sharpe || best_output.sharpe.is_nan()
+                    }
+                    None => true,
+                };
+                if is_better {
+                    best = Some((parameters.clone(), output));
+                }
+            }
+            let Some((parameters, in_sample)) = best else {
+                continue;
+            };
+
+            let run = parameters.run(out_of_sample_source.clone()).await;
+            if run.history.is_empty() {
+                continue;
+            }
+            let out_of_sample = PerformanceCalculator::calculate(
+                config.clone(),
+                run.history.clone(),
+                &run.trades,
+                None,
+            );
+            Self::chain(&mut history, run.history);
+            trades.extend(run.trades);
+
+            windows.push(WalkForwardWindow {
+                in_sample_dates: Self::date_range(&in_sample_source),
+                out_of_sample_dates: Self::date_range(&out_of_sample_source),
+                parameters,
+                in_sample,
+                out_of_sample,
+            });
+        }
+
+        let out_of_sample = (!history.is_empty())
+            .then(|| PerformanceCalculator::calculate(config, history, &trades, None));
+        WalkForwardResult {
+            windows,
+            out_of_sample,
+        }
+    }
+
+    fn date_range(source: &Penelope) -> (i64, i64) {
+        let first = source.get_date(0).copied().unwrap_or_default();
+        let last = source
+            .get_date(source.len().saturating_sub(1))
+            .copied()
+            .unwrap_or_default();
+        (first, last)
+    }
+
+    //Every window starts with fresh cash so values are rescaled to start from the last value of
+    //the previous window, the join is then neither a return nor a cash flow. Trades aren't
+    //rescaled
+    fn chain(chained: &mut Vec<StrategySnapshot>, history: Vec<StrategySnapshot>) {
+        let (Some(last), Some(first)) = (chained.last().cloned(), history.first().cloned()) else {
+            chained.extend(history);
+            return;
+        };
+        let scale = if first.portfolio_value == 0.0 {
+            1.0
+        } else {
+            last.portfolio_value / first.portfolio_value
+        };
+        for snapshot in history {
+            chained.push(StrategySnapshot {
+                date: snapshot.date,
+                portfolio_value: snapshot.portfolio_value * scale,
+                net_cash_flow: last.net_cash_flow
+                    + (snapshot.net_cash_flow - first.net_cash_flow) * scale,
+                inflation: snapshot.inflation,
+            });
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! Calculates target weights from historical returns, the output can be passed to
+//! [StaticWeightStrategyBuilder::with_weights](crate::strategy::staticweight::StaticWeightStrategyBuilder::with_weights).
+//!
+//! Returns are period returns and are assumed to be aligned by date. Series of unequal length are
+//! truncated to the shorter series when calculating covariance.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use itertools::Itertools;
+
+use crate::perf::PortfolioAnalytics;
+use crate::strategy::staticweight::PortfolioAllocation;
+
+const MAX_ITERATIONS: usize = 1000;
+const TOLERANCE: f64 = 1e-12;
+//Step is too small to make progress after this many halvings
+const MAX_STEP_HALVINGS: usize = 64;
+
+pub enum RiskParityMethod {
+    /// Weights proportional to `1 / std_i`, ignores correlation.
+    InverseVolatility,
+    /// Weights where every asset contributes the same amount to portfolio volatility.
+    EqualRiskContribution,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WeightsError {
+    /// Symbol has no return variance so can't be risk weighted.
+    ZeroVolatility(String),
+    /// Solver didn't converge within the iteration limit.
+    DidNotConverge(usize),
+}
+
+impl Error for WeightsError {}
+
+impl Display for WeightsError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            WeightsError::ZeroVolatility(symbol) => {
+                write!(f, "ZeroVolatility: {} has no return variance", symbol)
+            }
+            WeightsError::DidNotConverge(iterations) => {
+                write!(
+                    f,
+                    "DidNotConverge: no solution after {} iterations",
+                    iterations
+                )
+            }
+        }
+    }
+}
+
+/// Returns weights that sum to one, an empty allocation if there are no symbols.
+pub fn risk_parity_weights(
+    returns_by_symbol: &HashMap<String, Vec<f64>>,
+    method: RiskParityMethod,
+) -> Result<PortfolioAllocation, WeightsError> {
+    let symbols = returns_by_symbol.keys().sorted().collect_vec();
+    let covariance = symbols
+        .iter()
+        .map(|first| {
+            symbols
+                .iter()
+                .map(|second| {
+                    PortfolioAnalytics::covariance(
+                        &returns_by_symbol[*first],
+                        &returns_by_symbol[*second],
+                    )
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+
+    for (pos, symbol) in symbols.iter().enumerate() {
+        if covariance[pos][pos] <= 0.0 {
+            return Err(WeightsError::ZeroVolatility(symbol.to_string()));
+        }
+    }
+
+    let raw = match method {
+        RiskParityMethod::InverseVolatility => (0..symbols.len())
+            .map(|pos| 1.0 / covariance[pos][pos].sqrt())
+            .collect_vec(),
+        RiskParityMethod::EqualRiskContribution => equal_risk_contribution(&covariance)?,
+    };
+
+    let total = raw.iter().sum::<f64>();
+    Ok(symbols
+        .into_iter()
+        .zip(raw)
+        .map(|(symbol, weight)| (symbol.clone(), weight / total))
+        .collect())
+}
+
+//Newton-Raphson on the convex problem min 0.5 * y'Σy - (1/n) * sum(ln y_i), the solution has
+//y_i * (Σy)_i = 1/n for every asset so normalising y gives equal risk contributions. Steps are
+//halved until y stays positive, non-finite steps and steps that can't be halved enough stop the
+//solver.
+fn equal_risk_contribution(covariance: &[Vec<f64>]) -> Result<Vec<f64>, WeightsError> {
+    let n = covariance.len();
+    let budget = 1.0 / n as f64;
+    let mut y = (0..n)
+        .map(|pos| 1.0 / covariance[pos][pos].sqrt())
+        .collect_vec();
+
+    for _ in 0..MAX_ITERATIONS {
+        let sigma_y = (0..n)
+            .map(|i| (0..n).map(|j| covariance[i][j] * y[j]).sum::<f64>())
+            .collect_vec();
+        let gradient = (0..n).map(|i| sigma_y[i] - budget / y[i]).collect_vec();
+        //Written so that a NaN gradient doesn't count as converged
+        if gradient.iter().all(|g| g.abs() < TOLERANCE) {
+            return Ok(y);
+        }
+
+        let mut hessian = covariance.to_vec();
+        for i in 0..n {
+            hessian[i][i] += budget / y[i].powi(2);
+        }
+        let Some(step) = solve(hessian, gradient).filter(|step| step.iter().all(|s| s.is_finite()))
+        else {
+            break;
+        };
+
+        let stays_positive = |scale: f64| (0..n).all(|i| y[i] - scale * step[i] > 0.0);
+        let Some(scale) = (0..=MAX_STEP_HALVINGS)
+            .map(|halvings| 0.5_f64.powi(halvings as i32))
+            .find(|scale| stays_positive(*scale))
+        else {
+            break;
+        };
+        for i in 0..n {
+            y[i] -= scale * step[i];
+        }
+    }
+    Err(WeightsError::DidNotConverge(MAX_ITERATIONS))
+}
+
+//Gaussian elimination with partial pivoting, returns None if the matrix is singular or has a
+//non-finite pivot
+fn solve(mut matrix: Vec<Vec<f64>>, mut rhs: Vec<f64>) -> Option<Vec<f64>> {
+    let n = rhs.len();
+    for col in 0..n {
+        let pivot =
+            (col..n).max_by(|a, b| matrix[*a][col].abs().total_cmp(&matrix[*b][col].abs()))?;
+        if matrix[pivot][col] == 0.0 || !matrix[pivot][col].is_finite() {
+            return None;
+        }
+        matrix.swap(col, pivot);
+        rhs.swap(col, pivot);
+        let pivot_row = matrix[col].clone();
+        for row in (col + 1)..n {
+            let factor = matrix[row][col] / pivot_row[col];
+            for (value, pivot_value) in matrix[row].iter_mut().zip(pivot_row.iter()).skip(col) {
+                *value -= factor * pivot_value;
+            }
+            rhs[row] -= factor * rhs[col];
+        }
+    }
+
+    let mut solution = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum = ((row + 1)..n)
+            .map(|k| matrix[row][k] * solution[k])
+            .sum::<f64>();
+        solution[row] = (rhs[row] - sum) / matrix[row][row];
+    }
+    Some(solution)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::perf::PortfolioAnalytics;
+
+    use super::{risk_parity_weights, solve, RiskParityMethod, WeightsError, MAX_ITERATIONS};
+
+    fn returns() -> HashMap<String, Vec<f64>> {
+        let mut returns = HashMap::new();
+        returns.insert(
+            "ABC".to_string(),
+            vec![0.01, -0.02, 0.03, 0.00, 0.01, -0.01],
+        );
+        returns.insert("BCD".to_string(), vec![0.02, 0.01, -0.01, 0.01, 0.00, 0.01]);
+        returns.insert(
+            "CDE".to_string(),
+            vec![0.03, -0.04, 0.05, -0.01, 0.02, -0.03],
+        );
+        returns
+    }
+
+    #[test]
+    fn test_that_inverse_volatility_weights_favour_low_volatility() {
+        let weights = risk_parity_weights(&returns(), RiskParityMethod::InverseVolatility).unwrap();
+        assert!((weights.values().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!(weights["BCD"] > weights["ABC"]);
+        assert!(weights["ABC"] > weights["CDE"]);
+    }
+
+    #[test]
+    fn test_that_equal_risk_contribution_equalises_contributions() {
+        let returns = returns();
+        let weights =
+            risk_parity_weights(&returns, RiskParityMethod::EqualRiskContribution).unwrap();
+        assert!((weights.values().sum::<f64>() - 1.0).abs() < 1e-12);
+
+        let contributions = PortfolioAnalytics::risk_contributions(&returns, &weights);
+        let first = contributions["ABC"];
+        for contribution in contributions.values() {
+            assert!((contribution - first).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_that_flat_returns_are_rejected() {
+        let mut returns = returns();
+        returns.insert("DEF".to_string(), vec![0.01; 6]);
+        let weights = risk_parity_weights(&returns, RiskParityMethod::InverseVolatility);
+        assert_eq!(
+            weights,
+            Err(WeightsError::ZeroVolatility("DEF".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_that_solve_rejects_nan_pivots() {
+        let matrix = vec![vec![f64::NAN, 1.0], vec![1.0, 2.0]];
+        assert_eq!(solve(matrix, vec![1.0, 1.0]), None);
+
+        let matrix = vec![vec![2.0, 1.0], vec![1.0, 3.0]];
+        let solution = solve(matrix, vec![3.0, 4.0]).unwrap();
+        assert!((solution[0] - 1.0).abs() < 1e-12);
+        assert!((solution[1] - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_that_nan_returns_do_not_converge() {
+        let mut returns = returns();
+        returns.get_mut("ABC").unwrap()[2] = f64::NAN;
+        let weights = risk_parity_weights(&returns, RiskParityMethod::EqualRiskContribution);
+        assert_eq!(weights, Err(WeightsError::DidNotConverge(MAX_ITERATIONS)));
+    }
+}
@@ -0,0 +1,65 @@
+use alator::broker::uist::UistBrokerBuilder;
+use alator::strategy::momentum::MomentumStrategyBuilder;
+use alator::strategy::Strategy;
+use rotala::exchange::uist_v1::TradeType;
+use rotala::http::uist::uistv1_client::{TestClient, UistClient};
+use rotala::input::penelope::Penelope;
+
+#[tokio::test]
+async fn momentum_integration_test() {
+    //ABC leads for the first half, CDE overtakes it in the second half
+    let abc = [
+        100.0, 102.0, 104.0, 106.0, 106.0, 106.0, 106.0, 106.0, 106.0, 106.0,
+    ];
+    let bcd = [100.0; 10];
+    let cde = [
+        100.0, 98.0, 96.0, 94.0, 98.0, 102.0, 106.0, 110.0, 114.0, 118.0,
+    ];
+    let mut source = Penelope::new();
+    for i in 0..abc.len() {
+        let date = 100 + i as i64;
+        source.add_quote(abc[i], abc[i], date, "ABC");
+        source.add_quote(bcd[i], bcd[i], date, "BCD");
+        source.add_quote(cde[i], cde[i], date, "CDE");
+    }
+
+    let mut client = TestClient::single("Momentum", source);
+    let resp = client.init("Momentum".to_string()).await.unwrap();
+    let brkr = UistBrokerBuilder::new()
+        .with_client(client, resp.backtest_id)
+        .build()
+        .await;
+
+    let mut strat = MomentumStrategyBuilder::new()
+        .with_brkr(brkr)
+        .with_symbols(&["ABC", "BCD", "CDE"])
+        .with_lookback_periods(2)
+        .with_n_long(1)
+        .default();
+
+    strat.init(&100_000.0);
+    strat.run().await;
+
+    let ranked = strat.rank();
+    assert_eq!(ranked.len(), 3);
+    assert_eq!(ranked[0].0, "CDE");
+    assert_eq!(ranked[2].0, "BCD");
+
+    let trades = strat.get_trades();
+    assert_eq!(trades[0].symbol, "ABC");
+    assert_eq!(trades[0].typ, TradeType::Buy);
+    //ABC is sold when CDE takes the lead, BCD never leads so is never traded
+    assert!(trades
+        .iter()
+        .any(|trade| trade.symbol == "ABC" && trade.typ == TradeType::Sell));
+    assert!(trades
+        .iter()
+        .any(|trade| trade.symbol == "CDE" && trade.typ == TradeType::Buy));
+    assert!(!trades.iter().any(|trade| trade.symbol == "BCD"));
+
+    let stats = strat.get_simulation_statistics();
+    assert_eq!(stats.total_ticks, strat.get_history().len());
+    assert!(stats.total_rebalances > 0);
+
+    let _perf = strat.perf(alator::perf::Frequency::Daily);
+}
@@ -0,0 +1,173 @@
+use alator::broker::uist::{UistBroker, UistBrokerBuilder};
+use alator::broker::Portfolio;
+use alator::strategy::reversal::ReversalStrategyBuilder;
+use alator::strategy::Strategy;
+use rotala::exchange::uist_v1::{OrderType, TradeType};
+use rotala::http::uist::uistv1_client::{TestClient, UistClient};
+use rotala::input::penelope::Penelope;
+
+async fn setup_broker(source: Penelope) -> UistBroker<TestClient> {
+    let mut client = TestClient::single("Reversal", source);
+    let resp = client.init("Reversal".to_string()).await.unwrap();
+    UistBrokerBuilder::new()
+        .with_client(client, resp.backtest_id)
+        .build()
+        .await
+}
+
+#[tokio::test]
+async fn reversal_integration_test() {
+    let prices = [
+        100.0, 100.0, 100.0, 85.0, 86.0, 90.0, 80.0, 82.0, 82.0, 70.0, 72.0, 75.0, 76.0, 78.0,
+    ];
+    let mut source = Penelope::new();
+    for (i, price) in prices.iter().enumerate() {
+        source.add_quote(*price, *price, 100 + i as i64, "ABC");
+    }
+
+    let mut strat = ReversalStrategyBuilder::new()
+        .with_brkr(setup_broker(source).await)
+        .with_symbol("ABC")
+        .with_lookback(3)
+        .with_entry_pct(0.1)
+        .with_stop_loss_pct(0.05)
+        .default();
+
+    strat.init(&100_000.0);
+    strat.run().await;
+
+    //Enters after the drop to 85, stopped out at 80, enters again after the drop to 70 and holds
+    let trades = strat.get_trades();
+    assert_eq!(trades.len(), 3);
+
+    assert_eq!(trades[0].typ, TradeType::Buy);
+    assert_eq!(trades[0].value / trades[0].quantity, 86.0);
+    assert_eq!(trades[1].typ, TradeType::Sell);
+    assert_eq!(trades[1].value / trades[1].quantity, 80.0);
+    assert_eq!(trades[1].quantity, trades[0].quantity);
+    assert_eq!(trades[2].typ, TradeType::Buy);
+    assert_eq!(trades[2].value / trades[2].quantity, 72.0);
+
+    //Two entries and two stops, stop placed after the second entry is never triggered
+    let stats = strat.get_simulation_statistics();
+    assert_eq!(stats.total_ticks, strat.get_history().len());
+    assert_eq!(stats.total_orders_submitted, 4);
+    assert_eq!(stats.total_trades_executed, 3);
+    assert_eq!(stats.total_rebalances, 0);
+
+    let curve = strat.get_equity_curve_series();
+    assert_eq!(curve.len(), strat.get_history().len());
+    let (first_date, first_value) = curve[0];
+    let (_, last_value) = curve[curve.len() - 1];
+    assert_eq!(strat.get_pnl_since(first_date), last_value - first_value);
+
+    let _perf = strat.perf(alator::perf::Frequency::Daily);
+}
+
+#[tokio::test]
+async fn reversal_moves_entry_to_new_low() {
+    let prices = [
+        100.0, 100.0, 100.0, 85.0, 80.0, 75.0, 70.0, 72.0, 74.0, 76.0,
+    ];
+    let mut source = Penelope::new();
+    for (i, price) in prices.iter().enumerate() {
+        source.add_quote(*price, *price, 100 + i as i64, "ABC");
+    }
+
+    let mut strat = ReversalStrategyBuilder::new()
+        .with_brkr(setup_broker(source).await)
+        .with_symbol("ABC")
+        .with_lookback(3)
+        .with_entry_pct(0.1)
+        .default();
+
+    strat.init(&100_000.0);
+    strat.run().await;
+
+    //Entry follows the price down from 85 to 70 and executes when the price turns
+    let trades = strat.get_trades();
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].typ, TradeType::Buy);
+    assert_eq!(trades[0].value / trades[0].quantity, 72.0);
+
+    //Four entries and the stop, only the stop is left open
+    let stats = strat.get_simulation_statistics();
+    assert_eq!(stats.total_orders_submitted, 5);
+    let open_orders = strat.state().brkr().get_open_orders();
+    assert_eq!(open_orders.len(), 1);
+    assert_eq!(open_orders[0].order_type, OrderType::StopSell);
+}
+
+#[tokio::test]
+async fn reversal_cancels_entry_after_timeout() {
+    let prices = [100.0, 100.0, 100.0, 85.0];
+    let mut source = Penelope::new();
+    for (i, price) in prices.iter().enumerate() {
+        source.add_quote(*price, *price, 100 + i as i64, "ABC");
+    }
+    //ABC stops quoting so the entry can't execute
+    for i in 0..10 {
+        source.add_quote(10.0, 10.0, 100 + i, "XYZ");
+    }
+
+    let mut strat = ReversalStrategyBuilder::new()
+        .with_brkr(setup_broker(source).await)
+        .with_symbol("ABC")
+        .with_lookback(3)
+        .with_entry_pct(0.1)
+        .with_entry_timeout(2)
+        .default();
+
+    strat.init(&100_000.0);
+    strat.run().await;
+
+    assert!(strat.get_trades().is_empty());
+    assert_eq!(strat.get_simulation_statistics().total_orders_submitted, 1);
+    assert!(strat.state().brkr().get_open_orders().is_empty());
+}
+
+#[tokio::test]
+async fn reversal_goes_short_when_stop_triggers() {
+    let prices = [
+        100.0, 100.0, 100.0, 85.0, 86.0, 90.0, 80.0, 82.0, 84.0, 88.0, 89.0, 90.0,
+    ];
+    let mut source = Penelope::new();
+    for (i, price) in prices.iter().enumerate() {
+        source.add_quote(*price, *price, 100 + i as i64, "ABC");
+    }
+    let mut client = TestClient::single("Reversal", source);
+    let resp = client.init("Reversal".to_string()).await.unwrap();
+    let brkr = UistBrokerBuilder::new()
+        .with_client(client, resp.backtest_id)
+        .with_short_margin_requirement(0.5)
+        .build()
+        .await;
+
+    let mut strat = ReversalStrategyBuilder::new()
+        .with_brkr(brkr)
+        .with_symbol("ABC")
+        .with_lookback(3)
+        .with_entry_pct(0.1)
+        .with_stop_loss_pct(0.05)
+        .default();
+
+    strat.init(&100_000.0);
+    strat.run().await;
+
+    //Enters at 86, the stop at 80 sells twice the position and the short is covered at 88
+    let trades = strat.get_trades();
+    assert_eq!(trades.len(), 3);
+    assert_eq!(trades[0].typ, TradeType::Buy);
+    assert_eq!(trades[0].value / trades[0].quantity, 86.0);
+    assert_eq!(trades[1].typ, TradeType::Sell);
+    assert_eq!(trades[1].value / trades[1].quantity, 80.0);
+    assert_eq!(trades[1].quantity, trades[0].quantity * 2.0);
+    assert_eq!(trades[2].typ, TradeType::Buy);
+    assert_eq!(trades[2].value / trades[2].quantity, 88.0);
+    assert_eq!(trades[2].quantity, trades[0].quantity);
+
+    //Entry is sized so that cash after entry covers the margin on the short
+    assert_eq!(trades[0].quantity, (95_000.0_f64 / (85.0 * 1.5)).floor());
+    assert_eq!(strat.get_simulation_statistics().total_orders_submitted, 3);
+    assert!(strat.state().brkr().get_position_qty("ABC").is_none());
+}
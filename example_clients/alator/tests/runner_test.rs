@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use alator::perf::Frequency;
+use alator::strategy::runner::{BacktestError, ParallelBacktestRunner, StrategyConfig};
+use alator::strategy::staticweight::PortfolioAllocation;
+use rotala::input::penelope::Penelope;
+
+fn config(source: &Arc<Penelope>, abc_weight: f64) -> StrategyConfig {
+    let mut weights: PortfolioAllocation = HashMap::new();
+    weights.insert("ABC".to_string(), abc_weight);
+    weights.insert("BCD".to_string(), 1.0 - abc_weight);
+    StrategyConfig {
+        source: Arc::clone(source),
+        weights,
+        initial_cash: 100_000.0,
+        trade_costs: Vec::new(),
+        perf_config: Frequency::Daily.into(),
+    }
+}
+
+#[tokio::test]
+async fn parallel_runner_integration_test() {
+    let source = Arc::new(Penelope::random(100, vec!["ABC", "BCD"]));
+    //Exchange can't start without prices so this task panics
+    let empty = Arc::new(Penelope::new());
+
+    let configs = vec![
+        config(&source, 0.2),
+        config(&empty, 0.5),
+        config(&source, 0.5),
+        config(&source, 0.8),
+    ];
+    let results = ParallelBacktestRunner::new(configs).run().await;
+
+    assert_eq!(results.len(), 4);
+    assert_eq!(
+        results[1].as_ref().unwrap_err(),
+        &BacktestError::StrategyPanicked(1)
+    );
+    let outputs = [&results[0], &results[2], &results[3]]
+        .iter()
+        .map(|res| res.as_ref().unwrap())
+        .collect::<Vec<_>>();
+    //Same prices with different weights give different results
+    assert_ne!(outputs[0].ret, outputs[2].ret);
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use alator::broker::uist::UistBrokerBuilder;
+use alator::broker::BrokerQuote;
+use alator::strategy::signal::{Signal, SignalBasedStrategyBuilder};
+use alator::strategy::Strategy;
+use rotala::exchange::uist_v1::TradeType;
+use rotala::http::uist::uistv1_client::{TestClient, UistClient};
+use rotala::input::penelope::Penelope;
+
+//Scores +1 if the price rose on the last tick, falls are neutral because the broker can't short
+#[derive(Default)]
+struct LastMove {
+    last: HashMap<String, f64>,
+}
+
+impl Signal for LastMove {
+    fn compute(&mut self, symbol: &str, quote: &dyn BrokerQuote) -> f64 {
+        let price = quote.get_bid();
+        let score = match self.last.get(symbol) {
+            Some(last) if price > *last => 1.0,
+            _ => 0.0,
+        };
+        self.last.insert(symbol.to_string(), price);
+        score
+    }
+}
+
+//Always neutral, dilutes the other signal
+struct Flat;
+
+impl Signal for Flat {
+    fn compute(&mut self, _symbol: &str, _quote: &dyn BrokerQuote) -> f64 {
+        0.0
+    }
+}
+
+#[tokio::test]
+async fn signal_integration_test() {
+    let abc = [
+        100.0, 101.0, 102.0, 103.0, 102.0, 101.0, 100.0, 99.0, 98.0, 97.0,
+    ];
+    let bcd = [
+        100.0, 99.0, 98.0, 99.0, 100.0, 101.0, 102.0, 103.0, 104.0, 105.0,
+    ];
+    let mut source = Penelope::new();
+    for i in 0..abc.len() {
+        let date = 100 + i as i64;
+        source.add_quote(abc[i], abc[i], date, "ABC");
+        source.add_quote(bcd[i], bcd[i], date, "BCD");
+    }
+
+    let mut client = TestClient::single("Signal", source);
+    let resp = client.init("Signal".to_string()).await.unwrap();
+    let brkr = UistBrokerBuilder::new()
+        .with_client(client, resp.backtest_id)
+        .build()
+        .await;
+
+    let mut strat = SignalBasedStrategyBuilder::new()
+        .with_brkr(brkr)
+        .with_symbols(&["ABC", "BCD"])
+        .with_signal(Box::<LastMove>::default(), 3.0)
+        .with_signal(Box::new(Flat), 1.0)
+        .default();
+
+    strat.init(&100_000.0);
+    strat.run().await;
+
+    //Weighted average of 1.0 * 3 and 0.0 * 1
+    let scores = strat.get_scores();
+    assert_eq!(scores.get("ABC"), Some(&0.0));
+    assert_eq!(scores.get("BCD"), Some(&0.75));
+
+    //Long ABC while it rises, then switches to BCD
+    let trades = strat.get_trades();
+    assert_eq!(trades[0].symbol, "ABC");
+    assert_eq!(trades[0].typ, TradeType::Buy);
+    assert!(trades
+        .iter()
+        .any(|trade| trade.symbol == "ABC" && trade.typ == TradeType::Sell));
+    assert!(trades
+        .iter()
+        .any(|trade| trade.symbol == "BCD" && trade.typ == TradeType::Buy));
+
+    let stats = strat.get_simulation_statistics();
+    assert_eq!(stats.total_ticks, strat.get_history().len());
+
+    let _perf = strat.perf(alator::perf::Frequency::Daily);
+}
@@ -1,9 +1,11 @@
 use std::collections::HashMap;
 
-use alator::broker::uist::UistBrokerBuilder;
+use alator::broker::uist::{BacktestBuilder, UistBrokerBuilder};
 use alator::broker::BrokerCost;
 
 use alator::strategy::staticweight::{PortfolioAllocation, StaticWeightStrategyBuilder};
+use alator::strategy::SimulationStatistics;
+use alator::strategy::Strategy;
 use rotala::http::uist::uistv1_client::{TestClient, UistClient};
 use rotala::input::penelope::Penelope;
 
@@ -38,3 +40,72 @@ async fn staticweight_integration_test() {
 
     let _perf = strat.perf(alator::perf::Frequency::Daily);
 }
+
+#[tokio::test]
+async fn staticweight_simulation_statistics_test() {
+    let mut weights: PortfolioAllocation = HashMap::new();
+    weights.insert("ABC".to_string(), 0.5);
+    weights.insert("BCD".to_string(), 0.5);
+
+    let mut stats = Vec::new();
+    for name in ["First", "Second"] {
+        let mut source = Penelope::new();
+        for date in 100..105 {
+            source.add_quote(100.0, 100.0, date, "ABC");
+            source.add_quote(50.0, 50.0, date, "BCD");
+        }
+        let mut client = TestClient::single(name, source);
+        let resp = client.init(name.to_string()).await.unwrap();
+        let brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
+
+        let mut strat = StaticWeightStrategyBuilder::new()
+            .with_brkr(brkr)
+            .with_weights(weights.clone())
+            .default();
+        strat.init(&100_000.0);
+        strat.run().await;
+
+        //Orders from init count towards the target before they execute so no more are sent
+        let strat_stats = strat.get_simulation_statistics();
+        assert_eq!(strat_stats.total_ticks, strat.get_history().len());
+        assert_eq!(strat_stats.total_rebalances, 1);
+        assert_eq!(strat_stats.total_orders_submitted, 2);
+        assert_eq!(strat_stats.total_trades_executed, 2);
+        stats.push(strat_stats);
+    }
+
+    let total = SimulationStatistics::aggregate(&stats);
+    assert_eq!(total.strategies_count, 2);
+    assert_eq!(total.total_ticks, 10);
+    assert_eq!(total.total_rebalances, 2);
+    assert_eq!(total.total_orders_submitted, 4);
+    assert_eq!(total.total_trades_executed, 4);
+}
+
+#[tokio::test]
+async fn staticweight_backtest_builder_test() {
+    let mut weights: PortfolioAllocation = HashMap::new();
+    weights.insert("ABC".to_string(), 0.5);
+    weights.insert("BCD".to_string(), 0.5);
+
+    let brkr = BacktestBuilder::new()
+        .with_source(Penelope::random(100, vec!["ABC", "BCD"]))
+        .with_trade_costs(vec![BrokerCost::PctOfValue(0.01)])
+        .build()
+        .await;
+
+    let mut strat = StaticWeightStrategyBuilder::new()
+        .with_brkr(brkr)
+        .with_weights(weights)
+        .default();
+
+    strat.init(&100_000.0);
+    strat.run().await;
+
+    let stats = strat.get_simulation_statistics();
+    assert_eq!(stats.total_ticks, strat.get_history().len());
+    assert!(stats.total_trades_executed > 0);
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use alator::broker::uist::UistBrokerBuilder;
+use alator::perf::Frequency;
+use alator::strategy::staticweight::StaticWeightStrategyBuilder;
+use alator::strategy::walkforward::{StrategyParameters, StrategyRun, WalkForwardOptimizer};
+use alator::strategy::Strategy;
+use rotala::http::uist::uistv1_client::{TestClient, UistClient};
+use rotala::input::penelope::Penelope;
+
+//Holds all of the portfolio in one symbol
+#[derive(Clone, Debug, PartialEq)]
+struct SingleHolding(&'static str);
+
+impl StrategyParameters for SingleHolding {
+    async fn run(&self, source: Penelope) -> StrategyRun {
+        let mut client = TestClient::single("WalkForward", source);
+        let resp = client.init("WalkForward".to_string()).await.unwrap();
+        let brkr = UistBrokerBuilder::new()
+            .with_client(client, resp.backtest_id)
+            .build()
+            .await;
+
+        let mut weights = HashMap::new();
+        weights.insert(self.0.to_string(), 1.0);
+        let mut strat = StaticWeightStrategyBuilder::new()
+            .with_brkr(brkr)
+            .with_weights(weights)
+            .default();
+        strat.init(&100_000.0);
+        strat.run().await;
+        StrategyRun {
+            history: strat.get_history(),
+            trades: strat.get_trades(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn walkforward_integration_test() {
+    //ABC rises and BCD falls for the first 20 ticks, then they reverse
+    let mut source = Penelope::new();
+    let mut abc: f64 = 100.0;
+    let mut bcd: f64 = 100.0;
+    for i in 0..40 {
+        let (abc_move, bcd_move) = if i < 20 { (1.02, 0.98) } else { (0.98, 1.02) };
+        //Small alternating noise so that volatility isn't zero
+        let noise = if i % 2 == 0 { 1.001 } else { 0.999 };
+        abc *= abc_move * noise;
+        bcd *= bcd_move * noise;
+        source.add_quote(abc, abc, 100 + i, "ABC");
+        source.add_quote(bcd, bcd, 100 + i, "BCD");
+    }
+
+    let optimizer =
+        WalkForwardOptimizer::new(10, 5, vec![SingleHolding("ABC"), SingleHolding("BCD")]);
+    let result = optimizer.run(&source, Frequency::Daily).await;
+
+    //Windows start every 5 ticks while there are 15 ticks left
+    assert_eq!(result.windows.len(), 6);
+    assert_eq!(result.windows[0].in_sample_dates, (100, 109));
+    assert_eq!(result.windows[0].out_of_sample_dates, (110, 114));
+    assert_eq!(result.windows[1].in_sample_dates.0, 105);
+
+    assert_eq!(result.windows[0].parameters, SingleHolding("ABC"));
+    assert_eq!(result.windows[5].parameters, SingleHolding("BCD"));
+    assert!(result.windows[0].in_sample.sharpe > 0.0);
+    assert!(result.out_of_sample.is_some());
+}
@@ -4,7 +4,9 @@ use std::sync::Mutex;
 use actix_web::{web, App, HttpServer};
 use rotala::{
     http::uist::{
-        uistv1_server::{delete_order, fetch_quotes, info, init, insert_order, tick},
+        uistv1_server::{
+            delete_order, fetch_quotes, halt, info, init, insert_order, metrics, resume, tick,
+        },
         AppState,
     },
     input::penelope::Penelope,
@@ -31,6 +33,9 @@ async fn main() -> std::io::Result<()> {
             .service(tick)
             .service(insert_order)
             .service(delete_order)
+            .service(halt)
+            .service(resume)
+            .service(metrics)
     })
     .bind((address, port))?
     .run()
@@ -0,0 +1,80 @@
+//! Models for the permanent move in price caused by trading a large quantity relative to the
+//! volume that normally trades in a symbol.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Returns the permanent price impact, as a fraction of price, of trading `qty` in a symbol that
+/// trades `adv` on an average day with daily volatility `sigma`.
+pub trait MarketImpactModel: std::fmt::Debug + Send + Sync {
+    fn permanent_impact(&self, qty: f64, adv: f64, sigma: f64) -> f64;
+}
+
+/// Almgren square-root model, `sigma * sqrt(qty / adv)`.
+#[derive(Clone, Debug)]
+pub struct AlmgrenImpact;
+
+impl MarketImpactModel for AlmgrenImpact {
+    fn permanent_impact(&self, qty: f64, adv: f64, sigma: f64) -> f64 {
+        if adv <= 0.0 {
+            return 0.0;
+        }
+        sigma * (qty.abs() / adv).sqrt()
+    }
+}
+
+/// Impact model with the average daily volume and daily volatility of each symbol. Symbols that
+/// are missing from either map have no impact.
+#[derive(Clone, Debug)]
+pub struct MarketImpact {
+    pub model: Arc<dyn MarketImpactModel>,
+    pub adv: HashMap<String, f64>,
+    pub sigma: HashMap<String, f64>,
+}
+
+impl MarketImpact {
+    pub fn new(
+        model: Box<dyn MarketImpactModel>,
+        adv: HashMap<String, f64>,
+        sigma: HashMap<String, f64>,
+    ) -> Self {
+        Self {
+            model: Arc::from(model),
+            adv,
+            sigma,
+        }
+    }
+
+    /// Impact of `qty` already traded in `symbol`, signed so that net buying raises the price and
+    /// net selling lowers it.
+    pub fn impact(&self, symbol: &str, qty: f64) -> f64 {
+        match (self.adv.get(symbol), self.sigma.get(symbol)) {
+            (Some(adv), Some(sigma)) => {
+                self.model.permanent_impact(qty, *adv, *sigma) * qty.signum()
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{AlmgrenImpact, MarketImpact, MarketImpactModel};
+
+    #[test]
+    fn test_that_almgren_impact_grows_with_square_root_of_participation() {
+        assert_eq!(AlmgrenImpact.permanent_impact(100.0, 10_000.0, 0.02), 0.002);
+        assert_eq!(AlmgrenImpact.permanent_impact(400.0, 10_000.0, 0.02), 0.004);
+        assert_eq!(AlmgrenImpact.permanent_impact(100.0, 0.0, 0.02), 0.0);
+
+        let impact = MarketImpact::new(
+            Box::new(AlmgrenImpact),
+            HashMap::from([("ABC".to_string(), 10_000.0)]),
+            HashMap::from([("ABC".to_string(), 0.02)]),
+        );
+        assert_eq!(impact.impact("ABC", -100.0), -0.002);
+        assert_eq!(impact.impact("BCD", 100.0), 0.0);
+    }
+}
@@ -3,5 +3,7 @@
 //! to Orderbooks and the logic contained within the Exchange itself primarily relates to the
 //! orchestration of the backtest (for example, ticking forward or synchronizing state with clients
 //! ).
+pub mod impact;
 pub mod jura_v1;
+pub mod slippage;
 pub mod uist_v1;
@@ -0,0 +1,56 @@
+//! Models for the difference between the quoted price and the price that a market order executes
+//! at.
+
+/// Returns the execution price for an order of `qty` given the quoted `price`, buys should
+/// execute above the quote and sells below.
+pub trait SlippageModel: std::fmt::Debug + Send + Sync {
+    fn apply(&self, price: f64, qty: f64, is_buy: bool) -> f64;
+}
+
+/// Moves the price by a fixed number of basis points, independent of size.
+#[derive(Clone, Debug)]
+pub struct FixedBpsSlippage(pub f64);
+
+impl SlippageModel for FixedBpsSlippage {
+    fn apply(&self, price: f64, _qty: f64, is_buy: bool) -> f64 {
+        let impact = price * self.0 / 10_000.0;
+        if is_buy {
+            price + impact
+        } else {
+            price - impact
+        }
+    }
+}
+
+/// Moves the price by `k * sqrt(qty)`, so impact grows with size but at a decreasing rate.
+#[derive(Clone, Debug)]
+pub struct SquareRootSlippage {
+    pub k: f64,
+}
+
+impl SlippageModel for SquareRootSlippage {
+    fn apply(&self, price: f64, qty: f64, is_buy: bool) -> f64 {
+        let impact = self.k * qty.abs().sqrt();
+        if is_buy {
+            price + impact
+        } else {
+            price - impact
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FixedBpsSlippage, SlippageModel, SquareRootSlippage};
+
+    #[test]
+    fn test_that_slippage_moves_price_against_order() {
+        let fixed = FixedBpsSlippage(50.0);
+        assert_eq!(fixed.apply(100.0, 10.0, true), 100.5);
+        assert_eq!(fixed.apply(100.0, 10.0, false), 99.5);
+
+        let sqrt = SquareRootSlippage { k: 0.1 };
+        assert_eq!(sqrt.apply(100.0, 100.0, true), 101.0);
+        assert_eq!(sqrt.apply(100.0, 100.0, false), 99.0);
+    }
+}
@@ -1,7 +1,11 @@
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
 
+use super::impact::MarketImpact;
+use super::slippage::SlippageModel;
 use crate::input::penelope::{PenelopeQuote, PenelopeQuoteByDate};
+use crate::metrics::{PENDING_ORDERS, TICK_DURATION_SECONDS};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct UistQuote {
@@ -9,6 +13,8 @@ pub struct UistQuote {
     pub ask: f64,
     pub date: i64,
     pub symbol: String,
+    #[serde(default)]
+    pub volume: Option<f64>,
 }
 
 impl From<PenelopeQuote> for UistQuote {
@@ -18,6 +24,7 @@ impl From<PenelopeQuote> for UistQuote {
             ask: value.ask,
             date: value.date,
             symbol: value.symbol,
+            volume: value.volume,
         }
     }
 }
@@ -38,6 +45,8 @@ pub enum OrderType {
     LimitBuy,
     StopSell,
     StopBuy,
+    TrailingStopSell,
+    TrailingStopBuy,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -47,6 +56,9 @@ pub struct Trade {
     pub quantity: f64,
     pub date: i64,
     pub typ: TradeType,
+    //Defaults so that trades serialized before this field was added can still be read
+    #[serde(default)]
+    pub order_id: Option<OrderId>,
 }
 
 impl Trade {
@@ -63,6 +75,7 @@ impl Trade {
             quantity,
             date,
             typ,
+            order_id: None,
         }
     }
 }
@@ -74,6 +87,25 @@ pub struct Order {
     pub symbol: String,
     pub shares: f64,
     pub price: Option<f64>,
+    //Distance of the stop from the best price seen since the order was inserted, only set for
+    //trailing stops. Defaults so that orders serialized before this field was added can be read
+    #[serde(default)]
+    pub trailing_delta: Option<f64>,
+    //Date from which the order is removed from the book without executing
+    #[serde(default)]
+    pub expiry: Option<i64>,
+    //Orders in the same group are removed from the book when any one of them executes
+    #[serde(default)]
+    pub oco_group_id: Option<u64>,
+    //Set on the children of a bracket order when they enter the book
+    #[serde(default)]
+    pub parent_order_id: Option<OrderId>,
+    //Hidden quantity of iceberg orders, shares is the visible slice
+    #[serde(default)]
+    pub reserve_qty: f64,
+    //Lots to sell first, not used by the exchange but carried for brokers that track lots
+    #[serde(default)]
+    pub lot_ids: Option<Vec<u64>>,
 }
 
 impl Order {
@@ -92,6 +124,43 @@ impl Order {
         &self.order_type
     }
 
+    pub fn get_trailing_delta(&self) -> &Option<f64> {
+        &self.trailing_delta
+    }
+
+    pub fn get_expiry(&self) -> &Option<i64> {
+        &self.expiry
+    }
+
+    pub fn get_oco_group_id(&self) -> &Option<u64> {
+        &self.oco_group_id
+    }
+
+    pub fn get_parent_order_id(&self) -> &Option<OrderId> {
+        &self.parent_order_id
+    }
+
+    pub fn get_reserve_qty(&self) -> f64 {
+        self.reserve_qty
+    }
+
+    pub fn get_lot_ids(&self) -> &Option<Vec<u64>> {
+        &self.lot_ids
+    }
+
+    pub fn with_lot_ids(mut self, lot_ids: Vec<u64>) -> Self {
+        self.lot_ids = Some(lot_ids);
+        self
+    }
+
+    /// Replaces the visible slice of an iceberg order after it executes. Every slice but the last
+    /// is the display quantity so the next slice is the lesser of the current slice and reserve.
+    pub fn replenish_from_reserve(&mut self) {
+        let next = self.shares.min(self.reserve_qty);
+        self.reserve_qty -= next;
+        self.shares = next;
+    }
+
     fn set_order_id(&mut self, order_id: u64) {
         self.order_id = Some(order_id);
     }
@@ -103,6 +172,12 @@ impl Order {
             symbol: symbol.into(),
             shares,
             price: None,
+            trailing_delta: None,
+            expiry: None,
+            oco_group_id: None,
+            parent_order_id: None,
+            reserve_qty: 0.0,
+            lot_ids: None,
         }
     }
 
@@ -113,6 +188,33 @@ impl Order {
             symbol: symbol.into(),
             shares,
             price: Some(price),
+            trailing_delta: None,
+            expiry: None,
+            oco_group_id: None,
+            parent_order_id: None,
+            reserve_qty: 0.0,
+            lot_ids: None,
+        }
+    }
+
+    fn trailing(
+        order_type: OrderType,
+        symbol: impl Into<String>,
+        shares: f64,
+        trailing_delta: f64,
+    ) -> Self {
+        Self {
+            order_id: None,
+            order_type,
+            symbol: symbol.into(),
+            shares,
+            price: None,
+            trailing_delta: Some(trailing_delta),
+            expiry: None,
+            oco_group_id: None,
+            parent_order_id: None,
+            reserve_qty: 0.0,
+            lot_ids: None,
         }
     }
 
@@ -139,6 +241,94 @@ impl Order {
     pub fn limit_sell(symbol: impl Into<String>, shares: f64, price: f64) -> Self {
         Order::delayed(OrderType::LimitSell, symbol, shares, price)
     }
+
+    /// Limit buy that is removed from the book unexecuted on the first tick at or after `expiry`.
+    pub fn limit_buy_gtd(symbol: impl Into<String>, shares: f64, price: f64, expiry: i64) -> Self {
+        let mut order = Order::limit_buy(symbol, shares, price);
+        order.expiry = Some(expiry);
+        order
+    }
+
+    /// Limit sell that is removed from the book unexecuted on the first tick at or after `expiry`.
+    pub fn limit_sell_gtd(symbol: impl Into<String>, shares: f64, price: f64, expiry: i64) -> Self {
+        let mut order = Order::limit_sell(symbol, shares, price);
+        order.expiry = Some(expiry);
+        order
+    }
+
+    /// Limit buy that shows at most `display_qty` and replenishes from the reserve each time the
+    /// visible slice executes, until `total_qty` has executed.
+    pub fn iceberg_buy(
+        symbol: impl Into<String>,
+        total_qty: f64,
+        display_qty: f64,
+        price: f64,
+    ) -> Self {
+        let mut order = Order::limit_buy(symbol, display_qty.min(total_qty), price);
+        order.reserve_qty = total_qty - order.shares;
+        order
+    }
+
+    /// Limit sell that shows at most `display_qty` and replenishes from the reserve each time the
+    /// visible slice executes, until `total_qty` has executed.
+    pub fn iceberg_sell(
+        symbol: impl Into<String>,
+        total_qty: f64,
+        display_qty: f64,
+        price: f64,
+    ) -> Self {
+        let mut order = Order::limit_sell(symbol, display_qty.min(total_qty), price);
+        order.reserve_qty = total_qty - order.shares;
+        order
+    }
+
+    /// Sells once the bid falls `trailing_delta` below the highest bid seen since insertion.
+    pub fn trailing_stop_sell(symbol: impl Into<String>, shares: f64, trailing_delta: f64) -> Self {
+        Order::trailing(OrderType::TrailingStopSell, symbol, shares, trailing_delta)
+    }
+
+    /// Buys once the ask rises `trailing_delta` above the lowest ask seen since insertion.
+    pub fn trailing_stop_buy(symbol: impl Into<String>, shares: f64, trailing_delta: f64) -> Self {
+        Order::trailing(OrderType::TrailingStopBuy, symbol, shares, trailing_delta)
+    }
+
+    /// Market buy sized by value, the broker resolves this into a share quantity.
+    pub fn dollar_buy(symbol: &str, notional: f64) -> DollarOrder {
+        DollarOrder {
+            order_type: OrderType::MarketBuy,
+            symbol: symbol.to_string(),
+            notional,
+        }
+    }
+
+    /// Market sell sized by value, the broker resolves this into a share quantity.
+    pub fn dollar_sell(symbol: &str, notional: f64) -> DollarOrder {
+        DollarOrder {
+            order_type: OrderType::MarketSell,
+            symbol: symbol.to_string(),
+            notional,
+        }
+    }
+}
+
+/// Market order for a cash value rather than a number of shares. The exchange only accepts
+/// [Order] so this has to be resolved, which can result in fractional shares.
+#[derive(Clone, Debug)]
+pub struct DollarOrder {
+    pub order_type: OrderType,
+    pub symbol: String,
+    pub notional: f64,
+}
+
+impl DollarOrder {
+    pub fn is_buy(&self) -> bool {
+        matches!(self.order_type, OrderType::MarketBuy)
+    }
+
+    /// Market order for `shares`, with the same direction as this order.
+    pub fn resolve(&self, shares: f64) -> Order {
+        Order::market(self.order_type, self.symbol.clone(), shares)
+    }
 }
 
 impl Eq for Order {}
@@ -151,12 +341,106 @@ impl PartialEq for Order {
     }
 }
 
+/// Per-symbol rules that orders must meet to be inserted into the book. Quantities are rounded
+/// down to a multiple of `min_lot_size`, orders with a value below `min_notional` after rounding
+/// are not inserted.
+#[derive(Clone, Debug, Default)]
+pub struct ExchangePolicy {
+    pub min_lot_size: HashMap<String, f64>,
+    pub min_notional: HashMap<String, f64>,
+}
+
+impl ExchangePolicy {
+    /// Quantity that the order is allowed to trade given the current quote, zero if the order
+    /// can't be inserted.
+    fn allowed_qty(&self, order: &Order, quote: Option<&PenelopeQuote>) -> f64 {
+        let mut shares = order.shares;
+        if let Some(lot) = self.min_lot_size.get(&order.symbol) {
+            shares = (shares / lot).floor() * lot;
+        }
+        if let (Some(min_notional), Some(quote)) = (self.min_notional.get(&order.symbol), quote) {
+            let price = match order.order_type {
+                OrderType::MarketBuy
+                | OrderType::LimitBuy
+                | OrderType::StopBuy
+                | OrderType::TrailingStopBuy => quote.ask,
+                OrderType::MarketSell
+                | OrderType::LimitSell
+                | OrderType::StopSell
+                | OrderType::TrailingStopSell => quote.bid,
+            };
+            if shares * price < *min_notional {
+                shares = 0.0;
+            }
+        }
+        shares
+    }
+}
+
+/// Order whose quantity was changed by the [ExchangePolicy]. `order` has the adjusted quantity,
+/// if this is zero then the order wasn't inserted and has no id.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExchangePolicyAdjustment {
+    pub order: Order,
+    pub requested_qty: f64,
+}
+
+/// Halts trading in a symbol when the mid price moves by more than `halt_threshold_pct`, as a
+/// fraction of the last mid price, between ticks. Orders for the symbol stay in the book without
+/// executing for `cooldown_ticks`, including the tick on which the move happened.
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    pub halt_threshold_pct: f64,
+    pub cooldown_ticks: usize,
+}
+
+/// Executed trades, inserted orders, expired orders, policy adjustments, and orders queued
+/// because they were received outside the session, from a tick.
+pub type TickOutput = (
+    Vec<Trade>,
+    Vec<Order>,
+    Vec<Order>,
+    Vec<ExchangePolicyAdjustment>,
+    Vec<Order>,
+);
+
+/// Trading session as offsets from midnight UTC, open is inclusive and close is exclusive.
+/// Orders received outside the session are held until the next tick inside the session.
+#[derive(Clone, Debug)]
+pub struct SessionHours {
+    pub market_open_offset_secs: i64,
+    pub market_close_offset_secs: i64,
+}
+
+impl SessionHours {
+    pub fn is_open(&self, date: i64) -> bool {
+        let offset = date.rem_euclid(86400);
+        offset >= self.market_open_offset_secs && offset < self.market_close_offset_secs
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct UistV1 {
     orderbook: OrderBook,
     trade_log: Vec<Trade>,
-    //This is cleared on every tick
-    order_buffer: Vec<Order>,
+    //This is cleared on every tick, orders are held with the children of bracket orders
+    order_buffer: Vec<(Order, Vec<Order>)>,
+    last_oco_group: u64,
+    policy: ExchangePolicy,
+    //Only applied to market orders, other orders execute at the quote or not at all
+    slippage: Option<Arc<dyn SlippageModel>>,
+    //Only applied to market orders, successive fills in a symbol on a tick move the price
+    market_impact: Option<Arc<MarketImpact>>,
+    circuit_breaker: Option<CircuitBreaker>,
+    //Mid price on the last tick that each symbol was quoted
+    last_prices: HashMap<String, f64>,
+    //Ticks remaining until trading resumes
+    halted: HashMap<String, usize>,
+    //Orders received for a halted symbol, inserted once the symbol resumes
+    halted_queue: HashMap<String, Vec<Order>>,
+    session_hours: Option<SessionHours>,
+    //Orders received outside the session, held with their bracket children
+    after_hours_queue: Vec<(Order, Vec<Order>)>,
 }
 
 impl UistV1 {
@@ -165,16 +449,107 @@ impl UistV1 {
             orderbook: OrderBook::default(),
             trade_log: Vec::new(),
             order_buffer: Vec::new(),
+            last_oco_group: 0,
+            policy: ExchangePolicy::default(),
+            slippage: None,
+            market_impact: None,
+            circuit_breaker: None,
+            last_prices: HashMap::new(),
+            halted: HashMap::new(),
+            halted_queue: HashMap::new(),
+            session_hours: None,
+            after_hours_queue: Vec::new(),
         }
     }
 
-    fn sort_order_buffer(&mut self) {
-        self.order_buffer.sort_by(|a, _b| match a.get_order_type() {
-            OrderType::LimitSell | OrderType::StopSell | OrderType::MarketSell => {
-                std::cmp::Ordering::Less
+    pub fn with_policy(policy: ExchangePolicy) -> Self {
+        let mut exchange = Self::new();
+        exchange.policy = policy;
+        exchange
+    }
+
+    pub fn set_policy(&mut self, policy: ExchangePolicy) {
+        self.policy = policy;
+    }
+
+    pub fn set_slippage(&mut self, slippage: Option<Box<dyn SlippageModel>>) {
+        self.slippage = slippage.map(Arc::from);
+    }
+
+    pub fn set_market_impact(&mut self, market_impact: Option<MarketImpact>) {
+        self.market_impact = market_impact.map(Arc::new);
+    }
+
+    pub fn set_circuit_breaker(&mut self, circuit_breaker: Option<CircuitBreaker>) {
+        self.circuit_breaker = circuit_breaker;
+    }
+
+    pub fn set_session_hours(&mut self, session_hours: Option<SessionHours>) {
+        self.session_hours = session_hours;
+    }
+
+    /// Symbols that can't trade, with the number of ticks until trading resumes.
+    pub fn get_halted_symbols(&self) -> &HashMap<String, usize> {
+        &self.halted
+    }
+
+    /// Stops `symbol` trading for the next `duration_ticks` ticks. Orders already in the book are
+    /// held, new orders are queued and inserted when the symbol resumes.
+    pub fn halt_symbol(&mut self, symbol: &str, duration_ticks: usize) {
+        if duration_ticks > 0 {
+            self.halted.insert(symbol.to_string(), duration_ticks);
+        }
+    }
+
+    /// Ends a halt early, queued orders are inserted on the next tick.
+    pub fn resume_symbol(&mut self, symbol: &str) {
+        self.halted.remove(symbol);
+        self.release_halted_queue(symbol);
+    }
+
+    fn release_halted_queue(&mut self, symbol: &str) {
+        if let Some(orders) = self.halted_queue.remove(symbol) {
+            for order in orders {
+                self.order_buffer.push((order, Vec::new()));
             }
-            _ => std::cmp::Ordering::Greater,
-        })
+        }
+    }
+
+    fn update_circuit_breakers(&mut self, quotes: &PenelopeQuoteByDate) {
+        for (symbol, quote) in quotes {
+            let mid = (quote.bid + quote.ask) / 2.0;
+            if let (Some(breaker), Some(last)) =
+                (&self.circuit_breaker, self.last_prices.get(symbol))
+            {
+                if breaker.cooldown_ticks > 0
+                    && ((mid - last) / last).abs() > breaker.halt_threshold_pct
+                {
+                    self.halted.insert(symbol.clone(), breaker.cooldown_ticks);
+                }
+            }
+            self.last_prices.insert(symbol.clone(), mid);
+        }
+    }
+
+    //Used by the server so that every backtest shares one model
+    pub(crate) fn set_shared_slippage(&mut self, slippage: Option<Arc<dyn SlippageModel>>) {
+        self.slippage = slippage;
+    }
+
+    //Used by the server so that every backtest shares one model
+    pub(crate) fn set_shared_market_impact(&mut self, market_impact: Option<Arc<MarketImpact>>) {
+        self.market_impact = market_impact;
+    }
+
+    fn sort_order_buffer(&mut self) {
+        self.order_buffer
+            .sort_by(|(a, _), _b| match a.get_order_type() {
+                OrderType::LimitSell
+                | OrderType::StopSell
+                | OrderType::MarketSell
+                | OrderType::TrailingStopSell => std::cmp::Ordering::Less,
+                _ => std::cmp::Ordering::Greater,
+            })
     }
 
     pub fn insert_order(&mut self, order: Order) {
@@ -182,28 +557,131 @@ impl UistV1 {
         // ordering of trades
         // This impacts order_id where an order X can come in before order X+1 but the latter can
         // have an order_id that is less than the former.
-        self.order_buffer.push(order);
+        if self.halted.contains_key(order.get_symbol()) {
+            self.halted_queue
+                .entry(order.get_symbol().to_string())
+                .or_default()
+                .push(order);
+            return;
+        }
+        self.order_buffer.push((order, Vec::new()));
+    }
+
+    /// Inserts an entry order with children, typically a take profit and stop loss, that are
+    /// only added to the book once the entry executes. Children are inserted at the end of the
+    /// tick the parent executes on so they can't execute against the same quote. Children are
+    /// independent orders, put them in an OCO group if one should cancel the others.
+    pub fn insert_bracket_order(&mut self, parent: Order, children: Vec<Order>) {
+        self.order_buffer.push((parent, children));
+    }
+
+    /// Inserts two orders so that when either executes the other is cancelled on the same tick.
+    /// Order ids are assigned on the next tick so the group id shared by both orders is returned.
+    pub fn insert_oco_pair(&mut self, mut a: Order, mut b: Order) -> u64 {
+        let group_id = self.last_oco_group;
+        self.last_oco_group += 1;
+        a.oco_group_id = Some(group_id);
+        b.oco_group_id = Some(group_id);
+        self.insert_order(a);
+        self.insert_order(b);
+        group_id
     }
 
     pub fn delete_order(&mut self, order_id: OrderId) {
         self.orderbook.delete_order(order_id);
     }
 
-    pub fn tick(&mut self, quotes: &PenelopeQuoteByDate) -> (Vec<Trade>, Vec<Order>) {
+    /// Returns executed trades, orders inserted into the book, orders that expired, orders
+    /// adjusted by the [ExchangePolicy], and orders queued outside [SessionHours].
+    pub fn tick(&mut self, quotes: &PenelopeQuoteByDate) -> TickOutput {
+        //Duration is observed when the timer drops at the end of the tick
+        let _timer = TICK_DURATION_SECONDS.start_timer();
+        //Orders were received by the client at the date of these quotes
+        let now = quotes.values().next().map(|quote| quote.date);
+        let in_session = match (&self.session_hours, now) {
+            (Some(session_hours), Some(now)) => session_hours.is_open(now),
+            _ => true,
+        };
+        if in_session {
+            let mut promoted = std::mem::take(&mut self.after_hours_queue);
+            promoted.append(&mut self.order_buffer);
+            self.order_buffer = promoted;
+        }
+
         //To eliminate lookahead bias, we only insert new orders after we have executed any orders
         //that were on the stack first
-        let executed_trades = self.orderbook.execute_orders(quotes);
+        self.update_circuit_breakers(quotes);
+        let (executed_trades, expired_orders, activated_children) = self.orderbook.execute_orders(
+            quotes,
+            self.slippage.as_deref(),
+            self.market_impact.as_deref(),
+            &self.halted,
+        );
         for executed_trade in &executed_trades {
             self.trade_log.push(executed_trade.clone());
         }
 
+        let mut after_hours_orders = Vec::new();
+        if !in_session {
+            for (order, children) in std::mem::take(&mut self.order_buffer) {
+                after_hours_orders.push(order.clone());
+                self.after_hours_queue.push((order, children));
+            }
+        }
+
         self.sort_order_buffer();
-        for order in self.order_buffer.iter_mut() {
-            self.orderbook.insert_order(order);
+        let mut inserted_orders = activated_children;
+        let mut adjustments = Vec::new();
+        for (mut order, children) in std::mem::take(&mut self.order_buffer) {
+            let allowed = self
+                .policy
+                .allowed_qty(&order, quotes.get(order.get_symbol()));
+            if allowed != order.shares {
+                let requested_qty = order.shares;
+                order.shares = allowed;
+                if allowed == 0.0 {
+                    adjustments.push(ExchangePolicyAdjustment {
+                        order,
+                        requested_qty,
+                    });
+                    continue;
+                }
+                self.orderbook.insert_order(&mut order);
+                adjustments.push(ExchangePolicyAdjustment {
+                    order: order.clone(),
+                    requested_qty,
+                });
+            } else {
+                self.orderbook.insert_order(&mut order);
+            }
+            if !children.is_empty() {
+                self.orderbook
+                    .pending_children
+                    .insert(order.order_id.unwrap(), children);
+            }
+            inserted_orders.push(order);
         }
 
-        let inserted_orders = std::mem::take(&mut self.order_buffer);
-        (executed_trades, inserted_orders)
+        let mut resumed = Vec::new();
+        self.halted.retain(|symbol, remaining| {
+            *remaining -= 1;
+            if *remaining == 0 {
+                resumed.push(symbol.clone());
+            }
+            *remaining > 0
+        });
+        for symbol in resumed {
+            self.release_halted_queue(&symbol);
+        }
+        PENDING_ORDERS.set(self.orderbook.inner.len() as f64);
+
+        (
+            executed_trades,
+            inserted_orders,
+            expired_orders,
+            adjustments,
+            after_hours_orders,
+        )
     }
 }
 
@@ -217,6 +695,10 @@ impl Default for UistV1 {
 struct OrderBook {
     inner: VecDeque<Order>,
     last_inserted: u64,
+    //High water mark of bid for trailing sells, low water mark of ask for trailing buys
+    water_marks: HashMap<OrderId, f64>,
+    //Children of bracket orders keyed by parent, these are inserted when the parent executes
+    pending_children: HashMap<OrderId, Vec<Order>>,
 }
 
 impl Default for OrderBook {
@@ -230,6 +712,8 @@ impl OrderBook {
         Self {
             inner: std::collections::VecDeque::new(),
             last_inserted: 0,
+            water_marks: HashMap::new(),
+            pending_children: HashMap::new(),
         }
     }
 
@@ -246,6 +730,8 @@ impl OrderBook {
         if let Some(position) = delete_position {
             self.inner.remove(position);
         }
+        self.water_marks.remove(&delete_order_id);
+        self.pending_children.remove(&delete_order_id);
     }
 
     pub fn insert_order(&mut self, order: &mut Order) {
@@ -267,6 +753,7 @@ impl OrderBook {
             quantity: order.get_shares(),
             date,
             typ: TradeType::Buy,
+            order_id: order.order_id,
         }
     }
 
@@ -279,23 +766,96 @@ impl OrderBook {
             quantity: order.get_shares(),
             date,
             typ: TradeType::Sell,
+            order_id: order.order_id,
+        }
+    }
+
+    /// Execution price is the price after slippage so trade value reflects the slippage.
+    fn slip(mut trade: Trade, slippage: Option<&dyn SlippageModel>) -> Trade {
+        if let Some(model) = slippage {
+            let price = trade.value / trade.quantity;
+            let is_buy = matches!(trade.typ, TradeType::Buy);
+            trade.value = model.apply(price, trade.quantity, is_buy) * trade.quantity;
+        }
+        trade
+    }
+
+    /// Moves the execution price by the impact of the quantity already traded in the symbol on
+    /// this tick, then adds this trade to that quantity. Without depth, each fill stands in for
+    /// the next level of the book.
+    fn impact(
+        mut trade: Trade,
+        market_impact: Option<&MarketImpact>,
+        traded: &mut HashMap<String, f64>,
+    ) -> Trade {
+        if let Some(model) = market_impact {
+            let net_qty = traded.entry(trade.symbol.clone()).or_insert(0.0);
+            trade.value *= 1.0 + model.impact(&trade.symbol, *net_qty);
+            *net_qty += match trade.typ {
+                TradeType::Buy => trade.quantity,
+                TradeType::Sell => -trade.quantity,
+            };
         }
+        trade
     }
 
-    pub fn execute_orders(&mut self, quotes: &PenelopeQuoteByDate) -> Vec<Trade> {
+    /// Returns executed trades, expired orders, and bracket children added to the book.
+    pub fn execute_orders(
+        &mut self,
+        quotes: &PenelopeQuoteByDate,
+        slippage: Option<&dyn SlippageModel>,
+        market_impact: Option<&MarketImpact>,
+        halted: &HashMap<String, usize>,
+    ) -> (Vec<Trade>, Vec<Order>, Vec<Order>) {
+        //Net quantity of market orders filled in each symbol on this tick
+        let mut traded = HashMap::new();
         let mut completed_orderids = Vec::new();
         let mut trade_results = Vec::new();
+        let mut expired_orders = Vec::new();
+        let mut filled_groups = HashSet::new();
+        let mut activated_children = Vec::new();
+        let mut replenished_orderids = Vec::new();
         if self.is_empty() {
-            return trade_results;
+            return (trade_results, expired_orders, activated_children);
         }
+        //All quotes passed on a tick share the same date
+        let now = quotes.values().next().map(|quote| quote.date);
         for order in self.inner.iter() {
+            if now.unwrap_or(i64::MIN) >= order.expiry.unwrap_or(i64::MAX) {
+                completed_orderids.push(order.order_id.unwrap());
+                expired_orders.push(order.clone());
+                continue;
+            }
+            if let Some(group_id) = order.oco_group_id {
+                if filled_groups.contains(&group_id) {
+                    completed_orderids.push(order.order_id.unwrap());
+                    continue;
+                }
+            }
             let security_id = &order.symbol;
+            if halted.contains_key(security_id) {
+                continue;
+            }
             if let Some(quote) = quotes.get(security_id) {
                 let quote_copy: UistQuote = quote.clone().into();
                 let date = quote_copy.date;
                 let result = match order.order_type {
-                    OrderType::MarketBuy => Some(Self::execute_buy(quote_copy, order, date)),
-                    OrderType::MarketSell => Some(Self::execute_sell(quote_copy, order, date)),
+                    OrderType::MarketBuy => Some(Self::slip(
+                        Self::impact(
+                            Self::execute_buy(quote_copy, order, date),
+                            market_impact,
+                            &mut traded,
+                        ),
+                        slippage,
+                    )),
+                    OrderType::MarketSell => Some(Self::slip(
+                        Self::impact(
+                            Self::execute_sell(quote_copy, order, date),
+                            market_impact,
+                            &mut traded,
+                        ),
+                        slippage,
+                    )),
                     OrderType::LimitBuy => {
                         //Unwrap is safe because LimitBuy will always have a price
                         let order_price = order.price;
@@ -332,24 +892,88 @@ impl OrderBook {
                             None
                         }
                     }
+                    //Water mark is updated with the current quote before the stop is checked so
+                    //the order can't execute on the first quote it sees
+                    OrderType::TrailingStopSell => {
+                        let delta = order.trailing_delta.unwrap_or(0.0);
+                        let high = self
+                            .water_marks
+                            .entry(order.order_id.unwrap())
+                            .or_insert(quote_copy.bid);
+                        *high = high.max(quote_copy.bid);
+                        if quote_copy.bid <= *high - delta {
+                            Some(Self::execute_sell(quote_copy, order, date))
+                        } else {
+                            None
+                        }
+                    }
+                    OrderType::TrailingStopBuy => {
+                        let delta = order.trailing_delta.unwrap_or(0.0);
+                        let low = self
+                            .water_marks
+                            .entry(order.order_id.unwrap())
+                            .or_insert(quote_copy.ask);
+                        *low = low.min(quote_copy.ask);
+                        if quote_copy.ask >= *low + delta {
+                            Some(Self::execute_buy(quote_copy, order, date))
+                        } else {
+                            None
+                        }
+                    }
                 };
                 if let Some(trade) = &result {
-                    completed_orderids.push(order.order_id.unwrap());
+                    if order.reserve_qty > 0.0 {
+                        replenished_orderids.push(order.order_id.unwrap());
+                    } else {
+                        completed_orderids.push(order.order_id.unwrap());
+                    }
                     trade_results.push(trade.clone());
+                    if let Some(group_id) = order.oco_group_id {
+                        filled_groups.insert(group_id);
+                    }
+                }
+            }
+        }
+        //Siblings earlier in the book than the executed order
+        for order in self.inner.iter() {
+            let order_id = order.order_id.unwrap();
+            if let Some(group_id) = order.oco_group_id {
+                if filled_groups.contains(&group_id) && !completed_orderids.contains(&order_id) {
+                    completed_orderids.push(order_id);
+                }
+            }
+        }
+        for order in self.inner.iter_mut() {
+            if replenished_orderids.contains(&order.order_id.unwrap()) {
+                order.replenish_from_reserve();
+            }
+        }
+        //Children are taken before the parent is deleted as deletion discards them
+        for trade in &trade_results {
+            let parent_id = trade.order_id.unwrap();
+            if let Some(children) = self.pending_children.remove(&parent_id) {
+                for mut child in children {
+                    child.parent_order_id = Some(parent_id);
+                    self.insert_order(&mut child);
+                    activated_children.push(child);
                 }
             }
         }
         for order_id in completed_orderids {
             self.delete_order(order_id);
         }
-        trade_results
+        (trade_results, expired_orders, activated_children)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::UistV1;
-    use crate::exchange::uist_v1::OrderType;
+    use std::collections::HashMap;
+
+    use super::{CircuitBreaker, ExchangePolicy, SessionHours, UistV1};
+    use crate::exchange::impact::{AlmgrenImpact, MarketImpact};
+    use crate::exchange::slippage::FixedBpsSlippage;
+    use crate::exchange::uist_v1::{OrderType, TradeType};
     use crate::input::penelope::Penelope;
 
     use super::Order;
@@ -490,4 +1114,312 @@ mod tests {
             OrderType::MarketSell
         )
     }
+
+    #[test]
+    fn test_that_trailing_stop_sell_follows_market_up() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 101.00, 100, "ABC".to_owned());
+        source.add_quote(100.00, 101.00, 101, "ABC".to_owned());
+        source.add_quote(110.00, 111.00, 102, "ABC".to_owned());
+        source.add_quote(106.00, 107.00, 103, "ABC".to_owned());
+        source.add_quote(104.00, 105.00, 104, "ABC".to_owned());
+        let mut exchange = UistV1::new();
+
+        exchange.insert_order(Order::trailing_stop_sell("ABC", 100.0, 5.0));
+        exchange.tick(source.get_quotes_unchecked(&100));
+        exchange.tick(source.get_quotes_unchecked(&101));
+        exchange.tick(source.get_quotes_unchecked(&102));
+        //A fixed stop at 95 would not execute here, the trailing stop has moved up to 105
+        exchange.tick(source.get_quotes_unchecked(&103));
+        assert_eq!(exchange.trade_log.len(), 0);
+
+        exchange.tick(source.get_quotes_unchecked(&104));
+        assert_eq!(exchange.trade_log.len(), 1);
+        let trade = exchange.trade_log.remove(0);
+        assert_eq!(trade.value / trade.quantity, 104.00);
+        assert!(exchange.orderbook.water_marks.is_empty());
+    }
+
+    #[test]
+    fn test_that_trailing_stop_buy_follows_market_down() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 101.00, 100, "ABC".to_owned());
+        source.add_quote(90.00, 91.00, 101, "ABC".to_owned());
+        source.add_quote(93.00, 94.00, 102, "ABC".to_owned());
+        source.add_quote(95.00, 96.00, 103, "ABC".to_owned());
+        let mut exchange = UistV1::new();
+
+        exchange.insert_order(Order::trailing_stop_buy("ABC", 100.0, 5.0));
+        exchange.tick(source.get_quotes_unchecked(&100));
+        exchange.tick(source.get_quotes_unchecked(&101));
+        exchange.tick(source.get_quotes_unchecked(&102));
+        assert_eq!(exchange.trade_log.len(), 0);
+
+        exchange.tick(source.get_quotes_unchecked(&103));
+        assert_eq!(exchange.trade_log.len(), 1);
+        let trade = exchange.trade_log.remove(0);
+        assert_eq!(trade.value / trade.quantity, 96.00);
+    }
+
+    #[test]
+    fn test_that_gtd_order_expires_without_executing() {
+        let (source, mut exchange) = setup();
+
+        //Limit price is high enough to execute on every tick
+        exchange.insert_order(Order::limit_buy_gtd("ABC", 100.0, 200.0, 101));
+        exchange.insert_order(Order::limit_buy("ABC", 100.0, 50.0));
+        exchange.tick(source.get_quotes_unchecked(&100));
+
+        let res = exchange.tick(source.get_quotes_unchecked(&101));
+        assert!(res.0.is_empty());
+        assert_eq!(res.2.len(), 1);
+        assert_eq!(*res.2[0].get_expiry(), Some(101));
+        assert_eq!(exchange.orderbook.inner.len(), 1);
+
+        let res = exchange.tick(source.get_quotes_unchecked(&102));
+        assert!(res.2.is_empty());
+    }
+
+    #[test]
+    fn test_that_oco_pair_cancels_sibling_on_same_tick() {
+        let (source, mut exchange) = setup();
+
+        //Both limit prices are crossed on the next tick
+        exchange.insert_order(Order::limit_buy("ABC", 100.0, 110.0));
+        exchange.insert_order(Order::limit_buy("ABC", 100.0, 120.0));
+        exchange.tick(source.get_quotes_unchecked(&100));
+        let res = exchange.tick(source.get_quotes_unchecked(&101));
+        assert_eq!(res.0.len(), 2);
+
+        let group_id = exchange.insert_oco_pair(
+            Order::limit_buy("ABC", 100.0, 110.0),
+            Order::limit_buy("ABC", 100.0, 120.0),
+        );
+        assert_eq!(group_id, 0);
+        let res = exchange.tick(source.get_quotes_unchecked(&101));
+        assert_eq!(res.1[0].oco_group_id, Some(0));
+        let res = exchange.tick(source.get_quotes_unchecked(&102));
+        assert_eq!(res.0.len(), 1);
+        assert!(exchange.orderbook.is_empty());
+    }
+
+    #[test]
+    fn test_that_bracket_children_enter_book_when_parent_executes() {
+        let (source, mut exchange) = setup();
+
+        exchange.insert_bracket_order(
+            Order::limit_buy("ABC", 100.0, 102.0),
+            vec![
+                Order::limit_sell("ABC", 100.0, 110.0),
+                Order::stop_sell("ABC", 100.0, 90.0),
+            ],
+        );
+        let res = exchange.tick(source.get_quotes_unchecked(&100));
+        assert_eq!(res.1.len(), 1);
+        assert_eq!(exchange.orderbook.inner.len(), 1);
+        let parent_id = res.1[0].order_id.unwrap();
+
+        //Ask of 103 is above the limit so parent doesn't execute and children stay out of book
+        let res = exchange.tick(source.get_quotes_unchecked(&101));
+        assert!(res.0.is_empty());
+        assert_eq!(exchange.orderbook.inner.len(), 1);
+
+        //Deleting the parent discards the children
+        exchange.delete_order(parent_id);
+        assert!(exchange.orderbook.pending_children.is_empty());
+
+        exchange.insert_bracket_order(
+            Order::market_buy("ABC", 100.0),
+            vec![Order::limit_sell("ABC", 100.0, 105.0)],
+        );
+        exchange.tick(source.get_quotes_unchecked(&100));
+        let res = exchange.tick(source.get_quotes_unchecked(&101));
+        assert_eq!(res.0.len(), 1);
+        assert_eq!(res.1.len(), 1);
+        assert_eq!(res.1[0].parent_order_id, res.0[0].order_id);
+        assert_eq!(exchange.orderbook.inner.len(), 1);
+
+        let res = exchange.tick(source.get_quotes_unchecked(&102));
+        assert_eq!(res.0.len(), 1);
+        assert!(exchange.orderbook.is_empty());
+    }
+
+    #[test]
+    fn test_that_iceberg_order_executes_in_slices_until_total() {
+        let mut source = Penelope::new();
+        for date in 100..105 {
+            source.add_quote(101.00, 102.00, date, "ABC".to_owned());
+        }
+        let mut exchange = UistV1::new();
+
+        exchange.insert_order(Order::iceberg_buy("ABC", 250.0, 100.0, 105.0));
+        exchange.tick(source.get_quotes_unchecked(&100));
+
+        let mut filled = Vec::new();
+        for date in 101..105 {
+            let res = exchange.tick(source.get_quotes_unchecked(&date));
+            filled.extend(res.0.iter().map(|trade| trade.quantity));
+        }
+        assert_eq!(filled, vec![100.0, 100.0, 50.0]);
+        assert_eq!(filled.iter().sum::<f64>(), 250.0);
+        assert!(exchange.orderbook.is_empty());
+    }
+
+    #[test]
+    fn test_that_circuit_breaker_halts_symbol_until_cooldown() {
+        let mut source = Penelope::new();
+        source.add_quote(100.00, 100.00, 100, "ABC".to_owned());
+        source.add_quote(120.00, 120.00, 101, "ABC".to_owned());
+        source.add_quote(121.00, 121.00, 102, "ABC".to_owned());
+        source.add_quote(122.00, 122.00, 103, "ABC".to_owned());
+        let mut exchange = UistV1::new();
+        exchange.set_circuit_breaker(Some(CircuitBreaker {
+            halt_threshold_pct: 0.1,
+            cooldown_ticks: 2,
+        }));
+
+        exchange.insert_order(Order::market_buy("ABC", 100.0));
+        exchange.tick(source.get_quotes_unchecked(&100));
+
+        let res = exchange.tick(source.get_quotes_unchecked(&101));
+        assert!(res.0.is_empty());
+        assert_eq!(exchange.get_halted_symbols().get("ABC"), Some(&1));
+
+        let res = exchange.tick(source.get_quotes_unchecked(&102));
+        assert!(res.0.is_empty());
+        assert!(exchange.get_halted_symbols().is_empty());
+
+        let res = exchange.tick(source.get_quotes_unchecked(&103));
+        assert_eq!(res.0.len(), 1);
+        assert_eq!(res.0[0].value, 122.0 * 100.0);
+    }
+
+    #[test]
+    fn test_that_orders_for_halted_symbol_are_queued_until_resume() {
+        let mut source = Penelope::new();
+        for date in 100..105 {
+            source.add_quote(101.00, 102.00, date, "ABC".to_owned());
+        }
+        let mut exchange = UistV1::new();
+
+        exchange.halt_symbol("ABC", 2);
+        exchange.insert_order(Order::market_buy("ABC", 100.0));
+        let res = exchange.tick(source.get_quotes_unchecked(&100));
+        assert!(res.1.is_empty());
+        exchange.tick(source.get_quotes_unchecked(&101));
+        //Halt ended on the last tick so the order is inserted now and executes on the next
+        let res = exchange.tick(source.get_quotes_unchecked(&102));
+        assert_eq!(res.1.len(), 1);
+        let res = exchange.tick(source.get_quotes_unchecked(&103));
+        assert_eq!(res.0.len(), 1);
+
+        exchange.halt_symbol("ABC", 10);
+        exchange.insert_order(Order::market_buy("ABC", 100.0));
+        exchange.resume_symbol("ABC");
+        let res = exchange.tick(source.get_quotes_unchecked(&104));
+        assert_eq!(res.1.len(), 1);
+    }
+
+    #[test]
+    fn test_that_orders_outside_session_are_queued_until_open() {
+        let mut source = Penelope::new();
+        for date in [0, 34200, 34201] {
+            source.add_quote(101.00, 102.00, date, "ABC".to_owned());
+        }
+        let mut exchange = UistV1::new();
+        exchange.set_session_hours(Some(SessionHours {
+            market_open_offset_secs: 34200,
+            market_close_offset_secs: 57600,
+        }));
+
+        exchange.insert_order(Order::market_buy("ABC", 100.0));
+        let res = exchange.tick(source.get_quotes_unchecked(&0));
+        assert!(res.1.is_empty());
+        assert_eq!(res.4.len(), 1);
+
+        let res = exchange.tick(source.get_quotes_unchecked(&34200));
+        assert_eq!(res.1.len(), 1);
+        let res = exchange.tick(source.get_quotes_unchecked(&34201));
+        assert_eq!(res.0.len(), 1);
+    }
+
+    #[test]
+    fn test_that_slippage_is_applied_to_market_orders_only() {
+        let (source, mut exchange) = setup();
+        exchange.set_slippage(Some(Box::new(FixedBpsSlippage(100.0))));
+
+        exchange.insert_order(Order::market_buy("ABC", 100.0));
+        exchange.insert_order(Order::limit_sell("ABC", 100.0, 100.0));
+        exchange.tick(source.get_quotes_unchecked(&100));
+        let res = exchange.tick(source.get_quotes_unchecked(&101));
+
+        let sell = res
+            .0
+            .iter()
+            .find(|trade| trade.typ == TradeType::Sell)
+            .unwrap();
+        assert_eq!(sell.value, 102.0 * 100.0);
+        let buy = res
+            .0
+            .iter()
+            .find(|trade| trade.typ == TradeType::Buy)
+            .unwrap();
+        assert_eq!(buy.value, 103.0 * 1.01 * 100.0);
+    }
+
+    #[test]
+    fn test_that_market_impact_accumulates_over_fills_in_a_tick() {
+        let (source, mut exchange) = setup();
+        exchange.set_market_impact(Some(MarketImpact::new(
+            Box::new(AlmgrenImpact),
+            HashMap::from([("ABC".to_string(), 10_000.0)]),
+            HashMap::from([("ABC".to_string(), 0.02)]),
+        )));
+
+        exchange.insert_order(Order::market_buy("ABC", 100.0));
+        exchange.insert_order(Order::market_buy("ABC", 300.0));
+        exchange.insert_order(Order::limit_sell("ABC", 100.0, 100.0));
+        exchange.tick(source.get_quotes_unchecked(&100));
+        let res = exchange.tick(source.get_quotes_unchecked(&101));
+
+        let buys = res
+            .0
+            .iter()
+            .filter(|trade| trade.typ == TradeType::Buy)
+            .collect::<Vec<_>>();
+        //First fill has nothing traded before it, second is moved by 0.02 * sqrt(100 / 10_000)
+        assert_eq!(buys[0].value, 103.0 * 100.0);
+        assert_eq!(buys[1].value, 103.0 * 300.0 * 1.002);
+        //Limit orders execute at the quote
+        let sell = res
+            .0
+            .iter()
+            .find(|trade| trade.typ == TradeType::Sell)
+            .unwrap();
+        assert_eq!(sell.value, 102.0 * 100.0);
+    }
+
+    #[test]
+    fn test_that_policy_rounds_down_to_lot_and_drops_small_orders() {
+        let (source, _exchange) = setup();
+        let mut policy = ExchangePolicy::default();
+        policy.min_lot_size.insert("ABC".to_string(), 100.0);
+        policy.min_notional.insert("ABC".to_string(), 15_000.0);
+        let mut exchange = UistV1::with_policy(policy);
+
+        exchange.insert_order(Order::market_buy("ABC", 250.0));
+        //Rounds down to 100 shares, which is below the minimum value
+        exchange.insert_order(Order::market_buy("ABC", 150.0));
+        let res = exchange.tick(source.get_quotes_unchecked(&100));
+        assert_eq!(res.1.len(), 1);
+        assert_eq!(res.1[0].shares, 200.0);
+        assert_eq!(res.3.len(), 2);
+        assert_eq!(res.3[0].requested_qty, 250.0);
+        assert_eq!(res.3[1].order.shares, 0.0);
+        assert!(res.3[1].order.order_id.is_none());
+
+        let res = exchange.tick(source.get_quotes_unchecked(&101));
+        assert_eq!(res.0.len(), 1);
+        assert_eq!(res.0[0].quantity, 200.0);
+    }
 }
@@ -1,6 +1,12 @@
 use std::collections::HashMap;
-
-use crate::exchange::uist_v1::{Order, OrderId, Trade, UistV1};
+use std::sync::Arc;
+
+use crate::exchange::impact::MarketImpact;
+use crate::exchange::slippage::SlippageModel;
+use crate::exchange::uist_v1::{
+    CircuitBreaker, ExchangePolicy, ExchangePolicyAdjustment, Order, OrderId, SessionHours, Trade,
+    UistV1,
+};
 use crate::input::penelope::{Penelope, PenelopeQuoteByDate};
 
 type BacktestId = u64;
@@ -17,15 +23,100 @@ pub struct AppState {
     pub backtests: HashMap<BacktestId, BacktestState>,
     pub last: BacktestId,
     pub datasets: HashMap<String, Penelope>,
+    //Applied to the exchange of every backtest
+    pub policy: ExchangePolicy,
+    pub slippage: Option<Arc<dyn SlippageModel>>,
+    pub market_impact: Option<Arc<MarketImpact>>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    pub session_hours: Option<SessionHours>,
 }
 
+pub type TickResult = (
+    bool,
+    Vec<Trade>,
+    Vec<Order>,
+    Vec<Order>,
+    Vec<ExchangePolicyAdjustment>,
+    HashMap<String, usize>,
+    Vec<Order>,
+);
+
 impl AppState {
     pub fn create(datasets: &mut HashMap<String, Penelope>) -> Self {
         Self {
             backtests: HashMap::new(),
             last: 0,
             datasets: std::mem::take(datasets),
+            policy: ExchangePolicy::default(),
+            slippage: None,
+            market_impact: None,
+            circuit_breaker: None,
+            session_hours: None,
+        }
+    }
+
+    fn new_exchange(&self) -> UistV1 {
+        let mut exchange = UistV1::with_policy(self.policy.clone());
+        exchange.set_shared_slippage(self.slippage.clone());
+        exchange.set_shared_market_impact(self.market_impact.clone());
+        exchange.set_circuit_breaker(self.circuit_breaker.clone());
+        exchange.set_session_hours(self.session_hours.clone());
+        exchange
+    }
+
+    /// Sets the session hours on existing backtests and on any created later.
+    pub fn with_session_hours(mut self, session_hours: SessionHours) -> Self {
+        for backtest in self.backtests.values_mut() {
+            backtest
+                .exchange
+                .set_session_hours(Some(session_hours.clone()));
+        }
+        self.session_hours = Some(session_hours);
+        self
+    }
+
+    /// Sets the circuit breaker on existing backtests and on any created later.
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        for backtest in self.backtests.values_mut() {
+            backtest
+                .exchange
+                .set_circuit_breaker(Some(circuit_breaker.clone()));
+        }
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// Sets the policy on existing backtests and on any created later.
+    pub fn with_policy(mut self, policy: ExchangePolicy) -> Self {
+        for backtest in self.backtests.values_mut() {
+            backtest.exchange.set_policy(policy.clone());
+        }
+        self.policy = policy;
+        self
+    }
+
+    /// Sets the slippage model on existing backtests and on any created later.
+    pub fn with_slippage(mut self, slippage: Box<dyn SlippageModel>) -> Self {
+        let slippage: Arc<dyn SlippageModel> = Arc::from(slippage);
+        for backtest in self.backtests.values_mut() {
+            backtest
+                .exchange
+                .set_shared_slippage(Some(slippage.clone()));
         }
+        self.slippage = Some(slippage);
+        self
+    }
+
+    /// Sets the market impact model on existing backtests and on any created later.
+    pub fn with_market_impact(mut self, market_impact: MarketImpact) -> Self {
+        let market_impact = Arc::new(market_impact);
+        for backtest in self.backtests.values_mut() {
+            backtest
+                .exchange
+                .set_shared_market_impact(Some(market_impact.clone()));
+        }
+        self.market_impact = Some(market_impact);
+        self
     }
 
     pub fn single(name: &str, data: Penelope) -> Self {
@@ -48,21 +139,33 @@ impl AppState {
             backtests,
             last: 1,
             datasets,
+            policy: ExchangePolicy::default(),
+            slippage: None,
+            market_impact: None,
+            circuit_breaker: None,
+            session_hours: None,
         }
     }
 
-    pub fn tick(&mut self, backtest_id: BacktestId) -> Option<(bool, Vec<Trade>, Vec<Order>)> {
+    pub fn tick(&mut self, backtest_id: BacktestId) -> Option<TickResult> {
         if let Some(backtest) = self.backtests.get_mut(&backtest_id) {
             if let Some(dataset) = self.datasets.get(&backtest.dataset_name) {
                 let mut has_next = false;
                 let mut executed_trades = Vec::new();
                 let mut inserted_orders = Vec::new();
+                let mut expired_orders = Vec::new();
+                let mut policy_adjustments = Vec::new();
+                let mut after_hours_orders = Vec::new();
 
                 if let Some(quotes) = dataset.get_quotes(&backtest.date) {
                     let mut res = backtest.exchange.tick(quotes);
                     executed_trades.append(&mut res.0);
                     inserted_orders.append(&mut res.1);
+                    expired_orders.append(&mut res.2);
+                    policy_adjustments.append(&mut res.3);
+                    after_hours_orders.append(&mut res.4);
                 }
+                let halted_symbols = backtest.exchange.get_halted_symbols().clone();
 
                 let new_pos = backtest.pos + 1;
                 if dataset.has_next(new_pos) {
@@ -70,7 +173,15 @@ impl AppState {
                     backtest.date = *dataset.get_date(new_pos).unwrap();
                 }
                 backtest.pos = new_pos;
-                return Some((has_next, executed_trades, inserted_orders));
+                return Some((
+                    has_next,
+                    executed_trades,
+                    inserted_orders,
+                    expired_orders,
+                    policy_adjustments,
+                    halted_symbols,
+                    after_hours_orders,
+                ));
             }
         }
         None
@@ -88,7 +199,7 @@ impl AppState {
     pub fn init(&mut self, dataset_name: String) -> Option<BacktestId> {
         if let Some(dataset) = self.datasets.get(&dataset_name) {
             let new_id = self.last + 1;
-            let exchange = UistV1::new();
+            let exchange = self.new_exchange();
             let backtest = BacktestState {
                 id: new_id,
                 date: *dataset.get_date(0).unwrap(),
@@ -97,6 +208,7 @@ impl AppState {
                 dataset_name,
             };
             self.backtests.insert(new_id, backtest);
+            self.last = new_id;
             return Some(new_id);
         }
         None
@@ -118,12 +230,33 @@ impl AppState {
         None
     }
 
+    pub fn halt_symbol(
+        &mut self,
+        symbol: &str,
+        duration_ticks: usize,
+        backtest_id: BacktestId,
+    ) -> Option<()> {
+        if let Some(backtest) = self.backtests.get_mut(&backtest_id) {
+            backtest.exchange.halt_symbol(symbol, duration_ticks);
+            return Some(());
+        }
+        None
+    }
+
+    pub fn resume_symbol(&mut self, symbol: &str, backtest_id: BacktestId) -> Option<()> {
+        if let Some(backtest) = self.backtests.get_mut(&backtest_id) {
+            backtest.exchange.resume_symbol(symbol);
+            return Some(());
+        }
+        None
+    }
+
     pub fn new_backtest(&mut self, dataset_name: &str) -> Option<BacktestId> {
         let new_id = self.last + 1;
 
         // Check that dataset exists
         if let Some(dataset) = self.datasets.get(dataset_name) {
-            let exchange = UistV1::new();
+            let exchange = self.new_exchange();
 
             let backtest = BacktestState {
                 id: new_id,
@@ -146,8 +279,10 @@ pub mod uistv1_client {
 
     use std::future;
     use std::future::Future;
+    use std::sync::Arc;
 
     use anyhow::{Error, Result};
+    use tokio::sync::Mutex;
 
     use super::uistv1_server::{
         DeleteOrderRequest, FetchQuotesResponse, InfoResponse, InitResponse, InsertOrderRequest,
@@ -155,7 +290,7 @@ pub mod uistv1_client {
     };
     use super::AppState;
 
-    use crate::exchange::uist_v1::{Order, OrderId};
+    use crate::exchange::uist_v1::{ExchangePolicy, Order, OrderId};
     use crate::input::penelope::Penelope;
 
     pub type BacktestId = u64;
@@ -197,6 +332,10 @@ pub mod uistv1_client {
         fn tick(&mut self, backtest_id: BacktestId) -> impl Future<Output = Result<TickResponse>> {
             if let Some(resp) = self.state.tick(backtest_id) {
                 future::ready(Ok(TickResponse {
+                    after_hours_orders: resp.6,
+                    halted_symbols: resp.5,
+                    policy_adjustments: resp.4,
+                    expired_orders: resp.3,
                     inserted_orders: resp.2,
                     executed_trades: resp.1,
                     has_next: resp.0,
@@ -278,6 +417,50 @@ pub mod uistv1_client {
                 state: AppState::single(name, data),
             }
         }
+
+        pub fn single_with_policy(name: &str, data: Penelope, policy: ExchangePolicy) -> Self {
+            Self {
+                state: AppState::single(name, data).with_policy(policy),
+            }
+        }
+
+        /// Wraps a state that has already been configured, for example with a circuit breaker.
+        pub fn from_state(state: AppState) -> Self {
+            Self { state }
+        }
+    }
+
+    /// Allows one client, and the exchange behind it, to be shared by multiple brokers. Each
+    /// broker should call `init` to get a separate backtest, the lock is only held for the
+    /// duration of each call.
+    impl<C: UistClient> UistClient for Arc<Mutex<C>> {
+        async fn tick(&mut self, backtest_id: BacktestId) -> Result<TickResponse> {
+            self.lock().await.tick(backtest_id).await
+        }
+
+        async fn delete_order(&mut self, order_id: OrderId, backtest_id: BacktestId) -> Result<()> {
+            self.lock().await.delete_order(order_id, backtest_id).await
+        }
+
+        async fn insert_order(&mut self, order: Order, backtest_id: BacktestId) -> Result<()> {
+            self.lock().await.insert_order(order, backtest_id).await
+        }
+
+        async fn fetch_quotes(&mut self, backtest_id: BacktestId) -> Result<FetchQuotesResponse> {
+            self.lock().await.fetch_quotes(backtest_id).await
+        }
+
+        async fn init(&mut self, dataset_name: String) -> Result<InitResponse> {
+            self.lock().await.init(dataset_name).await
+        }
+
+        async fn info(&mut self, backtest_id: BacktestId) -> Result<InfoResponse> {
+            self.lock().await.info(backtest_id).await
+        }
+
+        async fn now(&mut self, backtest_id: BacktestId) -> Result<NowResponse> {
+            self.lock().await.now(backtest_id).await
+        }
     }
 
     #[derive(Debug)]
@@ -375,11 +558,11 @@ pub mod uistv1_client {
 pub mod uistv1_server {
     use core::fmt;
     use serde::{Deserialize, Serialize};
-    use std::{error::Error, sync::Mutex};
+    use std::{collections::HashMap, error::Error, sync::Mutex};
 
-    use crate::exchange::uist_v1::{Order, OrderId, Trade};
+    use crate::exchange::uist_v1::{ExchangePolicyAdjustment, Order, OrderId, Trade};
     use crate::input::penelope::PenelopeQuoteByDate;
-    use actix_web::{get, post, web, ResponseError};
+    use actix_web::{get, post, web, HttpResponse, ResponseError};
 
     use super::{AppState, BacktestId};
 
@@ -416,6 +599,18 @@ pub mod uistv1_server {
         pub has_next: bool,
         pub executed_trades: Vec<Trade>,
         pub inserted_orders: Vec<Order>,
+        //Defaults so that responses from servers without order expiry can still be read
+        #[serde(default)]
+        pub expired_orders: Vec<Order>,
+        #[serde(default)]
+        pub policy_adjustments: Vec<ExchangePolicyAdjustment>,
+        //Symbols that can't trade, with the number of ticks until trading resumes
+        #[serde(default)]
+        pub halted_symbols: HashMap<String, usize>,
+        //Orders received outside the session, these are returned in `inserted_orders` once the
+        //session opens
+        #[serde(default)]
+        pub after_hours_orders: Vec<Order>,
     }
 
     #[get("/backtest/{backtest_id}/tick")]
@@ -428,6 +623,10 @@ pub mod uistv1_server {
 
         if let Some(result) = uist.tick(backtest_id) {
             Ok(web::Json(TickResponse {
+                after_hours_orders: result.6,
+                halted_symbols: result.5,
+                policy_adjustments: result.4,
+                expired_orders: result.3,
                 inserted_orders: result.2,
                 executed_trades: result.1,
                 has_next: result.0,
@@ -458,6 +657,49 @@ pub mod uistv1_server {
         }
     }
 
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct HaltRequest {
+        pub symbol: String,
+        pub duration_ticks: usize,
+    }
+
+    #[post("/backtest/{backtest_id}/halt")]
+    pub async fn halt(
+        app: web::Data<UistState>,
+        path: web::Path<(BacktestId,)>,
+        halt: web::Json<HaltRequest>,
+    ) -> Result<web::Json<()>, UistV1Error> {
+        let mut uist = app.lock().unwrap();
+        let (backtest_id,) = path.into_inner();
+
+        if let Some(()) = uist.halt_symbol(&halt.symbol, halt.duration_ticks, backtest_id) {
+            Ok(web::Json(()))
+        } else {
+            Err(UistV1Error::UnknownBacktest)
+        }
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ResumeRequest {
+        pub symbol: String,
+    }
+
+    #[post("/backtest/{backtest_id}/resume")]
+    pub async fn resume(
+        app: web::Data<UistState>,
+        path: web::Path<(BacktestId,)>,
+        resume: web::Json<ResumeRequest>,
+    ) -> Result<web::Json<()>, UistV1Error> {
+        let mut uist = app.lock().unwrap();
+        let (backtest_id,) = path.into_inner();
+
+        if let Some(()) = uist.resume_symbol(&resume.symbol, backtest_id) {
+            Ok(web::Json(()))
+        } else {
+            Err(UistV1Error::UnknownBacktest)
+        }
+    }
+
     #[derive(Debug, Deserialize, Serialize)]
     pub struct InsertOrderRequest {
         pub order: Order,
@@ -573,6 +815,13 @@ pub mod uistv1_server {
             Err(UistV1Error::UnknownBacktest)
         }
     }
+
+    #[get("/metrics")]
+    pub async fn metrics() -> HttpResponse {
+        HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(crate::metrics::gather())
+    }
 }
 
 #[cfg(test)]
@@ -603,7 +852,9 @@ mod tests {
                 .service(fetch_quotes)
                 .service(tick)
                 .service(insert_order)
-                .service(delete_order),
+                .service(delete_order)
+                .service(halt)
+                .service(resume),
         )
         .await;
 
@@ -643,6 +894,66 @@ mod tests {
         let resp5: TickResponse = test::call_and_read_body_json(&app, req5).await;
 
         assert!(resp5.executed_trades.len() == 1);
-        assert!(resp5.executed_trades.first().unwrap().symbol == "ABC")
+        assert!(resp5.executed_trades.first().unwrap().symbol == "ABC");
+
+        let req6 = test::TestRequest::post()
+            .set_json(HaltRequest {
+                symbol: "ABC".to_string(),
+                duration_ticks: 10,
+            })
+            .uri(format!("/backtest/{backtest_id}/halt").as_str())
+            .to_request();
+        test::call_and_read_body(&app, req6).await;
+
+        let req7 = test::TestRequest::get()
+            .uri(format!("/backtest/{backtest_id}/tick").as_str())
+            .to_request();
+        let resp7: TickResponse = test::call_and_read_body_json(&app, req7).await;
+        assert_eq!(resp7.halted_symbols.get("ABC"), Some(&9));
+
+        let req8 = test::TestRequest::post()
+            .set_json(ResumeRequest {
+                symbol: "ABC".to_string(),
+            })
+            .uri(format!("/backtest/{backtest_id}/resume").as_str())
+            .to_request();
+        test::call_and_read_body(&app, req8).await;
+
+        let req9 = test::TestRequest::get()
+            .uri(format!("/backtest/{backtest_id}/tick").as_str())
+            .to_request();
+        let resp9: TickResponse = test::call_and_read_body_json(&app, req9).await;
+        assert!(resp9.halted_symbols.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_are_returned_after_tick() {
+        let uist = Penelope::random(10, vec!["ABC"]);
+        let state = AppState::single("fake", uist);
+        let uist_state = web::Data::new(Mutex::new(state));
+
+        let app = test::init_service(
+            App::new()
+                .app_data(uist_state)
+                .service(init)
+                .service(tick)
+                .service(metrics),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/init/fake").to_request();
+        let resp: InitResponse = test::call_and_read_body_json(&app, req).await;
+        let backtest_id = resp.backtest_id;
+
+        let req1 = test::TestRequest::get()
+            .uri(format!("/backtest/{backtest_id}/tick").as_str())
+            .to_request();
+        let _resp1: TickResponse = test::call_and_read_body_json(&app, req1).await;
+
+        let req2 = test::TestRequest::get().uri("/metrics").to_request();
+        let body = test::call_and_read_body(&app, req2).await;
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("tick_duration_seconds_count"));
+        assert!(text.contains("pending_orders"));
     }
 }
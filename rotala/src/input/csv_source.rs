@@ -0,0 +1,190 @@
+//! Reads quotes from a CSV file on demand rather than holding the full dataset in memory.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+use csv::{Position, StringRecord};
+
+use super::penelope::{Penelope, PenelopeQuote, PenelopeQuoteByDate};
+use crate::source::DataLoadError;
+
+/// Quotes in a CSV file with columns `timestamp,symbol,bid,ask`, timestamp is unix seconds. The
+/// file must have a header row and rows with the same timestamp must be contiguous and in date
+/// order.
+///
+/// The file is indexed on creation by storing the byte offset of the first row for each
+/// timestamp, quotes are only parsed when requested. Exchanges run on [Penelope] so a window of
+/// dates can be loaded with [CsvQuoteSource::to_penelope].
+pub struct CsvQuoteSource {
+    reader: csv::Reader<File>,
+    index: BTreeMap<i64, u64>,
+}
+
+impl CsvQuoteSource {
+    pub fn from_path(path: &Path) -> Result<Self, DataLoadError> {
+        let mut reader =
+            csv::Reader::from_path(path).map_err(|e| DataLoadError::Io(e.to_string()))?;
+        //Headers are read lazily so the first offset would point at the header row
+        reader
+            .headers()
+            .map_err(|e| DataLoadError::Parse(e.to_string()))?;
+
+        let mut index = BTreeMap::new();
+        let mut last_date = None;
+        let mut record = StringRecord::new();
+        loop {
+            let offset = reader.position().byte();
+            let has_record = reader
+                .read_record(&mut record)
+                .map_err(|e| DataLoadError::Parse(e.to_string()))?;
+            if !has_record {
+                break;
+            }
+            let date = Self::parse_date(&record)?;
+            if last_date == Some(date) {
+                continue;
+            }
+            if last_date.is_some_and(|last| last > date) {
+                return Err(DataLoadError::Parse(format!(
+                    "Timestamps out of order at {:?}",
+                    record
+                )));
+            }
+            index.insert(date, offset);
+            last_date = Some(date);
+        }
+
+        Ok(Self { reader, index })
+    }
+
+    fn parse_date(record: &StringRecord) -> Result<i64, DataLoadError> {
+        if record.len() < 4 {
+            return Err(DataLoadError::Parse(format!(
+                "Expected 4 columns: {:?}",
+                record
+            )));
+        }
+        record[0]
+            .parse::<i64>()
+            .map_err(|e| DataLoadError::Parse(format!("{}: {}", &record[0], e)))
+    }
+
+    fn parse_quote(record: &StringRecord, date: i64) -> Result<PenelopeQuote, DataLoadError> {
+        let parse = |pos: usize| -> Result<f64, DataLoadError> {
+            record[pos]
+                .parse::<f64>()
+                .map_err(|e| DataLoadError::Parse(format!("{}: {}", &record[pos], e)))
+        };
+        Ok(PenelopeQuote {
+            bid: parse(2)?,
+            ask: parse(3)?,
+            symbol: record[1].to_string(),
+            date,
+            volume: None,
+            open: None,
+            high: None,
+            low: None,
+        })
+    }
+
+    pub fn get_dates(&self) -> Vec<i64> {
+        self.index.keys().copied().collect()
+    }
+
+    /// Seeks to the first row for `date` and reads until the timestamp changes.
+    pub fn get_quotes(&mut self, date: &i64) -> Result<Option<PenelopeQuoteByDate>, DataLoadError> {
+        let Some(offset) = self.index.get(date) else {
+            return Ok(None);
+        };
+
+        let mut position = Position::new();
+        position.set_byte(*offset);
+        self.reader
+            .seek(position)
+            .map_err(|e| DataLoadError::Io(e.to_string()))?;
+
+        let mut quotes = PenelopeQuoteByDate::new();
+        let mut record = StringRecord::new();
+        while self
+            .reader
+            .read_record(&mut record)
+            .map_err(|e| DataLoadError::Parse(e.to_string()))?
+        {
+            if Self::parse_date(&record)? != *date {
+                break;
+            }
+            let quote = Self::parse_quote(&record, *date)?;
+            quotes.insert(quote.symbol.clone(), quote);
+        }
+        Ok(Some(quotes))
+    }
+
+    /// Loads quotes with dates in `start..=end`.
+    pub fn to_penelope(&mut self, start: i64, end: i64) -> Result<Penelope, DataLoadError> {
+        let dates = self
+            .index
+            .range(start..=end)
+            .map(|(date, _offset)| *date)
+            .collect::<Vec<_>>();
+
+        let mut penelope = Penelope::new();
+        for date in dates {
+            let mut quotes = self
+                .get_quotes(&date)?
+                .unwrap_or_default()
+                .into_values()
+                .collect::<Vec<_>>();
+            quotes.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+            for quote in quotes {
+                penelope.add_quote(quote.bid, quote.ask, quote.date, quote.symbol);
+            }
+        }
+        Ok(penelope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CsvQuoteSource;
+
+    #[test]
+    fn test_that_csv_source_reads_quotes_by_date() {
+        //Process id keeps concurrent runs from sharing the file
+        let path = std::env::temp_dir().join(format!(
+            "rotala_test_that_csv_source_reads_quotes_by_date_{}.csv",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "timestamp,symbol,bid,ask\n\
+             100,ABC,100.0,101.0\n\
+             100,BCD,10.0,11.0\n\
+             101,ABC,102.0,103.0\n\
+             102,ABC,104.0,105.0\n\
+             102,BCD,12.0,13.0\n",
+        )
+        .unwrap();
+
+        let mut source = CsvQuoteSource::from_path(&path).unwrap();
+        assert_eq!(source.get_dates(), vec![100, 101, 102]);
+
+        let quotes = source.get_quotes(&102).unwrap().unwrap();
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(quotes.get("BCD").unwrap().bid, 12.0);
+        //Reads can go backwards
+        let quotes = source.get_quotes(&101).unwrap().unwrap();
+        assert_eq!(quotes.len(), 1);
+        assert_eq!(quotes.get("ABC").unwrap().ask, 103.0);
+        assert!(source.get_quotes(&103).unwrap().is_none());
+        let quotes = source.get_quotes(&100).unwrap().unwrap();
+        assert_eq!(quotes.len(), 2);
+
+        let penelope = source.to_penelope(101, 102).unwrap();
+        assert_eq!(penelope.get_date(0), Some(&101));
+        assert_eq!(penelope.get_quotes(&102).unwrap().len(), 2);
+        assert!(penelope.get_quotes(&100).is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -6,4 +6,6 @@
 //!
 //! Sources should be called through inputs so that clients do not have to marshall data into internal
 //! types.
+pub mod csv_source;
 pub mod penelope;
+pub mod resampler;
@@ -1,10 +1,13 @@
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
 
 use rand::thread_rng;
 use rand_distr::{Distribution, Uniform};
 use serde::{Deserialize, Serialize};
 
-use crate::source::get_binance_1m_klines;
+use crate::source::{get_binance_1m_klines, get_yfinance_csv, DataLoadError};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PenelopeQuote {
@@ -12,10 +15,79 @@ pub struct PenelopeQuote {
     pub ask: f64,
     pub symbol: String,
     pub date: i64,
+    //Not all sources have volume, omitted from JSON when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<f64>,
+    //Only set for quotes added from candles, close is the bid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub high: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low: Option<f64>,
+}
+
+impl PenelopeQuote {
+    /// Rebuilds the candle for quotes added with [Penelope::add_ohlcv], `None` for quotes that
+    /// were added without open/high/low/volume.
+    pub fn to_ohlcv(&self) -> Option<OhlcvQuote> {
+        Some(OhlcvQuote {
+            open: self.open?,
+            high: self.high?,
+            low: self.low?,
+            close: self.bid,
+            volume: self.volume?,
+            date: self.date,
+            symbol: self.symbol.clone(),
+        })
+    }
 }
 
 pub type PenelopeQuoteByDate = HashMap<String, PenelopeQuote>;
 
+/// Spread used to derive an ask from the close of an [OhlcvQuote], as a fraction of the close.
+pub const DEFAULT_OHLCV_SPREAD: f64 = 0.001;
+
+/// Candlestick for one period. Exchanges only trade on bid/ask so the close is the bid and the ask
+/// is the close plus [DEFAULT_OHLCV_SPREAD].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OhlcvQuote {
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    pub date: i64,
+    pub symbol: String,
+}
+
+/// Adjustment factors keyed by symbol, each entry is `(date, ratio)`. Quotes before `date` are
+/// divided by `ratio` so a 2-for-1 split is `(split_date, 2.0)`.
+///
+/// [PriceAdjustment::FullyAdjusted] is intended for factors that also include dividends but the
+/// factors are applied in the same way.
+#[derive(Clone, Debug)]
+pub enum PriceAdjustment {
+    None,
+    SplitAdjusted(HashMap<String, Vec<(i64, f64)>>),
+    FullyAdjusted(HashMap<String, Vec<(i64, f64)>>),
+}
+
+#[derive(Debug)]
+pub enum PenelopeError {
+    InvalidJson(String),
+}
+
+impl Error for PenelopeError {}
+
+impl fmt::Display for PenelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PenelopeError::InvalidJson(err) => write!(f, "InvalidJson: {err}"),
+        }
+    }
+}
+
 // Penelope produces data for exchanges to use. Exchanges bind their underlying data representation
 // to that used by Penelope: `PenelopeQuote`.
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -37,10 +109,36 @@ impl Penelope {
         self.dates.get(pos)
     }
 
+    pub fn get_position(&self, date: &i64) -> Option<usize> {
+        self.dates.iter().position(|d| d == date)
+    }
+
     pub fn has_next(&self, pos: usize) -> bool {
         self.dates.len() > pos
     }
 
+    /// Number of dates with quotes.
+    pub fn len(&self) -> usize {
+        self.dates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dates.is_empty()
+    }
+
+    /// Copy of the quotes on the dates at positions `start..end`, positions past the last date are
+    /// ignored.
+    pub fn slice(&self, start: usize, end: usize) -> Penelope {
+        let mut source = Penelope::new();
+        for date in self.dates.iter().take(end).skip(start) {
+            if let Some(quotes) = self.inner.get(date) {
+                source.dates.push(*date);
+                source.inner.insert(*date, quotes.clone());
+            }
+        }
+        source
+    }
+
     pub fn new() -> Self {
         Self {
             dates: Vec::new(),
@@ -58,14 +156,203 @@ impl Penelope {
         penelope
     }
 
+    /// Parses a flat array of quotes: `[{"symbol": "ABC", "date": 100, "bid": 99.0, "ask": 101.0}]`.
+    /// Quotes do not need to be sorted by date.
+    pub fn from_json(json: &str) -> Result<Self, PenelopeError> {
+        let mut quotes: Vec<PenelopeQuote> =
+            serde_json::from_str(json).map_err(|e| PenelopeError::InvalidJson(e.to_string()))?;
+        //Stable sort so that a later quote for the same symbol and date still overwrites
+        quotes.sort_by_key(|quote| quote.date);
+
+        let mut penelope = Self::new();
+        for quote in quotes {
+            penelope.insert_quote(quote);
+        }
+        Ok(penelope)
+    }
+
+    /// Writes quotes in the format read by [Penelope::from_json], ordered by date then symbol.
+    pub fn to_json(&self) -> String {
+        let mut quotes = Vec::new();
+        for date in &self.dates {
+            if let Some(date_row) = self.inner.get(date) {
+                let mut row = date_row.values().collect::<Vec<&PenelopeQuote>>();
+                row.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+                quotes.extend(row);
+            }
+        }
+        //Serializing a Vec of plain structs cannot fail
+        serde_json::to_string(&quotes).unwrap()
+    }
+
+    /// Loads a Yahoo Finance CSV export for one symbol. `Adj Close` is used as the mid price and
+    /// `spread` is the bid-ask spread as a fraction of that price.
+    pub fn from_yfinance_csv(
+        path: &Path,
+        symbol: &str,
+        spread: f64,
+    ) -> Result<Self, DataLoadError> {
+        let mut penelope = Self::new();
+        for quote in get_yfinance_csv(path)? {
+            let half_spread = quote.adj_close * spread / 2.0;
+            penelope.add_quote_with_volume(
+                quote.adj_close - half_spread,
+                quote.adj_close + half_spread,
+                quote.date,
+                symbol,
+                quote.volume,
+            );
+        }
+        Ok(penelope)
+    }
+
+    /// Loads every `.csv` file in `dir` with [Penelope::from_yfinance_csv] using the file name,
+    /// without extension, as the symbol.
+    pub fn from_yfinance_csv_directory(dir: &Path, spread: f64) -> Result<Self, DataLoadError> {
+        let mut paths = std::fs::read_dir(dir)
+            .map_err(|e| DataLoadError::Io(e.to_string()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "csv"))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        //Quotes have to be inserted in date order but files can have different dates
+        let mut quotes = Vec::new();
+        for path in paths {
+            let symbol = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or_else(|| DataLoadError::Io(format!("Invalid file name: {:?}", path)))?
+                .to_string();
+            for quote in get_yfinance_csv(&path)? {
+                quotes.push((symbol.clone(), quote));
+            }
+        }
+        quotes.sort_by_key(|(_symbol, quote)| quote.date);
+
+        let mut penelope = Self::new();
+        for (symbol, quote) in quotes {
+            let half_spread = quote.adj_close * spread / 2.0;
+            penelope.add_quote_with_volume(
+                quote.adj_close - half_spread,
+                quote.adj_close + half_spread,
+                quote.date,
+                symbol,
+                quote.volume,
+            );
+        }
+        Ok(penelope)
+    }
+
+    /// Applies adjustment retroactively to quotes already held, so should be called after all
+    /// quotes are added. Multiple factors for a symbol are cumulative.
+    pub fn with_price_adjustment(&mut self, adjustment: PriceAdjustment) -> &mut Self {
+        let factors = match adjustment {
+            PriceAdjustment::None => return self,
+            PriceAdjustment::SplitAdjusted(factors) => factors,
+            PriceAdjustment::FullyAdjusted(factors) => factors,
+        };
+
+        for (symbol, symbol_factors) in factors {
+            for (adjustment_date, ratio) in symbol_factors {
+                for (date, date_row) in self.inner.iter_mut() {
+                    if *date >= adjustment_date {
+                        continue;
+                    }
+                    if let Some(quote) = date_row.get_mut(&symbol) {
+                        quote.bid /= ratio;
+                        quote.ask /= ratio;
+                        for price in [&mut quote.open, &mut quote.high, &mut quote.low]
+                            .into_iter()
+                            .flatten()
+                        {
+                            *price /= ratio;
+                        }
+                    }
+                }
+            }
+        }
+        self
+    }
+
+    /// Fills dates where a symbol has no quote with the most recent prior quote, so orders can
+    /// execute on dates like bank holidays. Filled quotes are dated at the missing date. If
+    /// `max_staleness` is set then quotes older than that many seconds are not used.
+    ///
+    /// Applies to quotes already held, so should be called after all quotes are added.
+    pub fn with_forward_fill(&mut self, max_staleness: Option<i64>) -> &mut Self {
+        let mut last_quotes: HashMap<String, PenelopeQuote> = HashMap::new();
+        for date in &self.dates {
+            let date_row = self.inner.get_mut(date).unwrap();
+            for (symbol, quote) in date_row.iter() {
+                last_quotes.insert(symbol.clone(), quote.clone());
+            }
+            for (symbol, last) in &last_quotes {
+                if date_row.contains_key(symbol) {
+                    continue;
+                }
+                if let Some(max) = max_staleness {
+                    if date - last.date > max {
+                        continue;
+                    }
+                }
+                let mut filled = last.clone();
+                filled.date = *date;
+                date_row.insert(symbol.clone(), filled);
+            }
+        }
+        self
+    }
+
     pub fn add_quote(&mut self, bid: f64, ask: f64, date: i64, symbol: impl Into<String> + Clone) {
-        //Inserts should be in sorted order
-        let quote = PenelopeQuote {
+        self.insert_quote(PenelopeQuote {
             bid,
             ask,
             date,
             symbol: symbol.into(),
-        };
+            volume: None,
+            open: None,
+            high: None,
+            low: None,
+        });
+    }
+
+    pub fn add_quote_with_volume(
+        &mut self,
+        bid: f64,
+        ask: f64,
+        date: i64,
+        symbol: impl Into<String> + Clone,
+        volume: f64,
+    ) {
+        self.insert_quote(PenelopeQuote {
+            bid,
+            ask,
+            date,
+            symbol: symbol.into(),
+            volume: Some(volume),
+            open: None,
+            high: None,
+            low: None,
+        });
+    }
+
+    pub fn add_ohlcv(&mut self, quote: &OhlcvQuote) {
+        self.insert_quote(PenelopeQuote {
+            bid: quote.close,
+            ask: quote.close * (1.0 + DEFAULT_OHLCV_SPREAD),
+            date: quote.date,
+            symbol: quote.symbol.clone(),
+            volume: Some(quote.volume),
+            open: Some(quote.open),
+            high: Some(quote.high),
+            low: Some(quote.low),
+        });
+    }
+
+    fn insert_quote(&mut self, quote: PenelopeQuote) {
+        //Inserts should be in sorted order
+        let date = quote.date;
 
         if let Some(date_row) = self.inner.get_mut(&date) {
             date_row.insert(quote.symbol.clone(), quote);
@@ -102,3 +389,167 @@ impl Default for Penelope {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{OhlcvQuote, Penelope, PriceAdjustment, DEFAULT_OHLCV_SPREAD};
+
+    #[test]
+    fn test_that_slice_copies_dates_in_range() {
+        let source = Penelope::random(10, vec!["ABC", "BCD"]);
+        let slice = source.slice(2, 5);
+        assert_eq!(slice.len(), 3);
+        assert_eq!(slice.get_date(0), Some(&102));
+        assert_eq!(slice.get_date(2), Some(&104));
+        assert_eq!(
+            slice.get_quotes(&103).unwrap().get("ABC").unwrap().bid,
+            source.get_quotes(&103).unwrap().get("ABC").unwrap().bid
+        );
+
+        assert_eq!(source.slice(8, 20).len(), 2);
+        assert!(source.slice(20, 30).is_empty());
+    }
+
+    #[test]
+    fn test_that_split_adjustment_changes_prior_prices() {
+        let mut source = Penelope::new();
+        source.add_quote(100.0, 102.0, 100, "ABC");
+        source.add_quote(50.0, 51.0, 100, "BCD");
+        source.add_quote(50.0, 51.0, 101, "ABC");
+        source.add_quote(52.0, 53.0, 102, "ABC");
+
+        let mut factors = HashMap::new();
+        factors.insert("ABC".to_string(), vec![(101, 2.0)]);
+        source.with_price_adjustment(PriceAdjustment::SplitAdjusted(factors));
+
+        let before = source.get_quotes(&100).unwrap().get("ABC").unwrap();
+        assert_eq!(before.bid, 50.0);
+        assert_eq!(before.ask, 51.0);
+
+        let after = source.get_quotes(&101).unwrap().get("ABC").unwrap();
+        assert_eq!(after.bid, 50.0);
+        let after = source.get_quotes(&102).unwrap().get("ABC").unwrap();
+        assert_eq!(after.bid, 52.0);
+
+        //Other symbols are unaffected
+        assert_eq!(
+            source.get_quotes(&100).unwrap().get("BCD").unwrap().bid,
+            50.0
+        );
+    }
+
+    #[test]
+    fn test_that_adjustments_are_cumulative() {
+        let mut source = Penelope::new();
+        source.add_quote(400.0, 400.0, 100, "ABC");
+        source.add_quote(200.0, 200.0, 101, "ABC");
+        source.add_quote(100.0, 100.0, 102, "ABC");
+
+        let mut factors = HashMap::new();
+        factors.insert("ABC".to_string(), vec![(101, 2.0), (102, 2.0)]);
+        source.with_price_adjustment(PriceAdjustment::FullyAdjusted(factors));
+
+        assert_eq!(
+            source.get_quotes(&100).unwrap().get("ABC").unwrap().bid,
+            100.0
+        );
+        assert_eq!(
+            source.get_quotes(&101).unwrap().get("ABC").unwrap().bid,
+            100.0
+        );
+        assert_eq!(
+            source.get_quotes(&102).unwrap().get("ABC").unwrap().bid,
+            100.0
+        );
+    }
+
+    #[test]
+    fn test_that_ohlcv_quotes_trade_at_close() {
+        let mut source = Penelope::new();
+        source.add_ohlcv(&OhlcvQuote {
+            open: 98.0,
+            high: 105.0,
+            low: 97.0,
+            close: 100.0,
+            volume: 1_000.0,
+            date: 100,
+            symbol: "ABC".to_string(),
+        });
+
+        let quote = source.get_quotes(&100).unwrap().get("ABC").unwrap();
+        assert_eq!(quote.bid, 100.0);
+        assert_eq!(quote.ask, 100.0 * (1.0 + DEFAULT_OHLCV_SPREAD));
+        assert_eq!(quote.volume, Some(1_000.0));
+
+        let candle = quote.to_ohlcv().unwrap();
+        assert_eq!(candle.open, 98.0);
+        assert_eq!(candle.high, 105.0);
+        assert_eq!(candle.low, 97.0);
+        assert_eq!(candle.close, 100.0);
+        assert_eq!(candle.volume, 1_000.0);
+    }
+
+    #[test]
+    fn test_that_forward_fill_uses_last_quote_within_staleness() {
+        let build = || {
+            let mut source = Penelope::new();
+            for date in 100..104 {
+                source.add_quote(100.0, 101.0, date, "ABC");
+            }
+            source.add_quote(10.0, 11.0, 100, "BCD");
+            source.add_quote(12.0, 13.0, 103, "BCD");
+            source
+        };
+
+        let mut source = build();
+        source.with_forward_fill(None);
+        let filled = source.get_quotes(&102).unwrap().get("BCD").unwrap();
+        assert_eq!(filled.bid, 10.0);
+        assert_eq!(filled.date, 102);
+        assert_eq!(
+            source.get_quotes(&103).unwrap().get("BCD").unwrap().bid,
+            12.0
+        );
+
+        let mut source = build();
+        source.with_forward_fill(Some(1));
+        assert!(source.get_quotes(&101).unwrap().contains_key("BCD"));
+        assert!(!source.get_quotes(&102).unwrap().contains_key("BCD"));
+    }
+
+    #[test]
+    fn test_that_json_round_trip_preserves_quotes() {
+        let mut source = Penelope::new();
+        source.add_quote(99.0, 101.0, 100, "ABC");
+        source.add_quote(9.0, 11.0, 100, "BCD");
+        source.add_quote(102.0, 103.0, 101, "ABC");
+
+        let json = source.to_json();
+        let parsed = Penelope::from_json(&json).unwrap();
+
+        assert_eq!(parsed.dates, vec![100, 101]);
+        let quote = parsed.get_quotes(&100).unwrap().get("BCD").unwrap();
+        assert_eq!(quote.bid, 9.0);
+        assert_eq!(quote.ask, 11.0);
+        assert_eq!(parsed.get_quotes(&101).unwrap().len(), 1);
+        assert_eq!(parsed.to_json(), json);
+    }
+
+    #[test]
+    fn test_that_json_handles_unsorted_and_empty_input() {
+        let json = r#"[
+            {"symbol": "ABC", "date": 101, "bid": 100.0, "ask": 102.0},
+            {"symbol": "ABC", "date": 100, "bid": 99.0, "ask": 101.0}
+        ]"#;
+        let parsed = Penelope::from_json(json).unwrap();
+        assert_eq!(parsed.dates, vec![100, 101]);
+
+        let empty = Penelope::from_json("[]").unwrap();
+        assert!(empty.dates.is_empty());
+        assert_eq!(empty.to_json(), "[]");
+
+        assert!(Penelope::from_json("{}").is_err());
+    }
+}
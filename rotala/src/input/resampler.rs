@@ -0,0 +1,133 @@
+//! Converts high-frequency [Penelope] data into lower-frequency snapshots.
+
+use std::collections::HashMap;
+
+use super::penelope::{Penelope, PenelopeQuote};
+
+/// Periods are aligned to epoch so `Daily` periods start at midnight UTC.
+#[derive(Clone, Debug)]
+pub enum Frequency {
+    Second,
+    Minute,
+    Hour,
+    Daily,
+}
+
+impl Frequency {
+    pub fn seconds(&self) -> i64 {
+        match self {
+            Frequency::Second => 1,
+            Frequency::Minute => 60,
+            Frequency::Hour => 3600,
+            Frequency::Daily => 86400,
+        }
+    }
+}
+
+pub struct TickDataResampler {
+    pub target_frequency: Frequency,
+}
+
+impl TickDataResampler {
+    pub fn new(target_frequency: Frequency) -> Self {
+        Self { target_frequency }
+    }
+
+    /// Takes the last quote for each symbol in each period. Output quotes are dated at the last
+    /// date seen in the period, so every symbol in a period shares a date.
+    pub fn resample_quotes(&self, source: &Penelope) -> Penelope {
+        let period_length = self.target_frequency.seconds();
+
+        let mut periods: Vec<(i64, HashMap<String, PenelopeQuote>)> = Vec::new();
+        let mut pos = 0;
+        while let Some(date) = source.get_date(pos) {
+            let period = date.div_euclid(period_length);
+            if periods.last().map(|(last, _)| *last) != Some(period) {
+                periods.push((period, HashMap::new()));
+            }
+            let (_, latest) = periods.last_mut().unwrap();
+            if let Some(quotes) = source.get_quotes(date) {
+                for (symbol, quote) in quotes {
+                    //Volume is summed over the period
+                    let mut quote = quote.clone();
+                    if let Some(prev) = latest.get(symbol).and_then(|prev| prev.volume) {
+                        quote.volume = Some(prev + quote.volume.unwrap_or(0.0));
+                    }
+                    latest.insert(symbol.clone(), quote);
+                }
+            }
+            pos += 1;
+        }
+
+        let mut resampled = Penelope::new();
+        for (_period, quotes) in periods {
+            let last_date = quotes.values().map(|quote| quote.date).max().unwrap();
+            let mut symbols = quotes.keys().collect::<Vec<_>>();
+            symbols.sort();
+            for symbol in symbols {
+                let quote = quotes.get(symbol).unwrap();
+                match quote.volume {
+                    Some(volume) => resampled.add_quote_with_volume(
+                        quote.bid,
+                        quote.ask,
+                        last_date,
+                        symbol.clone(),
+                        volume,
+                    ),
+                    None => resampled.add_quote(quote.bid, quote.ask, last_date, symbol.clone()),
+                }
+            }
+        }
+        resampled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frequency, TickDataResampler};
+    use crate::input::penelope::Penelope;
+
+    #[test]
+    fn test_that_daily_resampling_of_second_data_has_one_period_per_day() {
+        let mut source = Penelope::new();
+        for date in 0..(3 * 86400) {
+            source.add_quote(date as f64, date as f64 + 1.0, date, "ABC");
+        }
+
+        let resampled = TickDataResampler::new(Frequency::Daily).resample_quotes(&source);
+        assert_eq!(resampled.get_date(2), Some(&(3 * 86400 - 1)));
+        assert!(resampled.get_date(3).is_none());
+
+        let last = resampled.get_quotes(&86399).unwrap().get("ABC").unwrap();
+        assert_eq!(last.bid, 86399.0);
+    }
+
+    #[test]
+    fn test_that_resampling_keeps_last_quote_of_each_symbol() {
+        let mut source = Penelope::new();
+        source.add_quote(100.0, 101.0, 0, "ABC");
+        source.add_quote(50.0, 51.0, 0, "BCD");
+        source.add_quote(102.0, 103.0, 30, "ABC");
+        source.add_quote(104.0, 105.0, 60, "ABC");
+
+        let resampled = TickDataResampler::new(Frequency::Minute).resample_quotes(&source);
+        let first = resampled.get_quotes(&30).unwrap();
+        assert_eq!(first.get("ABC").unwrap().bid, 102.0);
+        assert_eq!(first.get("BCD").unwrap().bid, 50.0);
+        assert_eq!(resampled.get_quotes(&60).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_that_resampling_sums_volume_over_period() {
+        let mut source = Penelope::new();
+        source.add_quote_with_volume(100.0, 101.0, 0, "ABC", 10.0);
+        source.add_quote_with_volume(102.0, 103.0, 30, "ABC", 15.0);
+        source.add_quote_with_volume(104.0, 105.0, 60, "ABC", 20.0);
+
+        let resampled = TickDataResampler::new(Frequency::Minute).resample_quotes(&source);
+        let first = resampled.get_quotes(&30).unwrap().get("ABC").unwrap();
+        assert_eq!(first.volume, Some(25.0));
+        let second = resampled.get_quotes(&60).unwrap().get("ABC").unwrap();
+        assert_eq!(second.volume, Some(20.0));
+    }
+}
@@ -0,0 +1,40 @@
+//! Prometheus metrics for exchanges and the brokers that run against them.
+//!
+//! Metrics are registered with the default registry on first use and are shared by everything in
+//! the process. Gauges hold the value from the last update so running more than one backtest in a
+//! process will mix values from each.
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_counter, register_gauge, register_histogram, Counter, Encoder, Gauge, Histogram,
+    TextEncoder,
+};
+
+lazy_static! {
+    pub static ref TRADES_EXECUTED_TOTAL: Counter = register_counter!(
+        "trades_executed_total",
+        "Trades reconciled by brokers with the exchange"
+    )
+    .unwrap();
+    pub static ref PENDING_ORDERS: Gauge = register_gauge!(
+        "pending_orders",
+        "Orders in the exchange orderbook at the end of the last tick"
+    )
+    .unwrap();
+    pub static ref CASH_BALANCE: Gauge =
+        register_gauge!("cash_balance", "Broker cash balance after the last check").unwrap();
+    pub static ref TICK_DURATION_SECONDS: Histogram = register_histogram!(
+        "tick_duration_seconds",
+        "Time taken by the exchange to execute a tick"
+    )
+    .unwrap();
+}
+
+/// Every metric in the default registry in the Prometheus text format.
+pub fn gather() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap();
+    String::from_utf8(buffer).unwrap()
+}
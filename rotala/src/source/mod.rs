@@ -1,7 +1,29 @@
 //! Sources are external data sources that are used to create Inputs and then Exchanges. Source
 //! creation should be hidden from users and embedded within the creation of Inputs. Each Source
 //! should have its own internal format that is converted into an Input format within the Input.
+use std::error::Error;
+use std::fmt;
 use std::io::{Cursor, Write};
+use std::path::Path;
+
+use time::{macros::format_description, Date};
+
+#[derive(Debug)]
+pub enum DataLoadError {
+    Io(String),
+    Parse(String),
+}
+
+impl Error for DataLoadError {}
+
+impl fmt::Display for DataLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataLoadError::Io(err) => write!(f, "Io: {err}"),
+            DataLoadError::Parse(err) => write!(f, "Parse: {err}"),
+        }
+    }
+}
 
 pub struct BinanceKlinesQuote {
     pub open_date: i64,
@@ -66,3 +88,56 @@ pub fn get_binance_1m_klines() -> Vec<BinanceKlinesQuote> {
     }
     result
 }
+
+pub struct YFinanceQuote {
+    pub date: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub adj_close: f64,
+    pub volume: f64,
+}
+
+// Reads the CSV export from Yahoo Finance: `Date,Open,High,Low,Close,Adj Close,Volume`. Dates are
+// converted to epoch at midnight UTC. Yahoo uses `null` for days without prices, these rows are
+// skipped.
+pub fn get_yfinance_csv(path: &Path) -> Result<Vec<YFinanceQuote>, DataLoadError> {
+    let date_format = format_description!("[year]-[month]-[day]");
+    let mut rdr = csv::Reader::from_path(path).map_err(|e| DataLoadError::Io(e.to_string()))?;
+
+    let mut result = Vec::new();
+    for row in rdr.records() {
+        let row = row.map_err(|e| DataLoadError::Parse(e.to_string()))?;
+        if row.len() < 7 {
+            return Err(DataLoadError::Parse(format!(
+                "Expected 7 columns: {:?}",
+                row
+            )));
+        }
+        if row.iter().any(|field| field == "null") {
+            continue;
+        }
+
+        let date = Date::parse(&row[0], &date_format)
+            .map_err(|e| DataLoadError::Parse(e.to_string()))?
+            .midnight()
+            .assume_utc()
+            .unix_timestamp();
+        let parse = |pos: usize| -> Result<f64, DataLoadError> {
+            row[pos]
+                .parse::<f64>()
+                .map_err(|e| DataLoadError::Parse(format!("{}: {}", &row[pos], e)))
+        };
+        result.push(YFinanceQuote {
+            date,
+            open: parse(1)?,
+            high: parse(2)?,
+            low: parse(3)?,
+            close: parse(4)?,
+            adj_close: parse(5)?,
+            volume: parse(6)?,
+        });
+    }
+    Ok(result)
+}
@@ -0,0 +1,35 @@
+use std::path::Path;
+
+use rotala::input::penelope::Penelope;
+
+const JAN_2: i64 = 1704153600;
+const JAN_4: i64 = JAN_2 + 2 * 86400;
+
+#[test]
+fn test_that_yfinance_csv_loads_with_spread() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/yfinance/ABC.csv");
+    let source = Penelope::from_yfinance_csv(&path, "ABC", 0.02).unwrap();
+
+    let quote = source.get_quotes(&JAN_2).unwrap().get("ABC").unwrap();
+    assert_eq!(quote.bid, 99.0);
+    assert_eq!(quote.ask, 101.0);
+
+    //Null row is skipped
+    assert!(source.get_quotes(&JAN_4).is_none());
+    assert_eq!(source.get_position(&(JAN_4 + 86400)), Some(2));
+}
+
+#[test]
+fn test_that_yfinance_directory_merges_symbols_by_date() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/yfinance");
+    let source = Penelope::from_yfinance_csv_directory(&dir, 0.0).unwrap();
+
+    assert_eq!(source.get_quotes(&JAN_2).unwrap().len(), 2);
+    assert_eq!(source.get_position(&JAN_4), Some(2));
+    assert_eq!(
+        source.get_quotes(&JAN_4).unwrap().get("BCD").unwrap().bid,
+        51.0
+    );
+
+    assert!(Penelope::from_yfinance_csv(&dir.join("XYZ.csv"), "XYZ", 0.0).is_err());
+}